@@ -0,0 +1,134 @@
+//! Grouping molecules into size bins and drawing balanced samples from them,
+//! for building benchmark subsets that aren't dominated by the most common
+//! molecule sizes
+
+use std::collections::BTreeMap;
+
+use crate::molecule::Molecule;
+
+/// The property used to bin molecules
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeMeasure {
+    #[default]
+    HeavyAtoms,
+    /// the molecular weight in Daltons
+    MolecularWeight,
+}
+
+impl SizeMeasure {
+    pub fn measure(&self, mol: &Molecule) -> f64 {
+        match self {
+            SizeMeasure::HeavyAtoms => mol.n_heavy_atoms() as f64,
+            SizeMeasure::MolecularWeight => mol.molecular_weight(),
+        }
+    }
+}
+
+/// Molecules grouped into bins of equal width. Bin `i` holds the molecules
+/// whose size is in `[i * width, (i + 1) * width)`, and only bins with at
+/// least one molecule are stored
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bins {
+    pub measure: SizeMeasure,
+    pub width: f64,
+    /// bin index -> positions of the molecules in the binned list
+    pub bins: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Bins {
+    /// bin `mols` by `measure` into bins of `width`. panics if `width` is not
+    /// positive
+    pub fn new(mols: &[Molecule], measure: SizeMeasure, width: f64) -> Self {
+        assert!(width > 0.0, "bin width must be positive, got {width}");
+        let mut bins: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, mol) in mols.iter().enumerate() {
+            let bin = (measure.measure(mol) / width).floor() as usize;
+            bins.entry(bin).or_default().push(i);
+        }
+        Self {
+            measure,
+            width,
+            bins,
+        }
+    }
+
+    /// the half-open range of sizes covered by bin `bin`
+    pub fn range(&self, bin: usize) -> (f64, f64) {
+        (bin as f64 * self.width, (bin + 1) as f64 * self.width)
+    }
+
+    /// the number of molecules in each non-empty bin
+    pub fn counts(&self) -> Vec<(usize, usize)> {
+        self.bins.iter().map(|(&b, mols)| (b, mols.len())).collect()
+    }
+
+    /// draw up to `per_bin` molecules from each bin without replacement,
+    /// returning their positions grouped by bin in increasing order. bins
+    /// with fewer molecules contribute all of them. the same `seed` always
+    /// gives the same sample
+    pub fn sample(&self, per_bin: usize, seed: u64) -> Vec<usize> {
+        let mut rng = SplitMix64(seed);
+        let mut ret = Vec::new();
+        for mols in self.bins.values() {
+            let mut mols = mols.clone();
+            // partial Fisher-Yates shuffle of the first `per_bin` slots
+            let n = per_bin.min(mols.len());
+            for i in 0..n {
+                let j = i + (rng.next() % (mols.len() - i) as u64) as usize;
+                mols.swap(i, j);
+            }
+            let mut chosen = mols[..n].to_vec();
+            chosen.sort();
+            ret.extend(chosen);
+        }
+        ret
+    }
+}
+
+/// The SplitMix64 generator, which is plenty for sampling and avoids pulling
+/// in a dependency
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn bins() {
+        let mols = vec![
+            mol("[#6H4]"),
+            mol("[#6H3]-[#6H3]"),
+            mol("[#6H3]-[#8H]"),
+            mol("[#6H3]-[#6H2]-[#6H2]-[#6H3]"),
+            mol("[#6H3]-[#6H2]-[#6H2]-[#8H]"),
+            mol("[#6H3]-[#6H2]-[#6H2]-[#6H2]-[#8H]"),
+        ];
+        let bins = Bins::new(&mols, SizeMeasure::HeavyAtoms, 2.0);
+        assert_eq!(bins.counts(), [(0, 1), (1, 2), (2, 3)]);
+        assert_eq!(bins.range(1), (2.0, 4.0));
+        assert_eq!(bins.bins[&2], [3, 4, 5]);
+
+        let sample = bins.sample(1, 42);
+        assert_eq!(sample.len(), 3);
+        assert_eq!(sample[0], 0);
+        assert_eq!(sample, bins.sample(1, 42));
+        assert_eq!(bins.sample(10, 0), [0, 1, 2, 3, 4, 5]);
+
+        // methane is under 20 Da, and ethane and methanol are between 20 and 40
+        let bins = Bins::new(&mols, SizeMeasure::MolecularWeight, 20.0);
+        assert_eq!(bins.bins[&0], [0]);
+        assert_eq!(bins.bins[&1], [1, 2]);
+    }
+}
@@ -0,0 +1,693 @@
+//! Canonical keys for molecules and bulk canonicalization of whole datasets,
+//! for finding duplicates within a dataset or the overlap between two.
+//!
+//! A key only describes the constitution: elements, isotopes, H counts,
+//! charges, aromaticity, and bonds. Stereochemistry is ignored, since chirality tags
+//! depend on the order of the atoms in the input. [canonical_smiles] writes
+//! the stereochemistry too, rewriting each chirality tag for the order the
+//! atoms are written in
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    elements,
+    error::ChomperError,
+    molecule::{BondType, Direction, MolAtom, Molecule},
+    rdkit,
+    smarts::Chiral,
+    stereo::{self, IMPLICIT_H},
+    symmetry::{canonical_order, canonical_order_colored},
+};
+
+/// a string that is the same for two molecules exactly when they have the
+/// same constitution, regardless of the order of their atoms and bonds
+pub fn canonical_key(mol: &Molecule) -> String {
+    canonical_key_colored(mol, &vec![0; mol.atoms.len()])
+}
+
+/// like [canonical_key], but atoms are also told apart by their entry in
+/// `colors`, like those from [crate::symmetry::colors], which is written
+/// after each atom with a nonzero color
+pub fn canonical_key_colored(mol: &Molecule, colors: &[usize]) -> String {
+    let order = canonical_order_colored(mol, colors);
+    let mut atoms = vec![0; order.len()];
+    for (i, &c) in order.iter().enumerate() {
+        atoms[c] = i;
+    }
+    let mut ret = String::new();
+    for (c, &i) in atoms.iter().enumerate() {
+        let a = &mol.atoms[i];
+        if c > 0 {
+            ret.push('.');
+        }
+        if let Some(n) = a.isotope {
+            write!(ret, "{n}i").unwrap();
+        }
+        write!(ret, "{}h{}{:+}", a.atomic_number, a.n_hydrogens, a.charge)
+            .unwrap();
+        if a.aromatic {
+            ret.push('a');
+        }
+        if colors[i] != 0 {
+            write!(ret, "~{}", colors[i]).unwrap();
+        }
+    }
+    let mut bonds: Vec<_> = mol
+        .bonds
+        .iter()
+        .map(|b| {
+            let (i, j) = (order[b.atom1], order[b.atom2]);
+            (i.min(j), i.max(j), b.bond_type)
+        })
+        .collect();
+    bonds.sort();
+    ret.push('|');
+    for (k, (i, j, t)) in bonds.into_iter().enumerate() {
+        if k > 0 {
+            ret.push('.');
+        }
+        write!(ret, "{i}{}{j}", bond_symbol(t)).unwrap();
+    }
+    ret
+}
+
+/// write the connected molecule `mol` as SMARTS, starting from the first atom
+/// in `order`, like that from [canonical_order], and visiting neighbors in
+/// that order, with each atom written by `atom_smarts`. with a canonical
+/// order, molecules with the same [canonical_key] are written the same way.
+/// every bond is written explicitly, and each ring closure is written with
+/// its bond symbol at both ends
+pub fn write_canonical(
+    mol: &Molecule,
+    order: &[usize],
+    atom_smarts: impl Fn(usize) -> String,
+) -> String {
+    let style = SmartsStyle {
+        mol,
+        atoms: (0..mol.atoms.len()).map(atom_smarts).collect(),
+    };
+    write_dfs(mol, order, &style)
+}
+
+/// mark the bonds of the DFS tree from `u` in `tree`
+pub(crate) fn dfs_tree(
+    adj: &[Vec<(usize, usize)>],
+    u: usize,
+    visited: &mut [bool],
+    tree: &mut [bool],
+) {
+    for &(v, k) in &adj[u] {
+        if !visited[v] {
+            visited[v] = true;
+            tree[k] = true;
+            dfs_tree(adj, v, visited, tree);
+        }
+    }
+}
+
+/// How [write_dfs] writes the atoms and bonds of a molecule
+trait DfsStyle {
+    /// the text of atom `u` when its neighbors are written in the order
+    /// `neighbors`, with [IMPLICIT_H] for each implicit hydrogen
+    fn atom(&self, u: usize, neighbors: &[usize]) -> String;
+
+    /// the text of bond `k` written from atom `from`
+    fn bond(&self, k: usize, from: usize) -> String;
+
+    /// whether a ring-closure bond is written again where the ring closes,
+    /// rather than only where it opens
+    const CLOSE_WITH_BOND: bool;
+}
+
+/// The [DfsStyle] of [write_canonical]
+struct SmartsStyle<'a> {
+    mol: &'a Molecule,
+    /// the SMARTS of each atom
+    atoms: Vec<String>,
+}
+
+impl DfsStyle for SmartsStyle<'_> {
+    const CLOSE_WITH_BOND: bool = true;
+
+    fn atom(&self, u: usize, _: &[usize]) -> String {
+        self.atoms[u].clone()
+    }
+
+    fn bond(&self, k: usize, _: usize) -> String {
+        bond_symbol(self.mol.bonds[k].bond_type).to_string()
+    }
+}
+
+/// write `mol` in `style`, starting each connected component from its first
+/// atom in `order` and visiting neighbors in that order. components are
+/// joined by `.`
+fn write_dfs(mol: &Molecule, order: &[usize], style: &impl DfsStyle) -> String {
+    let n = mol.atoms.len();
+    let mut adj = vec![Vec::new(); n];
+    for (k, b) in mol.bonds.iter().enumerate() {
+        adj[b.atom1].push((b.atom2, k));
+        adj[b.atom2].push((b.atom1, k));
+    }
+    for nbrs in &mut adj {
+        nbrs.sort_by_key(|&(v, _)| order[v]);
+    }
+    let mut atoms: Vec<usize> = (0..n).collect();
+    atoms.sort_by_key(|&a| order[a]);
+    let mut visited = vec![false; n];
+    let mut tree = vec![false; mol.bonds.len()];
+    let mut roots = Vec::new();
+    for a in atoms {
+        if !visited[a] {
+            visited[a] = true;
+            dfs_tree(&adj, a, &mut visited, &mut tree);
+            roots.push(a);
+        }
+    }
+    let walk = Walk {
+        mol,
+        adj: &adj,
+        tree: &tree,
+        style,
+    };
+    let mut written = vec![false; n];
+    let mut open = BTreeMap::new();
+    let mut parts = Vec::new();
+    for root in roots {
+        let mut ret = String::new();
+        walk.emit(root, None, &mut written, &mut open, &mut ret);
+        parts.push(ret);
+    }
+    parts.join(".")
+}
+
+/// The parts of [write_dfs] that stay fixed while it walks the molecule
+struct Walk<'a, S> {
+    mol: &'a Molecule,
+    adj: &'a [Vec<(usize, usize)>],
+    tree: &'a [bool],
+    style: &'a S,
+}
+
+impl<S: DfsStyle> Walk<'_, S> {
+    /// write atom `u`, reached from `parent`, then its ring closures and its
+    /// DFS subtree. `open` maps each bond with an open ring closure to its
+    /// label
+    fn emit(
+        &self,
+        u: usize,
+        parent: Option<usize>,
+        written: &mut [bool],
+        open: &mut BTreeMap<usize, usize>,
+        ret: &mut String,
+    ) {
+        written[u] = true;
+        let closures: Vec<_> = self.adj[u]
+            .iter()
+            .filter(|(_, k)| !self.tree[*k])
+            .copied()
+            .collect();
+        let children: Vec<_> = self.adj[u]
+            .iter()
+            .filter(|&&(v, k)| self.tree[k] && !written[v])
+            .copied()
+            .collect();
+        let mut neighbors: Vec<usize> = parent.into_iter().collect();
+        neighbors.extend(std::iter::repeat_n(
+            IMPLICIT_H,
+            self.mol.atoms[u].n_hydrogens,
+        ));
+        neighbors.extend(closures.iter().map(|&(v, _)| v));
+        neighbors.extend(children.iter().map(|&(v, _)| v));
+        ret.push_str(&self.style.atom(u, &neighbors));
+        for (_, k) in closures {
+            match open.remove(&k) {
+                Some(label) => {
+                    if S::CLOSE_WITH_BOND {
+                        ret.push_str(&self.style.bond(k, u));
+                    }
+                    write_label(ret, label);
+                }
+                None => {
+                    ret.push_str(&self.style.bond(k, u));
+                    let label = (1..).find(|l| !open.values().any(|v| v == l));
+                    let label = label.unwrap();
+                    open.insert(k, label);
+                    write_label(ret, label);
+                }
+            }
+        }
+        for (i, &(v, k)) in children.iter().enumerate() {
+            let branch = i + 1 < children.len();
+            if branch {
+                ret.push('(');
+            }
+            ret.push_str(&self.style.bond(k, u));
+            self.emit(v, Some(u), written, open, ret);
+            if branch {
+                ret.push(')');
+            }
+        }
+    }
+}
+
+/// write ring closure `label`, with a `%` for two-digit labels
+fn write_label(ret: &mut String, label: usize) {
+    if label > 9 {
+        ret.push('%');
+    }
+    write!(ret, "{label}").unwrap();
+}
+
+/// the SMARTS symbol for a bond of type `bond_type`
+pub fn bond_symbol(bond_type: BondType) -> char {
+    match bond_type {
+        BondType::Single => '-',
+        BondType::Double => '=',
+        BondType::Triple => '#',
+        BondType::Aromatic => ':',
+    }
+}
+
+/// write `mol` as an isomeric SMILES string in canonical atom order, with
+/// every atom in brackets and without atom maps. chirality tags are rewritten
+/// for the order the neighbors of each center are written in, and the
+/// directions of the bonds around stereo double bonds are kept, so the
+/// output describes the same stereoisomer as `mol`. two inputs of the same
+/// stereoisomer can still be written differently when an automorphism of the
+/// constitution exchanges stereocenters, as in meso compounds
+pub fn canonical_smiles(mol: &Molecule) -> String {
+    write_smiles(mol, &canonical_order(mol), |i, chirality| {
+        let atom = &mol.atoms[i];
+        let sym = elements::symbol(atom.atomic_number).unwrap_or("*");
+        let sym = if atom.aromatic {
+            sym.to_lowercase()
+        } else {
+            sym.to_owned()
+        };
+        bracket_atom(&sym, atom, chirality) + "]"
+    })
+}
+
+/// the bracket atom for `atom` with the element `element` and the chirality
+/// tag `chirality`, without a closing bracket so a map can be added
+fn bracket_atom(element: &str, atom: &MolAtom, chirality: &Chiral) -> String {
+    let mut ret = String::from("[");
+    if let Some(n) = atom.isotope {
+        write!(ret, "{n}").unwrap();
+    }
+    ret.push_str(element);
+    match chirality {
+        Chiral::Acw => ret.push('@'),
+        Chiral::Cw => ret.push_str("@@"),
+        Chiral::None => {}
+    }
+    match atom.n_hydrogens {
+        0 => {}
+        1 => ret.push('H'),
+        h => write!(ret, "H{h}").unwrap(),
+    }
+    match atom.charge {
+        0 => {}
+        1 => ret.push('+'),
+        -1 => ret.push('-'),
+        q => write!(ret, "{q:+}").unwrap(),
+    }
+    ret
+}
+
+/// write `mol` as SMILES, starting each connected component from its first
+/// atom in `order` and visiting neighbors in that order. `atom_smiles` writes
+/// the atom at each position given its chirality tag for the written order
+fn write_smiles(
+    mol: &Molecule,
+    order: &[usize],
+    atom_smiles: impl Fn(usize, &Chiral) -> String,
+) -> String {
+    write_dfs(mol, order, &SmilesStyle { mol, atom_smiles })
+}
+
+/// The [DfsStyle] of [write_smiles]
+struct SmilesStyle<'a, F> {
+    mol: &'a Molecule,
+    atom_smiles: F,
+}
+
+impl<F> SmilesStyle<'_, F> {
+    /// the chirality tag of atom `u` when its neighbors are written in the
+    /// order `written`
+    fn chirality(&self, u: usize, written: &[usize]) -> Chiral {
+        let tag = &self.mol.atoms[u].chirality;
+        if *tag == Chiral::None {
+            return Chiral::None;
+        }
+        let mut input: Vec<Option<usize>> =
+            stereo::smiles_neighbors(self.mol, u)
+                .into_iter()
+                .map(Some)
+                .collect();
+        let mut perm = Vec::new();
+        for w in written {
+            let Some(p) = input.iter().position(|&i| i == Some(*w)) else {
+                return tag.clone();
+            };
+            input[p] = None;
+            perm.push(p);
+        }
+        if stereo::is_odd(&perm) {
+            stereo::flip(tag)
+        } else {
+            tag.clone()
+        }
+    }
+}
+
+impl<F: Fn(usize, &Chiral) -> String> DfsStyle for SmilesStyle<'_, F> {
+    const CLOSE_WITH_BOND: bool = false;
+
+    fn atom(&self, u: usize, neighbors: &[usize]) -> String {
+        (self.atom_smiles)(u, &self.chirality(u, neighbors))
+    }
+
+    /// an empty string where the bond is implied
+    fn bond(&self, k: usize, from: usize) -> String {
+        let bond = &self.mol.bonds[k];
+        if let Some(d) = bond.direction {
+            let up = (d == Direction::Up) == (bond.atom1 == from);
+            return if up { "/" } else { "\\" }.to_owned();
+        }
+        let aromatic = self.mol.atoms[bond.atom1].aromatic
+            && self.mol.atoms[bond.atom2].aromatic;
+        match (bond.bond_type, aromatic) {
+            (BondType::Single, false) | (BondType::Aromatic, true) => {
+                String::new()
+            }
+            (t, _) => bond_symbol(t).to_string(),
+        }
+    }
+}
+
+/// the SMILES in `smiles` with every repeat of the same molecule removed,
+/// keeping the first occurrence of each in its original form, along with the
+/// number of SMILES dropped and an error for each SMILES that rdkit couldn't
+/// read, which are skipped too. molecules are compared by their canonical
+/// isomeric SMILES from rdkit, ignoring atom maps and explicit hydrogens, so
+/// differently mapped cmiles of one molecule count as duplicates but
+/// stereoisomers don't
+pub fn unique_smiles(
+    smiles: impl IntoIterator<Item = String>,
+) -> (Vec<String>, usize, Vec<ChomperError>) {
+    let mut seen = HashSet::new();
+    let mut ret = Vec::new();
+    let mut n_dropped = 0;
+    let mut errors = Vec::new();
+    for s in smiles {
+        match rdkit::canonical_smiles(&s).map(|key| seen.insert(key)) {
+            Ok(true) => ret.push(s),
+            Ok(false) => n_dropped += 1,
+            Err(e) => errors.push(e),
+        }
+    }
+    (ret, n_dropped, errors)
+}
+
+/// Assigns each distinct key a small integer ID. Sharing one interner across
+/// several calls to [canonicalize_all] makes their IDs comparable, which is
+/// what [KeyIndex::overlap] relies on
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    ids: HashMap<String, usize>,
+    keys: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the ID for `key`, assigning the next one if it is new
+    pub fn intern(&mut self, key: String) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let id = self.keys.len();
+        self.keys.push(key.clone());
+        self.ids.insert(key, id);
+        id
+    }
+
+    pub fn get(&self, key: &str) -> Option<usize> {
+        self.ids.get(key).copied()
+    }
+
+    /// the key interned as `id`. panics if `id` was not returned by `self`
+    pub fn key(&self, id: usize) -> &str {
+        &self.keys[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// A multimap from key IDs to the positions of the molecules with that key,
+/// as returned by [canonicalize_all]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyIndex {
+    /// the key ID of each molecule, in the input order
+    pub ids: Vec<usize>,
+    /// key ID -> molecule positions, in increasing order
+    pub records: HashMap<usize, Vec<usize>>,
+}
+
+impl KeyIndex {
+    /// the number of distinct keys
+    pub fn n_unique(&self) -> usize {
+        self.records.len()
+    }
+
+    /// the groups of molecule positions sharing a key, for every key shared
+    /// by more than one molecule, sorted by their first position
+    pub fn duplicates(&self) -> Vec<&[usize]> {
+        let mut ret: Vec<_> = self
+            .records
+            .values()
+            .filter(|r| r.len() > 1)
+            .map(Vec::as_slice)
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// the key IDs present in both `self` and `other`, in increasing order.
+    /// both must have been built with the same [Interner]
+    pub fn overlap(&self, other: &KeyIndex) -> Vec<usize> {
+        let mut ret: Vec<_> = self
+            .records
+            .keys()
+            .filter(|id| other.records.contains_key(id))
+            .copied()
+            .collect();
+        ret.sort();
+        ret
+    }
+}
+
+/// compute the [canonical_key] of every molecule in `mols`, splitting the work
+/// across the available threads, and intern the keys in `interner`
+pub fn canonicalize_all(
+    mols: &[Molecule],
+    interner: &mut Interner,
+) -> KeyIndex {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = mols.len().div_ceil(threads).max(1);
+    let keys: Vec<String> = std::thread::scope(|s| {
+        let handles: Vec<_> = mols
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk.iter().map(canonical_key).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    let mut ret = KeyIndex::default();
+    for (i, key) in keys.into_iter().enumerate() {
+        let id = interner.intern(key);
+        ret.ids.push(id);
+        ret.records.entry(id).or_default().push(i);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        molecule::mol,
+        rdkit,
+        stereo::{bond_label, cip_label},
+        Dataset,
+    };
+
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let a = mol("[#6H3]-[#6H2]-[#8H]");
+        let b = mol("[#8H]-[#6H2]-[#6H3]");
+        let c = mol("[#6H3]-[#8]-[#6H3]");
+        assert_eq!(canonical_key(&a), canonical_key(&b));
+        assert_ne!(canonical_key(&a), canonical_key(&c));
+        assert_eq!(canonical_key(&a), "6h2+0.6h3+0.8h1+0|0-1.0-2");
+
+        // the same ring written from different starting atoms
+        let d = mol("[cH]1:[cH]:[cH]:[cH]:[cH]:[cH0]:1-[#8H]");
+        let e = mol("[#8H]-[cH0]1:[cH]:[cH]:[cH]:[cH]:[cH]:1");
+        assert_eq!(canonical_key(&d), canonical_key(&e));
+
+        // deuterium is told apart from hydrogen
+        let f = mol("[2H]-[#8H]");
+        assert_ne!(canonical_key(&f), canonical_key(&mol("[#1]-[#8H]")));
+        assert_eq!(canonical_key(&f), "2i1h0+0.8h1+0|0-1");
+        assert_eq!(canonical_smiles(&f), "[2H][OH]");
+    }
+
+    #[test]
+    fn bulk() {
+        let left = vec![
+            mol("[#6H3]-[#6H2]-[#8H]"),
+            mol("[#6H4]"),
+            mol("[#8H]-[#6H2]-[#6H3]"),
+        ];
+        let right = vec![mol("[#6H3]-[#8]-[#6H3]"), mol("[#6H4]")];
+        let mut interner = Interner::new();
+        let l = canonicalize_all(&left, &mut interner);
+        let r = canonicalize_all(&right, &mut interner);
+        assert_eq!(l.n_unique(), 2);
+        assert_eq!(l.duplicates(), [&[0, 2][..]]);
+        assert_eq!(l.ids[0], l.ids[2]);
+        assert!(r.duplicates().is_empty());
+        assert_eq!(l.overlap(&r), [l.ids[1]]);
+        assert_eq!(interner.len(), 3);
+        assert_eq!(interner.get(interner.key(l.ids[1])), Some(l.ids[1]));
+    }
+
+    #[test]
+    fn two_digit_labels() {
+        // a hub bonded to every atom of an 11-membered ring, which keeps ten
+        // ring closures open at once when written from the hub
+        let mut s = "[#6]123456789%10-[#6H]%11".to_owned();
+        for i in 1..=9 {
+            s += &format!("-[#6H]{i}");
+        }
+        s += "-[#6H]%10%11";
+        let m = mol(&s);
+        let order: Vec<_> = (0..m.atoms.len()).collect();
+        let smarts = write_canonical(&m, &order, |i| {
+            format!("[#6H{}]", m.atoms[i].n_hydrogens)
+        });
+        assert!(smarts.contains("%10"), "{smarts}");
+        assert_eq!(canonical_key(&mol(&smarts)), canonical_key(&m));
+        let smiles = write_smiles(&m, &order, |i, _| {
+            format!("[CH{}]", m.atoms[i].n_hydrogens)
+        });
+        assert!(smiles.contains("%10"), "{smiles}");
+        assert_eq!(canonical_key(&mol(&smiles)), canonical_key(&m));
+    }
+
+    /// write `s` with [write_smiles], mapping each atom to its original
+    /// position, and check that every stereocenter and stereo double bond of
+    /// the parsed result has the same label as in `s`
+    fn stereo_round_trip(s: &str) {
+        let m = mol(s);
+        let out = write_smiles(&m, &canonical_order(&m), |i, chirality| {
+            let atom = &m.atoms[i];
+            let z = format!("#{}", atom.atomic_number);
+            format!("{}:{}]", bracket_atom(&z, atom, chirality), i + 1)
+        });
+        let back = mol(&out);
+        let orig = |j: usize| back.atoms[j].mol_index.unwrap() - 1;
+        let mut n_labels = 0;
+        for j in 0..back.atoms.len() {
+            assert_eq!(cip_label(&back, j), cip_label(&m, orig(j)), "{out}");
+            n_labels += usize::from(cip_label(&back, j).is_some());
+        }
+        for (k, b) in back.bonds.iter().enumerate() {
+            let (i, j) = (orig(b.atom1), orig(b.atom2));
+            let o = m
+                .bonds
+                .iter()
+                .position(|b| {
+                    (b.atom1, b.atom2) == (i, j) || (b.atom1, b.atom2) == (j, i)
+                })
+                .unwrap();
+            assert_eq!(bond_label(&back, k), bond_label(&m, o), "{out}");
+            n_labels += usize::from(bond_label(&back, k).is_some());
+        }
+        assert!(n_labels > 0, "{s} has no stereochemistry");
+    }
+
+    #[test]
+    fn smiles() {
+        // alanine, a ring stereocenter, and both isomers of a double bond
+        // written from either end
+        stereo_round_trip("[#7H2][#6@@H]([#6H3])[#6](=[#8])[#8H]");
+        stereo_round_trip("[#8H][#6](=[#8])[#6@H]([#6H3])[#7H2]");
+        stereo_round_trip("[#6@@H]1([#9])[#6H2][#8][#6H2][#6H2]1");
+        stereo_round_trip("[#6H2]1[#6@H]([#9])[#6H2][#8][#6H2]1");
+        stereo_round_trip("[#9]/[#6H]=[#6H]/[#6H2][#8H]");
+        stereo_round_trip("[#8H][#6H2]/[#6H]=[#6H]\\[#9]");
+        stereo_round_trip("[#6H3]/[#6H]=[#6H]/[#6@H]([#9])[#17]");
+
+        let m = mol("[#7H2][#6@@H]([#6H3])[#6](=[#8])[#8H]");
+        assert_eq!(canonical_smiles(&m), "[C]([C@H]([CH3])[NH2])(=[O])[OH]");
+        let m = mol("[cH]1:[cH]:[cH]:[cH]:[cH]:[c]:1-[#8H]");
+        assert_eq!(canonical_smiles(&m), "[c]1([cH][cH][cH][cH][cH]1)[OH]");
+    }
+
+    #[test]
+    fn unique() {
+        let smiles = [
+            "C1CC",
+            "CCO",
+            "N[C@@H](C)C(=O)O",
+            "[C:1]([C:2]([O:3][H:9])([H:7])[H:8])([H:4])([H:5])[H:6]",
+            "N[C@H](C)C(=O)O",
+            "OCC",
+        ];
+        let (got, n_dropped, errors) = unique_smiles(smiles.map(str::to_owned));
+        assert_eq!(got, [smiles[1], smiles[2], smiles[4]]);
+        assert_eq!(n_dropped, 2);
+        // the unclosed ring is skipped, not compared
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("C1CC"));
+    }
+
+    #[test]
+    fn rdkit_smiles() {
+        let ds = Dataset::load("testfiles/opt.json").unwrap();
+        let smiles = ds.clone().to_smiles();
+        let mols = ds.molecules().unwrap();
+        let mut failed = Vec::new();
+        for (s, mol) in smiles.iter().zip(&mols) {
+            let got = rdkit::canonical_smiles(&canonical_smiles(mol)).unwrap();
+            let want = rdkit::canonical_smiles(s).unwrap();
+            if got != want {
+                failed.push((s, got, want));
+            }
+        }
+        assert!(failed.is_empty(), "{failed:#?}");
+    }
+}
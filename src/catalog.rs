@@ -0,0 +1,256 @@
+//! Named collections of SMARTS patterns loaded from disk
+
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use crate::{
+    error::ChomperError,
+    matcher::{match_matrix, match_matrix_timed, MatchMatrix, MatchOptions},
+    molecule::Molecule,
+    query::Query,
+    smarts::{InputKind, Smarts},
+    timing::Timings,
+};
+
+/// A single named pattern, keeping the original string alongside the parsed
+/// form
+pub struct Pattern {
+    pub name: String,
+    pub smarts: String,
+    pub pattern: Smarts,
+}
+
+/// An ordered collection of named SMARTS or SMIRKS patterns. Every pattern is
+/// parsed as [InputKind::Smarts] when it is added, so a catalog that loads
+/// successfully contains only valid patterns
+#[derive(Default)]
+pub struct PatternCatalog {
+    patterns: Vec<Pattern>,
+    index: HashMap<String, usize>,
+}
+
+impl PatternCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// load a catalog from `path`, choosing the format from the file
+    /// extension. .toml and .json files are read with
+    /// [PatternCatalog::from_toml] and [PatternCatalog::from_json], and
+    /// anything else is treated as plain text for [PatternCatalog::from_text]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChomperError> {
+        let path = path.as_ref();
+        let s = read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&s),
+            Some("json") => Self::from_json(&s),
+            _ => Self::from_text(&s),
+        }
+    }
+
+    /// parse a flat TOML table of `name = "pattern"` pairs. table headers are
+    /// allowed but ignored, so the pairs can live under a `[patterns]` table
+    pub fn from_toml(s: &str) -> Result<Self, ChomperError> {
+        let mut ret = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[')
+            {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(ChomperError::Format(format!(
+                    "line {}: expected `name = value`",
+                    i + 1
+                )));
+            };
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| {
+                    value.strip_prefix('\'').and_then(|v| v.strip_suffix('\''))
+                })
+                .ok_or_else(|| {
+                    ChomperError::Format(format!(
+                        "line {}: expected a quoted string",
+                        i + 1
+                    ))
+                })?;
+            ret.add(name.trim().trim_matches('"'), value)?;
+        }
+        Ok(ret)
+    }
+
+    /// parse a JSON array of `{"name": ..., "smarts": ...}` objects, or an
+    /// object mapping names to patterns. patterns from an array keep their
+    /// order in the file, while those from an object are sorted by name
+    pub fn from_json(s: &str) -> Result<Self, ChomperError> {
+        #[derive(serde::Deserialize)]
+        struct Entry {
+            name: String,
+            smarts: String,
+        }
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Layout {
+            List(Vec<Entry>),
+            Map(std::collections::BTreeMap<String, String>),
+        }
+        let entries: Vec<(String, String)> = match serde_json::from_str(s)? {
+            Layout::List(v) => {
+                v.into_iter().map(|e| (e.name, e.smarts)).collect()
+            }
+            Layout::Map(m) => m.into_iter().collect(),
+        };
+        let mut ret = Self::new();
+        for (name, smarts) in entries {
+            ret.add(&name, &smarts)?;
+        }
+        Ok(ret)
+    }
+
+    /// parse plain text with one `name pattern` pair per line. blank lines and
+    /// lines starting with `#` are skipped
+    pub fn from_text(s: &str) -> Result<Self, ChomperError> {
+        let mut ret = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once(char::is_whitespace)
+            else {
+                return Err(ChomperError::Format(format!(
+                    "line {}: expected `name pattern`",
+                    i + 1
+                )));
+            };
+            ret.add(name, value.trim())?;
+        }
+        Ok(ret)
+    }
+
+    /// parse `smarts` and add it to the catalog under `name`
+    pub fn add(
+        &mut self,
+        name: &str,
+        smarts: &str,
+    ) -> Result<(), ChomperError> {
+        if self.index.contains_key(name) {
+            return Err(ChomperError::Format(format!(
+                "duplicate pattern name {name}"
+            )));
+        }
+        let pattern =
+            Smarts::try_parse_as(smarts.to_owned(), InputKind::Smarts)
+                .map_err(|e| ChomperError::Pattern {
+                    name: name.to_owned(),
+                    smarts: smarts.to_owned(),
+                    error: Box::new(e),
+                })?;
+        self.index.insert(name.to_owned(), self.patterns.len());
+        self.patterns.push(Pattern {
+            name: name.to_owned(),
+            smarts: smarts.to_owned(),
+            pattern,
+        });
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Pattern> {
+        self.index.get(name).map(|&i| &self.patterns[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Pattern> {
+        self.patterns.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// match every pattern in the catalog against `molecules`. the query
+    /// indices in the returned matrix follow the catalog order
+    pub fn match_all(
+        &self,
+        molecules: &[Molecule],
+        options: &MatchOptions,
+    ) -> MatchMatrix {
+        match_matrix(&self.queries(), molecules, options)
+    }
+
+    /// like [PatternCatalog::match_all], but record the time spent matching
+    /// each molecule in `timings`, as in [match_matrix_timed]
+    pub fn match_all_timed(
+        &self,
+        molecules: &[Molecule],
+        options: &MatchOptions,
+        timings: &mut Timings,
+    ) -> MatchMatrix {
+        match_matrix_timed(&self.queries(), molecules, options, timings)
+    }
+
+    /// the [Query] for each pattern, in catalog order
+    fn queries(&self) -> Vec<Query> {
+        self.patterns
+            .iter()
+            .map(|p| Query::from(&p.pattern))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{molecule::mol, timing::Stage};
+
+    use super::*;
+
+    fn names(c: &PatternCatalog) -> Vec<&str> {
+        c.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn load_formats() {
+        for ext in ["toml", "json", "txt"] {
+            let got = PatternCatalog::load(format!("testfiles/catalog.{ext}"))
+                .unwrap();
+            assert_eq!(names(&got), ["alcohol", "carbonyl", "amine"], "{ext}");
+            assert_eq!(got.get("carbonyl").unwrap().smarts, "[#6:1]=[#8:2]");
+            assert_eq!(got.get("amine").unwrap().pattern.atoms.len(), 1);
+        }
+        let got =
+            PatternCatalog::from_json(r#"{"b": "[#7:1]", "a": "[#6:1]"}"#)
+                .unwrap();
+        assert_eq!(names(&got), ["a", "b"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let got = PatternCatalog::from_text("bad [#6:1]?[#6:2]")
+            .err()
+            .unwrap();
+        assert!(
+            matches!(got, ChomperError::Pattern { ref name, .. } if name == "bad")
+        );
+        assert!(PatternCatalog::from_text("a [#6:1]\na [#7:1]").is_err());
+    }
+
+    #[test]
+    fn match_all() {
+        let c = PatternCatalog::load("testfiles/catalog.txt").unwrap();
+        let mols = [mol("[#6H3:1]-[#6H:2]=[#8:3]"), mol("[#6H3:1]-[#7H2:2]")];
+        let got = c.match_all(&mols, &MatchOptions::default());
+        assert_eq!(got.entries, [(1, 0, 1), (2, 1, 1)]);
+
+        // one sample for each molecule
+        let mut timings = Timings::default();
+        let timed =
+            c.match_all_timed(&mols, &MatchOptions::default(), &mut timings);
+        assert_eq!(timed, got);
+        assert_eq!(timings.samples(Stage::Match).len(), 2);
+    }
+}
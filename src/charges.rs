@@ -0,0 +1,157 @@
+//! Per-atom partial charges computed outside of chomper, like AM1-BCC charges
+//! from OpenFF, keyed by the mapped SMILES of each molecule.
+//!
+//! Charges are listed in atom-map order, so the first charge belongs to the
+//! atom with map 1, and are reordered into atom order when attached to a
+//! [Molecule]
+
+use std::{collections::BTreeMap, fs::read_to_string, path::Path};
+
+use crate::{
+    error::ChomperError,
+    molecule::{Molecule, MoleculeError},
+};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartialCharges {
+    /// mapped SMILES -> the charge of each atom, in map order
+    charges: BTreeMap<String, Vec<f64>>,
+}
+
+impl PartialCharges {
+    /// load charges from `path`, which is read as CSV if it ends in `.csv`
+    /// and JSON otherwise. see [PartialCharges::from_json] and
+    /// [PartialCharges::from_csv] for the formats
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChomperError> {
+        let path = path.as_ref();
+        let s = read_to_string(path)?;
+        if path.extension().is_some_and(|e| e == "csv") {
+            Self::from_csv(&s)
+        } else {
+            Self::from_json(&s)
+        }
+    }
+
+    /// parse a JSON object from mapped SMILES to a list of charges in map
+    /// order
+    pub fn from_json(s: &str) -> Result<Self, ChomperError> {
+        Ok(Self {
+            charges: serde_json::from_str(s)?,
+        })
+    }
+
+    /// parse CSV with a header line and then one `smiles,map_index,charge`
+    /// row per atom. every map from 1 to the number of atoms must be present
+    /// for each molecule
+    pub fn from_csv(s: &str) -> Result<Self, ChomperError> {
+        let mut by_map: BTreeMap<String, BTreeMap<usize, f64>> =
+            BTreeMap::new();
+        for (i, line) in s.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            let [smiles, map, charge] = fields[..] else {
+                return Err(ChomperError::Format(format!(
+                    "line {}: expected 3 fields, got {}",
+                    i + 1,
+                    fields.len()
+                )));
+            };
+            let invalid = |what: &str, field: &str| {
+                ChomperError::Format(format!(
+                    "line {}: invalid {what} {field}",
+                    i + 1
+                ))
+            };
+            by_map.entry(smiles.to_owned()).or_default().insert(
+                map.parse().map_err(|_| invalid("map index", map))?,
+                charge.parse().map_err(|_| invalid("charge", charge))?,
+            );
+        }
+        let mut charges = BTreeMap::new();
+        for (smiles, atoms) in by_map {
+            if !atoms.keys().copied().eq(1..=atoms.len()) {
+                return Err(ChomperError::Format(format!(
+                    "atom maps for {smiles} are not 1..=n"
+                )));
+            }
+            charges.insert(smiles, atoms.into_values().collect());
+        }
+        Ok(Self { charges })
+    }
+
+    pub fn len(&self) -> usize {
+        self.charges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.charges.is_empty()
+    }
+
+    /// the charges for `smiles`, in map order
+    pub fn get(&self, smiles: &str) -> Option<&[f64]> {
+        self.charges.get(smiles).map(Vec::as_slice)
+    }
+
+    /// attach the charges for `smiles` to `mol`, reordering them from map
+    /// order into atom order with [Molecule::map_order]. `mol` is returned
+    /// unchanged if there are no charges for `smiles`
+    pub fn attach(
+        &self,
+        smiles: &str,
+        mol: Molecule,
+    ) -> Result<Molecule, MoleculeError> {
+        let Some(charges) = self.get(smiles) else {
+            return Ok(mol);
+        };
+        if charges.len() != mol.atoms.len() {
+            return Err(MoleculeError::PartialChargeSize {
+                n_atoms: mol.atoms.len(),
+                n_charges: charges.len(),
+            });
+        }
+        let ordered = mol.map_order().into_iter().map(|j| charges[j]).collect();
+        mol.with_partial_charges(ordered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::Smarts;
+
+    use super::*;
+
+    #[test]
+    fn load() {
+        let json = PartialCharges::load("testfiles/charges.json").unwrap();
+        let csv = PartialCharges::load("testfiles/charges.csv").unwrap();
+        assert_eq!(json, csv);
+        assert_eq!(json.len(), 2);
+
+        // the maps are out of order in the molecule, so the charges have to
+        // be reordered
+        let key = "[H:3][O:2][H:1]";
+        let mol = Molecule::try_from(&Smarts::parse(
+            "[#1H0:3]-[#8H0:2]-[#1H0:1]".to_owned(),
+        ))
+        .unwrap();
+        let got = json.attach(key, mol.clone()).unwrap();
+        assert_eq!(got.partial_charges, Some(vec![0.42, -0.83, 0.41]));
+        assert_eq!(json.get(key), Some(&[0.41, -0.83, 0.42][..]));
+        // no charges for this key
+        assert_eq!(json.attach("[H:1]", mol.clone()), Ok(mol.clone()));
+        let err = PartialCharges::from_json(r#"{"x": [0.0]}"#)
+            .unwrap()
+            .attach("x", mol);
+        assert_eq!(
+            err,
+            Err(MoleculeError::PartialChargeSize {
+                n_atoms: 3,
+                n_charges: 1
+            })
+        );
+
+        assert!(PartialCharges::from_csv("smiles,map,charge\nC,2,0.0").is_err());
+    }
+}
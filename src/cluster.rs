@@ -0,0 +1,117 @@
+//! Summarizing clusters of molecules by a few representatives, for building
+//! small QC submission sets that still cover every cluster.
+//!
+//! The representatives of a cluster are the members nearest its centre,
+//! measured by their mean [Fingerprint] similarity to the other members, so
+//! the first representative is the medoid
+
+use crate::{
+    fingerprint::Fingerprint,
+    molecule::Molecule,
+    symmetry::{colors, AtomInvariant},
+    Provenance,
+};
+
+/// The fingerprint radius used by [representatives]
+pub const RADIUS: usize = 2;
+
+/// One member chosen to represent its cluster
+#[derive(Clone, Debug, PartialEq)]
+pub struct Representative {
+    /// the position of the molecule in the list passed to [representatives]
+    pub index: usize,
+    pub provenance: Option<Provenance>,
+    /// the mean Tanimoto similarity to the other members of the cluster, or
+    /// 1 for a cluster of one
+    pub centrality: f64,
+}
+
+/// choose up to `k` representatives for each cluster in `clusters`, each of
+/// which lists positions in `mols`. the representatives of each cluster are
+/// sorted from most to least central, with ties broken by position
+pub fn representatives(
+    mols: &[Molecule],
+    clusters: &[Vec<usize>],
+    k: usize,
+) -> Vec<Vec<Representative>> {
+    representatives_with(mols, clusters, k, &|_: &Molecule, _| 0)
+}
+
+/// like [representatives], but with the fingerprints colored by `invariant`,
+/// so members are compared by the chemistry it sees, like the force field
+/// parameters assigned to each atom, rather than by their elements alone
+pub fn representatives_with(
+    mols: &[Molecule],
+    clusters: &[Vec<usize>],
+    k: usize,
+    invariant: &impl AtomInvariant,
+) -> Vec<Vec<Representative>> {
+    let mut fps: Vec<Option<Fingerprint>> = vec![None; mols.len()];
+    for &i in clusters.iter().flatten() {
+        if fps[i].is_none() {
+            let c = colors(&mols[i], invariant);
+            fps[i] = Some(Fingerprint::colored(&mols[i], RADIUS, &c));
+        }
+    }
+    let fp = |i: usize| fps[i].as_ref().unwrap();
+    clusters
+        .iter()
+        .map(|members| {
+            let mut ret: Vec<_> = members
+                .iter()
+                .map(|&i| {
+                    let others = members.iter().filter(|&&j| j != i);
+                    let n = others.clone().count();
+                    let centrality = if n == 0 {
+                        1.0
+                    } else {
+                        others.map(|&j| fp(i).tanimoto(fp(j))).sum::<f64>()
+                            / n as f64
+                    };
+                    Representative {
+                        index: i,
+                        provenance: mols[i].provenance.clone(),
+                        centrality,
+                    }
+                })
+                .collect();
+            ret.sort_by(|a, b| {
+                b.centrality
+                    .total_cmp(&a.centrality)
+                    .then(a.index.cmp(&b.index))
+            });
+            ret.truncate(k);
+            ret
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn medoid() {
+        let mols = [
+            mol("[#6H3]-[#6H2]-[#6H2]-[#8H]"),
+            mol("[#6H3]-[#6H2]-[#6H2]-[#6H2]-[#8H]"),
+            mol("[#8H]-[#6H2]-[#6H2]-[#6H3]"),
+            mol("[#6H3]-[#7H2]"),
+            mol("[cH]1:[cH]:[cH]:[cH]:[cH]:[cH]:1"),
+        ];
+        let clusters = vec![vec![0, 1, 2, 3], vec![4]];
+        let got = representatives(&mols, &clusters, 2);
+        assert_eq!(got.len(), 2);
+        // the two copies of propanol are nearest the rest of the cluster
+        let indices: Vec<_> = got[0].iter().map(|r| r.index).collect();
+        assert_eq!(indices, [0, 2]);
+        assert!(got[0][0].centrality >= got[0][1].centrality);
+        assert_eq!(got[1][0].index, 4);
+        assert_eq!(got[1][0].centrality, 1.0);
+        assert!(representatives(&mols, &clusters, 0)
+            .iter()
+            .all(Vec::is_empty));
+    }
+}
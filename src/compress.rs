@@ -0,0 +1,135 @@
+//! Transparent decompression of dataset files.
+//!
+//! Compressed files are recognized by their leading magic bytes rather than
+//! their extension, and decompressed as they are read, so a large .json.gz
+//! never has to be held in memory in both forms. Decompression goes through
+//! the same embedded Python interpreter as [crate::rdkit]: gzip through the
+//! standard library and zstd through `compression.zstd` on Python 3.14 or
+//! later, or the `zstandard` package otherwise. Every dataset loader in
+//! [crate::Dataset] reads its input through here
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use pyo3::{
+    prelude::{PyAnyMethods, PyBytesMethods},
+    types::{PyBytes, PyModule},
+    Py, PyAny, Python,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// `name` without any trailing .gz or .zst, which is decompressed when read
+pub fn uncompressed(name: &str) -> &str {
+    name.strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".zst"))
+        .unwrap_or(name)
+}
+
+/// The compression formats recognized by [open]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// the format of a file starting with `head`
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// A [Read] over a binary file object opened in Python
+struct PyReader {
+    file: Py<PyAny>,
+}
+
+impl Read for PyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Python::with_gil(|py| {
+            let data = self
+                .file
+                .bind(py)
+                .call_method1("read", (buf.len(),))
+                .map_err(io::Error::other)?;
+            let data = data.downcast::<PyBytes>().map_err(|e| {
+                io::Error::other(format!("read returned {e}, not bytes"))
+            })?;
+            let data = data.as_bytes();
+            buf[..data.len()].copy_from_slice(data);
+            Ok(data.len())
+        })
+    }
+}
+
+/// open `path` in Python with the `open` function from the first of `modules`
+/// that can be imported
+fn py_open(path: &Path, modules: &[&str]) -> io::Result<Box<dyn Read + Send>> {
+    Python::with_gil(|py| {
+        let module = modules
+            .iter()
+            .find_map(|m| PyModule::import_bound(py, *m).ok())
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "decompressing {} requires one of the Python modules {}",
+                    path.display(),
+                    modules.join(", ")
+                ))
+            })?;
+        let file = module.call_method1("open", (path, "rb"))?;
+        Ok(Box::new(PyReader {
+            file: file.unbind(),
+        }) as Box<dyn Read + Send>)
+    })
+}
+
+/// open the file at `path` for reading, decompressing it on the fly if it is
+/// compressed in one of the [Compression] formats
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn Read + Send>> {
+    let path = path.as_ref();
+    let mut f = File::open(path)?;
+    let mut head = [0; 4];
+    let n = f.read(&mut head)?;
+    match Compression::detect(&head[..n]) {
+        Compression::None => {
+            f.rewind()?;
+            Ok(Box::new(f))
+        }
+        Compression::Gzip => py_open(path, &["gzip"]),
+        Compression::Zstd => py_open(path, &["compression.zstd", "zstandard"]),
+    }
+}
+
+/// read all of the file at `path` into a string, decompressing it like [open]
+pub fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut ret = String::new();
+    open(path)?.read_to_string(&mut ret)?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip() {
+        let want = std::fs::read_to_string("testfiles/small.smi").unwrap();
+        assert_eq!(read_to_string("testfiles/small.smi.gz").unwrap(), want);
+        assert_eq!(read_to_string("testfiles/small.smi").unwrap(), want);
+
+        assert_eq!(Compression::detect(&ZSTD_MAGIC), Compression::Zstd);
+        assert_eq!(Compression::detect(b"{"), Compression::None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Named bundles of the options accepted across chomper, so that a workflow
+//! can be selected by name instead of by setting each option separately
+
+use std::{error::Error, fmt::Display, str::FromStr, time::Duration};
+
+use crate::{
+    conformance::ConformanceOptions, generate::GenerateOptions,
+    matcher::MatchOptions, query::HydrogenPolicy, smarts::InputKind,
+};
+
+/// The names accepted by [ChomperConfig::preset], in the order they are
+/// listed by the CLI
+pub const PRESETS: [&str; 3] =
+    ["openff-typing", "strict-validation", "fast-ingest"];
+
+/// The options for every stage of a chomper workflow. The default is the same
+/// as constructing each set of options with its own default
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChomperConfig {
+    /// how patterns given to the parser are read
+    pub input_kind: InputKind,
+    pub generate: GenerateOptions,
+    pub matching: MatchOptions,
+    /// the checks run against rdkit
+    pub conformance: ConformanceOptions,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownPreset(pub String);
+
+impl Display for UnknownPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown preset {}, expected one of {}",
+            self.0,
+            PRESETS.join(", ")
+        )
+    }
+}
+
+impl Error for UnknownPreset {}
+
+impl ChomperConfig {
+    /// the preset named `name`, one of [PRESETS]:
+    ///
+    /// - `openff-typing` reads patterns as SMARTS and matches them the way the
+    ///   OpenFF toolkit assigns parameters, collapsing the forward and
+    ///   reversed matches of the same atoms
+    /// - `strict-validation` compares chirality when matching and requires
+    ///   complete atom maps, including on hydrogens, when checking against
+    ///   rdkit, giving up on a record after 30 seconds
+    /// - `fast-ingest` skips the per-atom comparisons with rdkit, stops each
+    ///   match search at the first match, and drops shared H counts from
+    ///   generated patterns
+    pub fn preset(name: &str) -> Result<Self, UnknownPreset> {
+        let default = Self::default();
+        let ret = match name {
+            "openff-typing" => Self {
+                input_kind: InputKind::Smarts,
+                generate: GenerateOptions {
+                    hydrogens: HydrogenPolicy::Total,
+                },
+                matching: MatchOptions {
+                    dedup_symmetric: true,
+                    ..default.matching
+                },
+                ..default
+            },
+            "strict-validation" => Self {
+                matching: MatchOptions {
+                    use_chirality: true,
+                    ..default.matching
+                },
+                conformance: ConformanceOptions {
+                    strict_maps: Some(true),
+                    timeout: Some(Duration::from_secs(30)),
+                    ..default.conformance
+                },
+                ..default
+            },
+            "fast-ingest" => Self {
+                generate: GenerateOptions {
+                    hydrogens: HydrogenPolicy::Drop,
+                },
+                matching: MatchOptions {
+                    max_matches: Some(1),
+                    ..default.matching
+                },
+                conformance: ConformanceOptions {
+                    check_hydrogens: false,
+                    check_charges: false,
+                    check_bond_orders: false,
+                    ..default.conformance
+                },
+                ..default
+            },
+            _ => return Err(UnknownPreset(name.to_owned())),
+        };
+        Ok(ret)
+    }
+}
+
+impl FromStr for ChomperConfig {
+    type Err = UnknownPreset;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::preset(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        for name in PRESETS {
+            assert_ne!(
+                ChomperConfig::preset(name).unwrap(),
+                ChomperConfig::default(),
+                "{name}"
+            );
+        }
+        let got: ChomperConfig = "openff-typing".parse().unwrap();
+        assert_eq!(got.input_kind, InputKind::Smarts);
+        assert!(got.matching.dedup_symmetric);
+
+        let err = ChomperConfig::preset("slow").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown preset slow, expected one of openff-typing, \
+             strict-validation, fast-ingest"
+        );
+    }
+}
@@ -0,0 +1,677 @@
+//! Checking parsed molecules against the graphs rdkit perceives for the same
+//! records.
+//!
+//! The SMARTS written by rdkit does not always list atoms in the same order as
+//! the input, and unmapped molecules give no other way to pair atoms up, so
+//! the comparison is between multisets of atom and bond invariants rather
+//! than atom by atom. This catches dropped or invented atoms and bonds and
+//! wrong H counts, charges, and bond orders, but not, for example, two atoms
+//! whose H counts are swapped. Aromaticity is not compared, since the SMARTS
+//! rdkit writes for a molecule only marks it through the bonds.
+//!
+//! Each failure is tagged with a [FailureKind], and [Summary::by_kind] counts
+//! the failing records of each kind, to show which gaps in the parser affect
+//! a dataset the most. Each record is checked in isolation, so a panic
+//! anywhere in its checks, or a record that takes longer than
+//! [ConformanceOptions::timeout], becomes a failure for that record instead
+//! of ending the run
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::mpsc,
+    time::Duration,
+};
+
+use crate::{
+    elements,
+    rdkit::{smiles_to_graph, to_smarts},
+    smarts::{panic_message, Atom, BondOrder, Chiral, InputKind, Smarts},
+    timing::Stage,
+    Dataset, Provenance,
+};
+
+/// Which properties of the parsed molecules are compared to rdkit. Element
+/// identity and the number of atoms and bonds are always checked
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConformanceOptions {
+    pub check_hydrogens: bool,
+    pub check_charges: bool,
+    pub check_bond_orders: bool,
+    /// if set, also require complete atom maps with [Smarts::check_maps],
+    /// with the value passed as its `require_hydrogens`
+    pub strict_maps: Option<bool>,
+    /// if set, give up on a record after this long and report a
+    /// [FailureKind::Timeout]. the abandoned check keeps running in the
+    /// background until it finishes
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ConformanceOptions {
+    fn default() -> Self {
+        Self {
+            check_hydrogens: true,
+            check_charges: true,
+            check_bond_orders: true,
+            strict_maps: None,
+            timeout: None,
+        }
+    }
+}
+
+/// The categories of [Failure], in the order the checks on a record run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FailureKind {
+    /// rdkit could not read the SMILES or write it as SMARTS
+    Rdkit,
+    /// the scanner hit a character it doesn't recognize
+    Scan,
+    /// the parser or evaluator rejected the token stream, like an unclosed
+    /// bracket or ring
+    Parse,
+    /// a parsed atom has a valence no neutral or isoelectronic element allows
+    Valence,
+    /// the parsed graph differs from the one rdkit perceives
+    Mismatch,
+    /// the atom maps are incomplete, with [ConformanceOptions::strict_maps]
+    AtomMap,
+    /// a check panicked after the molecule was parsed
+    Panic,
+    /// the checks took longer than [ConformanceOptions::timeout]
+    Timeout,
+}
+
+impl FailureKind {
+    /// a short, stable code for this kind, for grepping logs. codes are
+    /// never reused, so E004, from a removed kind, is skipped
+    pub fn code(&self) -> &'static str {
+        match self {
+            FailureKind::Rdkit => "E001",
+            FailureKind::Scan => "E002",
+            FailureKind::Parse => "E003",
+            FailureKind::Valence => "E005",
+            FailureKind::Mismatch => "E006",
+            FailureKind::AtomMap => "E007",
+            FailureKind::Panic => "E008",
+            FailureKind::Timeout => "E009",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailureKind::Rdkit => "rdkit failure",
+            FailureKind::Scan => "scan error",
+            FailureKind::Parse => "parse error",
+            FailureKind::Valence => "valence failure",
+            FailureKind::Mismatch => "rdkit mismatch",
+            FailureKind::AtomMap => "atom map",
+            FailureKind::Panic => "panic",
+            FailureKind::Timeout => "timeout",
+        }
+    }
+
+    /// the kind of a panic caught in `stage` of [Smarts::parse_caught]
+    fn from_stage(stage: Stage) -> Self {
+        match stage {
+            Stage::Scan => FailureKind::Scan,
+            _ => FailureKind::Parse,
+        }
+    }
+}
+
+/// A single reason a record failed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.kind.code(),
+            self.kind.name(),
+            self.message
+        )
+    }
+}
+
+/// The outcome for a single record. the record passed if `failures` is empty
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordResult {
+    pub provenance: Provenance,
+    pub smiles: String,
+    pub failures: Vec<Failure>,
+}
+
+impl RecordResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The results for every record in a dataset, ordered by dataset key and then
+/// by position within each entry
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub records: Vec<RecordResult>,
+}
+
+impl Summary {
+    pub fn n_passed(&self) -> usize {
+        self.records.iter().filter(|r| r.passed()).count()
+    }
+
+    pub fn n_failed(&self) -> usize {
+        self.records.len() - self.n_passed()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &RecordResult> {
+        self.records.iter().filter(|r| !r.passed())
+    }
+
+    /// count the failing records of each [FailureKind], keeping the SMILES of
+    /// the first `n_examples` of them. a record with several failures of one
+    /// kind is only counted once for it
+    pub fn by_kind(&self, n_examples: usize) -> KindSummary {
+        let mut kinds: BTreeMap<FailureKind, KindCount> = BTreeMap::new();
+        for rec in self.failed() {
+            let mut seen: Vec<FailureKind> = Vec::new();
+            for failure in &rec.failures {
+                if seen.contains(&failure.kind) {
+                    continue;
+                }
+                seen.push(failure.kind);
+                let entry = kinds.entry(failure.kind).or_default();
+                entry.count += 1;
+                if entry.examples.len() < n_examples {
+                    entry.examples.push(rec.smiles.clone());
+                }
+            }
+        }
+        KindSummary {
+            n_records: self.records.len(),
+            kinds,
+        }
+    }
+}
+
+/// The number of failing records of one [FailureKind], with a few examples
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KindCount {
+    pub count: usize,
+    pub examples: Vec<String>,
+}
+
+/// The failures in a [Summary] grouped by kind, as returned by
+/// [Summary::by_kind]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KindSummary {
+    pub n_records: usize,
+    pub kinds: BTreeMap<FailureKind, KindCount>,
+}
+
+/// Display one row per kind, most common first, followed by its examples
+impl Display for KindSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort_by_key(|(_, k)| std::cmp::Reverse(k.count));
+        writeln!(f, "{} records", self.n_records)?;
+        for (kind, k) in kinds {
+            writeln!(f, "{} {:<16}{:>8}", kind.code(), kind.name(), k.count)?;
+            for ex in &k.examples {
+                writeln!(f, "    {ex}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Display the pass and fail counts followed by each failing record and its
+/// reasons
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} passed, {} failed", self.n_passed(), self.n_failed())?;
+        for rec in self.failed() {
+            let p = &rec.provenance;
+            write!(f, "{}", p.dataset_key)?;
+            if let Some(id) = &p.record_id {
+                write!(f, " ({id})")?;
+            }
+            writeln!(f, ": {}", rec.smiles)?;
+            for reason in &rec.failures {
+                writeln!(f, "    {reason}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// parse every record in `dataset` and compare it to rdkit's interpretation
+/// of the same SMILES. records that fail to convert or parse are reported as
+/// failures rather than aborting the run
+pub fn run(dataset: &Dataset, options: &ConformanceOptions) -> Summary {
+    let mut records = Vec::new();
+    for (key, recs) in &dataset.entries {
+        for rec in recs {
+            let provenance = Provenance {
+                file: rec.file.clone(),
+                dataset_key: key.clone(),
+                record_id: rec.record_id.clone(),
+            };
+            records.push(RecordResult {
+                provenance,
+                smiles: rec.cmiles.clone(),
+                failures: check_isolated(&rec.cmiles, options),
+            });
+        }
+    }
+    Summary { records }
+}
+
+/// the result of `f`, or a failure if it panics or takes longer than
+/// `timeout`. with a timeout, `f` runs on its own thread, which is left
+/// running if it doesn't finish in time
+fn isolated<T: Send + 'static>(
+    timeout: Option<Duration>,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Failure> {
+    let caught = |e: Box<dyn std::any::Any + Send>| {
+        Failure::new(FailureKind::Panic, panic_message(&*e))
+    };
+    let Some(timeout) = timeout else {
+        return catch_unwind(AssertUnwindSafe(f)).map_err(caught);
+    };
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        // the receiver is gone if the check timed out
+        let _ = tx.send(catch_unwind(AssertUnwindSafe(f)));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(r) => r.map_err(caught),
+        Err(_) => Err(Failure::new(
+            FailureKind::Timeout,
+            format!("no result after {timeout:?}"),
+        )),
+    }
+}
+
+/// [check] `smiles` in isolation, according to [ConformanceOptions::timeout]
+fn check_isolated(smiles: &str, options: &ConformanceOptions) -> Vec<Failure> {
+    let (smiles, opts) = (smiles.to_owned(), options.clone());
+    isolated(options.timeout, move || check(&smiles, &opts))
+        .unwrap_or_else(|f| vec![f])
+}
+
+/// run every check on a single SMILES string, stopping at the first stage
+/// that fails
+fn check(smiles: &str, options: &ConformanceOptions) -> Vec<Failure> {
+    let rdkit = to_smarts(smiles.to_owned())
+        .and_then(|smarts| Ok((smarts, smiles_to_graph(smiles)?)));
+    let (smarts, reference) = match rdkit {
+        Ok(pair) => pair,
+        Err(e) => return vec![Failure::new(FailureKind::Rdkit, e.to_string())],
+    };
+    let parsed = match Smarts::parse_caught(smarts, InputKind::Smiles) {
+        Ok(p) => p,
+        Err((stage, msg)) => {
+            return vec![Failure::new(FailureKind::from_stage(stage), msg)]
+        }
+    };
+    let mut ret: Vec<_> = options
+        .strict_maps
+        .and_then(|h| parsed.check_maps(h).err())
+        .map(|e| Failure::new(FailureKind::AtomMap, e.to_string()))
+        .into_iter()
+        .collect();
+    ret.extend(
+        valence_errors(&parsed)
+            .into_iter()
+            .map(|msg| Failure::new(FailureKind::Valence, msg)),
+    );
+    ret.extend(
+        compare(&parsed, &reference, options)
+            .into_iter()
+            .map(|msg| Failure::new(FailureKind::Mismatch, msg)),
+    );
+    ret
+}
+
+/// the valences allowed for neutral atoms of the elements chomper checks.
+/// noble gases are included with a valence of 0 so that ions isoelectronic
+/// with them, like Na+ and Cl-, are checked too
+fn allowed_valences(atomic_number: usize) -> Option<&'static [usize]> {
+    Some(match atomic_number {
+        0 | 2 | 10 | 18 | 36 | 54 => &[0],
+        1 | 9 | 17 | 35 => &[1],
+        8 => &[2],
+        5 | 7 => &[3],
+        6 | 14 => &[4],
+        15 => &[3, 5],
+        16 => &[2, 4, 6],
+        53 => &[1, 3, 5],
+        _ => return None,
+    })
+}
+
+/// describe each atom in `smarts` whose valence isn't allowed for its
+/// element. a charged atom is checked against the element it is
+/// isoelectronic with, so N+ must look like C and O- like F. each aromatic
+/// bond counts as a single bond, plus one more for the whole atom, which
+/// covers both the pyridine and pyrrole styles of nitrogen. atoms with an
+/// unknown H count or bond order, or elements without a table entry, are
+/// skipped
+pub fn valence_errors(smarts: &Smarts) -> Vec<String> {
+    let mut ret = Vec::new();
+    'atoms: for (i, atom) in smarts.atoms.iter().enumerate() {
+        let (Some(n_hydrogens), Some(z)) =
+            (atom.n_hydrogens, atom.atomic_number)
+        else {
+            continue;
+        };
+        let z = z as isize - atom.charge;
+        let Some(allowed) = usize::try_from(z).ok().and_then(allowed_valences)
+        else {
+            continue;
+        };
+        let (mut valence, mut aromatic) = (n_hydrogens, false);
+        for bond in &smarts.bonds {
+            if bond.atom1 != i && bond.atom2 != i {
+                continue;
+            }
+            if bond.order == BondOrder::Aromatic {
+                valence += 1;
+                aromatic = true;
+                continue;
+            }
+            match bond.order.as_f64() {
+                Some(o) => valence += o as usize,
+                None => continue 'atoms,
+            }
+        }
+        let max = valence + usize::from(aromatic);
+        if !allowed.iter().any(|v| (valence..=max).contains(v)) {
+            ret.push(format!(
+                "atom {i} {atom} has valence {valence}, expected one of \
+                 {allowed:?}"
+            ));
+        }
+    }
+    ret
+}
+
+/// compare the invariants of `parsed` and `reference`, returning a reason for
+/// each kind of mismatch
+pub fn compare(
+    parsed: &Smarts,
+    reference: &Smarts,
+    options: &ConformanceOptions,
+) -> Vec<String> {
+    let mut ret = Vec::new();
+    let (n, m) = (parsed.atoms.len(), reference.atoms.len());
+    if n != m {
+        ret.push(format!("{n} atoms, but rdkit has {m}"));
+    }
+    let (n, m) = (parsed.bonds.len(), reference.bonds.len());
+    if n != m {
+        ret.push(format!("{n} bonds, but rdkit has {m}"));
+    }
+
+    let atom_key = |a: &Atom| {
+        let h = if options.check_hydrogens {
+            a.n_hydrogens
+        } else {
+            None
+        };
+        let q = if options.check_charges { a.charge } else { 0 };
+        Atom::new(a.atomic_number, h, q, Chiral::None, None)
+    };
+    let atoms = |s: &Smarts| s.atoms.iter().map(atom_key).collect::<Vec<_>>();
+    if let Some(reason) =
+        difference("atoms", atoms(parsed), atoms(reference), |a| a.to_string())
+    {
+        ret.push(reason);
+    }
+
+    let bonds = |s: &Smarts| {
+        s.bonds
+            .iter()
+            .map(|b| {
+                // 0 is the wildcard in the element table
+                let x = s.atoms[b.atom1].atomic_number.unwrap_or(0);
+                let y = s.atoms[b.atom2].atomic_number.unwrap_or(0);
+                let order = if options.check_bond_orders {
+                    b.order.as_f64().map(|o| (2.0 * o) as usize)
+                } else {
+                    None
+                };
+                (x.min(y), x.max(y), order)
+            })
+            .collect::<Vec<_>>()
+    };
+    if let Some(reason) = difference(
+        "bonds",
+        bonds(parsed),
+        bonds(reference),
+        |&(x, y, order)| {
+            let order =
+                order.map_or("?".to_owned(), |o| (o as f64 / 2.0).to_string());
+            format!(
+                "{}-{} ({order})",
+                elements::symbol(x).unwrap_or("?"),
+                elements::symbol(y).unwrap_or("?")
+            )
+        },
+    ) {
+        ret.push(reason);
+    }
+    ret
+}
+
+/// describe the multiset difference between `ours` and `theirs`, if there is
+/// one
+fn difference<T: Ord>(
+    label: &str,
+    mut ours: Vec<T>,
+    mut theirs: Vec<T>,
+    show: impl Fn(&T) -> String,
+) -> Option<String> {
+    ours.sort();
+    theirs.sort();
+    let (mut i, mut j) = (0, 0);
+    let (mut only_ours, mut only_theirs) = (Vec::new(), Vec::new());
+    while i < ours.len() || j < theirs.len() {
+        match (ours.get(i), theirs.get(j)) {
+            (Some(a), Some(b)) if a == b => {
+                i += 1;
+                j += 1;
+            }
+            (Some(a), Some(b)) if a < b => {
+                only_ours.push(show(a));
+                i += 1;
+            }
+            (Some(a), None) => {
+                only_ours.push(show(a));
+                i += 1;
+            }
+            (_, Some(b)) => {
+                only_theirs.push(show(b));
+                j += 1;
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    if only_ours.is_empty() && only_theirs.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{label} only in chomper: [{}]; only in rdkit: [{}]",
+        only_ours.join(", "),
+        only_theirs.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::Bond;
+
+    use super::*;
+
+    fn parse(s: &str) -> Smarts {
+        Smarts::parse(s.to_owned())
+    }
+
+    #[test]
+    fn compare_graphs() {
+        let opts = ConformanceOptions::default();
+        let mol = parse("[#6H3:1]-[#6H:2]=[#8:3]");
+        // the same graph with the atoms listed in a different order
+        let reference = Smarts::from_parts(
+            vec![
+                Atom::new(8, 0, 0, Chiral::None, 3),
+                Atom::new(6, 1, 0, Chiral::None, 2),
+                Atom::new(6, 3, 0, Chiral::None, 1),
+            ],
+            vec![
+                Bond::new(0, 1, BondOrder::Double),
+                Bond::new(1, 2, BondOrder::Single),
+            ],
+        )
+        .unwrap();
+        assert!(compare(&mol, &reference, &opts).is_empty());
+
+        let got = compare(&parse("[#6H3:1]-[#6H:2]-[#8:3]"), &reference, &opts);
+        assert_eq!(
+            got,
+            ["bonds only in chomper: [C-O (1)]; only in rdkit: [C-O (2)]"]
+        );
+        let opts = ConformanceOptions {
+            check_bond_orders: false,
+            ..Default::default()
+        };
+        assert!(
+            compare(&parse("[#6H3:1]-[#6H:2]-[#8:3]"), &reference, &opts)
+                .is_empty()
+        );
+
+        let got = compare(&parse("[#6H4:1]"), &reference, &opts);
+        assert_eq!(
+            got,
+            [
+                "1 atoms, but rdkit has 3",
+                "0 bonds, but rdkit has 2",
+                "atoms only in chomper: [[C H4 +0]]; only in rdkit: \
+                 [[C H1 +0], [C H3 +0], [O H0 +0]]",
+                "bonds only in chomper: []; only in rdkit: [C-C (?), C-O (?)]",
+            ]
+        );
+    }
+
+    #[test]
+    fn valence() {
+        for ok in [
+            "[#6H3]-[#6H2]-[#8H]",
+            "[#7H4+]",
+            "[#8H0-]-[#6H3]",
+            "[#11+]",
+            "[#8H0]=[#16H0](=[#8H0])(-[#6H3])-[#6H3]",
+            // pyridine and pyrrole
+            "[cH]1:[cH]:[cH]:[nH0]:[cH]:[cH]:1",
+            "[cH]1:[cH]:[nH]:[cH]:[cH]:1",
+        ] {
+            assert!(valence_errors(&parse(ok)).is_empty(), "{ok}");
+        }
+        let got = valence_errors(&parse("[#6H4]-[#8H]"));
+        assert_eq!(
+            got,
+            ["atom 0 [C H4 +0] has valence 5, expected one of [4]"]
+        );
+        assert_eq!(valence_errors(&parse("[#7H4]")).len(), 1);
+    }
+
+    #[test]
+    fn failure_kinds() {
+        let kind = |s: &str| {
+            let (stage, _) =
+                Smarts::parse_caught(s.to_owned(), InputKind::Smiles)
+                    .unwrap_err();
+            FailureKind::from_stage(stage)
+        };
+        assert_eq!(kind("[#6H4]?"), FailureKind::Scan);
+        assert_eq!(kind("[#6H3]1-[#6H3]"), FailureKind::Parse);
+        assert!(Smarts::parse_caught("[#6H4]".to_owned(), InputKind::Smiles)
+            .is_ok());
+
+        let record = |smiles: &str, kinds: &[FailureKind]| RecordResult {
+            provenance: Provenance::default(),
+            smiles: smiles.to_owned(),
+            failures: kinds.iter().map(|&k| Failure::new(k, "")).collect(),
+        };
+        use FailureKind::*;
+        let summary = Summary {
+            records: vec![
+                record("a", &[Scan]),
+                record("b", &[]),
+                record("c", &[Mismatch, Mismatch, Valence]),
+                record("d", &[Scan]),
+                record("e", &[Scan]),
+            ],
+        };
+        let got = summary.by_kind(2);
+        assert_eq!(got.n_records, 5);
+        assert_eq!(
+            got.kinds[&Scan],
+            KindCount {
+                count: 3,
+                examples: vec!["a".to_owned(), "d".to_owned()]
+            }
+        );
+        assert_eq!(got.kinds[&Mismatch].count, 1);
+        assert_eq!(got.kinds.len(), 3);
+        let table = got.to_string();
+        assert!(table.starts_with(
+            "5 records\nE002 scan error             3\n    a\n    d\n"
+        ));
+        assert_eq!(
+            Failure::new(Valence, "atom 0").to_string(),
+            "E005 valence failure: atom 0"
+        );
+    }
+
+    #[test]
+    fn isolation() {
+        assert_eq!(isolated(None, || 1), Ok(1));
+        let got = isolated(None, || panic!("boom")).unwrap_err();
+        assert_eq!(got, Failure::new(FailureKind::Panic, "boom"));
+
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(isolated(timeout, || 1), Ok(1));
+        let got = isolated(timeout, || panic!("boom")).unwrap_err();
+        assert_eq!(got.kind, FailureKind::Panic);
+        let got = isolated(timeout, || {
+            std::thread::sleep(Duration::from_secs(1));
+        })
+        .unwrap_err();
+        assert_eq!(got.kind, FailureKind::Timeout);
+    }
+
+    #[test]
+    fn run_dataset() {
+        let ds = Dataset::load("testfiles/opt.json").unwrap();
+        let summary = run(&ds, &ConformanceOptions::default());
+        assert!(!summary.records.is_empty());
+        assert_eq!(summary.n_failed(), 0, "{summary}");
+    }
+}
@@ -0,0 +1,219 @@
+//! 3D geometries attached to [crate::molecule::Molecule]s.
+//!
+//! Coordinates are stored internally in Ångström regardless of the unit they
+//! were supplied in, so conformers read from different sources (XYZ and SD
+//! files in Ångström, QCArchive in Bohr) can be compared directly
+
+use serde::{Deserialize, Serialize};
+
+/// 1 Bohr in Ångström, from CODATA 2018
+pub const BOHR_TO_ANGSTROM: f64 = 0.529_177_210_903;
+
+/// A unit of length for conformer coordinates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LengthUnit {
+    #[default]
+    Angstrom,
+    Bohr,
+}
+
+impl LengthUnit {
+    /// the factor converting a length in `self` to Ångström
+    pub fn to_angstrom(&self) -> f64 {
+        match self {
+            LengthUnit::Angstrom => 1.0,
+            LengthUnit::Bohr => BOHR_TO_ANGSTROM,
+        }
+    }
+}
+
+/// A single geometry for a molecule, with one position per atom in the same
+/// order as the atoms of the molecule, and an optional energy in Hartree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Conformer {
+    coordinates: Vec<[f64; 3]>,
+    energy: Option<f64>,
+}
+
+impl Conformer {
+    /// build a conformer from `coordinates` given in `unit`
+    pub fn new(coordinates: Vec<[f64; 3]>, unit: LengthUnit) -> Self {
+        let f = unit.to_angstrom();
+        Self {
+            coordinates: coordinates
+                .into_iter()
+                .map(|[x, y, z]| [x * f, y * f, z * f])
+                .collect(),
+            energy: None,
+        }
+    }
+
+    /// build a conformer from a flat list of `x, y, z` triples in `unit`, as
+    /// stored by QCArchive. returns `None` if the length of `flat` is not a
+    /// multiple of 3
+    pub fn from_flat(flat: &[f64], unit: LengthUnit) -> Option<Self> {
+        if !flat.len().is_multiple_of(3) {
+            return None;
+        }
+        let coords = flat.chunks_exact(3).map(|c| [c[0], c[1], c[2]]);
+        Some(Self::new(coords.collect(), unit))
+    }
+
+    pub fn with_energy(mut self, energy: f64) -> Self {
+        self.energy = Some(energy);
+        self
+    }
+
+    /// the number of atoms with positions in the conformer
+    pub fn len(&self) -> usize {
+        self.coordinates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coordinates.is_empty()
+    }
+
+    /// the coordinates in Ångström
+    pub fn coordinates(&self) -> &[[f64; 3]] {
+        &self.coordinates
+    }
+
+    /// the coordinates converted to `unit`
+    pub fn coordinates_in(&self, unit: LengthUnit) -> Vec<[f64; 3]> {
+        let f = unit.to_angstrom();
+        self.coordinates
+            .iter()
+            .map(|[x, y, z]| [x / f, y / f, z / f])
+            .collect()
+    }
+
+    /// the position of atom `i` in Ångström
+    pub fn position(&self, i: usize) -> [f64; 3] {
+        self.coordinates[i]
+    }
+
+    /// the energy in Hartree, if one was provided
+    pub fn energy(&self) -> Option<f64> {
+        self.energy
+    }
+
+    /// the distance between atoms `i` and `j` in Ångström
+    pub fn distance(&self, i: usize, j: usize) -> f64 {
+        norm(sub(self.position(j), self.position(i)))
+    }
+
+    /// the angle `i-j-k` in degrees, between 0 and 180
+    pub fn angle(&self, i: usize, j: usize, k: usize) -> f64 {
+        let a = sub(self.position(i), self.position(j));
+        let b = sub(self.position(k), self.position(j));
+        let cos = dot(a, b) / (norm(a) * norm(b));
+        cos.clamp(-1.0, 1.0).acos().to_degrees()
+    }
+
+    /// the dihedral angle `i-j-k-l` in degrees, between -180 and 180, with the
+    /// same sign convention as rdkit and QCArchive torsion drives
+    pub fn dihedral(&self, i: usize, j: usize, k: usize, l: usize) -> f64 {
+        let b1 = sub(self.position(j), self.position(i));
+        let b2 = sub(self.position(k), self.position(j));
+        let b3 = sub(self.position(l), self.position(k));
+        let n2 = cross(b2, b3);
+        let x = dot(cross(b1, b2), n2);
+        let y = norm(b2) * dot(b1, n2);
+        y.atan2(x).to_degrees()
+    }
+}
+
+/// The final geometry of a QCArchive optimization record, in the layout of
+/// its `final_molecule`: one symbol per atom, in the map order of the record's
+/// cmiles, and the coordinates flattened and in Bohr. The other fields of the
+/// QCSchema molecule are not kept
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub symbols: Vec<String>,
+    pub geometry: Vec<f64>,
+}
+
+impl Geometry {
+    /// the geometry as a [Conformer] in map order, with `energy` in Hartree
+    /// if there is one. returns `None` unless there are 3 coordinates for
+    /// each symbol
+    pub fn conformer(&self, energy: Option<f64>) -> Option<Conformer> {
+        if self.geometry.len() != 3 * self.symbols.len() {
+            return None;
+        }
+        let ret = Conformer::from_flat(&self.geometry, LengthUnit::Bohr)?;
+        Some(match energy {
+            Some(e) => ret.with_energy(e),
+            None => ret,
+        })
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        let flat = [0.0, 0.0, 0.0, 0.0, 0.0, 2.0];
+        let c = Conformer::from_flat(&flat, LengthUnit::Bohr)
+            .unwrap()
+            .with_energy(-1.17);
+        assert_eq!(c.len(), 2);
+        assert_eq!(c.position(1), [0.0, 0.0, 2.0 * BOHR_TO_ANGSTROM]);
+        assert_eq!(c.coordinates_in(LengthUnit::Bohr)[1], [0.0, 0.0, 2.0]);
+        assert_eq!(c.energy(), Some(-1.17));
+        assert!(Conformer::from_flat(&[0.0; 4], LengthUnit::Angstrom).is_none());
+
+        let g = Geometry {
+            symbols: vec!["H".to_owned(); 2],
+            geometry: flat.to_vec(),
+        };
+        assert_eq!(g.conformer(Some(-1.17)), Some(c));
+        let g = Geometry {
+            symbols: vec!["H".to_owned()],
+            ..g
+        };
+        assert_eq!(g.conformer(None), None);
+    }
+
+    #[test]
+    fn geometry() {
+        let c = Conformer::new(
+            vec![
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 1.0, 1.0],
+                [-1.0, 1.0, 0.0],
+            ],
+            LengthUnit::Angstrom,
+        );
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-10;
+        assert!(close(c.distance(0, 2), 2f64.sqrt()));
+        assert!(close(c.angle(0, 1, 2), 90.0));
+        assert!(close(c.dihedral(0, 1, 2, 3), -90.0));
+        assert!(close(c.dihedral(3, 2, 1, 0), -90.0));
+        assert!(close(c.dihedral(0, 1, 2, 4).abs(), 180.0));
+    }
+}
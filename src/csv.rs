@@ -0,0 +1,162 @@
+//! Minimal reader for CSV and TSV files, following RFC 4180 for quoting:
+//! fields containing the delimiter, quotes, or line breaks are wrapped in
+//! double quotes, with quotes inside them doubled
+
+use std::path::Path;
+
+use crate::{compress::read_to_string, error::ChomperError};
+
+/// The contents of a delimited file, with the first row as the header
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// the position of the column named `name` in the header
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
+}
+
+/// the delimiter for the file at `path`: a tab for .tsv and .tab files, and a
+/// comma otherwise
+pub fn delimiter(path: &Path) -> char {
+    let path = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz" | "zst") => Path::new(path.file_stem().unwrap_or_default()),
+        _ => path,
+    };
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tsv" | "tab") => '\t',
+        _ => ',',
+    }
+}
+
+/// read the file at `path`, which may be compressed, with the delimiter
+/// chosen by [delimiter] from its name without any .gz or .zst suffix
+pub fn read_csv(path: impl AsRef<Path>) -> Result<Table, ChomperError> {
+    let path = path.as_ref();
+    parse_csv(&read_to_string(path)?, delimiter(path))
+}
+
+/// split `s` into a header and rows of fields separated by `delimiter`.
+/// blank lines are skipped, and every row must have as many fields as the
+/// header. a leading UTF-8 byte order mark, as written by Excel, is dropped
+/// so it doesn't end up in the first column name
+pub fn parse_csv(s: &str, delimiter: char) -> Result<Table, ChomperError> {
+    let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+    let mut rows: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    // whether the current row has any content, to skip blank lines
+    let mut started = false;
+    let mut line = 1;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => {
+                    line += usize::from(c == '\n');
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                started = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if started {
+                    row.push(std::mem::take(&mut field));
+                    rows.push((line, std::mem::take(&mut row)));
+                }
+                started = false;
+                line += 1;
+            }
+            _ if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                started = true;
+            }
+            _ => {
+                field.push(c);
+                started = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(ChomperError::Format(format!(
+            "unclosed quote on line {line}"
+        )));
+    }
+    if started {
+        row.push(field);
+        rows.push((line, row));
+    }
+
+    let mut rows = rows.into_iter();
+    let Some((_, header)) = rows.next() else {
+        return Ok(Table::default());
+    };
+    let rows = rows
+        .map(|(line, row)| {
+            if row.len() == header.len() {
+                Ok(row)
+            } else {
+                Err(ChomperError::Format(format!(
+                    "line {line} has {} fields, but the header has {}",
+                    row.len(),
+                    header.len()
+                )))
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Table { header, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        let s = "id,smiles,note\r\n\
+                 1,CCO,plain\r\n\
+                 \n\
+                 2,\"C(=O)O\",\"has \"\"quotes\"\", commas,\nand lines\"\n\
+                 3,C,";
+        let got = parse_csv(s, ',').unwrap();
+        assert_eq!(got.header, ["id", "smiles", "note"]);
+        assert_eq!(got.column("smiles"), Some(1));
+        assert_eq!(
+            got.rows,
+            [
+                vec!["1", "CCO", "plain"],
+                vec!["2", "C(=O)O", "has \"quotes\", commas,\nand lines"],
+                vec!["3", "C", ""],
+            ]
+        );
+
+        let got = parse_csv("a\tb\nx\ty\n", '\t').unwrap();
+        assert_eq!(got.rows, [["x", "y"]]);
+
+        let err = parse_csv("a,b\n1\n", ',').unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2 has 1 fields, but the header has 2"
+        );
+        assert!(parse_csv("a\n\"1\n", ',').is_err());
+
+        let got = parse_csv("\u{feff}smiles,name\nC,methane\n", ',').unwrap();
+        assert_eq!(got.column("smiles"), Some(0));
+    }
+}
@@ -0,0 +1,236 @@
+//! Structural differences between two parsed molecules
+
+use std::fmt::Display;
+
+use crate::smarts::{Atom, Bond, Chiral, Smarts};
+
+/// A single field that differs between a pair of atoms, with both values
+/// rendered as strings
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+/// One difference between the left and right molecules. Atom and bond
+/// indices refer to positions in the molecule the item came from, so
+/// [Change::ChangedAtom] carries both
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    AddedAtom(usize, Atom),
+    RemovedAtom(usize, Atom),
+    ChangedAtom {
+        left: usize,
+        right: usize,
+        fields: Vec<FieldChange>,
+    },
+    AddedBond(Bond),
+    RemovedBond(Bond),
+    ChangedBond {
+        left: Bond,
+        right: Bond,
+    },
+}
+
+/// The differences between two molecules, as returned by [diff]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// compare `left` to `right`. if every atom in both molecules has an atom
+/// map, atoms are paired by map number, otherwise they are paired by position.
+/// bonds are paired when they connect paired atoms
+pub fn diff(left: &Smarts, right: &Smarts) -> Diff {
+    let mapped = left
+        .atoms
+        .iter()
+        .chain(&right.atoms)
+        .all(|a| a.mol_index.is_some());
+    // left position -> right position
+    let pairs: Vec<Option<usize>> = if mapped {
+        let right_maps = right.atom_maps();
+        left.atoms
+            .iter()
+            .map(|a| right_maps.position(a.mol_index?))
+            .collect()
+    } else {
+        (0..left.atoms.len())
+            .map(|i| (i < right.atoms.len()).then_some(i))
+            .collect()
+    };
+
+    let mut changes = Vec::new();
+    let mut paired = vec![false; right.atoms.len()];
+    for (i, a) in left.atoms.iter().enumerate() {
+        match pairs[i] {
+            Some(j) => {
+                paired[j] = true;
+                let fields = atom_changes(a, &right.atoms[j]);
+                if !fields.is_empty() {
+                    changes.push(Change::ChangedAtom {
+                        left: i,
+                        right: j,
+                        fields,
+                    });
+                }
+            }
+            None => changes.push(Change::RemovedAtom(i, a.clone())),
+        }
+    }
+    for (j, b) in right.atoms.iter().enumerate() {
+        if !paired[j] {
+            changes.push(Change::AddedAtom(j, b.clone()));
+        }
+    }
+
+    let mut paired = vec![false; right.bonds.len()];
+    for bond in &left.bonds {
+        let found =
+            pairs[bond.atom1].zip(pairs[bond.atom2]).and_then(|(a, b)| {
+                right.bonds.iter().position(|r| {
+                    (r.atom1, r.atom2) == (a, b) || (r.atom1, r.atom2) == (b, a)
+                })
+            });
+        match found {
+            Some(j) => {
+                paired[j] = true;
+                if right.bonds[j].order != bond.order {
+                    changes.push(Change::ChangedBond {
+                        left: bond.clone(),
+                        right: right.bonds[j].clone(),
+                    });
+                }
+            }
+            None => changes.push(Change::RemovedBond(bond.clone())),
+        }
+    }
+    for (j, bond) in right.bonds.iter().enumerate() {
+        if !paired[j] {
+            changes.push(Change::AddedBond(bond.clone()));
+        }
+    }
+    Diff { changes }
+}
+
+fn atom_changes(a: &Atom, b: &Atom) -> Vec<FieldChange> {
+    let chirality = |c: &Chiral| format!("{c:?}").to_lowercase();
+    let opt = |m: Option<usize>| m.map_or("none".to_owned(), |m| m.to_string());
+    [
+        ("element", a.symbol().to_owned(), b.symbol().to_owned()),
+        ("n_hydrogens", opt(a.n_hydrogens), opt(b.n_hydrogens)),
+        (
+            "charge",
+            format!("{:+}", a.charge),
+            format!("{:+}", b.charge),
+        ),
+        (
+            "chirality",
+            chirality(&a.chirality),
+            chirality(&b.chirality),
+        ),
+        ("aromatic", a.aromatic.to_string(), b.aromatic.to_string()),
+        ("mol_index", opt(a.mol_index), opt(b.mol_index)),
+    ]
+    .into_iter()
+    .filter(|(_, l, r)| l != r)
+    .map(|(field, left, right)| FieldChange { field, left, right })
+    .collect()
+}
+
+/// Display a change on one line, starting with `-` for an item only in the
+/// left molecule, `+` for an item only in the right molecule, and `~` for a
+/// changed item
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::AddedAtom(i, a) => write!(f, "+ atom {i} {a}"),
+            Change::RemovedAtom(i, a) => write!(f, "- atom {i} {a}"),
+            Change::ChangedAtom {
+                left,
+                right,
+                fields,
+            } => {
+                write!(f, "~ atom {left}")?;
+                if left != right {
+                    write!(f, " -> {right}")?;
+                }
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|c| format!("{} {} -> {}", c.field, c.left, c.right))
+                    .collect();
+                write!(f, ": {}", fields.join(", "))
+            }
+            Change::AddedBond(b) => write!(f, "+ bond {b}"),
+            Change::RemovedBond(b) => write!(f, "- bond {b}"),
+            Change::ChangedBond { left, right } => {
+                write!(f, "~ bond {left} -> {right}")
+            }
+        }
+    }
+}
+
+/// Display one change per line
+impl Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::BondOrder;
+
+    use super::*;
+
+    fn parse(s: &str) -> Smarts {
+        Smarts::parse(s.to_owned())
+    }
+
+    #[test]
+    fn mapped() {
+        let got = diff(
+            &parse("[#6H3:1]-[#6H2:2]-[#8H:3]"),
+            &parse("[#6H3:1]-[#6H:2]=[#8:3]"),
+        );
+        let want = "~ atom 1: n_hydrogens 2 -> 1
+~ atom 2: n_hydrogens 1 -> 0
+~ bond 1-2 (single) -> 1=2 (double)
+";
+        assert_eq!(got.to_string(), want);
+
+        // pairing follows the maps rather than the positions
+        let got = diff(&parse("[#6:1]-[#8:2]"), &parse("[#8:2]-[#6:1]"));
+        assert!(got.is_empty());
+
+        assert!(diff(&parse("[#6:1]"), &parse("[#6:1]")).is_empty());
+    }
+
+    #[test]
+    fn unmapped() {
+        let got = diff(&parse("[#6H3]-[#8H]"), &parse("[#6H3]-[#7H2]-[#6H3]"));
+        let want = "~ atom 1: element O -> N, n_hydrogens 1 -> 2
++ atom 2 [C H3 +0]
++ bond 1-2 (single)
+";
+        assert_eq!(got.to_string(), want);
+        let got = diff(&parse("[#6H3]-[#7H2]-[#6H3]"), &parse("[#6H3]-[#7H2]"));
+        assert_eq!(
+            got.changes,
+            [
+                Change::RemovedAtom(2, Atom::new(6, 3, 0, Chiral::None, None)),
+                Change::RemovedBond(Bond::new(1, 2, BondOrder::Single)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Element symbols indexed by atomic number
+
+/// Element symbols indexed by atomic number. Index 0 is the wildcard `*`,
+/// matching its meaning in SMARTS
+pub const SYMBOLS: [&str; 119] = [
+    "*", "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg",
+    "Al", "Si", "P", "S", "Cl", "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn",
+    "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb",
+    "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In",
+    "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd", "Pm",
+    "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta",
+    "W", "Re", "Os", "Ir", "Pt", "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At",
+    "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk",
+    "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt",
+    "Ds", "Rg", "Cn", "Nh", "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+/// return the element symbol for `atomic_number`, if there is one
+pub fn symbol(atomic_number: usize) -> Option<&'static str> {
+    SYMBOLS.get(atomic_number).copied()
+}
+
+/// return the atomic number for the element symbol `sym`. the lookup is case
+/// sensitive, so `Co` is cobalt while `CO` is not an element
+pub fn atomic_number(sym: &str) -> Option<usize> {
+    SYMBOLS.iter().position(|s| *s == sym)
+}
+
+/// Single-bond covalent radii in Ångström indexed by atomic number, from
+/// Cordero et al. (2008), using the sp3 value for carbon and the low-spin
+/// values for Mn, Fe, and Co. Index 0 and the elements after radon have no
+/// radius
+const COVALENT_RADII: [f64; 87] = [
+    0.0, 0.31, 0.28, 1.28, 0.96, 0.84, 0.76, 0.71, 0.66, 0.57, 0.58, 1.66,
+    1.41, 1.21, 1.11, 1.07, 1.05, 1.02, 1.06, 2.03, 1.76, 1.70, 1.60, 1.53,
+    1.39, 1.39, 1.32, 1.26, 1.24, 1.32, 1.22, 1.22, 1.20, 1.19, 1.20, 1.20,
+    1.16, 2.20, 1.95, 1.90, 1.75, 1.64, 1.54, 1.47, 1.46, 1.42, 1.39, 1.45,
+    1.44, 1.42, 1.39, 1.39, 1.38, 1.39, 1.40, 2.44, 2.15, 2.07, 2.04, 2.03,
+    2.01, 1.99, 1.98, 1.98, 1.96, 1.94, 1.92, 1.92, 1.89, 1.90, 1.87, 1.87,
+    1.75, 1.70, 1.62, 1.51, 1.44, 1.41, 1.36, 1.36, 1.32, 1.45, 1.46, 1.48,
+    1.40, 1.50, 1.50,
+];
+
+/// return the covalent radius of `atomic_number` in Ångström, if it is known
+pub fn covalent_radius(atomic_number: usize) -> Option<f64> {
+    COVALENT_RADII
+        .get(atomic_number)
+        .copied()
+        .filter(|&r| r > 0.0)
+}
+
+/// Standard atomic weights in Daltons indexed by atomic number, using the
+/// conventional values for elements with an interval and the mass number of
+/// the longest-lived isotope for Tc, Pm, Po, At, and Rn
+const MASSES: [f64; 87] = [
+    0.0, 1.008, 4.0026, 6.94, 9.0122, 10.81, 12.011, 14.007, 15.999, 18.998,
+    20.180, 22.990, 24.305, 26.982, 28.085, 30.974, 32.06, 35.45, 39.95,
+    39.098, 40.078, 44.956, 47.867, 50.942, 51.996, 54.938, 55.845, 58.933,
+    58.693, 63.546, 65.38, 69.723, 72.630, 74.922, 78.971, 79.904, 83.798,
+    85.468, 87.62, 88.906, 91.224, 92.906, 95.95, 98.0, 101.07, 102.91, 106.42,
+    107.87, 112.41, 114.82, 118.71, 121.76, 127.60, 126.90, 131.29, 132.91,
+    137.33, 138.91, 140.12, 140.91, 144.24, 145.0, 150.36, 151.96, 157.25,
+    158.93, 162.50, 164.93, 167.26, 168.93, 173.05, 174.97, 178.49, 180.95,
+    183.84, 186.21, 190.23, 192.22, 195.08, 196.97, 200.59, 204.38, 207.2,
+    208.98, 209.0, 210.0, 222.0,
+];
+
+/// return the standard atomic weight of `atomic_number`, if it is known
+pub fn mass(atomic_number: usize) -> Option<f64> {
+    MASSES.get(atomic_number).copied().filter(|&m| m > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(symbol(6), Some("C"));
+        assert_eq!(symbol(17), Some("Cl"));
+        assert_eq!(symbol(118), Some("Og"));
+        assert_eq!(symbol(119), None);
+        assert_eq!(atomic_number("Br"), Some(35));
+        assert_eq!(atomic_number("BR"), None);
+        for (i, s) in SYMBOLS.iter().enumerate() {
+            assert_eq!(atomic_number(s), Some(i));
+        }
+        assert_eq!(covalent_radius(6), Some(0.76));
+        assert_eq!(covalent_radius(86), Some(1.50));
+        assert_eq!(covalent_radius(0), None);
+        assert_eq!(covalent_radius(87), None);
+        assert_eq!(mass(6), Some(12.011));
+        assert_eq!(mass(86), Some(222.0));
+        assert_eq!(mass(0), None);
+    }
+}
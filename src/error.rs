@@ -0,0 +1,196 @@
+//! The crate-wide error type, for the steps that turn a file of records into
+//! parsed molecules. Each of these steps returns a [ChomperError] instead of
+//! panicking, so a caller working through a large dataset can report a bad
+//! record and move on to the next one
+
+use std::{fmt::Display, io};
+
+use pyo3::PyErr;
+
+use crate::{
+    molecule::MoleculeError,
+    msgpack::MsgpackError,
+    smarts::{ParseError, ScanError},
+    Provenance,
+};
+
+/// The reasons reading, writing, converting, or parsing a dataset can fail
+#[derive(Debug)]
+pub enum ChomperError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Msgpack(MsgpackError),
+    /// rdkit could not read or convert its input, which is `smiles`, the
+    /// SMARTS it was given, or a description of a mol block
+    Rdkit {
+        smiles: String,
+        message: String,
+    },
+    Scan(ScanError),
+    Parse(ParseError),
+    /// the evaluator rejected the parsed expressions, like an unclosed ring
+    Eval(String),
+    Molecule(MoleculeError),
+    /// an exception raised by Python outside of rdkit, like pyarrow failing
+    /// to read or write a Parquet file
+    Python(PyErr),
+    /// a request to a server like QCArchive failed, with `message` from the
+    /// exception raised by `urllib`
+    Request {
+        url: String,
+        message: String,
+    },
+    /// a file or string wasn't laid out the way its reader expects, like a
+    /// CSV row with too few fields or a SMIRKS without `>>`
+    Format(String),
+    /// the pattern `name` in a [crate::catalog::PatternCatalog] could not be
+    /// parsed from `smarts`
+    Pattern {
+        name: String,
+        smarts: String,
+        error: Box<ChomperError>,
+    },
+    /// `error` happened while handling the record described by `provenance`
+    Record {
+        provenance: Provenance,
+        error: Box<ChomperError>,
+    },
+}
+
+impl ChomperError {
+    /// attach `provenance` to `self`, so the message says which record failed
+    pub fn in_record(self, provenance: Provenance) -> Self {
+        Self::Record {
+            provenance,
+            error: Box::new(self),
+        }
+    }
+}
+
+impl Display for ChomperError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChomperError::Io(e) => write!(f, "{e}"),
+            ChomperError::Json(e) => write!(f, "{e}"),
+            ChomperError::Msgpack(e) => write!(f, "{e}"),
+            ChomperError::Rdkit { smiles, message } => {
+                write!(f, "rdkit failed on {smiles}: {message}")
+            }
+            ChomperError::Scan(e) => write!(f, "{e}"),
+            ChomperError::Parse(e) => write!(f, "{e}"),
+            ChomperError::Eval(e) => write!(f, "{e}"),
+            ChomperError::Molecule(e) => write!(f, "{e}"),
+            ChomperError::Python(e) => write!(f, "{e}"),
+            ChomperError::Request { url, message } => {
+                write!(f, "request to {url} failed: {message}")
+            }
+            ChomperError::Format(e) => write!(f, "{e}"),
+            ChomperError::Pattern {
+                name,
+                smarts,
+                error,
+            } => write!(f, "invalid pattern {name} ({smarts}): {error}"),
+            ChomperError::Record { provenance, error } => {
+                let id = provenance.record_id.as_deref().unwrap_or("?");
+                write!(f, "record {id} of {}: {error}", provenance.dataset_key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChomperError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChomperError::Io(e) => Some(e),
+            ChomperError::Json(e) => Some(e),
+            ChomperError::Msgpack(e) => Some(e),
+            ChomperError::Scan(e) => Some(e),
+            ChomperError::Parse(e) => Some(e),
+            ChomperError::Molecule(e) => Some(e),
+            ChomperError::Python(e) => Some(e),
+            ChomperError::Pattern { error, .. } => Some(error),
+            ChomperError::Record { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChomperError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ChomperError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<MsgpackError> for ChomperError {
+    fn from(e: MsgpackError) -> Self {
+        Self::Msgpack(e)
+    }
+}
+
+impl From<ScanError> for ChomperError {
+    fn from(e: ScanError) -> Self {
+        Self::Scan(e)
+    }
+}
+
+impl From<ParseError> for ChomperError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<MoleculeError> for ChomperError {
+    fn from(e: MoleculeError) -> Self {
+        Self::Molecule(e)
+    }
+}
+
+impl From<PyErr> for ChomperError {
+    fn from(e: PyErr) -> Self {
+        Self::Python(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::{smarts::Smarts, Dataset};
+
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let got = Dataset::load("testfiles/missing.json").err().unwrap();
+        assert!(matches!(got, ChomperError::Io(_)));
+        assert!(got.source().is_some());
+
+        let got = Smarts::try_parse("[#6]1-[#6]".to_owned()).unwrap_err();
+        assert!(matches!(got, ChomperError::Eval(_)));
+        let got = got.in_record(Provenance {
+            file: None,
+            dataset_key: "ethane".to_owned(),
+            record_id: Some("7".to_owned()),
+        });
+        assert_eq!(
+            got.to_string(),
+            "record 7 of ethane: ring label 1 opened at atom 0 is never closed"
+        );
+        assert!(got.source().is_some());
+
+        let got = ChomperError::Request {
+            url: "http://localhost:7777/api".to_owned(),
+            message: "connection refused".to_owned(),
+        };
+        assert_eq!(
+            got.to_string(),
+            "request to http://localhost:7777/api failed: connection refused"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Fixed-length numeric descriptions of atom environments, for feeding
+//! chomper output to machine learning models.
+//!
+//! Each atom becomes one row of features:
+//!
+//! - a one-hot encoding of its element, over [ELEMENTS] plus a final column
+//!   for everything else
+//! - its degree, total H count, formal charge, and whether it is aromatic or
+//!   in a ring
+//! - for each distance `d` from 1 to the featurizer's radius, the number of
+//!   atoms of each element class exactly `d` bonds away
+//!
+//! A batch of rows can be written as CSV with a header of feature names, or
+//! as a NumPy .npy array of `f64`
+
+use std::io::Write;
+
+use crate::{elements, error::ChomperError, molecule::Molecule};
+
+/// The elements with their own one-hot columns
+pub const ELEMENTS: [usize; 10] = [1, 6, 7, 8, 9, 15, 16, 17, 35, 53];
+
+/// The number of element classes, including the catchall
+const N_CLASSES: usize = ELEMENTS.len() + 1;
+
+fn class(atomic_number: usize) -> usize {
+    ELEMENTS
+        .iter()
+        .position(|&e| e == atomic_number)
+        .unwrap_or(ELEMENTS.len())
+}
+
+fn class_name(c: usize) -> &'static str {
+    ELEMENTS
+        .get(c)
+        .and_then(|&e| elements::symbol(e))
+        .unwrap_or("other")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Featurizer {
+    /// how many bonds away to count neighbors
+    pub radius: usize,
+}
+
+impl Default for Featurizer {
+    fn default() -> Self {
+        Self { radius: 2 }
+    }
+}
+
+impl Featurizer {
+    pub fn new(radius: usize) -> Self {
+        Self { radius }
+    }
+
+    /// the number of features per atom
+    pub fn n_features(&self) -> usize {
+        N_CLASSES + 5 + N_CLASSES * self.radius
+    }
+
+    /// the name of each feature, in the order they appear in a row
+    pub fn names(&self) -> Vec<String> {
+        let mut ret: Vec<String> = (0..N_CLASSES)
+            .map(|c| format!("is_{}", class_name(c)))
+            .collect();
+        ret.extend(
+            ["degree", "n_hydrogens", "charge", "aromatic", "ring"]
+                .map(String::from),
+        );
+        for d in 1..=self.radius {
+            ret.extend(
+                (0..N_CLASSES).map(|c| format!("n_{}_at_{d}", class_name(c))),
+            );
+        }
+        ret
+    }
+
+    /// the features for every atom in `mol`, in atom order
+    pub fn molecule(&self, mol: &Molecule) -> Vec<Vec<f64>> {
+        let ring = mol.ring_atoms();
+        (0..mol.atoms.len())
+            .map(|i| {
+                let atom = &mol.atoms[i];
+                let mut row = vec![0.0; self.n_features()];
+                row[class(atom.atomic_number)] = 1.0;
+                row[N_CLASSES] = mol.degree(i) as f64;
+                row[N_CLASSES + 1] = atom.n_hydrogens as f64;
+                row[N_CLASSES + 2] = atom.charge as f64;
+                row[N_CLASSES + 3] = f64::from(u8::from(atom.aromatic));
+                row[N_CLASSES + 4] = f64::from(u8::from(ring[i]));
+                for (j, d) in shells(mol, i, self.radius) {
+                    let col = N_CLASSES + 5 + (d - 1) * N_CLASSES;
+                    row[col + class(mol.atoms[j].atomic_number)] += 1.0;
+                }
+                row
+            })
+            .collect()
+    }
+
+    /// the features for every atom in each of `mols`, concatenated
+    pub fn batch<'a>(
+        &self,
+        mols: impl IntoIterator<Item = &'a Molecule>,
+    ) -> Features {
+        Features {
+            names: self.names(),
+            rows: mols.into_iter().flat_map(|m| self.molecule(m)).collect(),
+        }
+    }
+}
+
+/// the atoms between 1 and `radius` bonds away from atom `i`, with their
+/// shortest distances
+fn shells(mol: &Molecule, i: usize, radius: usize) -> Vec<(usize, usize)> {
+    let mut dist = vec![None; mol.atoms.len()];
+    dist[i] = Some(0);
+    let mut frontier = vec![i];
+    let mut ret = Vec::new();
+    for d in 1..=radius {
+        let mut next = Vec::new();
+        for &a in &frontier {
+            for b in mol.neighbors(a) {
+                if dist[b].is_none() {
+                    dist[b] = Some(d);
+                    ret.push((b, d));
+                    next.push(b);
+                }
+            }
+        }
+        frontier = next;
+    }
+    ret
+}
+
+/// A table of features with one row per atom
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Features {
+    pub names: Vec<String>,
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl Features {
+    /// write `self` as CSV, with a header line of feature names
+    pub fn write_csv(&self, mut w: impl Write) -> Result<(), ChomperError> {
+        writeln!(w, "{}", self.names.join(","))?;
+        for row in &self.rows {
+            let row: Vec<_> = row.iter().map(f64::to_string).collect();
+            writeln!(w, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// write `self` as a version 1.0 .npy file containing a C-ordered
+    /// little-endian `f64` array of shape `(rows, features)`
+    pub fn write_npy(&self, mut w: impl Write) -> Result<(), ChomperError> {
+        let shape = (self.rows.len(), self.names.len());
+        let mut header = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+            shape.0, shape.1
+        );
+        // the magic string, version, and header length take 10 bytes, and
+        // the header is padded with spaces and a newline so that the data
+        // starts on a multiple of 64 bytes
+        let len = 10 + header.len() + 1;
+        header.extend(std::iter::repeat_n(' ', len.next_multiple_of(64) - len));
+        header.push('\n');
+        w.write_all(b"\x93NUMPY\x01\x00")?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for row in &self.rows {
+            for x in row {
+                w.write_all(&x.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    fn ethanol() -> Molecule {
+        mol("[#6H3]-[#6H2]-[#8H]")
+    }
+
+    #[test]
+    fn features() {
+        let f = Featurizer::new(2);
+        let names = f.names();
+        assert_eq!(names.len(), f.n_features());
+        let rows = f.molecule(&ethanol());
+        assert_eq!(rows.len(), 3);
+        let col = |name: &str| names.iter().position(|n| n == name).unwrap();
+        let methyl = &rows[0];
+        assert_eq!(methyl[col("is_C")], 1.0);
+        assert_eq!(methyl[col("is_O")], 0.0);
+        assert_eq!(methyl[col("degree")], 1.0);
+        assert_eq!(methyl[col("n_hydrogens")], 3.0);
+        assert_eq!(methyl[col("ring")], 0.0);
+        assert_eq!(methyl[col("n_C_at_1")], 1.0);
+        assert_eq!(methyl[col("n_O_at_2")], 1.0);
+        assert_eq!(methyl[col("n_O_at_1")], 0.0);
+        assert_eq!(rows[1][col("n_C_at_1")], 1.0);
+        assert_eq!(rows[1][col("n_O_at_1")], 1.0);
+    }
+
+    #[test]
+    fn export() {
+        let mol = ethanol();
+        let feats = Featurizer::new(1).batch([&mol, &mol]);
+        assert_eq!(feats.rows.len(), 6);
+
+        let mut csv = Vec::new();
+        feats.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("is_H,is_C,"));
+
+        let mut npy = Vec::new();
+        feats.write_npy(&mut npy).unwrap();
+        assert!(npy.starts_with(b"\x93NUMPY\x01\x00"));
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(header.contains("'shape': (6, 27)"));
+        assert_eq!(npy.len(), 10 + header_len + 6 * 27 * 8);
+    }
+}
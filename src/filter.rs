@@ -0,0 +1,193 @@
+//! Predicates for selecting molecules from a dataset
+
+use std::fmt::Display;
+
+use crate::{elements, molecule::Molecule, Provenance};
+
+/// The elements supported by the OpenFF force fields
+pub const OPENFF_ELEMENTS: [usize; 10] = [1, 6, 7, 8, 9, 15, 16, 17, 35, 53];
+
+/// A test on a whole [Molecule]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// the net charge is exactly this
+    Charge(isize),
+    /// the spin multiplicity is exactly this
+    Multiplicity(usize),
+    /// the net charge is zero
+    Neutral,
+    /// the multiplicity is one
+    ClosedShell,
+    /// every atom is one of these elements, given by atomic number
+    Elements(Vec<usize>),
+    /// every listed filter accepts the molecule
+    All(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn accepts(&self, mol: &Molecule) -> bool {
+        match self {
+            Filter::Charge(q) => mol.charge == *q,
+            Filter::Multiplicity(m) => mol.multiplicity == *m,
+            Filter::Neutral => mol.charge == 0,
+            Filter::ClosedShell => mol.multiplicity == 1,
+            Filter::Elements(allowed) => disallowed(mol, allowed).is_empty(),
+            Filter::All(fs) => fs.iter().all(|f| f.accepts(mol)),
+        }
+    }
+}
+
+/// return the molecules in `mols` accepted by `filter`
+pub fn apply(mols: Vec<Molecule>, filter: &Filter) -> Vec<Molecule> {
+    mols.into_iter().filter(|m| filter.accepts(m)).collect()
+}
+
+/// the distinct elements in `mol` missing from `allowed`, in increasing order
+fn disallowed(mol: &Molecule, allowed: &[usize]) -> Vec<usize> {
+    let mut ret: Vec<usize> = mol
+        .atoms
+        .iter()
+        .map(|a| a.atomic_number)
+        .filter(|n| !allowed.contains(n))
+        .collect();
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+/// A molecule containing elements outside an allow-list
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementViolation {
+    /// the position of the molecule in the checked list
+    pub index: usize,
+    pub provenance: Option<Provenance>,
+    /// the offending atomic numbers, in increasing order
+    pub elements: Vec<usize>,
+}
+
+/// The result of [check_elements]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ElementReport {
+    pub n_molecules: usize,
+    pub violations: Vec<ElementViolation>,
+}
+
+impl ElementReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Display the number of offending molecules followed by one line for each,
+/// identified by its provenance when it has one
+impl Display for ElementReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} of {} molecules contain disallowed elements",
+            self.violations.len(),
+            self.n_molecules
+        )?;
+        for v in &self.violations {
+            match &v.provenance {
+                Some(p) => {
+                    write!(f, "{}", p.dataset_key)?;
+                    if let Some(id) = &p.record_id {
+                        write!(f, " ({id})")?;
+                    }
+                }
+                None => write!(f, "molecule {}", v.index)?,
+            }
+            let syms: Vec<_> = v
+                .elements
+                .iter()
+                .map(|&n| elements::symbol(n).unwrap_or("?"))
+                .collect();
+            writeln!(f, ": {}", syms.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// check every molecule in `mols` against the `allowed` atomic numbers
+pub fn check_elements(mols: &[Molecule], allowed: &[usize]) -> ElementReport {
+    let violations = mols
+        .iter()
+        .enumerate()
+        .filter_map(|(index, mol)| {
+            let elements = disallowed(mol, allowed);
+            (!elements.is_empty()).then(|| ElementViolation {
+                index,
+                provenance: mol.provenance.clone(),
+                elements,
+            })
+        })
+        .collect();
+    ElementReport {
+        n_molecules: mols.len(),
+        violations,
+    }
+}
+
+/// parse a comma-separated list of element symbols, like `H,C,N,O`, into
+/// atomic numbers. the name `openff` stands for [OPENFF_ELEMENTS]
+pub fn parse_elements(s: &str) -> Result<Vec<usize>, String> {
+    let mut ret = Vec::new();
+    for sym in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if sym.eq_ignore_ascii_case("openff") {
+            ret.extend(OPENFF_ELEMENTS);
+            continue;
+        }
+        match elements::atomic_number(sym) {
+            Some(n) if n > 0 => ret.push(n),
+            _ => return Err(format!("unknown element {sym}")),
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn charge_and_multiplicity() {
+        let mols = vec![mol("[#6H4]"), mol("[#6H3]"), mol("[#7H4+]")];
+        let got = apply(mols.clone(), &Filter::Neutral);
+        assert_eq!(got.len(), 2);
+        let got = apply(mols.clone(), &Filter::Multiplicity(2));
+        assert_eq!(got, [mols[1].clone()]);
+        let f = Filter::All(vec![Filter::Charge(1), Filter::ClosedShell]);
+        assert_eq!(apply(mols.clone(), &f), [mols[2].clone()]);
+        assert!(Filter::All(vec![]).accepts(&mols[0]));
+    }
+
+    #[test]
+    fn elements() {
+        let mols = vec![
+            mol("[#6H3]-[#8H]"),
+            mol("[#14H3]-[#5H2]"),
+            mol("[#6H3]-[#14H2]-[#6H3]"),
+        ];
+        let f = Filter::Elements(OPENFF_ELEMENTS.to_vec());
+        assert_eq!(apply(mols.clone(), &f), [mols[0].clone()]);
+
+        let report = check_elements(&mols, &OPENFF_ELEMENTS);
+        assert!(!report.passed());
+        assert_eq!(report.violations[0].elements, [5, 14]);
+        assert_eq!(
+            report.to_string(),
+            "2 of 3 molecules contain disallowed elements
+molecule 1: B, Si
+molecule 2: Si
+"
+        );
+
+        assert_eq!(parse_elements("H, C,O"), Ok(vec![1, 6, 8]));
+        assert_eq!(parse_elements("openff").unwrap().len(), 10);
+        assert!(parse_elements("C,Xx").is_err());
+        assert!(parse_elements("*").is_err());
+    }
+}
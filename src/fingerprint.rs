@@ -0,0 +1,137 @@
+//! Circular fingerprints of molecules, in the style of Morgan or ECFP
+//! fingerprints, for comparing molecules by similarity.
+//!
+//! Each atom starts with an identifier computed from its element, H count,
+//! charge, aromaticity, and degree, and optionally a color from an
+//! [crate::symmetry::AtomInvariant]. Each round replaces it with a hash of the
+//! previous identifier and those of its neighbors, along with the bond types
+//! to them, so that after `r` rounds it describes the atoms within `r` bonds.
+//! Every identifier from every round sets one bit, modulo [N_BITS]. The hash
+//! is written out here rather than taken from the standard library, so
+//! fingerprints are stable across Rust versions
+
+use crate::{molecule::Molecule, rings::BitSet};
+
+/// The number of bits in a fingerprint
+pub const N_BITS: usize = 2048;
+
+/// combine `h` and `x` into a new hash, using the SplitMix64 finalizer
+fn mix(h: u64, x: u64) -> u64 {
+    let mut z = h.rotate_left(5) ^ x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    bits: BitSet,
+}
+
+impl Fingerprint {
+    /// the fingerprint of `mol` covering environments up to `radius` bonds
+    /// from each atom. a radius of 2 corresponds to ECFP4
+    pub fn new(mol: &Molecule, radius: usize) -> Self {
+        Self::colored(mol, radius, &vec![0; mol.atoms.len()])
+    }
+
+    /// like [Fingerprint::new], but with the entry in `colors` for each atom,
+    /// like those from [crate::symmetry::colors], added to its starting
+    /// identifier. atoms with color 0 start as they do in [Fingerprint::new]
+    pub fn colored(mol: &Molecule, radius: usize, colors: &[usize]) -> Self {
+        let mut ids: Vec<u64> = mol
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                [
+                    a.n_hydrogens as u64,
+                    a.charge as u64,
+                    a.aromatic as u64,
+                    mol.degree(i) as u64,
+                ]
+                .into_iter()
+                .chain((colors[i] != 0).then_some(colors[i] as u64))
+                .fold(a.atomic_number as u64, mix)
+            })
+            .collect();
+        let mut neighbors = vec![Vec::new(); mol.atoms.len()];
+        for b in &mol.bonds {
+            neighbors[b.atom1].push((b.atom2, b.bond_type as u64));
+            neighbors[b.atom2].push((b.atom1, b.bond_type as u64));
+        }
+        let mut bits: BitSet = ids.iter().map(|&h| bit(h)).collect();
+        for _ in 0..radius {
+            ids = neighbors
+                .iter()
+                .zip(&ids)
+                .map(|(nbrs, &id)| {
+                    // sorted so the result doesn't depend on the bond order
+                    let mut env: Vec<_> =
+                        nbrs.iter().map(|&(j, t)| (t, ids[j])).collect();
+                    env.sort();
+                    env.into_iter().fold(id, |h, (t, j)| mix(mix(h, t), j))
+                })
+                .collect();
+            for &h in &ids {
+                bits.insert(bit(h));
+            }
+        }
+        Self { bits }
+    }
+
+    /// the number of bits set
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// the Tanimoto (Jaccard) similarity of `self` and `other`, from 0 for
+    /// no bits in common to 1 for identical fingerprints. two empty
+    /// fingerprints have a similarity of 1
+    pub fn tanimoto(&self, other: &Fingerprint) -> f64 {
+        let common = self.bits.n_common(&other.bits);
+        let union = self.len() + other.len() - common;
+        if union == 0 {
+            return 1.0;
+        }
+        common as f64 / union as f64
+    }
+}
+
+fn bit(h: u64) -> usize {
+    (h % N_BITS as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::Smarts;
+
+    use super::*;
+
+    fn fp(s: &str) -> Fingerprint {
+        let mol = Molecule::try_from(&Smarts::parse(s.to_owned())).unwrap();
+        Fingerprint::new(&mol, 2)
+    }
+
+    #[test]
+    fn similarity() {
+        let ethanol = fp("[#6H3]-[#6H2]-[#8H]");
+        // the same molecule with its atoms in a different order
+        assert_eq!(ethanol, fp("[#8H]-[#6H2]-[#6H3]"));
+        assert_eq!(ethanol.tanimoto(&ethanol), 1.0);
+
+        let propanol = fp("[#6H3]-[#6H2]-[#6H2]-[#8H]");
+        let benzene = fp("[cH]1:[cH]:[cH]:[cH]:[cH]:[cH]:1");
+        let near = ethanol.tanimoto(&propanol);
+        let far = ethanol.tanimoto(&benzene);
+        assert!(near > far, "{near} <= {far}");
+        assert!(near < 1.0);
+        assert_eq!(far, benzene.tanimoto(&ethanol));
+        // all six atoms of benzene are equivalent at every radius
+        assert_eq!(benzene.len(), 3);
+    }
+}
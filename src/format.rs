@@ -0,0 +1,713 @@
+//! Rewriting SMARTS and SMIRKS strings into a normal form, so that diffs of
+//! hand-edited force-field files only show changes in meaning.
+//!
+//! This works on the text rather than on a parsed [crate::smarts::Smarts], so
+//! it handles the full query syntax, including logical operators and
+//! recursive SMARTS, as written. The operands of each operator are sorted, so
+//! `[X4#6:1]` and `[#6X4:1]` format the same way, with atom primitives in the order isotope, element, chirality, H
+//! count, `D`, `X`, `v`, `h`, `R`, `r`, `x`, charge, and recursive SMARTS,
+//! and bond primitives in the order `-=#:~/\@`. Explicit `&` is dropped
+//! unless removing it would change how the primitives are read, as in
+//! `[C&a]`, and charges are written as `+`, `-`, or a sign and a count
+
+use std::fmt::Display;
+
+use crate::elements;
+
+/// How [format_smarts] lays out its output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormatOptions {
+    /// omit the spaces otherwise written around reaction arrows, giving a
+    /// form that tools like rdkit accept directly
+    pub compact: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatError {
+    /// the character `found` at character `offset` of the input can't appear
+    /// there
+    Unexpected { offset: usize, found: char },
+    /// the bracket or parenthesis `open` at character `offset` is never
+    /// closed
+    Unclosed { offset: usize, open: char },
+    /// the operator or bracket at character `offset` is missing an operand
+    MissingOperand { offset: usize },
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Unexpected { offset, found } => {
+                write!(f, "unexpected {found} at offset {offset}")
+            }
+            FormatError::Unclosed { offset, open } => {
+                write!(f, "{open} at offset {offset} is never closed")
+            }
+            FormatError::MissingOperand { offset } => {
+                write!(f, "missing operand at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// rewrite the SMARTS or SMIRKS `s` in normal form. whitespace in the input
+/// is ignored, so patterns split across lines or padded for alignment can be
+/// formatted too
+pub fn format_smarts(
+    s: &str,
+    options: &FormatOptions,
+) -> Result<String, FormatError> {
+    let chars: Vec<(usize, char)> = s
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| !c.is_whitespace())
+        .collect();
+    pattern(&chars, options)
+}
+
+type Chars = [(usize, char)];
+
+/// the position in `chars` of the `close` matching the `open` at `start`
+fn matching(
+    chars: &Chars,
+    start: usize,
+    open: char,
+    close: char,
+) -> Result<usize, FormatError> {
+    let mut depth = 0;
+    for (i, &(_, c)) in chars.iter().enumerate().skip(start) {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Ok(i);
+            }
+        }
+    }
+    Err(FormatError::Unclosed {
+        offset: chars[start].0,
+        open,
+    })
+}
+
+/// take the run of ASCII digits in `chars` starting at `*i`
+fn digits(chars: &Chars, i: &mut usize) -> String {
+    let mut ret = String::new();
+    while let Some(&(_, c)) = chars.get(*i) {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        ret.push(c);
+        *i += 1;
+    }
+    ret
+}
+
+fn is_bond_char(c: char) -> bool {
+    matches!(
+        c,
+        '-' | '=' | '#' | ':' | '~' | '@' | '/' | '\\' | '!' | ',' | ';' | '&'
+    )
+}
+
+fn pattern(
+    chars: &Chars,
+    options: &FormatOptions,
+) -> Result<String, FormatError> {
+    let mut ret = String::new();
+    let mut i = 0;
+    while let Some(&(offset, c)) = chars.get(i) {
+        match c {
+            '[' => {
+                let end = matching(chars, i, '[', ']')?;
+                ret.push('[');
+                ret.push_str(&bracket(&chars[i + 1..end], offset, options)?);
+                ret.push(']');
+                i = end + 1;
+            }
+            '>' => {
+                let mut arrow = String::new();
+                while chars.get(i).is_some_and(|&(_, c)| c == '>') {
+                    arrow.push('>');
+                    i += 1;
+                }
+                if options.compact {
+                    ret.push_str(&arrow);
+                } else {
+                    ret.push_str(&format!(" {arrow} "));
+                }
+            }
+            '%' => {
+                ret.push('%');
+                i += 1;
+                ret.push_str(&digits(chars, &mut i));
+            }
+            'B' | 'C'
+                if chars.get(i + 1).is_some_and(|&(_, n)| {
+                    (c, n) == ('B', 'r') || (c, n) == ('C', 'l')
+                }) =>
+            {
+                ret.push(c);
+                ret.push(chars[i + 1].1);
+                i += 2;
+            }
+            _ if c.is_ascii_digit() || "().BCNOPSFIbcnops*aA".contains(c) => {
+                ret.push(c);
+                i += 1;
+            }
+            _ if is_bond_char(c) => {
+                let start = i;
+                while chars.get(i).is_some_and(|&(_, c)| is_bond_char(c)) {
+                    i += 1;
+                }
+                let tokens = bond_tokens(&chars[start..i])?;
+                ret.push_str(&expression(tokens, offset)?.to_string());
+            }
+            _ => return Err(FormatError::Unexpected { offset, found: c }),
+        }
+    }
+    Ok(ret)
+}
+
+/// A primitive with the key it is sorted by: its rank in the order given in
+/// the module docs, then a number, like the atomic number of an element, and
+/// finally its text
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Prim {
+    rank: u8,
+    num: usize,
+    text: String,
+}
+
+const H_COUNT: u8 = 3;
+
+enum Tok {
+    Prim(Prim),
+    Not,
+    /// high-precedence and, either `&` or implied by juxtaposition
+    And,
+    Or,
+    /// low-precedence and, `;`
+    Low,
+}
+
+/// the contents of a bracket atom, starting at character `offset`, in normal
+/// form
+fn bracket(
+    chars: &Chars,
+    offset: usize,
+    options: &FormatOptions,
+) -> Result<String, FormatError> {
+    // the atom map is the last `:` outside of any recursive SMARTS
+    let mut depth = 0;
+    let mut map_start = None;
+    for (i, &(_, c)) in chars.iter().enumerate() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ':' if depth == 0 => map_start = Some(i),
+            _ => {}
+        }
+    }
+    let (expr, map) = match map_start {
+        Some(m) => (&chars[..m], Some(&chars[m..])),
+        None => (chars, None),
+    };
+    let mut map_text = String::new();
+    if let Some(map) = map {
+        let mut i = 1;
+        map_text = format!(":{}", digits(map, &mut i));
+        if let Some(&(offset, found)) = map.get(i) {
+            return Err(FormatError::Unexpected { offset, found });
+        }
+        if i == 1 {
+            return Err(FormatError::MissingOperand { offset: map[0].0 });
+        }
+    }
+    let mut node = expression(atom_tokens(expr, options)?, offset)?;
+    // a bracket starting with a bare H is read as a hydrogen atom, so an H
+    // count sorted to the front needs its count written out
+    if let Some(p) = first_leaf(&mut node) {
+        if p.rank == H_COUNT && p.text == "H" {
+            p.text = "H1".to_owned();
+        }
+    }
+    Ok(format!("{node}{map_text}"))
+}
+
+fn atom_tokens(
+    chars: &Chars,
+    options: &FormatOptions,
+) -> Result<Vec<(usize, Tok)>, FormatError> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+    let mut isotope = false;
+    let iso = digits(chars, &mut i);
+    if !iso.is_empty() {
+        isotope = true;
+        ret.push((
+            chars[0].0,
+            Tok::Prim(Prim {
+                rank: 0,
+                num: iso.parse().unwrap_or(0),
+                text: iso,
+            }),
+        ));
+    }
+    let counted = |rank: u8, letter: char, i: &mut usize| {
+        let n = digits(chars, i);
+        Prim {
+            rank,
+            num: n.parse().unwrap_or(0),
+            text: format!("{letter}{n}"),
+        }
+    };
+    while let Some(&(offset, c)) = chars.get(i) {
+        let first = ret.len() == usize::from(isotope);
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        // two-letter element symbols take precedence over reading the second
+        // letter as a primitive of its own, as in [Cr], but the only
+        // lowercase (aromatic) ones are se, as, and te
+        let two_letter = next
+            .filter(char::is_ascii_lowercase)
+            .map(|n| format!("{}{n}", c.to_ascii_uppercase()))
+            .filter(|sym| {
+                if c.is_ascii_uppercase() {
+                    elements::atomic_number(sym).is_some()
+                } else {
+                    ["Se", "As", "Te"].contains(&sym.as_str())
+                }
+            });
+        i += 1;
+        let prim = match c {
+            '!' => {
+                ret.push((offset, Tok::Not));
+                continue;
+            }
+            '&' => {
+                ret.push((offset, Tok::And));
+                continue;
+            }
+            ',' => {
+                ret.push((offset, Tok::Or));
+                continue;
+            }
+            ';' => {
+                ret.push((offset, Tok::Low));
+                continue;
+            }
+            '#' => {
+                let n = digits(chars, &mut i);
+                if n.is_empty() {
+                    return Err(FormatError::MissingOperand { offset });
+                }
+                Prim {
+                    rank: 1,
+                    num: n.parse().unwrap_or(0),
+                    text: format!("#{n}"),
+                }
+            }
+            '$' if next == Some('(') => {
+                let end = matching(chars, i, '(', ')')?;
+                let inner = pattern(&chars[i + 1..end], options)?;
+                i = end + 1;
+                Prim {
+                    rank: 12,
+                    num: 0,
+                    text: format!("$({inner})"),
+                }
+            }
+            '@' => {
+                let mut text = String::from("@");
+                while let Some(&(_, c @ ('@' | '?'))) = chars.get(i) {
+                    text.push(c);
+                    i += 1;
+                }
+                Prim {
+                    rank: 2,
+                    num: 0,
+                    text,
+                }
+            }
+            '+' | '-' => {
+                let mut n = 1;
+                while chars.get(i).is_some_and(|&(_, s)| s == c) {
+                    n += 1;
+                    i += 1;
+                }
+                let d = digits(chars, &mut i);
+                if !d.is_empty() {
+                    n = d.parse().unwrap_or(n);
+                }
+                let text = if n == 1 {
+                    c.to_string()
+                } else {
+                    format!("{c}{n}")
+                };
+                Prim {
+                    rank: 11,
+                    num: n,
+                    text,
+                }
+            }
+            _ if two_letter.is_some() => {
+                i += 1;
+                let sym = two_letter.unwrap();
+                element(&sym, c.is_ascii_lowercase())
+            }
+            'H' if first
+                && (isotope || !next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                element("H", false)
+            }
+            'H' => counted(H_COUNT, c, &mut i),
+            'D' => counted(4, c, &mut i),
+            'X' => counted(5, c, &mut i),
+            'v' => counted(6, c, &mut i),
+            'h' => counted(7, c, &mut i),
+            'R' => counted(8, c, &mut i),
+            'r' => counted(9, c, &mut i),
+            'x' => counted(10, c, &mut i),
+            '*' | 'a' | 'A' => Prim {
+                rank: 1,
+                num: 0,
+                text: c.to_string(),
+            },
+            'A'..='Z' | 'b' | 'c' | 'n' | 'o' | 'p' | 's'
+                if elements::atomic_number(
+                    &c.to_ascii_uppercase().to_string(),
+                )
+                .is_some() =>
+            {
+                element(
+                    &c.to_ascii_uppercase().to_string(),
+                    c.is_ascii_lowercase(),
+                )
+            }
+            _ => return Err(FormatError::Unexpected { offset, found: c }),
+        };
+        ret.push((offset, Tok::Prim(prim)));
+    }
+    Ok(ret)
+}
+
+/// the primitive for the element `sym`, written in lowercase if `aromatic`
+fn element(sym: &str, aromatic: bool) -> Prim {
+    Prim {
+        rank: 1,
+        num: elements::atomic_number(sym).unwrap_or(0),
+        text: if aromatic {
+            sym.to_lowercase()
+        } else {
+            sym.to_owned()
+        },
+    }
+}
+
+fn bond_tokens(chars: &Chars) -> Result<Vec<(usize, Tok)>, FormatError> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while let Some(&(offset, c)) = chars.get(i) {
+        i += 1;
+        let tok = match c {
+            '!' => Tok::Not,
+            '&' => Tok::And,
+            ',' => Tok::Or,
+            ';' => Tok::Low,
+            _ => {
+                let rank = ['-', '=', '#', ':', '~', '/', '\\', '@']
+                    .iter()
+                    .position(|&b| b == c)
+                    .ok_or(FormatError::Unexpected { offset, found: c })?;
+                let mut text = c.to_string();
+                if matches!(c, '/' | '\\')
+                    && chars.get(i).is_some_and(|&(_, c)| c == '?')
+                {
+                    text.push('?');
+                    i += 1;
+                }
+                Tok::Prim(Prim {
+                    rank: rank as u8,
+                    num: 0,
+                    text,
+                })
+            }
+        };
+        ret.push((offset, tok));
+    }
+    Ok(ret)
+}
+
+/// parse and sort the expression in `tokens`, starting at character `offset`
+fn expression(
+    tokens: Vec<(usize, Tok)>,
+    offset: usize,
+) -> Result<Node, FormatError> {
+    let mut node = parse(&tokens, offset)?;
+    sort(&mut node);
+    Ok(node)
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(Prim),
+    Not(Box<Node>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Low(Vec<Node>),
+}
+
+/// parse `tokens`, which start at character `offset`, by precedence: `!`,
+/// then high-precedence and, then `,`, then `;`
+fn parse(tokens: &[(usize, Tok)], offset: usize) -> Result<Node, FormatError> {
+    let mut pos = 0;
+    let node = parse_low(tokens, &mut pos, offset)?;
+    // every token is an operator or operand, so the only way to stop early
+    // is an operand missing before a binary operator
+    if let Some((offset, _)) = tokens.get(pos) {
+        return Err(FormatError::MissingOperand { offset: *offset });
+    }
+    Ok(node)
+}
+
+fn parse_low(
+    tokens: &[(usize, Tok)],
+    pos: &mut usize,
+    offset: usize,
+) -> Result<Node, FormatError> {
+    let mut ret = vec![parse_or(tokens, pos, offset)?];
+    while let Some((offset, Tok::Low)) = tokens.get(*pos) {
+        *pos += 1;
+        ret.push(parse_or(tokens, pos, *offset)?);
+    }
+    Ok(collapse(ret, Node::Low))
+}
+
+fn parse_or(
+    tokens: &[(usize, Tok)],
+    pos: &mut usize,
+    offset: usize,
+) -> Result<Node, FormatError> {
+    let mut ret = vec![parse_and(tokens, pos, offset)?];
+    while let Some((offset, Tok::Or)) = tokens.get(*pos) {
+        *pos += 1;
+        ret.push(parse_and(tokens, pos, *offset)?);
+    }
+    Ok(collapse(ret, Node::Or))
+}
+
+fn parse_and(
+    tokens: &[(usize, Tok)],
+    pos: &mut usize,
+    offset: usize,
+) -> Result<Node, FormatError> {
+    let mut ret = vec![parse_not(tokens, pos, offset)?];
+    loop {
+        match tokens.get(*pos) {
+            Some((offset, Tok::And)) => {
+                *pos += 1;
+                ret.push(parse_not(tokens, pos, *offset)?);
+            }
+            Some((_, Tok::Prim(_) | Tok::Not)) => {
+                ret.push(parse_not(tokens, pos, offset)?);
+            }
+            _ => break,
+        }
+    }
+    Ok(collapse(ret, Node::And))
+}
+
+fn parse_not(
+    tokens: &[(usize, Tok)],
+    pos: &mut usize,
+    offset: usize,
+) -> Result<Node, FormatError> {
+    match tokens.get(*pos) {
+        Some((offset, Tok::Not)) => {
+            *pos += 1;
+            Ok(Node::Not(Box::new(parse_not(tokens, pos, *offset)?)))
+        }
+        Some((_, Tok::Prim(p))) => {
+            *pos += 1;
+            Ok(Node::Leaf(p.clone()))
+        }
+        Some((offset, _)) => {
+            Err(FormatError::MissingOperand { offset: *offset })
+        }
+        None => Err(FormatError::MissingOperand { offset }),
+    }
+}
+
+/// `nodes` joined by `op`, or the only node if there is just one
+fn collapse(mut nodes: Vec<Node>, op: fn(Vec<Node>) -> Node) -> Node {
+    if nodes.len() == 1 {
+        nodes.pop().unwrap()
+    } else {
+        op(nodes)
+    }
+}
+
+/// the sort key of a node: the rank of each of its primitives in order, with
+/// whether it is negated, and then the primitive itself. negated primitives
+/// sort after plain ones of the same rank
+fn key(node: &Node) -> Vec<(u8, bool, &Prim)> {
+    match node {
+        Node::Leaf(p) => vec![(p.rank, false, p)],
+        Node::Not(n) => key(n)
+            .into_iter()
+            .map(|(rank, neg, p)| (rank, !neg, p))
+            .collect(),
+        Node::And(v) | Node::Or(v) | Node::Low(v) => {
+            v.iter().flat_map(key).collect()
+        }
+    }
+}
+
+fn sort(node: &mut Node) {
+    match node {
+        Node::Leaf(_) => {}
+        Node::Not(n) => sort(n),
+        Node::And(v) | Node::Or(v) | Node::Low(v) => {
+            v.iter_mut().for_each(sort);
+            v.sort_by(|a, b| key(a).cmp(&key(b)));
+        }
+    }
+}
+
+/// the first primitive written for `node`, unless it is negated
+fn first_leaf(node: &mut Node) -> Option<&mut Prim> {
+    match node {
+        Node::Leaf(p) => Some(p),
+        Node::Not(_) => None,
+        Node::And(v) | Node::Or(v) | Node::Low(v) => first_leaf(&mut v[0]),
+    }
+}
+
+/// whether writing `b` directly after `a` would read differently from `a&b`
+fn needs_and(a: &str, b: &str) -> bool {
+    let (Some(x), Some(y)) = (a.chars().last(), b.chars().next()) else {
+        return false;
+    };
+    (x.is_ascii_uppercase() && y.is_ascii_lowercase())
+        || (x, y) == ('a', 's')
+        || (y.is_ascii_digit() && (x.is_ascii_digit() || "@+-#".contains(x)))
+        || (x == '@' && y == '?')
+        || (x == y && "@+-".contains(x))
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |f: &mut std::fmt::Formatter<'_>, v: &[Node], sep| {
+            for (i, n) in v.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{sep}")?;
+                }
+                write!(f, "{n}")?;
+            }
+            Ok(())
+        };
+        match self {
+            Node::Leaf(p) => write!(f, "{}", p.text),
+            Node::Not(n) => write!(f, "!{n}"),
+            Node::And(v) => {
+                let mut prev = String::new();
+                for n in v {
+                    let s = n.to_string();
+                    if needs_and(&prev, &s) {
+                        write!(f, "&")?;
+                    }
+                    write!(f, "{s}")?;
+                    prev = s;
+                }
+                Ok(())
+            }
+            Node::Or(v) => join(f, v, ","),
+            Node::Low(v) => join(f, v, ";"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(s: &str) -> String {
+        format_smarts(s, &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn normal_form() {
+        for (input, want) in [
+            ("[#6X4:1]-[#1:2]", "[#6X4:1]-[#1:2]"),
+            ("[X4#6:1]-[#1:2]", "[#6X4:1]-[#1:2]"),
+            ("[#6&X4;H2]", "[#6X4;H2]"),
+            ("[H2;#6&X4]", "[#6X4;H2]"),
+            ("[#7,#6X3]", "[#6X3,#7]"),
+            ("[#16,#6]", "[#6,#16]"),
+            ("[r5C]", "[C&r5]"),
+            ("[H+]", "[H+]"),
+            ("[2H]", "[2H]"),
+            ("[+H]", "[H1+]"),
+            ("[N++]", "[N+2]"),
+            ("[O-1]", "[O-]"),
+            ("[X1!#1]", "[!#1X1]"),
+            ("[!H0#6]", "[#6!H0]"),
+            ("[Cr]", "[Cr]"),
+            ("[cr5]", "[cr5]"),
+            ("[s&a]", "[a&s]"),
+            ("[Cl]C(Br)=[se]", "[Cl]C(Br)=[se]"),
+            ("[$([#7]),$([#6X4])]", "[$([#6X4]),$([#7])]"),
+            ("[$([X4#6]!@;-[#1])]", "[$([#6X4]-;!@[#1])]"),
+            ("C!@;-C", "C-;!@C"),
+            ("[#6X4 : 1]\n  -[#1:2]", "[#6X4:1]-[#1:2]"),
+            (
+                "[#6:1]=[#6:2]>>[#6:1]-[#6:2]",
+                "[#6:1]=[#6:2] >> [#6:1]-[#6:2]",
+            ),
+            ("c1ccccc1%10", "c1ccccc1%10"),
+        ] {
+            assert_eq!(fmt(input), want, "{input}");
+            // formatting is idempotent
+            assert_eq!(fmt(want), want, "{want}");
+        }
+        let compact = FormatOptions { compact: true };
+        assert_eq!(
+            format_smarts("[#6:1] >> [#6:1]", &compact).unwrap(),
+            "[#6:1]>>[#6:1]"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let opts = FormatOptions::default();
+        assert_eq!(
+            format_smarts("C[#6", &opts),
+            Err(FormatError::Unclosed {
+                offset: 1,
+                open: '['
+            })
+        );
+        assert_eq!(
+            format_smarts("[#6,]", &opts),
+            Err(FormatError::MissingOperand { offset: 3 })
+        );
+        assert_eq!(
+            format_smarts("[]", &opts),
+            Err(FormatError::MissingOperand { offset: 0 })
+        );
+        assert_eq!(
+            format_smarts("C^C", &opts),
+            Err(FormatError::Unexpected {
+                offset: 1,
+                found: '^'
+            })
+        );
+        assert_eq!(
+            format_smarts("[#6:x]", &opts).unwrap_err().to_string(),
+            "unexpected x at offset 4"
+        );
+    }
+}
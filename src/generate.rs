@@ -0,0 +1,543 @@
+//! Generating SMIRKS patterns from clusters of chemical environments, with a
+//! trace of which molecules and atoms each part of a pattern came from.
+//!
+//! A cluster is a set of environments, each a tuple of atoms in one molecule,
+//! like the four atoms of a torsion. The generated pattern has one mapped atom
+//! for each position in the tuples, and a bond between two positions if they
+//! are bonded in every environment. Each atom lists the elements seen at its
+//! position, and its H count, connectivity, and formal charge, but only where
+//! every environment agrees on them. Bonds are decorated the same way with
+//! their type and ring membership, falling back to `~` when the types differ
+
+use std::fmt::Display;
+
+use crate::{
+    molecule::{BondType, Molecule},
+    query::HydrogenPolicy,
+    rings::RingInfo,
+    Provenance,
+};
+
+/// One environment in a cluster: positions `atoms` of molecule `molecule`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub molecule: usize,
+    pub atoms: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GenerateError {
+    Empty,
+    /// member `member` has `got` atoms, but the first member has `expected`
+    Length {
+        member: usize,
+        expected: usize,
+        got: usize,
+    },
+    /// member `member` refers to a molecule or atom that doesn't exist
+    OutOfRange {
+        member: usize,
+    },
+}
+
+impl Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::Empty => write!(f, "empty cluster"),
+            GenerateError::Length {
+                member,
+                expected,
+                got,
+            } => write!(
+                f,
+                "member {member} has {got} atoms, but the first has {expected}"
+            ),
+            GenerateError::OutOfRange { member } => {
+                write!(
+                    f,
+                    "member {member} refers to a missing molecule or atom"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// A molecule and atom that contributed to a decorator
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contributor {
+    /// the position of the member in the cluster
+    pub member: usize,
+    pub molecule: usize,
+    pub atom: usize,
+    pub provenance: Option<Provenance>,
+}
+
+/// One piece of a generated atom or bond, like `H1` or `#7`, and the members
+/// of the cluster that led to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decorator {
+    pub text: String,
+    pub contributors: Vec<Contributor>,
+}
+
+/// The decorators of one mapped atom
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtomTrace {
+    pub map: usize,
+    /// the element alternatives, which are joined with `,`
+    pub elements: Vec<Decorator>,
+    /// the properties shared by every member
+    pub decorators: Vec<Decorator>,
+}
+
+/// The decorators of the bond between mapped atoms `maps`. for bonds, the
+/// contributing atom is the first of the pair
+#[derive(Clone, Debug, PartialEq)]
+pub struct BondTrace {
+    pub maps: (usize, usize),
+    pub decorators: Vec<Decorator>,
+}
+
+/// A generated SMIRKS pattern and the trace of where each decorator came
+/// from, as returned by [generate]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generated {
+    pub smirks: String,
+    pub atoms: Vec<AtomTrace>,
+    pub bonds: Vec<BondTrace>,
+}
+
+impl Generated {
+    /// the members that contributed the decorator `text` of the atom mapped
+    /// to `map`, if it has one
+    pub fn why(&self, map: usize, text: &str) -> Option<&[Contributor]> {
+        let atom = self.atoms.iter().find(|a| a.map == map)?;
+        atom.elements
+            .iter()
+            .chain(&atom.decorators)
+            .find(|d| d.text == text)
+            .map(|d| d.contributors.as_slice())
+    }
+}
+
+/// Display the SMIRKS followed by one line per decorator with the number of
+/// members behind it and the records they came from
+impl Display for Generated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.smirks)?;
+        let line =
+            |f: &mut std::fmt::Formatter<'_>, label: &str, d: &Decorator| {
+                write!(
+                    f,
+                    "{label:<8}{:<6}{:>4} from",
+                    d.text,
+                    d.contributors.len()
+                )?;
+                for c in &d.contributors {
+                    match &c.provenance {
+                        Some(p) => write!(f, " {}", p.dataset_key)?,
+                        None => write!(f, " molecule {}", c.molecule)?,
+                    }
+                    write!(f, "@{}", c.atom)?;
+                }
+                writeln!(f)
+            };
+        for atom in &self.atoms {
+            let label = format!(":{}", atom.map);
+            for d in atom.elements.iter().chain(&atom.decorators) {
+                line(f, &label, d)?;
+            }
+        }
+        for bond in &self.bonds {
+            let label = format!(":{}-:{}", bond.maps.0, bond.maps.1);
+            for d in &bond.decorators {
+                line(f, &label, d)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the decorator `text` contributed by every member, or `None` if `value`
+/// differs between members
+fn shared<T: PartialEq>(
+    contributors: &[Contributor],
+    value: impl Fn(&Contributor) -> T,
+    text: impl Fn(T) -> Option<String>,
+) -> Option<Decorator> {
+    let first = value(&contributors[0]);
+    if contributors[1..].iter().any(|c| value(c) != first) {
+        return None;
+    }
+    Some(Decorator {
+        text: text(first)?,
+        contributors: contributors.to_vec(),
+    })
+}
+
+/// Options for [generate_with]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerateOptions {
+    /// how the H count shared by every member is written
+    pub hydrogens: HydrogenPolicy,
+}
+
+/// generate a SMIRKS pattern covering every environment in `cluster`, whose
+/// members refer to molecules in `mols`
+pub fn generate(
+    mols: &[Molecule],
+    cluster: &[Member],
+) -> Result<Generated, GenerateError> {
+    generate_with(mols, cluster, &GenerateOptions::default())
+}
+
+/// like [generate], but with the choices in `options`
+pub fn generate_with(
+    mols: &[Molecule],
+    cluster: &[Member],
+    options: &GenerateOptions,
+) -> Result<Generated, GenerateError> {
+    let Some(first) = cluster.first() else {
+        return Err(GenerateError::Empty);
+    };
+    let n = first.atoms.len();
+    for (i, m) in cluster.iter().enumerate() {
+        if m.atoms.len() != n {
+            return Err(GenerateError::Length {
+                member: i,
+                expected: n,
+                got: m.atoms.len(),
+            });
+        }
+        let in_range = mols
+            .get(m.molecule)
+            .is_some_and(|mol| m.atoms.iter().all(|&a| a < mol.atoms.len()));
+        if !in_range {
+            return Err(GenerateError::OutOfRange { member: i });
+        }
+    }
+    let rings: Vec<Option<RingInfo>> = {
+        let mut ret = vec![None; mols.len()];
+        for m in cluster {
+            if ret[m.molecule].is_none() {
+                ret[m.molecule] = Some(mols[m.molecule].ring_info());
+            }
+        }
+        ret
+    };
+    let at = |pos: usize| -> Vec<Contributor> {
+        cluster
+            .iter()
+            .enumerate()
+            .map(|(i, m)| Contributor {
+                member: i,
+                molecule: m.molecule,
+                atom: m.atoms[pos],
+                provenance: mols[m.molecule].provenance.clone(),
+            })
+            .collect()
+    };
+
+    let mut atoms = Vec::new();
+    for pos in 0..n {
+        let contributors = at(pos);
+        let atom = |c: &Contributor| &mols[c.molecule].atoms[c.atom];
+        let mut elements: Vec<Decorator> = Vec::new();
+        let mut numbers: Vec<usize> = Vec::new();
+        for c in &contributors {
+            let z = atom(c).atomic_number;
+            match numbers.iter().position(|&x| x == z) {
+                Some(i) => elements[i].contributors.push(c.clone()),
+                None => {
+                    numbers.push(z);
+                    elements.push(Decorator {
+                        text: format!("#{z}"),
+                        contributors: vec![c.clone()],
+                    });
+                }
+            }
+        }
+        let mut order: Vec<usize> = (0..elements.len()).collect();
+        order.sort_by_key(|&i| numbers[i]);
+        let elements = order.into_iter().map(|i| elements[i].clone()).collect();
+
+        let hydrogens = |c: &Contributor| {
+            let mol = &mols[c.molecule];
+            let implicit = atom(c).n_hydrogens;
+            let total = implicit
+                + mol
+                    .neighbors(c.atom)
+                    .filter(|&j| mol.atoms[j].atomic_number == 1)
+                    .count();
+            options.hydrogens.primitive(total, implicit)
+        };
+        let decorators = [
+            shared(&contributors, hydrogens, |h| Some(h?.to_string())),
+            shared(
+                &contributors,
+                |c| mols[c.molecule].total_degree(c.atom),
+                |x| Some(format!("X{x}")),
+            ),
+            shared(
+                &contributors,
+                |c| atom(c).charge,
+                |q| Some(format!("{q:+}")),
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        atoms.push(AtomTrace {
+            map: pos + 1,
+            elements,
+            decorators,
+        });
+    }
+
+    let mut bonds = Vec::new();
+    for i in 0..n {
+        for j in i + 1..n {
+            let bond = |c: &Contributor, m: &Member| {
+                let mol = &mols[c.molecule];
+                mol.bonds.iter().position(|b| {
+                    (b.atom1, b.atom2) == (m.atoms[i], m.atoms[j])
+                        || (b.atom2, b.atom1) == (m.atoms[i], m.atoms[j])
+                })
+            };
+            let contributors = at(i);
+            let found: Option<Vec<usize>> = contributors
+                .iter()
+                .zip(cluster)
+                .map(|(c, m)| bond(c, m))
+                .collect();
+            let Some(found) = found else {
+                continue;
+            };
+            let bond_type = |c: &Contributor| {
+                mols[c.molecule].bonds[found[c.member]].bond_type
+            };
+            let in_ring = |c: &Contributor| {
+                rings[c.molecule]
+                    .as_ref()
+                    .unwrap()
+                    .is_ring_bond(found[c.member])
+            };
+            let decorators = [
+                shared(&contributors, bond_type, |t| {
+                    Some(
+                        match t {
+                            BondType::Single => "-",
+                            BondType::Double => "=",
+                            BondType::Triple => "#",
+                            BondType::Aromatic => ":",
+                        }
+                        .to_owned(),
+                    )
+                })
+                .or(Some(Decorator {
+                    text: "~".to_owned(),
+                    contributors: contributors.clone(),
+                })),
+                shared(&contributors, in_ring, |r| {
+                    Some(if r { "@" } else { "!@" }.to_owned())
+                }),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            bonds.push(BondTrace {
+                maps: (i + 1, j + 1),
+                decorators,
+            });
+        }
+    }
+
+    Ok(Generated {
+        smirks: write(&atoms, &bonds),
+        atoms,
+        bonds,
+    })
+}
+
+/// write the SMIRKS for `atoms` and `bonds`, going along the chain of
+/// consecutive map numbers and writing any other bonds as ring closures
+fn write(atoms: &[AtomTrace], bonds: &[BondTrace]) -> String {
+    let bond_text = |b: &BondTrace| {
+        b.decorators
+            .iter()
+            .map(|d| d.text.as_str())
+            .collect::<String>()
+    };
+    let closures: Vec<_> =
+        bonds.iter().filter(|b| b.maps.1 > b.maps.0 + 1).collect();
+    let mut ret = String::new();
+    for atom in atoms {
+        let map = atom.map;
+        if map > 1 {
+            match bonds.iter().find(|b| b.maps == (map - 1, map)) {
+                Some(b) => ret.push_str(&bond_text(b)),
+                // not bonded to the previous atom, so start a new component
+                None => ret.push('.'),
+            }
+        }
+        let elements: Vec<_> =
+            atom.elements.iter().map(|d| d.text.as_str()).collect();
+        let rest: String =
+            atom.decorators.iter().map(|d| d.text.as_str()).collect();
+        let sep = if elements.len() > 1 && !rest.is_empty() {
+            ";"
+        } else {
+            ""
+        };
+        ret.push_str(&format!("[{}{sep}{rest}:{map}]", elements.join(",")));
+        for (l, b) in closures.iter().enumerate() {
+            if b.maps.0 == map || b.maps.1 == map {
+                let l = l + 1;
+                let label = if l < 10 {
+                    l.to_string()
+                } else {
+                    format!("%{l}")
+                };
+                ret.push_str(&format!("{}{label}", bond_text(b)));
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mol(s: &str, key: &str) -> Molecule {
+        let mut mol = crate::molecule::mol(s);
+        mol.provenance = Some(Provenance {
+            dataset_key: key.to_owned(),
+            ..Default::default()
+        });
+        mol
+    }
+
+    #[test]
+    fn trace() {
+        let mols = [
+            mol("[#6H3]-[#6H2]-[#8H]", "ethanol"),
+            mol("[#6H3]-[#7H]-[#6H3]", "dimethylamine"),
+            mol("[#6H3]-[#6H2]-[#8]-[#6H3]", "methyl ethyl ether"),
+        ];
+        let cluster = [
+            Member {
+                molecule: 0,
+                atoms: vec![1, 2],
+            },
+            Member {
+                molecule: 1,
+                atoms: vec![0, 1],
+            },
+            Member {
+                molecule: 2,
+                atoms: vec![1, 2],
+            },
+        ];
+        let got = generate(&mols, &cluster).unwrap();
+        // the H count and connectivity of :2 differ between members, so
+        // they are left out
+        assert_eq!(got.smirks, "[#6X4+0:1]-!@[#7,#8;+0:2]");
+        let why = got.why(1, "X4").unwrap();
+        assert_eq!(why.len(), 3);
+        let nitrogen = got.why(2, "#7").unwrap();
+        assert_eq!(nitrogen.len(), 1);
+        assert_eq!(
+            nitrogen[0].provenance.as_ref().unwrap().dataset_key,
+            "dimethylamine"
+        );
+        assert_eq!((nitrogen[0].member, nitrogen[0].atom), (1, 1));
+        assert!(got.why(1, "H2").is_none());
+
+        // but the first two members agree on the H count of :2
+        let got = generate(&mols, &cluster[..2]).unwrap();
+        assert_eq!(got.smirks, "[#6X4+0:1]-!@[#7,#8;H1+0:2]");
+        assert_eq!(got.why(2, "H1").unwrap().len(), 2);
+        assert!(got.to_string().contains("ethanol@2"));
+
+        let options = |hydrogens| GenerateOptions { hydrogens };
+        let got =
+            generate_with(&mols, &cluster[..2], &options(HydrogenPolicy::Drop))
+                .unwrap();
+        assert_eq!(got.smirks, "[#6X4+0:1]-!@[#7,#8;+0:2]");
+        let got = generate_with(
+            &mols,
+            &cluster[..2],
+            &options(HydrogenPolicy::Implicit),
+        )
+        .unwrap();
+        assert_eq!(got.smirks, "[#6X4+0:1]-!@[#7,#8;h1+0:2]");
+
+        assert_eq!(generate(&mols, &[]), Err(GenerateError::Empty));
+        let bad = Member {
+            molecule: 0,
+            atoms: vec![5, 0],
+        };
+        assert_eq!(
+            generate(&mols, &[bad]),
+            Err(GenerateError::OutOfRange { member: 0 })
+        );
+    }
+
+    #[test]
+    fn rings() {
+        // the three atoms of a cyclopropane angle are bonded in a ring, so
+        // the bond between :1 and :3 is a ring closure
+        let mols = [mol("[#6H2]1-[#6H2]-[#6H2]-1", "cyclopropane")];
+        let cluster = [Member {
+            molecule: 0,
+            atoms: vec![0, 1, 2],
+        }];
+        let got = generate(&mols, &cluster).unwrap();
+        assert_eq!(
+            got.smirks,
+            "[#6H2X4+0:1]-@1-@[#6H2X4+0:2]-@[#6H2X4+0:3]-@1"
+        );
+        assert_eq!(got.bonds.len(), 3);
+    }
+
+    #[test]
+    fn round_trip() {
+        use crate::{
+            query::{Query, Target},
+            smarts::{InputKind, Smarts},
+        };
+        let mols = [
+            mol("[#6H3]-[#6H2]-[#8H]", "ethanol"),
+            mol("[#6H3]-[#7H]-[#6H3]", "dimethylamine"),
+            mol("[#6H2]1-[#6H2]-[#6H2]-1", "cyclopropane"),
+        ];
+        let member = |molecule, atoms: &[usize]| Member {
+            molecule,
+            atoms: atoms.to_vec(),
+        };
+        let options = GenerateOptions {
+            hydrogens: HydrogenPolicy::Implicit,
+        };
+        let clusters = [
+            vec![member(0, &[1, 2]), member(1, &[0, 1])],
+            vec![member(2, &[0, 1, 2])],
+        ];
+        // every generated SMIRKS parses back into a query matching the
+        // atoms it was generated from
+        for cluster in clusters {
+            let got = generate_with(&mols, &cluster, &options).unwrap();
+            let smarts =
+                Smarts::try_parse_as(got.smirks.clone(), InputKind::Smarts)
+                    .unwrap();
+            let query = Query::from(&smarts);
+            for m in &cluster {
+                let matches =
+                    query.find_matches(&Target::new(&mols[m.molecule]));
+                assert!(matches.contains(&m.atoms), "{}", got.smirks);
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! The atoms and bonds to highlight when depicting where a pattern matched a
+//! molecule, as consumed by [rdkit::draw_highlighted]
+//!
+//! [rdkit::draw_highlighted]: crate::rdkit::draw_highlighted
+
+use std::collections::BTreeMap;
+
+use crate::{matcher::Matches, smarts::Smarts};
+
+/// An RGB color with components between 0 and 1, as expected by rdkit's
+/// drawing code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub f64, pub f64, pub f64);
+
+/// The colors given to successive matches by [Highlight::from_matches],
+/// reused from the start when there are more matches than colors
+pub const PALETTE: [Color; 6] = [
+    Color(1.0, 0.6, 0.6),
+    Color(0.6, 0.8, 1.0),
+    Color(0.6, 1.0, 0.6),
+    Color(1.0, 0.85, 0.5),
+    Color(0.85, 0.7, 1.0),
+    Color(0.6, 1.0, 1.0),
+];
+
+/// Atom and bond positions in a target molecule to highlight, each with a
+/// color
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Highlight {
+    /// target atom position -> color
+    pub atoms: BTreeMap<usize, Color>,
+    /// target bond position -> color
+    pub bonds: BTreeMap<usize, Color>,
+}
+
+impl Highlight {
+    /// highlight the atoms of `target` in the match `m` of `query`, along
+    /// with the target bonds corresponding to bonds of `query`, in `color`
+    pub fn from_match(
+        query: &Smarts,
+        target: &Smarts,
+        m: &[usize],
+        color: Color,
+    ) -> Self {
+        let mut ret = Self::default();
+        ret.add_match(query, target, m, color);
+        ret
+    }
+
+    /// highlight every match in `matches` of `query` against `target`, with
+    /// successive matches colored from [PALETTE]. where matches overlap, the
+    /// later color wins
+    pub fn from_matches(
+        query: &Smarts,
+        target: &Smarts,
+        matches: &Matches,
+    ) -> Self {
+        let mut ret = Self::default();
+        for (m, &color) in matches.matches.iter().zip(PALETTE.iter().cycle()) {
+            ret.add_match(query, target, m, color);
+        }
+        ret
+    }
+
+    fn add_match(
+        &mut self,
+        query: &Smarts,
+        target: &Smarts,
+        m: &[usize],
+        color: Color,
+    ) {
+        for &t in m {
+            self.atoms.insert(t, color);
+        }
+        for qb in &query.bonds {
+            let (a, b) = (m[qb.atom1], m[qb.atom2]);
+            let bond = target.bonds.iter().position(|tb| {
+                (tb.atom1, tb.atom2) == (a, b) || (tb.atom1, tb.atom2) == (b, a)
+            });
+            if let Some(bond) = bond {
+                self.bonds.insert(bond, color);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atoms.is_empty() && self.bonds.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        matcher::{find_matches, MatchOptions},
+        molecule::Molecule,
+        query::{Query, Target},
+        smarts::InputKind,
+    };
+
+    use super::*;
+
+    #[test]
+    fn from_matches() {
+        let query =
+            Smarts::parse_as("[#6:1]-[#8:2]".to_owned(), InputKind::Smarts);
+        let target = Smarts::parse("[#8H]-[#6H2]-[#6H2]-[#8H]".to_owned());
+        let matches = find_matches(
+            &Query::from(&query),
+            &Target::new(&Molecule::try_from(&target).unwrap()),
+            &MatchOptions::default(),
+        );
+        assert_eq!(matches.len(), 2);
+
+        let got = Highlight::from_match(
+            &query,
+            &target,
+            &matches.matches[0],
+            PALETTE[0],
+        );
+        assert_eq!(got.atoms.len(), 2);
+        assert_eq!(got.bonds.len(), 1);
+
+        let got = Highlight::from_matches(&query, &target, &matches);
+        assert_eq!(got.atoms.len(), 4);
+        // the C-C bond isn't part of either match
+        assert_eq!(got.bonds.keys().copied().collect::<Vec<_>>(), [0, 2]);
+        let colors: Vec<_> = got.bonds.values().copied().collect();
+        assert_ne!(colors[0], colors[1]);
+        assert!(Highlight::default().is_empty());
+    }
+}
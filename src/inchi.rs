@@ -0,0 +1,111 @@
+//! Native InChI generation through libinchi. Enabled by the `inchi` feature,
+//! which requires libinchi to be installed where the linker can find it, and
+//! which makes [crate::rdkit::to_inchi] and [crate::rdkit::to_inchikey] pass
+//! rdkit's mol block to libinchi instead of using rdkit's own InChI code
+
+use std::ffi::{c_char, c_int, CStr, CString};
+
+/// `inchi_Output` from inchi_api.h
+#[repr(C)]
+struct InchiOutput {
+    inchi: *mut c_char,
+    aux_info: *mut c_char,
+    message: *mut c_char,
+    log: *mut c_char,
+}
+
+#[link(name = "inchi")]
+extern "C" {
+    fn MakeINCHIFromMolfileText(
+        moltext: *const c_char,
+        options: *mut c_char,
+        result: *mut InchiOutput,
+    ) -> c_int;
+    fn FreeINCHI(out: *mut InchiOutput);
+    fn GetINCHIKeyFromINCHI(
+        inchi: *const c_char,
+        xtra1: c_int,
+        xtra2: c_int,
+        key: *mut c_char,
+        xtra1_out: *mut c_char,
+        xtra2_out: *mut c_char,
+    ) -> c_int;
+}
+
+/// `inchi_Ret_WARNING`. anything above this is an error
+const RET_WARNING: c_int = 1;
+
+/// generate the standard InChI for the molecule in the molfile `block`,
+/// returning libinchi's message on failure
+pub fn from_mol_block(block: &str) -> Result<String, String> {
+    let text = CString::new(block).map_err(|e| e.to_string())?;
+    // libinchi takes the options as a mutable string, so hand it ownership
+    // until the call returns
+    let options = CString::default().into_raw();
+    let mut out = InchiOutput {
+        inchi: std::ptr::null_mut(),
+        aux_info: std::ptr::null_mut(),
+        message: std::ptr::null_mut(),
+        log: std::ptr::null_mut(),
+    };
+    // SAFETY: the input strings are valid and nul-terminated, and the output
+    // strings are copied before `out` is freed
+    unsafe {
+        let ret = MakeINCHIFromMolfileText(text.as_ptr(), options, &mut out);
+        drop(CString::from_raw(options));
+        let copy = |p: *mut c_char| {
+            (!p.is_null())
+                .then(|| CStr::from_ptr(p).to_string_lossy().into_owned())
+        };
+        let result = match copy(out.inchi) {
+            Some(inchi) if (0..=RET_WARNING).contains(&ret) => Ok(inchi),
+            _ => Err(copy(out.message).unwrap_or_else(|| {
+                format!("InChI generation failed with code {ret}")
+            })),
+        };
+        FreeINCHI(&mut out);
+        result
+    }
+}
+
+/// compute the InChIKey for `inchi`
+pub fn key(inchi: &str) -> Result<String, String> {
+    let inchi = CString::new(inchi).map_err(|e| e.to_string())?;
+    // 27 characters plus the nul
+    let mut key = [0 as c_char; 28];
+    let mut xtra1 = [0 as c_char; 65];
+    let mut xtra2 = [0 as c_char; 65];
+    // SAFETY: the buffers are the sizes documented in inchi_api.h
+    let ret = unsafe {
+        GetINCHIKeyFromINCHI(
+            inchi.as_ptr(),
+            0,
+            0,
+            key.as_mut_ptr(),
+            xtra1.as_mut_ptr(),
+            xtra2.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        return Err(format!("InChIKey generation failed with code {ret}"));
+    }
+    // SAFETY: libinchi nul-terminates the key on success
+    let key = unsafe { CStr::from_ptr(key.as_ptr()) };
+    Ok(key.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::read_sdf;
+
+    use super::*;
+
+    #[test]
+    fn ethanol() {
+        let recs = read_sdf("testfiles/sdf/ethanol.sdf").unwrap();
+        let inchi = from_mol_block(&recs[0].mol_block).unwrap();
+        assert_eq!(inchi, "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3");
+        assert_eq!(key(&inchi).unwrap(), "LFQSCWFLJHTTHZ-UHFFFAOYSA-N");
+        assert!(from_mol_block("not a mol block").is_err());
+    }
+}
@@ -1,29 +1,994 @@
-use std::{collections::HashMap, error::Error, fs::File, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{read_dir, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use charges::PartialCharges;
+use conformer::{Conformer, Geometry};
+use error::ChomperError;
+use molecule::Molecule;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use smarts::{InputKind, Smarts};
+use timing::{Stage, Timings};
 
+pub mod binning;
+pub mod canonical;
+pub mod catalog;
+pub mod charges;
+pub mod cluster;
+pub mod compress;
+pub mod config;
+pub mod conformance;
+pub mod conformer;
+pub mod csv;
+pub mod diff;
+pub mod elements;
+pub mod error;
+pub mod featurize;
+pub mod filter;
+pub mod fingerprint;
+pub mod format;
+pub mod generate;
+pub mod highlight;
+#[cfg(feature = "inchi")]
+pub mod inchi;
+pub mod matcher;
+pub mod molecule;
+pub mod msgpack;
+pub mod openff;
+pub mod parquet;
+pub mod perception;
+pub mod pipeline;
+pub mod primitives;
+pub mod qcfractal;
+pub mod qcschema;
+pub mod query;
 pub mod rdkit;
+pub mod report;
+pub mod rings;
+pub mod ringsizes;
+pub mod ringsystems;
+pub mod schema;
+pub mod sdf;
 pub mod smarts;
+pub mod split;
+pub mod stats;
+pub mod stereo;
+pub mod stream;
+pub mod symmetry;
+pub mod syntax;
+pub mod tighten;
+pub mod timing;
+pub mod torsion;
+pub mod torsionlib;
+pub mod transform;
+pub mod validate;
+pub mod watch;
 
-#[derive(Deserialize)]
-struct Record {
+/// One record in a [Dataset], as yielded by [Dataset::stream]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Record {
     cmiles: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    record_id: Option<String>,
+    /// overrides for the charge and multiplicity computed from the atoms, as
+    /// given in QCArchive records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    molecular_charge: Option<isize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    molecular_multiplicity: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inchi_key: Option<String>,
+    /// free-form labels like `outlier`, attached with [Dataset::tag]
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<String>,
+    /// the dihedrals driven in a TorsionDrive record, as the 0-based indices
+    /// of their atoms in the QCArchive molecule, or 1 less than their atom
+    /// maps in the cmiles. see [torsion::driven_torsion]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dihedrals: Vec<[usize; 4]>,
+    /// the final geometry of an optimization record
+    #[serde(
+        default,
+        rename = "final_molecule",
+        skip_serializing_if = "Option::is_none"
+    )]
+    geometry: Option<Geometry>,
+    /// the energy in Hartree of each step of an optimization record
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    energies: Vec<f64>,
+    /// the file this record was read from, filled in by the loader
+    #[serde(skip)]
+    file: Option<PathBuf>,
+    /// any other fields of the record, like the extra columns of a CSV file,
+    /// kept so that results can be joined back to the original dataset
+    #[serde(flatten)]
+    extras: Map<String, Value>,
 }
 
-#[derive(Deserialize)]
+impl Record {
+    /// the canonical, mapped SMILES of the record
+    pub fn cmiles(&self) -> &str {
+        &self.cmiles
+    }
+
+    pub fn record_id(&self) -> Option<&str> {
+        self.record_id.as_deref()
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    pub fn dihedrals(&self) -> &[[usize; 4]] {
+        &self.dihedrals
+    }
+
+    pub fn geometry(&self) -> Option<&Geometry> {
+        self.geometry.as_ref()
+    }
+
+    /// the final energy of an optimization record in Hartree, the last of its
+    /// energies
+    pub fn energy(&self) -> Option<f64> {
+        self.energies.last().copied()
+    }
+
+    /// the final geometry and energy of an optimization record as a
+    /// [Conformer], with its positions in the map order of the cmiles, as
+    /// expected by [perception::validate]. use [Molecule::map_order] to
+    /// line it up with the atoms of a molecule
+    pub fn conformer(&self) -> Option<Conformer> {
+        self.geometry.as_ref()?.conformer(self.energy())
+    }
+
+    /// the file this record was read from, if it was read from one
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn extras(&self) -> &Map<String, Value> {
+        &self.extras
+    }
+
+    /// where this record came from, given the `key` of its entry
+    fn provenance(&self, key: &str) -> Provenance {
+        Provenance {
+            file: self.file.clone(),
+            dataset_key: key.to_owned(),
+            record_id: self.record_id.clone(),
+        }
+    }
+}
+
+/// The SMILES of one record with what is needed to find it again in the
+/// [Dataset] it came from, as returned by [Dataset::to_smiles_records]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SmilesRecord {
+    pub smiles: String,
+    /// the name of the entry holding the record
+    pub entry: String,
+    pub record_id: Option<String>,
+    /// the [Record::dihedrals] of the record
+    pub dihedrals: Vec<[usize; 4]>,
+    /// the [Record::extras] of the record
+    pub extras: Map<String, Value>,
+}
+
+/// The origin of a parsed molecule within a [Dataset], so downstream results
+/// can be traced back to the record that produced them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Provenance {
+    pub file: Option<PathBuf>,
+    pub dataset_key: String,
+    pub record_id: Option<String>,
+}
+
+/// The columns other than the SMILES that [Dataset::to_parquet] writes from
+/// the fields of each [Record]
+const PARQUET_COLUMNS: [&str; 7] = [
+    "entry",
+    "record_id",
+    "molecular_charge",
+    "molecular_multiplicity",
+    "inchi_key",
+    "tags",
+    "dihedrals",
+];
+
+/// The file formats [Dataset::load] reads. [Dataset::save] can write all of
+/// them but [DatasetFormat::Sdf] and [DatasetFormat::Csv]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DatasetFormat {
+    /// the layout read by [Dataset::load]
+    #[default]
+    Json,
+    /// the table written by [Dataset::to_parquet]
+    Parquet,
+    /// one SMILES per line followed by the name of its entry, as read by
+    /// [Dataset::from_smi]. only the SMILES and entry names are kept
+    Smi,
+    /// the JSON layout encoded with [msgpack], which [Dataset::load] reads
+    /// much faster than JSON
+    MessagePack,
+    /// an SD file, read with [Dataset::from_sdf] with entries named by their
+    /// `name` property
+    Sdf,
+    /// a CSV or TSV file, read with [Dataset::from_csv] from its `smiles`
+    /// column
+    Csv,
+}
+
+/// The extensions of the files [Dataset::load] reads, and the format of each
+pub const DATASET_EXTENSIONS: [(&str, DatasetFormat); 9] = [
+    (".json", DatasetFormat::Json),
+    (".sdf", DatasetFormat::Sdf),
+    (".mol", DatasetFormat::Sdf),
+    (".csv", DatasetFormat::Csv),
+    (".tsv", DatasetFormat::Csv),
+    (".tab", DatasetFormat::Csv),
+    (".smi", DatasetFormat::Smi),
+    (".parquet", DatasetFormat::Parquet),
+    (".msgpack", DatasetFormat::MessagePack),
+];
+
+impl DatasetFormat {
+    /// the format for `path` from its extension in [DATASET_EXTENSIONS],
+    /// ignoring a trailing .gz or .zst, or `None` if `path` doesn't name a
+    /// dataset file
+    pub fn detect(path: impl AsRef<Path>) -> Option<Self> {
+        let name = path.as_ref().to_string_lossy();
+        let name = compress::uncompressed(&name);
+        DATASET_EXTENSIONS
+            .iter()
+            .find(|(ext, _)| name.ends_with(ext))
+            .map(|&(_, format)| format)
+    }
+
+    /// like [DatasetFormat::detect], but JSON for anything else
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        Self::detect(path).unwrap_or_default()
+    }
+}
+
+/// A collection of records grouped into named entries. Entries are always
+/// visited in sorted order of their names, and records in the order they were
+/// read, so everything derived from a dataset comes out in the same order on
+/// every run
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Dataset {
-    entries: HashMap<String, Vec<Record>>,
+    entries: BTreeMap<String, Vec<Record>>,
+    /// any top-level fields other than the entries, like the `provenance` and
+    /// `type` of a QCArchive export, kept so that saving writes them back
+    #[serde(flatten)]
+    extras: Map<String, Value>,
 }
 
 impl Dataset {
-    pub fn load(path: impl AsRef<Path>) -> Result<Dataset, Box<dyn Error>> {
-        let f = File::open(path)?;
-        let r: Self = serde_json::from_reader(f)?;
-        Ok(r)
+    /// read the dataset at `path` in the format given by
+    /// [DatasetFormat::from_path], decompressing it first if it is compressed
+    /// with gzip or zstd. anything without a known extension is read as JSON
+    pub fn load(path: impl AsRef<Path>) -> Result<Dataset, ChomperError> {
+        let path = path.as_ref();
+        let mut ret = match DatasetFormat::from_path(path) {
+            DatasetFormat::Sdf => return Self::from_sdf(path, "name"),
+            DatasetFormat::Csv => return Self::from_csv(path, "smiles", None),
+            DatasetFormat::Smi => return Self::from_smi(path),
+            DatasetFormat::Parquet => {
+                return Self::from_parquet(path, "smiles")
+            }
+            DatasetFormat::MessagePack => {
+                let mut bytes = Vec::new();
+                compress::open(path)?.read_to_end(&mut bytes)?;
+                msgpack::from_slice(&bytes)?
+            }
+            DatasetFormat::Json => {
+                let r = compress::open(path)?;
+                Self::from_reader(BufReader::with_capacity(1 << 16, r))?
+            }
+        };
+        for rec in ret.entries.values_mut().flatten() {
+            rec.file = Some(path.to_owned());
+        }
+        Ok(ret)
+    }
+
+    /// like [Dataset::load], but read the JSON from `r`, like stdin. the
+    /// records have no file in their [Provenance]
+    pub fn from_reader(r: impl Read) -> Result<Dataset, ChomperError> {
+        Ok(serde_json::from_reader(r)?)
+    }
+
+    /// read the JSON dataset at `path` one record at a time, like
+    /// [Dataset::load] but without holding the whole dataset in memory. see
+    /// [stream::RecordStream] for the order of the records
+    pub fn stream(
+        path: impl AsRef<Path>,
+    ) -> Result<stream::RecordStream, ChomperError> {
+        stream::RecordStream::open(path)
+    }
+
+    /// write `self` to `path` in `format`. JSON is written in the same layout
+    /// that [Dataset::load] reads, including any tags and extras and the
+    /// top-level fields besides the entries, so a saved subset loads back as
+    /// the same dataset. SD and CSV files can't be written
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        format: DatasetFormat,
+    ) -> Result<(), ChomperError> {
+        let path = path.as_ref();
+        match format {
+            DatasetFormat::Json => write_file(path, |w| self.to_writer(w)),
+            DatasetFormat::Smi => write_file(path, |w| self.to_smi(w)),
+            DatasetFormat::MessagePack => write_file(path, |w| {
+                let bytes = msgpack::to_vec(&serde_json::to_value(self)?);
+                Ok(w.write_all(&bytes)?)
+            }),
+            DatasetFormat::Parquet => self.to_parquet(path),
+            DatasetFormat::Sdf | DatasetFormat::Csv => {
+                Err(ChomperError::Format(format!(
+                    "can't write datasets as {format:?}"
+                )))
+            }
+        }
+    }
+
+    /// like [Dataset::save] with [DatasetFormat::Json], but write to `w`
+    pub fn to_writer(&self, w: impl Write) -> Result<(), ChomperError> {
+        serde_json::to_writer_pretty(w, self)?;
+        Ok(())
+    }
+
+    /// like [Dataset::save] with [DatasetFormat::Smi], but write to `w`
+    pub fn to_smi(&self, mut w: impl Write) -> Result<(), ChomperError> {
+        for (key, recs) in &self.entries {
+            for rec in recs {
+                writeln!(w, "{} {key}", rec.cmiles)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// build a dataset from the records in the SD file at `path`, converting
+    /// each molfile block to SMILES with rdkit. each record is added to the
+    /// entry named by its `name_prop` data item, falling back on the molfile
+    /// title and then the file stem if the property is missing. its record
+    /// ID is its position in the file, and its data items go in its
+    /// [Record::extras]. fails if rdkit can't read any of the blocks, naming
+    /// the record that failed
+    pub fn from_sdf(
+        path: impl AsRef<Path>,
+        name_prop: &str,
+    ) -> Result<Dataset, ChomperError> {
+        let mut entries = BTreeMap::new();
+        add_sdf_records(path.as_ref(), name_prop, &mut entries)?;
+        Ok(Self {
+            entries,
+            extras: Map::new(),
+        })
+    }
+
+    /// like [Dataset::from_sdf], but for every .sdf and .mol file in the
+    /// directory tree rooted at `path`. fails on the first block rdkit can't
+    /// read
+    pub fn from_sdf_dir(
+        path: impl AsRef<Path>,
+        name_prop: &str,
+    ) -> Result<Dataset, ChomperError> {
+        let mut files = Vec::new();
+        find_sdf_files(path.as_ref(), &mut files)?;
+        let mut entries = BTreeMap::new();
+        for file in files {
+            add_sdf_records(&file, name_prop, &mut entries)?;
+        }
+        Ok(Self {
+            entries,
+            extras: Map::new(),
+        })
+    }
+
+    /// build a dataset from the SMILES file at `path`, with one SMILES per
+    /// line, optionally followed by whitespace and a name. each record is
+    /// added to the entry named by its name, or by the file stem if it has
+    /// none, and its record ID is its position among the non-blank lines
+    pub fn from_smi(path: impl AsRef<Path>) -> Result<Dataset, ChomperError> {
+        let path = path.as_ref();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut entries: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let text = compress::read_to_string(path)?;
+        let lines = text.lines().filter(|l| !l.trim().is_empty());
+        for (i, line) in lines.enumerate() {
+            let line = line.trim();
+            let (smiles, name) = match line.split_once(char::is_whitespace) {
+                Some((smiles, name)) => (smiles, name.trim()),
+                None => (line, ""),
+            };
+            let name = if name.is_empty() { &stem } else { name };
+            entries.entry(name.to_owned()).or_default().push(Record {
+                cmiles: smiles.to_owned(),
+                record_id: Some(i.to_string()),
+                file: Some(path.to_owned()),
+                ..Default::default()
+            });
+        }
+        Ok(Self {
+            entries,
+            extras: Map::new(),
+        })
+    }
+
+    /// build a dataset from the CSV file at `path`, or TSV if its extension
+    /// is .tsv or .tab, taking the SMILES of each row from the column named
+    /// `smiles_column`. every record goes in one entry named by the file
+    /// stem, and its record ID comes from the column named `id_column`, if
+    /// given, or is otherwise its row number, starting from 0 after the
+    /// header. the other columns go in the [Record::extras] of each record,
+    /// and rows with an empty SMILES are skipped
+    pub fn from_csv(
+        path: impl AsRef<Path>,
+        smiles_column: &str,
+        id_column: Option<&str>,
+    ) -> Result<Dataset, ChomperError> {
+        let path = path.as_ref();
+        let table = csv::read_csv(path)?;
+        let column = |name: &str| {
+            table.column(name).ok_or_else(|| {
+                ChomperError::Format(format!(
+                    "no column {name} in {}",
+                    path.display()
+                ))
+            })
+        };
+        let col = column(smiles_column)?;
+        let id_col = id_column.map(column).transpose()?;
+        let records = table
+            .rows
+            .into_iter()
+            .enumerate()
+            .filter(|(_, row)| !row[col].trim().is_empty())
+            .map(|(i, mut row)| Record {
+                cmiles: std::mem::take(&mut row[col]),
+                record_id: Some(match id_col {
+                    Some(c) => std::mem::take(&mut row[c]),
+                    None => i.to_string(),
+                }),
+                file: Some(path.to_owned()),
+                extras: table
+                    .header
+                    .iter()
+                    .zip(row)
+                    .enumerate()
+                    .filter(|&(c, _)| c != col && Some(c) != id_col)
+                    .map(|(_, (k, v))| (k.clone(), Value::String(v)))
+                    .collect(),
+                ..Default::default()
+            })
+            .collect();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        Ok(Self {
+            entries: BTreeMap::from([(name.into_owned(), records)]),
+            extras: Map::new(),
+        })
+    }
+
+    /// build a dataset from the Parquet file at `path`, taking the SMILES of
+    /// each row from the column named `smiles_column`. the columns written by
+    /// [Dataset::to_parquet] are read into the records when they are
+    /// present: `entry` names the entry of each row, defaulting to the file
+    /// stem, and `record_id` its record ID, defaulting to its row number like
+    /// [Dataset::from_csv]. every other column goes in the [Record::extras]
+    /// of each row where it isn't null, and rows with an empty SMILES are
+    /// skipped
+    pub fn from_parquet(
+        path: impl AsRef<Path>,
+        smiles_column: &str,
+    ) -> Result<Dataset, ChomperError> {
+        let path = path.as_ref();
+        let table = parquet::read_parquet(path, None)?;
+        if !table.columns.iter().any(|c| c == smiles_column) {
+            return Err(ChomperError::Format(format!(
+                "no column {smiles_column} in {}",
+                path.display()
+            )));
+        }
+        let smiles = table.strings(smiles_column)?;
+        let entry = table.strings("entry")?;
+        let record_id = table.strings("record_id")?;
+        let charge = table.column("molecular_charge")?;
+        let mult = table.column("molecular_multiplicity")?;
+        let inchi_key = table.strings("inchi_key")?;
+        let tags = table.column::<Vec<String>>("tags")?;
+        let dihedrals = table.column("dihedrals")?;
+        let mut extras = Vec::new();
+        for name in &table.columns {
+            if name != smiles_column && !PARQUET_COLUMNS.contains(&&**name) {
+                extras.push((name, table.json(name)?));
+            }
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut entries: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let rows = smiles
+            .into_iter()
+            .zip(entry)
+            .zip(record_id)
+            .zip(charge.into_iter().zip(mult))
+            .zip(inchi_key.into_iter().zip(tags).zip(dihedrals))
+            .enumerate();
+        for (i, row) in rows {
+            let ((((cmiles, entry), id), (charge, mult)), rest) = row;
+            let ((inchi_key, tags), dihedrals) = rest;
+            let Some(cmiles) = cmiles.filter(|s| !s.trim().is_empty()) else {
+                continue;
+            };
+            let entry = entry.unwrap_or_else(|| stem.to_string());
+            entries.entry(entry).or_default().push(Record {
+                cmiles,
+                record_id: id.or(Some(i.to_string())),
+                molecular_charge: charge,
+                molecular_multiplicity: mult,
+                inchi_key,
+                tags: tags.unwrap_or_default().into_iter().collect(),
+                dihedrals: dihedrals.unwrap_or_default(),
+                file: Some(path.to_owned()),
+                extras: extras
+                    .iter_mut()
+                    .filter(|(_, col)| !col[i].is_null())
+                    .map(|(name, col)| ((*name).clone(), col[i].take()))
+                    .collect(),
+                ..Default::default()
+            });
+        }
+        Ok(Self {
+            entries,
+            extras: Map::new(),
+        })
+    }
+
+    /// write `self` to `path` as a Parquet table with one row per record and
+    /// the columns `entry`, `smiles`, `record_id`, `molecular_charge`,
+    /// `molecular_multiplicity`, `inchi_key`, `tags`, and `dihedrals`, which
+    /// [Dataset::from_parquet] reads back when given the `smiles` column,
+    /// followed by a column for each key in the [Record::extras]
+    pub fn to_parquet(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ChomperError> {
+        parquet::write_parquet(path, &self.to_rows())
+    }
+
+    /// the rows written by [Dataset::to_parquet]
+    fn to_rows(&self) -> Vec<parquet::Row> {
+        let mut ret = Vec::new();
+        for (key, recs) in &self.entries {
+            for rec in recs {
+                let row = serde_json::json!({
+                    "entry": key,
+                    "smiles": rec.cmiles,
+                    "record_id": rec.record_id,
+                    "molecular_charge": rec.molecular_charge,
+                    "molecular_multiplicity": rec.molecular_multiplicity,
+                    "inchi_key": rec.inchi_key,
+                    "tags": rec.tags,
+                    "dihedrals": rec.dihedrals,
+                });
+                let Value::Object(mut row) = row else {
+                    unreachable!();
+                };
+                for (k, v) in &rec.extras {
+                    if !row.contains_key(k) {
+                        row.insert(k.clone(), v.clone());
+                    }
+                }
+                ret.push(row);
+            }
+        }
+        ret
+    }
+
+    /// consume `self`, convert each record to SMARTS with rdkit, and parse the
+    /// results, recording where each one came from in its
+    /// [Smarts::provenance]. stops at the first record that fails, naming it
+    /// in the error. see [pipeline::Pipeline] for carrying on past failures
+    pub fn parse(self) -> Result<Vec<Smarts>, ChomperError> {
+        self.parse_timed(&mut Timings::default())
+    }
+
+    /// like [Dataset::parse], but record the time spent converting each record
+    /// with rdkit and in each stage of parsing it in `timings`
+    pub fn parse_timed(
+        self,
+        timings: &mut Timings,
+    ) -> Result<Vec<Smarts>, ChomperError> {
+        let mut ret = Vec::new();
+        for (key, recs) in self.entries {
+            for rec in recs {
+                let provenance = Provenance {
+                    file: rec.file,
+                    dataset_key: key.clone(),
+                    record_id: rec.record_id,
+                };
+                let smarts = timings
+                    .time(Stage::Convert, || rdkit::to_smarts(rec.cmiles))
+                    .and_then(|s| {
+                        Smarts::parse_timed(s, InputKind::Smiles, timings)
+                    });
+                match smarts {
+                    Ok(s) => ret.push(s.with_provenance(provenance)),
+                    Err(e) => return Err(e.in_record(provenance)),
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// consume `self` and convert each record into a [Molecule], like
+    /// [Dataset::parse], but also applying any charge and multiplicity given
+    /// in the records
+    pub fn molecules(self) -> Result<Vec<Molecule>, ChomperError> {
+        self.molecules_with_charges(&PartialCharges::default())
+    }
+
+    /// like [Dataset::molecules], but also attach the partial charges in
+    /// `charges` keyed by each record's cmiles, if there are any
+    pub fn molecules_with_charges(
+        self,
+        charges: &PartialCharges,
+    ) -> Result<Vec<Molecule>, ChomperError> {
+        let mut ret = Vec::new();
+        for (key, recs) in self.entries {
+            for rec in recs {
+                let provenance = Provenance {
+                    file: rec.file,
+                    dataset_key: key.clone(),
+                    record_id: rec.record_id,
+                };
+                let mol = rdkit::to_smarts(rec.cmiles.clone())
+                    .and_then(Smarts::try_parse)
+                    .and_then(|s| {
+                        let s = s.with_provenance(provenance.clone());
+                        Ok(charges
+                            .attach(&rec.cmiles, Molecule::try_from(&s)?)?)
+                    });
+                let mut mol = mol.map_err(|e| e.in_record(provenance))?;
+                if let Some(q) = rec.molecular_charge {
+                    mol = mol.with_charge(q);
+                }
+                if let Some(m) = rec.molecular_multiplicity {
+                    mol = mol.with_multiplicity(m);
+                }
+                ret.push(mol);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// consume `self` and replace each record with one record for each of up
+    /// to `max_states` protonation states from [rdkit::charge_states]. the new
+    /// records keep the entry, record ID, and file of the record they came
+    /// from, but not its InChIKey, charge and multiplicity overrides,
+    /// dihedrals, or geometry, which no longer apply. records rdkit can't
+    /// read are left out, with an error for each
+    pub fn enumerate_charge_states(
+        self,
+        max_states: usize,
+    ) -> (Dataset, Vec<ChomperError>) {
+        let mut entries = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (key, recs) in self.entries {
+            let mut new = Vec::new();
+            for rec in recs {
+                let states = match rdkit::charge_states(&rec.cmiles, max_states)
+                {
+                    Ok(states) => states,
+                    Err(e) => {
+                        errors.push(e.in_record(rec.provenance(&key)));
+                        continue;
+                    }
+                };
+                new.extend(states.into_iter().map(|cmiles| Record {
+                    cmiles,
+                    record_id: rec.record_id.clone(),
+                    tags: rec.tags.clone(),
+                    file: rec.file.clone(),
+                    extras: rec.extras.clone(),
+                    ..Default::default()
+                }));
+            }
+            if !new.is_empty() {
+                entries.insert(key, new);
+            }
+        }
+        let extras = self.extras;
+        (Dataset { entries, extras }, errors)
+    }
+
+    /// remove every record whose InChIKey has already been seen, keeping the
+    /// first occurrence. the InChIKey stored in a record is used if there is
+    /// one, otherwise it is generated from the cmiles with rdkit. records
+    /// rdkit can't read are kept, since they can't be compared, with an error
+    /// for each. returns the number of records removed and the errors
+    pub fn dedup_by_inchikey(&mut self) -> (usize, Vec<ChomperError>) {
+        let mut seen = HashSet::new();
+        let mut removed = 0;
+        let mut errors = Vec::new();
+        for (key, recs) in &mut self.entries {
+            let before = recs.len();
+            recs.retain(|rec| {
+                let ik = match &rec.inchi_key {
+                    Some(ik) => Ok(ik.clone()),
+                    None => rdkit::to_inchikey(&rec.cmiles),
+                };
+                match ik {
+                    Ok(ik) => seen.insert(ik),
+                    Err(e) => {
+                        errors.push(e.in_record(rec.provenance(key)));
+                        true
+                    }
+                }
+            });
+            removed += before - recs.len();
+        }
+        self.entries.retain(|_, recs| !recs.is_empty());
+        (removed, errors)
+    }
+
+    /// add the records in `new_export` that are not already in `self` and
+    /// return just those records, grouped by entry as in `new_export`. a
+    /// record is already present if some record in `self`, in any entry, has
+    /// the same record ID and the same structure, which is compared by
+    /// InChIKey when both records have one and by canonical SMILES otherwise,
+    /// as in [Dataset::merge]. repeats within `new_export` are only added
+    /// once. records of `new_export` that rdkit can't read are skipped, with
+    /// an error for each, and those of `self` can't match anything
+    pub fn apply_update(
+        &mut self,
+        new_export: Dataset,
+    ) -> (Dataset, Vec<ChomperError>) {
+        // record ID -> the (InChIKey, canonical SMILES) of each record with
+        // that ID
+        let mut seen: HashMap<Option<String>, Vec<(Option<String>, String)>> =
+            HashMap::new();
+        let mut insert = |rec: &Record, canonical: String| {
+            let structures = seen.entry(rec.record_id.clone()).or_default();
+            let known = structures.iter().any(|(ik, smiles)| {
+                match (ik, &rec.inchi_key) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => *smiles == canonical,
+                }
+            });
+            if !known {
+                structures.push((rec.inchi_key.clone(), canonical));
+            }
+            !known
+        };
+        for rec in self.entries.values().flatten() {
+            if let Ok(canonical) = rdkit::canonical_smiles(&rec.cmiles) {
+                insert(rec, canonical);
+            }
+        }
+        let mut delta: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (name, recs) in new_export.entries {
+            for rec in recs {
+                match rdkit::canonical_smiles(&rec.cmiles)
+                    .map(|canonical| insert(&rec, canonical))
+                {
+                    Ok(true) => {
+                        self.entries
+                            .entry(name.clone())
+                            .or_default()
+                            .push(rec.clone());
+                        delta.entry(name.clone()).or_default().push(rec);
+                    }
+                    Ok(false) => {}
+                    Err(e) => errors.push(e.in_record(rec.provenance(&name))),
+                }
+            }
+        }
+        let delta = Dataset {
+            entries: delta,
+            extras: self.extras.clone(),
+        };
+        (delta, errors)
+    }
+
+    /// the number of records across all entries
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.values().all(Vec::is_empty)
+    }
+
+    /// split the records of `self` at random into a training set holding
+    /// `fraction` of them and a test set holding the rest, keeping their
+    /// entries. the same `seed` always gives the same split
+    pub fn split(&self, fraction: f64, seed: u64) -> (Dataset, Dataset) {
+        let records: Vec<usize> = (0..self.len()).collect();
+        self.partition(&split::split_groups(&records, fraction, seed))
+    }
+
+    /// like [Dataset::split], but keeping the records of molecules with the
+    /// same [split::scaffold] together, converting each record like
+    /// [Dataset::molecules]
+    pub fn split_by_scaffold(
+        &self,
+        fraction: f64,
+        seed: u64,
+    ) -> Result<(Dataset, Dataset), ChomperError> {
+        let mols = self.clone().molecules()?;
+        Ok(self.partition(&split::scaffold_split(&mols, fraction, seed)))
+    }
+
+    /// split `self` into the records whose entry in `first`, in the order of
+    /// [Dataset::to_smiles], is true and those whose entry is false
+    fn partition(&self, first: &[bool]) -> (Dataset, Dataset) {
+        let mut a: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let mut b: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let mut first = first.iter();
+        for (key, recs) in &self.entries {
+            for rec in recs {
+                let side = if *first.next().unwrap() {
+                    &mut a
+                } else {
+                    &mut b
+                };
+                side.entry(key.clone()).or_default().push(rec.clone());
+            }
+        }
+        let extras = &self.extras;
+        (
+            Dataset {
+                entries: a,
+                extras: extras.clone(),
+            },
+            Dataset {
+                entries: b,
+                extras: extras.clone(),
+            },
+        )
+    }
+
+    /// the [stats::DatasetStats] of the molecules in `self`, converting each
+    /// record like [Dataset::molecules]
+    pub fn stats(&self) -> Result<stats::DatasetStats, ChomperError> {
+        Ok(stats::DatasetStats::new(&self.clone().molecules()?))
+    }
+
+    /// the canonical SMILES of every record, as compared by
+    /// [canonical::unique_smiles], leaving out records rdkit can't read
+    fn canonical_smiles(&self) -> HashSet<String> {
+        self.entries
+            .values()
+            .flatten()
+            .filter_map(|rec| rdkit::canonical_smiles(&rec.cmiles).ok())
+            .collect()
+    }
+
+    /// add the records of `other` whose molecules are not already in `self`,
+    /// comparing molecules by canonical SMILES as in
+    /// [canonical::unique_smiles], and keeping each record in the entry it
+    /// had in `other`. repeats within `other` are only added once, and
+    /// records rdkit can't read are skipped. returns the number of records
+    /// added and an error for each record skipped
+    pub fn merge(&mut self, other: Dataset) -> (usize, Vec<ChomperError>) {
+        let mut seen = self.canonical_smiles();
+        let mut added = 0;
+        let mut errors = Vec::new();
+        for (name, recs) in other.entries {
+            for rec in recs {
+                match rdkit::canonical_smiles(&rec.cmiles)
+                    .map(|s| seen.insert(s))
+                {
+                    Ok(true) => {
+                        self.entries.entry(name.clone()).or_default().push(rec);
+                        added += 1;
+                    }
+                    Ok(false) => {}
+                    Err(e) => errors.push(e.in_record(rec.provenance(&name))),
+                }
+            }
+        }
+        (added, errors)
+    }
+
+    /// the records of `self` whose molecules do not appear anywhere in
+    /// `other`, comparing molecules by canonical SMILES as in
+    /// [canonical::unique_smiles]. for a fresh download `new` of a dataset
+    /// previously saved as `old`, `new.diff(&old)` holds the new molecules.
+    /// records of `self` that rdkit can't read are left out, with an error
+    /// for each, and those of `other` can't match anything
+    pub fn diff(&self, other: &Dataset) -> (Dataset, Vec<ChomperError>) {
+        let theirs = other.canonical_smiles();
+        let mut entries = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (key, recs) in &self.entries {
+            let mut new = Vec::new();
+            for rec in recs {
+                match rdkit::canonical_smiles(&rec.cmiles) {
+                    Ok(s) if !theirs.contains(&s) => new.push(rec.clone()),
+                    Ok(_) => {}
+                    Err(e) => errors.push(e.in_record(rec.provenance(key))),
+                }
+            }
+            if !new.is_empty() {
+                entries.insert(key.clone(), new);
+            }
+        }
+        let extras = self.extras.clone();
+        (Dataset { entries, extras }, errors)
+    }
+
+    /// add `tag` to every record for which `select` returns true, given the
+    /// name of its entry and its record ID, and return the number of records
+    /// that did not already have it
+    pub fn tag(
+        &mut self,
+        tag: &str,
+        mut select: impl FnMut(&str, Option<&str>) -> bool,
+    ) -> usize {
+        let mut n = 0;
+        for (key, recs) in &mut self.entries {
+            for rec in recs {
+                if select(key, rec.record_id.as_deref()) {
+                    n += usize::from(rec.tags.insert(tag.to_owned()));
+                }
+            }
+        }
+        n
+    }
+
+    /// remove `tag` from every record and return the number that had it
+    pub fn untag(&mut self, tag: &str) -> usize {
+        let mut n = 0;
+        for rec in self.entries.values_mut().flatten() {
+            n += usize::from(rec.tags.remove(tag));
+        }
+        n
+    }
+
+    /// the number of records with each tag
+    pub fn tag_counts(&self) -> BTreeMap<&str, usize> {
+        let mut ret = BTreeMap::new();
+        for tag in self.entries.values().flatten().flat_map(|r| &r.tags) {
+            *ret.entry(tag.as_str()).or_default() += 1;
+        }
+        ret
+    }
+
+    /// the records with every tag in `include` and none of the tags in
+    /// `exclude`, dropping any entries left empty
+    pub fn select_tags(&self, include: &[&str], exclude: &[&str]) -> Dataset {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, recs)| {
+                let recs: Vec<_> = recs
+                    .iter()
+                    .filter(|rec| {
+                        include.iter().all(|&t| rec.tags.contains(t))
+                            && !exclude.iter().any(|&t| rec.tags.contains(t))
+                    })
+                    .cloned()
+                    .collect();
+                (!recs.is_empty()).then(|| (key.clone(), recs))
+            })
+            .collect();
+        Dataset {
+            entries,
+            extras: self.extras.clone(),
+        }
     }
 
     /// consume `self` and return the contained vector of canonical SMILES
-    /// strings
+    /// strings. see [Dataset::to_smiles_records] to keep the entry name,
+    /// record ID, and extras of each one
     pub fn to_smiles(self) -> Vec<String> {
         self.entries
             .into_values()
@@ -31,4 +996,585 @@ impl Dataset {
             .map(|v| v.cmiles)
             .collect()
     }
+
+    /// consume `self` and return the SMILES of its distinct molecules with
+    /// the number of duplicates dropped and the errors for SMILES rdkit
+    /// couldn't read, as described in [canonical::unique_smiles]
+    pub fn unique_smiles(self) -> (Vec<String>, usize, Vec<ChomperError>) {
+        canonical::unique_smiles(self.to_smiles())
+    }
+
+    /// like [Dataset::to_smiles], but keep what identifies each record
+    /// alongside its SMILES, so that results computed from the SMILES can be
+    /// joined back to the dataset
+    pub fn to_smiles_records(self) -> Vec<SmilesRecord> {
+        self.entries
+            .into_iter()
+            .flat_map(|(entry, recs)| {
+                recs.into_iter().map(move |rec| SmilesRecord {
+                    smiles: rec.cmiles,
+                    entry: entry.clone(),
+                    record_id: rec.record_id,
+                    dihedrals: rec.dihedrals,
+                    extras: rec.extras,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Assembles a [Dataset] in memory, for tests and other callers that have
+/// SMILES in hand rather than a file
+#[derive(Clone, Debug, Default)]
+pub struct DatasetBuilder {
+    entries: BTreeMap<String, Vec<Record>>,
+}
+
+impl DatasetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a record for each of `smiles` to the entry `name`, after any it
+    /// already has. the records have no record IDs or other fields
+    pub fn add_entry<S: Into<String>>(
+        mut self,
+        name: impl Into<String>,
+        smiles: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let recs = self.entries.entry(name.into()).or_default();
+        recs.extend(smiles.into_iter().map(|s| Record {
+            cmiles: s.into(),
+            ..Default::default()
+        }));
+        self
+    }
+
+    pub fn build(self) -> Dataset {
+        Dataset {
+            extras: Map::new(),
+            entries: self.entries,
+        }
+    }
+}
+
+/// create the file at `path` and write it with `f`, buffered
+fn write_file(
+    path: &Path,
+    f: impl FnOnce(&mut BufWriter<File>) -> Result<(), ChomperError>,
+) -> Result<(), ChomperError> {
+    let mut w = BufWriter::new(File::create(path)?);
+    f(&mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// read the SD file `file` and add its records to `entries`, as described in
+/// [Dataset::from_sdf]
+fn add_sdf_records(
+    file: &Path,
+    name_prop: &str,
+    entries: &mut BTreeMap<String, Vec<Record>>,
+) -> Result<(), ChomperError> {
+    for (i, rec) in sdf::read_sdf(file)?.into_iter().enumerate() {
+        let name = match rec.properties.get(name_prop) {
+            Some(p) => p.clone(),
+            None if !rec.title().is_empty() => rec.title().to_owned(),
+            None => file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let cmiles =
+            rdkit::mol_block_to_smiles(&rec.mol_block).map_err(|e| {
+                e.in_record(Provenance {
+                    file: Some(file.to_owned()),
+                    dataset_key: name.clone(),
+                    record_id: Some(i.to_string()),
+                })
+            })?;
+        entries.entry(name).or_default().push(Record {
+            cmiles,
+            record_id: Some(i.to_string()),
+            file: Some(file.to_owned()),
+            extras: rec
+                .properties
+                .into_iter()
+                .map(|(k, v)| (k, Value::String(v)))
+                .collect(),
+            ..Default::default()
+        });
+    }
+    Ok(())
+}
+
+/// recursively collect the .sdf and .mol files under `dir` into `files`, in
+/// sorted order
+fn find_sdf_files(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), ChomperError> {
+    let mut paths = read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            find_sdf_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "sdf" || ext == "mol")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sdf_dir() {
+        let got = Dataset::from_sdf_dir("testfiles/sdf", "name").unwrap();
+        let mut keys: Vec<_> = got.entries.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["ethanol", "methanol", "water"]);
+        assert_eq!(got.to_smiles().len(), 3);
+
+        let got = Dataset::from_sdf("testfiles/sdf/ethanol.sdf", "id").unwrap();
+        let keys: Vec<_> = got.entries.keys().collect();
+        assert_eq!(keys, ["1", "2"]);
+
+        // a block rdkit can't read fails the whole directory
+        let got = Dataset::from_sdf_dir("testfiles/bad_sdf", "name");
+        let got = got.err().unwrap().to_string();
+        assert!(got.starts_with("record 0 of broken: rdkit failed"), "{got}");
+
+        // the error names the bad record even after good ones
+        let path = "testfiles/bad_sdf/partial.sdf";
+        let got = Dataset::from_sdf(path, "name").err().unwrap().to_string();
+        assert!(got.starts_with("record 1 of truncated: rdkit"), "{got}");
+    }
+
+    #[test]
+    fn csv() {
+        let path = "testfiles/registration.csv";
+        let got = Dataset::from_csv(path, "smiles", None).unwrap();
+        let recs = &got.entries["registration"];
+        assert_eq!(recs[1].record_id.as_deref(), Some("2"));
+        assert_eq!(recs[1].extras()["name"], "formic acid");
+        assert!(matches!(
+            Dataset::from_csv(path, "cmiles", None),
+            Err(ChomperError::Format(_))
+        ));
+
+        let ids = Dataset::from_csv(path, "smiles", Some("id")).unwrap();
+        let recs = &ids.entries["registration"];
+        assert_eq!(recs[1].record_id.as_deref(), Some("REG-3"));
+        assert!(!recs[1].extras().contains_key("id"));
+        assert!(Dataset::from_csv(path, "smiles", Some("key")).is_err());
+
+        // the extras survive a JSON round trip and come out with the SMILES
+        let mut buf = Vec::new();
+        got.to_writer(&mut buf).unwrap();
+        let got = Dataset::from_reader(buf.as_slice()).unwrap();
+        let got = got.to_smiles_records();
+        assert_eq!(
+            got[1],
+            SmilesRecord {
+                smiles: "C(=O)O".to_owned(),
+                entry: "registration".to_owned(),
+                record_id: Some("2".to_owned()),
+                dihedrals: Vec::new(),
+                extras: serde_json::from_str(
+                    r#"{"id": "REG-3", "name": "formic acid"}"#
+                )
+                .unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let mut ds = Dataset::from_smi("testfiles/small.smi").unwrap();
+        ds.tag("keep", |key, _| key == "ethanol");
+        let rec = &mut ds.entries.get_mut("ethanol").unwrap()[0];
+        rec.extras.insert("source".to_owned(), "vendor".into());
+        let rows = ds.to_rows();
+        assert_eq!(rows[0]["smiles"], "CCO");
+        assert_eq!(rows[0]["source"], "vendor");
+        assert_eq!(rows[0]["tags"], serde_json::json!(["keep"]));
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("chomper-{}.parquet", std::process::id()));
+        ds.to_parquet(&path).unwrap();
+        let got = Dataset::from_parquet(&path, "smiles").unwrap();
+        assert_eq!(got.to_rows(), rows);
+        // the extras survive, and records without one don't gain a null
+        assert_eq!(got.entries["ethanol"][0].extras["source"], "vendor");
+        assert!(got.entries["methanol"][0].extras.is_empty());
+
+        // without the chomper columns, everything goes in one entry
+        let rows = vec![
+            serde_json::json!({"smi": "CCO", "mw": 46.07}),
+            serde_json::json!({"smi": null, "mw": 0}),
+            serde_json::json!({"smi": "C", "mw": 16.04}),
+        ];
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|r| r.as_object().unwrap().clone())
+            .collect();
+        parquet::write_parquet(&path, &rows).unwrap();
+        let got = Dataset::from_parquet(&path, "smi").unwrap();
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let recs = &got.entries[&*stem];
+        assert_eq!(recs[1].record_id.as_deref(), Some("2"));
+        assert_eq!(recs[1].extras["mw"], 16.04);
+        assert_eq!(got.to_smiles(), ["CCO", "C"]);
+        assert!(Dataset::from_parquet(&path, "smiles").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn smi() {
+        let got = Dataset::from_smi("testfiles/small.smi").unwrap();
+        let keys: Vec<_> = got.entries.keys().collect();
+        assert_eq!(keys, ["ethanol", "methanol", "small"]);
+        assert_eq!(got.entries["small"][0].record_id.as_deref(), Some("2"));
+        assert_eq!(got.to_smiles(), ["CCO", "CO", "O"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = "testfiles/opt.json";
+        let ds = Dataset::load(path).unwrap();
+        let mut buf = Vec::new();
+        ds.to_writer(&mut buf).unwrap();
+        let got: Value = serde_json::from_slice(&buf).unwrap();
+        let want: Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap())
+                .unwrap();
+        // including the top-level provenance and type
+        assert_eq!(got["type"], "OptimizationResultCollection");
+        assert!(got["provenance"].is_object());
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn save() {
+        let ds = Dataset::load("testfiles/small.json.gz").unwrap();
+        let keys =
+            |ds: &Dataset| ds.entries.keys().cloned().collect::<Vec<_>>();
+        let dir = std::env::temp_dir().join("chomper-save");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("small.json");
+        assert_eq!(DatasetFormat::from_path(&path), DatasetFormat::Json);
+        ds.save(&path, DatasetFormat::Json).unwrap();
+        let got = Dataset::load(&path).unwrap();
+        assert_eq!(keys(&got), keys(&ds));
+        assert_eq!(got.to_smiles(), ["CCO", "O"]);
+
+        // MessagePack keeps everything JSON does
+        let path = dir.join("small.msgpack");
+        assert_eq!(DatasetFormat::from_path(&path), DatasetFormat::MessagePack);
+        ds.save(&path, DatasetFormat::MessagePack).unwrap();
+        let got = Dataset::load(&path).unwrap();
+        assert_eq!(keys(&got), keys(&ds));
+        assert_eq!(got.to_smiles(), ["CCO", "O"]);
+        assert_eq!(
+            DatasetFormat::from_path("a.msgpack.zst"),
+            DatasetFormat::MessagePack
+        );
+
+        // SMILES files keep the entry names
+        let path = dir.join("small.smi");
+        assert_eq!(DatasetFormat::from_path(&path), DatasetFormat::Smi);
+        ds.save(&path, DatasetFormat::Smi).unwrap();
+        let got = Dataset::from_smi(&path).unwrap();
+        assert_eq!(keys(&got), keys(&ds));
+        assert_eq!(got.to_smiles(), ["CCO", "O"]);
+    }
+
+    #[test]
+    fn load_formats() {
+        assert_eq!(DatasetFormat::detect("a.sdf.gz"), Some(DatasetFormat::Sdf));
+        assert_eq!(DatasetFormat::detect("a.tsv"), Some(DatasetFormat::Csv));
+        assert_eq!(DatasetFormat::detect("notes.txt"), None);
+        assert_eq!(DatasetFormat::from_path("notes.txt"), DatasetFormat::Json);
+
+        let got = Dataset::load("testfiles/small.smi").unwrap();
+        let want = Dataset::from_smi("testfiles/small.smi").unwrap();
+        assert_eq!(got.to_smiles(), want.to_smiles());
+        let got = Dataset::load("testfiles/registration.csv").unwrap();
+        let path = std::env::temp_dir().join("chomper-save.sdf");
+        assert!(got.save(&path, DatasetFormat::Sdf).is_err());
+        assert_eq!(got.to_smiles(), ["CCO", "C(=O)O"]);
+    }
+
+    #[test]
+    fn compressed() {
+        let got = Dataset::load("testfiles/small.json.gz").unwrap();
+        assert_eq!(got.to_smiles(), ["CCO", "O"]);
+    }
+
+    #[test]
+    fn provenance() {
+        let got = Dataset::load("testfiles/opt.json")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let want = Provenance {
+            file: Some("testfiles/opt.json".into()),
+            dataset_key: "https://api.qcarchive.molssi.org:443/".to_owned(),
+            record_id: Some("104321073".to_owned()),
+        };
+        assert!(got.iter().any(|s| s.provenance.as_ref() == Some(&want)));
+        assert!(got.iter().all(|s| s.provenance.is_some()));
+
+        // a dataset read from a stream has no file
+        let got = Dataset::from_reader(
+            r#"{"entries": {"a": [{"cmiles": "C"}]}}"#.as_bytes(),
+        )
+        .unwrap();
+        assert!(got.entries["a"][0].file.is_none());
+    }
+
+    #[test]
+    fn builder() {
+        let ds = DatasetBuilder::new()
+            .add_entry("b", ["CCO", "CO"])
+            .add_entry("a", vec!["O".to_owned()])
+            .add_entry("b", ["C"])
+            .build();
+        assert_eq!(ds.len(), 4);
+        assert!(DatasetBuilder::new().build().is_empty());
+        assert!(ds.entries["a"][0].record_id().is_none());
+        assert_eq!(ds.to_smiles(), ["O", "CCO", "CO", "C"]);
+    }
+
+    #[test]
+    fn split() {
+        let ds: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [{"cmiles": "C"}, {"cmiles": "N"}, {"cmiles": "O"}],
+                "b": [{"cmiles": "S"}, {"cmiles": "P"}]
+            }}"#,
+        )
+        .unwrap();
+        let (train, test) = ds.split(0.6, 3);
+        assert_eq!((train.len(), test.len()), (3, 2));
+        let mut all = train.clone().to_smiles();
+        all.extend(test.clone().to_smiles());
+        all.sort();
+        assert_eq!(all, ["C", "N", "O", "P", "S"]);
+        let (again, _) = ds.split(0.6, 3);
+        assert_eq!(again.to_smiles(), train.to_smiles());
+    }
+
+    #[test]
+    fn geometries() {
+        let json = r#"{"entries": {"a": [
+            {
+                "cmiles": "[H:2][H:1]",
+                "energies": [-1.1, -1.17],
+                "final_molecule": {
+                    "symbols": ["H", "H"],
+                    "geometry": [0.0, 0.0, 0.0, 0.0, 0.0, 1.4],
+                    "identifiers": {}
+                }
+            },
+            {"cmiles": "[H][H]"}
+        ]}}"#;
+        let got = Dataset::from_reader(json.as_bytes()).unwrap();
+        let mut buf = Vec::new();
+        got.to_writer(&mut buf).unwrap();
+        let got = Dataset::from_reader(buf.as_slice()).unwrap();
+        let recs = &got.entries["a"];
+        assert_eq!(recs[0].geometry().unwrap().symbols, ["H", "H"]);
+        assert_eq!(recs[0].energy(), Some(-1.17));
+        let c = recs[0].conformer().unwrap();
+        assert!(
+            (c.distance(0, 1) - 1.4 * conformer::BOHR_TO_ANGSTROM).abs()
+                < 1e-12
+        );
+        assert_eq!(c.energy(), Some(-1.17));
+        assert!(recs[1].geometry().is_none());
+        assert!(recs[1].conformer().is_none());
+    }
+
+    #[test]
+    fn dihedrals() {
+        // torsion drive records carry their dihedrals through to the SMILES
+        let json = r#"{"entries": {"a": [
+            {"cmiles": "[C:1][C:2][C:3][C:4]", "dihedrals": [[0, 1, 2, 3]]}
+        ]}}"#;
+        let got = Dataset::from_reader(json.as_bytes()).unwrap();
+        let mut buf = Vec::new();
+        got.to_writer(&mut buf).unwrap();
+        let got = Dataset::from_reader(buf.as_slice()).unwrap();
+        assert_eq!(got.entries["a"][0].dihedrals(), [[0, 1, 2, 3]]);
+        assert_eq!(got.to_smiles_records()[0].dihedrals, [[0, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn sorted_entries() {
+        let ds: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "b": [{"cmiles": "C"}, {"cmiles": "N"}],
+                "a": [{"cmiles": "O"}],
+                "c": [{"cmiles": "S"}]
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(ds.to_smiles(), ["O", "C", "N", "S"]);
+    }
+
+    #[test]
+    fn tags() {
+        let mut ds: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [
+                    {"cmiles": "C", "record_id": "1"},
+                    {"cmiles": "N", "record_id": "2", "tags": ["outlier"]}
+                ],
+                "b": [{"cmiles": "O", "record_id": "3"}]
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(ds.tag("ring-strain", |_, id| id != Some("2")), 2);
+        assert_eq!(ds.tag("ring-strain", |key, _| key == "b"), 0);
+        assert_eq!(ds.tag("outlier", |_, id| id == Some("3")), 1);
+        assert_eq!(
+            ds.tag_counts().into_iter().collect::<Vec<_>>(),
+            [("outlier", 2), ("ring-strain", 2)]
+        );
+        let sub = ds.select_tags(&["outlier"], &["ring-strain"]);
+        assert_eq!(sub.to_smiles(), ["N"]);
+        let sub = ds.select_tags(&["ring-strain"], &[]);
+        assert_eq!(sub.entries.len(), 2);
+
+        // tags survive a round trip through JSON
+        let mut json = Vec::new();
+        ds.to_writer(&mut json).unwrap();
+        let mut back: Dataset = serde_json::from_slice(&json).unwrap();
+        assert_eq!(back.tag_counts(), ds.tag_counts());
+        assert_eq!(back.untag("outlier"), 2);
+        assert_eq!(back.select_tags(&["outlier"], &[]).entries.len(), 0);
+    }
+
+    #[test]
+    fn apply_update() {
+        let mut ds: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [
+                    {"cmiles": "C", "record_id": "1"},
+                    {"cmiles": "N", "record_id": "2", "inchi_key": "NNN"}
+                ]
+            }}"#,
+        )
+        .unwrap();
+        let update: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [
+                    {"cmiles": "C", "record_id": "1"},
+                    {"cmiles": "[NH3]", "record_id": "2", "inchi_key": "NNN"},
+                    {"cmiles": "O", "record_id": "2"}
+                ],
+                "b": [
+                    {"cmiles": "C", "record_id": "3"},
+                    {"cmiles": "C", "record_id": "3"},
+                    {"cmiles": "[CH4:1]", "record_id": "1"},
+                    {"cmiles": "C1CC", "record_id": "4"}
+                ]
+            }}"#,
+        )
+        .unwrap();
+        let (delta, errors) = ds.apply_update(update);
+        // the same record ID and InChIKey make the same record, even with a
+        // different cmiles, as does the same molecule written differently, but
+        // a new structure under an old ID is new
+        assert_eq!(delta.entries["a"].len(), 1);
+        assert_eq!(delta.to_smiles(), ["O", "C"]);
+        assert_eq!(ds.to_smiles(), ["C", "N", "O", "C"]);
+        // the unclosed ring is reported instead of added
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().starts_with("record 4 of b: "));
+    }
+
+    #[test]
+    fn charge_states() {
+        let ds = DatasetBuilder::new()
+            .add_entry("glycine", ["NCC(=O)O"])
+            .add_entry("ring", ["C1CC"])
+            .build();
+        let (got, errors) = ds.enumerate_charge_states(2);
+        assert_eq!(got.entries["glycine"].len(), 2);
+        // the unclosed ring is left out and reported
+        assert!(!got.entries.contains_key("ring"));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().starts_with("record ? of ring: "));
+    }
+
+    #[test]
+    fn merge_diff() {
+        let old: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [{"cmiles": "CCO"}, {"cmiles": "N[C@@H](C)C(=O)O"}]
+            }}"#,
+        )
+        .unwrap();
+        let new: Dataset = serde_json::from_str(
+            r#"{"entries": {
+                "a": [{"cmiles": "OCC"}, {"cmiles": "N[C@H](C)C(=O)O"}],
+                "b": [
+                    {"cmiles": "[O:1]([H:2])[H:3]"},
+                    {"cmiles": "O"},
+                    {"cmiles": "C[C@H](N)C(=O)O"},
+                    {"cmiles": "C1CC", "record_id": "7"}
+                ]
+            }}"#,
+        )
+        .unwrap();
+        // D-alanine and water are new, written either way
+        let (delta, errors) = new.diff(&old);
+        assert_eq!(
+            delta.clone().to_smiles(),
+            ["N[C@H](C)C(=O)O", "[O:1]([H:2])[H:3]", "O"]
+        );
+        // the unclosed ring is reported instead of counting as new
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().starts_with("record 7 of b: "));
+        let mut merged = old.clone();
+        let (added, errors) = merged.merge(new);
+        assert_eq!((added, errors.len()), (2, 1));
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.entries["b"].len(), 1);
+        let (again, errors) = merged.diff(&old);
+        assert!(errors.is_empty());
+        assert!(again.diff(&delta).0.entries.is_empty());
+    }
+
+    #[test]
+    fn dedup() {
+        let mut ds = Dataset::load("testfiles/opt.json").unwrap();
+        let (removed, errors) = ds.dedup_by_inchikey();
+        assert_eq!(removed, 5543 - 1563);
+        assert!(errors.is_empty());
+        let recs = &ds.entries["https://api.qcarchive.molssi.org:443/"];
+        assert_eq!(recs.len(), 1563);
+        assert_eq!(recs[0].record_id.as_deref(), Some("104321073"));
+        assert_eq!(ds.dedup_by_inchikey().0, 0);
+
+        // a record rdkit can't read is kept and reported
+        let mut ds = DatasetBuilder::new()
+            .add_entry("a", ["CCO", "C1CC", "OCC"])
+            .build();
+        let (removed, errors) = ds.dedup_by_inchikey();
+        assert_eq!((removed, errors.len()), (1, 1));
+        assert_eq!(ds.to_smiles(), ["CCO", "C1CC"]);
+    }
 }
@@ -1,12 +1,953 @@
-use chomper::{rdkit::to_smarts, Dataset};
+use std::{io::Read, path::Path, process::exit, time::Duration};
 
-fn main() {
-    let mut smiles = Dataset::load("testfiles/opt.json").unwrap().to_smiles();
-    smiles.dedup();
+use chomper::{
+    canonical::unique_smiles,
+    catalog::PatternCatalog,
+    config::{ChomperConfig, PRESETS},
+    conformance::run,
+    diff::diff,
+    filter::{apply, check_elements, parse_elements, Filter},
+    format::{format_smarts, FormatOptions},
+    molecule::Molecule,
+    primitives::primitive_stats,
+    qcfractal::{Client, QCARCHIVE},
+    rdkit::to_smarts,
+    report::{coverage_table, diff_table, stats_tables, Report},
+    ringsizes::{ring_sizes, MACROCYCLE_SIZE},
+    ringsystems::ring_templates,
+    schema::write_jsonl,
+    smarts::{scan_debug, InputKind, Smarts},
+    timing::{Stage, Timings},
+    torsionlib::TorsionLibrary,
+    watch::{WatchConfig, Watcher},
+    Dataset, DatasetFormat,
+};
+
+const USAGE: &str = "usage: chomper COMMAND [ARGS]
+
+a DATASET is a QCArchive-style .json file, an .sdf or .mol file, whose
+records are grouped into entries by their name property or title, a .csv
+or .tsv file with a smiles column, a .smi file of SMILES, each optionally
+followed by a name, a .msgpack file written by chomper, or a .parquet file
+with a smiles column, which requires the Python module pyarrow. any of
+these but Parquet may be compressed with gzip or zstd, with a .gz or .zst
+suffix. any DATASET or INPUT may be -, to read it from stdin. a dataset
+read from stdin is JSON, while convert also accepts SMILES lines there. an
+OUTPUT dataset is JSON unless it ends with .parquet, .smi, or .msgpack
+
+check, parse, report, and watch also accept --preset NAME, which selects
+a named set of options that the command's own flags then adjust. run
+chomper presets for the list
+
+commands:
+    report [--markdown] [--timings] CATALOG DATASET [OUTPUT]
+        write an HTML report of the coverage of DATASET by the patterns in
+        CATALOG to OUTPUT, or to stdout if OUTPUT is omitted. with
+        --markdown, write Markdown instead of HTML. with --timings, print the
+        time spent in each stage to stderr
+
+    check [--examples N] [--strict-maps | --strict-maps-h]
+          [--timeout SECONDS] DATASET
+        compare every record in DATASET to rdkit's interpretation of it and
+        print the number of failing records of each kind, most common first,
+        with up to N (default 3) example SMILES for each. with --strict-maps,
+        also fail records whose heavy atoms don't all have unique, contiguous
+        atom maps, and with --strict-maps-h, require maps on hydrogens too.
+        with --timeout, fail records that take longer than SECONDS to check.
+        a panic while checking a record fails only that record
+
+    convert [--ids] INPUT
+        convert SMILES to SMARTS with rdkit and print the result. INPUT is a
+        dataset, a file with one SMILES per line, or a single SMILES. each
+        molecule is converted once, however many times it appears, and the
+        number of repeats skipped is printed to stderr. with --ids, INPUT
+        must be a dataset, and every record is converted and printed after
+        its entry name and record ID, separated by tabs
+
+    diff [--smiles] [--markdown] LEFT RIGHT
+        print the differences in atoms and bonds between the SMARTS LEFT and
+        RIGHT, or between each pair of lines if both are files. with
+        --smiles, the inputs are SMILES and are converted to SMARTS with
+        rdkit first. with --markdown, print them as a Markdown table. exits
+        with status 1 if there are any differences
+
+    export-graphs [--timings] DATASET [--out OUTPUT]
+        parse every record in DATASET and write the molecules to OUTPUT, or
+        to stdout if OUTPUT is omitted, as JSON Lines with one molecule per
+        line. --timings is the same as for report
+
+    filter --elements ELEMENTS DATASET [--out OUTPUT]
+        write the molecules in DATASET containing only the comma-separated
+        ELEMENTS, like H,C,N,O, to OUTPUT or stdout in the same format as
+        export-graphs, and summarize the rejected records on stderr. the name
+        openff stands for the elements supported by OpenFF
+
+    fetch [--server URL] [--type TYPE] [--spec SPEC] NAME [--out OUTPUT]
+        download the dataset NAME from the QCFractal server at URL, the
+        public QCArchive server by default, and write its records computed
+        with the specification SPEC, default by default, to OUTPUT, or to
+        stdout. TYPE is the dataset type, optimization by default
+
+    format [--compact] INPUT
+        print the SMARTS or SMIRKS INPUT in normal form, or each line of
+        INPUT if it is a file. with --compact, omit the spaces around
+        reaction arrows
+
+    merge [--new-since OLD] DATASET... [--out OUTPUT]
+        combine the DATASETs, adding the records of each one whose molecules
+        are not already in the ones before it, compared by canonical SMILES,
+        and write the result to OUTPUT, or to stdout. with --new-since, write
+        only the records whose molecules are not in the dataset OLD. the
+        number of records kept from each DATASET is printed to stderr
+
+    parse [--smarts] [--warnings] INPUT
+        parse the SMARTS INPUT, or each line of INPUT if it is a file, and
+        print its atoms and bonds. with --smarts, omitted H counts and bonds
+        keep their SMARTS meaning instead of being filled in as in SMILES.
+        with --warnings, also print any assumptions made to stderr
+
+    presets
+        list the names accepted by --preset
+
+    primitives INPUT
+        print how often each primitive, like an H count, charge, or ring
+        flag, decorates the atoms of each element in the SMARTS or SMIRKS
+        INPUT, or in each line of INPUT if it is a file
+
+    ring-sizes [--macrocycle N] [--tag TAG] DATASET [--out OUTPUT]
+        print the number of rings of each size in DATASET and the number of
+        molecules containing them, followed by the macrocycles, molecules
+        with a ring of at least N atoms, 12 by default. with --tag, also add
+        TAG to the records of the macrocycles and write the tagged dataset
+        to OUTPUT, or to stdout, printing the summary to stderr instead
+
+    ring-templates DATASET
+        print each distinct ring system in DATASET as a SMARTS pattern, with
+        the number of times it occurs and the number of molecules containing
+        it, from most to least common
+
+    scan INPUT
+        print the tokens the SMARTS INPUT, or each line of INPUT if it is a
+        file, is split into
+
+    select [--tag TAG]... [--without TAG]... DATASET [--out OUTPUT]
+        write the records in DATASET that have every TAG and none of the
+        --without tags to OUTPUT, or to stdout, as a dataset
+
+    split [--fraction F] [--seed N] [--scaffold] DATASET TRAIN TEST
+        split the records of DATASET at random into the datasets TRAIN,
+        holding a fraction F (default 0.8) of them, and TEST, holding the
+        rest. the same seed N (default 0) always gives the same split. with
+        --scaffold, keep molecules with the same ring framework together
+
+    stats [--json | --markdown] DATASET
+        print the number of molecules, duplicates, and molecules rdkit can't
+        read in DATASET and tables of the atoms of each element and the
+        molecules with each heavy-atom count and net charge. with --json,
+        print them as JSON instead, and with --markdown, as Markdown tables
+
+    tag [--entry KEY]... [--record ID]... TAG DATASET [--out OUTPUT]
+        add TAG to the records in DATASET in any of the entries KEY or with
+        any of the record IDs ID, or to every record if neither is given,
+        and write the tagged dataset to OUTPUT, or to stdout
+
+    tags DATASET
+        print the number of records in DATASET with each tag
+
+    torsion-lib LIBRARY DATASET
+        assign every rotatable bond in DATASET to a class of the TorsionLib
+        XML file LIBRARY, and print the number of bonds in each class
+
+    watch [--catalog CATALOG] [--interval SECONDS] DIR
+        poll DIR every SECONDS (default 5) for new files in any of the
+        DATASET formats. each one is parsed and written to NAME.chomper.json
+        alongside it, and if CATALOG is given, its coverage is also written
+        to NAME.report.html";
+
+fn die(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{msg}");
+    exit(1);
+}
+
+/// all of stdin
+fn read_stdin() -> String {
+    let mut ret = String::new();
+    std::io::stdin()
+        .read_to_string(&mut ret)
+        .unwrap_or_else(|e| die(format!("failed to read stdin: {e}")));
+    ret
+}
+
+/// the dataset at `path`, read with [Dataset::load], or the JSON dataset read
+/// from stdin if `path` is `-`
+fn load_dataset(path: &str) -> Dataset {
+    let ds = if path == "-" {
+        Dataset::from_reader(std::io::stdin().lock())
+    } else {
+        Dataset::load(path)
+    };
+    ds.unwrap_or_else(|e| die(format!("failed to load {path}: {e}")))
+}
+
+/// the config chosen by `--preset NAME` in `args`, or the default if there
+/// is none, and the rest of `args`
+fn preset(args: &[String]) -> (ChomperConfig, Vec<String>) {
+    let mut config = ChomperConfig::default();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--preset" {
+            let name = args.next().unwrap_or_else(|| die(USAGE));
+            config = ChomperConfig::preset(name).unwrap_or_else(|e| die(e));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (config, rest)
+}
+
+fn presets(args: &[String]) {
+    if !args.is_empty() {
+        die(USAGE);
+    }
+    for name in PRESETS {
+        println!("{name}");
+    }
+}
+
+/// the contents of `input` if it names a file, all of stdin if it is `-`, or
+/// else `input` itself
+fn read_input(input: &str) -> String {
+    if input == "-" {
+        read_stdin()
+    } else if Path::new(input).is_file() {
+        std::fs::read_to_string(input)
+            .unwrap_or_else(|e| die(format!("failed to read {input}: {e}")))
+    } else {
+        input.to_owned()
+    }
+}
+
+fn report(args: &[String]) {
+    let (config, args) = preset(args);
+    let markdown = args.iter().any(|a| a == "--markdown");
+    let show_timings = args.iter().any(|a| a == "--timings");
+    let args: Vec<&String> = args
+        .iter()
+        .filter(|a| *a != "--markdown" && *a != "--timings")
+        .collect();
+    let [catalog, dataset, rest @ ..] = args.as_slice() else {
+        die(USAGE);
+    };
+    let mut timings = Timings::default();
+    let catalog = PatternCatalog::load(catalog)
+        .unwrap_or_else(|e| die(format!("failed to load {catalog}: {e}")));
+    let mols = timings
+        .time(Stage::Load, || load_dataset(dataset))
+        .parse_timed(&mut timings)
+        .unwrap_or_else(|e| die(format!("failed to parse {dataset}: {e}")))
+        .iter()
+        .map(Molecule::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| die(format!("failed to parse {dataset}: {e}")));
+    let matrix = catalog.match_all_timed(&mols, &config.matching, &mut timings);
+    if show_timings {
+        eprint!("{timings}");
+    }
+    let mut report = Report::new(format!("Coverage of {dataset}"));
+    report.push(coverage_table(&catalog, &matrix, !markdown));
+    let out = if markdown {
+        report.to_markdown()
+    } else {
+        report.to_html()
+    };
+    match rest.first() {
+        Some(path) => std::fs::write(path, out)
+            .unwrap_or_else(|e| die(format!("failed to write {path}: {e}"))),
+        None => print!("{out}"),
+    }
+}
+
+fn convert(args: &[String]) {
+    let (ids, input) = match args {
+        [flag, input] if flag == "--ids" => (true, input),
+        [input] => (false, input),
+        _ => die(USAGE),
+    };
+    if ids {
+        if input != "-" && DatasetFormat::detect(input).is_none() {
+            die(format!("{input} is not a dataset"));
+        }
+        for rec in load_dataset(input).to_smiles_records() {
+            let id = rec.record_id.unwrap_or_default();
+            match to_smarts(rec.smiles) {
+                Ok(smarts) => println!("{}\t{id}\t{smarts}", rec.entry),
+                Err(e) => eprintln!("record {id} of {}: {e}", rec.entry),
+            }
+        }
+        return;
+    }
+    let smiles = if DatasetFormat::detect(input).is_some() {
+        load_dataset(input).to_smiles()
+    } else {
+        let text = read_input(input);
+        if input == "-" && text.trim_start().starts_with('{') {
+            Dataset::from_reader(text.as_bytes())
+                .unwrap_or_else(|e| die(format!("failed to load stdin: {e}")))
+                .to_smiles()
+        } else {
+            text.lines().map(str::to_owned).collect()
+        }
+    };
+    let (smiles, n_dropped, errors) = unique_smiles(smiles);
+    for e in errors {
+        eprintln!("{e}");
+    }
+    if n_dropped > 0 {
+        eprintln!("skipped {n_dropped} duplicate molecules");
+    }
     for smile in smiles {
-        println!("{:.80}", to_smarts(smile));
+        match to_smarts(smile) {
+            Ok(smarts) => println!("{smarts}"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+fn parse(args: &[String]) {
+    let (config, args) = preset(args);
+    let mut kind = config.input_kind;
+    let mut show_warnings = false;
+    let mut input = None;
+    for arg in &args {
+        match arg.as_str() {
+            "--smarts" => kind = InputKind::Smarts,
+            "--warnings" => show_warnings = true,
+            _ if input.is_none() => input = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(input) = input else {
+        die(USAGE);
+    };
+    for (i, line) in read_input(input).lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (smarts, warnings) =
+            match Smarts::parse_with_warnings(line.to_owned(), kind) {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("line {}: {e}", i + 1);
+                    continue;
+                }
+            };
+        println!("{line}");
+        for (j, atom) in smarts.atoms.iter().enumerate() {
+            println!("    atom {j} {atom}");
+        }
+        for bond in &smarts.bonds {
+            println!("    bond {bond}");
+        }
+        if show_warnings {
+            for w in warnings.iter() {
+                eprintln!("line {}: {w}", i + 1);
+            }
+        }
+    }
+}
+
+fn scan(args: &[String]) {
+    let [input] = args else {
+        die(USAGE);
+    };
+    for (i, line) in read_input(input).lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match scan_debug(line.to_owned()) {
+            Ok(tokens) => println!("{}", tokens.join(" ")),
+            Err(e) => eprintln!("line {}: {e}", i + 1),
+        }
+    }
+}
+
+fn primitives(args: &[String]) {
+    let [input] = args else {
+        die(USAGE);
+    };
+    let input = read_input(input);
+    let lines = input.lines().map(str::trim).filter(|l| !l.is_empty());
+    print!("{}", primitive_stats(lines));
+}
+
+fn diff_cmd(args: &[String]) {
+    let smiles = args.iter().any(|a| a == "--smiles");
+    let markdown = args.iter().any(|a| a == "--markdown");
+    let args: Vec<&String> = args
+        .iter()
+        .filter(|a| *a != "--smiles" && *a != "--markdown")
+        .collect();
+    let [left, right] = args.as_slice() else {
+        die(USAGE);
+    };
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .unwrap_or_else(|e| die(format!("failed to read {path}: {e}")))
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let files = std::path::Path::new(left.as_str()).is_file()
+        && std::path::Path::new(right.as_str()).is_file();
+    let pairs: Vec<(String, String)> = if files {
+        let (l, r) = (read(left), read(right));
+        if l.len() != r.len() {
+            die(format!(
+                "{left} has {} entries but {right} has {}",
+                l.len(),
+                r.len()
+            ));
+        }
+        l.into_iter().zip(r).collect()
+    } else {
+        vec![(left.to_string(), right.to_string())]
+    };
+    let parse = |s: String| {
+        let smarts = if smiles { to_smarts(s) } else { Ok(s) };
+        smarts
+            .and_then(Smarts::try_parse)
+            .unwrap_or_else(|e| die(format!("failed to parse: {e}")))
+    };
+    let diffs: Vec<_> = pairs
+        .into_iter()
+        .enumerate()
+        .map(|(i, (l, r))| {
+            (format!("entry {}", i + 1), diff(&parse(l), &parse(r)))
+        })
+        .filter(|(_, d)| !d.is_empty())
+        .collect();
+    if markdown {
+        let mut report =
+            Report::new(format!("Differences between {left} and {right}"));
+        report.push(diff_table(&diffs));
+        print!("{}", report.to_markdown());
+    } else {
+        for (name, d) in &diffs {
+            if files {
+                println!("{name}:");
+            }
+            print!("{d}");
+        }
+    }
+    if !diffs.is_empty() {
+        exit(1);
+    }
+}
+
+fn export_graphs(args: &[String]) {
+    let mut dataset = None;
+    let mut out = None;
+    let mut show_timings = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = Some(args.next().unwrap_or_else(|| die(USAGE))),
+            "--timings" => show_timings = true,
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(dataset) = dataset else {
+        die(USAGE);
+    };
+    let mut timings = Timings::default();
+    let mols = timings
+        .time(Stage::Load, || load_dataset(dataset))
+        .parse_timed(&mut timings)
+        .unwrap_or_else(|e| die(format!("failed to parse {dataset}: {e}")));
+    if show_timings {
+        eprint!("{timings}");
+    }
+    let res = match out {
+        Some(path) => std::fs::File::create(path)
+            .map_err(Into::into)
+            .and_then(|f| write_jsonl(std::io::BufWriter::new(f), &mols)),
+        None => write_jsonl(std::io::stdout().lock(), &mols),
+    };
+    if let Err(e) = res {
+        die(format!("failed to write graphs: {e}"));
+    }
+}
+
+fn filter(args: &[String]) {
+    let mut dataset = None;
+    let mut out = None;
+    let mut elements = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--out" => out = Some(value()),
+            "--elements" => {
+                elements =
+                    Some(parse_elements(value()).unwrap_or_else(|e| die(e)))
+            }
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let (Some(dataset), Some(elements)) = (dataset, elements) else {
+        die(USAGE);
+    };
+    let mols = load_dataset(dataset)
+        .molecules()
+        .unwrap_or_else(|e| die(format!("failed to convert {dataset}: {e}")));
+    let report = check_elements(&mols, &elements);
+    if !report.passed() {
+        eprint!("{report}");
+    }
+    let kept: Vec<Smarts> = apply(mols, &Filter::Elements(elements))
+        .iter()
+        .map(Smarts::from)
+        .collect();
+    let res = match out {
+        Some(path) => std::fs::File::create(path)
+            .map_err(Into::into)
+            .and_then(|f| write_jsonl(std::io::BufWriter::new(f), &kept)),
+        None => write_jsonl(std::io::stdout().lock(), &kept),
+    };
+    if let Err(e) = res {
+        die(format!("failed to write molecules: {e}"));
+    }
+}
+
+fn check(args: &[String]) {
+    let (config, args) = preset(args);
+    let mut n_examples = 3;
+    let mut options = config.conformance;
+    let mut dataset = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--examples" => {
+                let n = args.next().unwrap_or_else(|| die(USAGE));
+                n_examples = n.parse().unwrap_or_else(|e| {
+                    die(format!("invalid --examples {n}: {e}"))
+                });
+            }
+            "--timeout" => {
+                let t = args.next().unwrap_or_else(|| die(USAGE));
+                let secs = t
+                    .parse()
+                    .map_err(|e: std::num::ParseFloatError| e.to_string())
+                    .and_then(|t| {
+                        Duration::try_from_secs_f64(t)
+                            .map_err(|e| e.to_string())
+                    });
+                let secs = secs.unwrap_or_else(|e| {
+                    die(format!("invalid --timeout {t}: {e}\n\n{USAGE}"))
+                });
+                options.timeout = Some(secs);
+            }
+            "--strict-maps" => options.strict_maps = Some(false),
+            "--strict-maps-h" => options.strict_maps = Some(true),
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(dataset) = dataset else {
+        die(USAGE);
+    };
+    let ds = load_dataset(dataset);
+    let summary = run(&ds, &options);
+    print!("{}", summary.by_kind(n_examples));
+}
+
+fn fetch(args: &[String]) {
+    let mut server = QCARCHIVE;
+    let mut dataset_type = "optimization";
+    let mut spec = "default";
+    let mut name = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--server" => server = value(),
+            "--type" => dataset_type = value(),
+            "--spec" => spec = value(),
+            "--out" => out = Some(value()),
+            _ if name.is_none() => name = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(name) = name else {
+        die(USAGE);
+    };
+    let client = Client::new(server);
+    let ds = client
+        .fetch(dataset_type, name, spec)
+        .unwrap_or_else(|e| die(format!("failed to fetch {name}: {e}")));
+    save(&ds, out);
+}
+
+fn format_cmd(args: &[String]) {
+    let options = FormatOptions {
+        compact: args.iter().any(|a| a == "--compact"),
+    };
+    let args: Vec<&String> =
+        args.iter().filter(|a| *a != "--compact").collect();
+    let [input] = args.as_slice() else {
+        die(USAGE);
+    };
+    let lines = read_input(input);
+    for (i, line) in lines.lines().enumerate() {
+        if line.trim().is_empty() {
+            println!();
+            continue;
+        }
+        match format_smarts(line, &options) {
+            Ok(s) => println!("{s}"),
+            Err(e) => die(format!("line {}: {e}", i + 1)),
+        }
+    }
+}
+
+/// write `ds` to `out`, or to stdout if it is `None`, in the format given by
+/// the extension of `out`, defaulting to JSON
+fn save(ds: &Dataset, out: Option<&String>) {
+    let res = match out {
+        Some(path) => ds.save(path, DatasetFormat::from_path(path)),
+        None => ds.to_writer(std::io::stdout().lock()),
+    };
+    if let Err(e) = res {
+        die(format!("failed to write dataset: {e}"));
+    }
+}
+
+fn merge(args: &[String]) {
+    let mut inputs = Vec::new();
+    let mut old = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--new-since" => old = Some(value()),
+            "--out" => out = Some(value()),
+            _ => inputs.push(arg),
+        }
+    }
+    let Some((first, rest)) = inputs.split_first() else {
+        die(USAGE);
+    };
+    let mut ds = load_dataset(first);
+    eprintln!("{first}: {} records", ds.len());
+    for input in rest {
+        let (added, errors) = ds.merge(load_dataset(input));
+        for e in &errors {
+            eprintln!("skipping {e}");
+        }
+        eprintln!("{input}: {added} new records");
+    }
+    if let Some(old) = old {
+        let errors;
+        (ds, errors) = ds.diff(&load_dataset(old));
+        for e in &errors {
+            eprintln!("skipping {e}");
+        }
+        eprintln!("{} records not in {old}", ds.len());
+    }
+    save(&ds, out);
+}
+
+fn select(args: &[String]) {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    let mut dataset = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--tag" => include.push(value().as_str()),
+            "--without" => exclude.push(value().as_str()),
+            "--out" => out = Some(value()),
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(dataset) = dataset else {
+        die(USAGE);
+    };
+    let ds = load_dataset(dataset);
+    save(&ds.select_tags(&include, &exclude), out);
+}
+
+fn split(args: &[String]) {
+    let mut fraction = 0.8;
+    let mut seed = 0;
+    let mut scaffold = false;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--fraction" => {
+                let f = value();
+                fraction = f.parse().unwrap_or_else(|e| {
+                    die(format!("invalid --fraction {f}: {e}"))
+                });
+            }
+            "--seed" => {
+                let n = value();
+                seed = n.parse().unwrap_or_else(|e| {
+                    die(format!("invalid --seed {n}: {e}"))
+                });
+            }
+            "--scaffold" => scaffold = true,
+            _ => paths.push(arg),
+        }
+    }
+    let [dataset, train_path, test_path] = paths.as_slice() else {
+        die(USAGE);
+    };
+    let ds = load_dataset(dataset);
+    let (train, test) = if scaffold {
+        ds.split_by_scaffold(fraction, seed).unwrap_or_else(|e| {
+            die(format!("failed to convert {dataset}: {e}"))
+        })
+    } else {
+        ds.split(fraction, seed)
+    };
+    eprintln!("{} training and {} test records", train.len(), test.len());
+    save(&train, Some(train_path));
+    save(&test, Some(test_path));
+}
+
+fn stats(args: &[String]) {
+    let json = args.iter().any(|a| a == "--json");
+    let markdown = args.iter().any(|a| a == "--markdown");
+    let args: Vec<&String> = args
+        .iter()
+        .filter(|a| *a != "--json" && *a != "--markdown")
+        .collect();
+    let [dataset] = args.as_slice() else {
+        die(USAGE);
+    };
+    let stats = load_dataset(dataset)
+        .stats()
+        .unwrap_or_else(|e| die(format!("failed to convert {dataset}: {e}")));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+    } else if markdown {
+        let mut report = Report::new(format!("Statistics of {dataset}"));
+        for table in stats_tables(&stats) {
+            report.push(table);
+        }
+        print!("{}", report.to_markdown());
+    } else {
+        print!("{stats}");
+    }
+}
+
+fn tag(args: &[String]) {
+    let mut entries = Vec::new();
+    let mut records = Vec::new();
+    let mut positional = Vec::new();
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--entry" => entries.push(value().as_str()),
+            "--record" => records.push(value().as_str()),
+            "--out" => out = Some(value()),
+            _ => positional.push(arg),
+        }
+    }
+    let [tag, dataset] = positional.as_slice() else {
+        die(USAGE);
+    };
+    let mut ds = load_dataset(dataset);
+    let all = entries.is_empty() && records.is_empty();
+    let n = ds.tag(tag, |key, id| {
+        all || entries.contains(&key)
+            || id.is_some_and(|id| records.contains(&id))
+    });
+    eprintln!("tagged {n} records with {tag}");
+    save(&ds, out);
+}
+
+fn tags(args: &[String]) {
+    let [dataset] = args else {
+        die(USAGE);
+    };
+    let ds = load_dataset(dataset);
+    for (tag, count) in ds.tag_counts() {
+        println!("{count:>8} {tag}");
+    }
+}
+
+fn ring_sizes_cmd(args: &[String]) {
+    let mut macrocycle = MACROCYCLE_SIZE;
+    let mut tag = None;
+    let mut dataset = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--macrocycle" => {
+                let n = value();
+                macrocycle = n.parse().unwrap_or_else(|e| {
+                    die(format!("invalid --macrocycle {n}: {e}"))
+                });
+            }
+            "--tag" => tag = Some(value()),
+            "--out" => out = Some(value()),
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(dataset) = dataset else {
+        die(USAGE);
+    };
+    let mut ds = load_dataset(dataset);
+    let mols = ds
+        .clone()
+        .molecules()
+        .unwrap_or_else(|e| die(format!("failed to convert {dataset}: {e}")));
+    let sizes = ring_sizes(&mols, macrocycle);
+    let Some(tag) = tag else {
+        print!("{sizes}");
+        return;
+    };
+    eprint!("{sizes}");
+    let n = sizes.tag_macrocycles(&mut ds, tag);
+    eprintln!("tagged {n} records with {tag}");
+    save(&ds, out);
+}
+
+fn ring_templates_cmd(args: &[String]) {
+    let [dataset] = args else {
+        die(USAGE);
+    };
+    let mols = load_dataset(dataset)
+        .molecules()
+        .unwrap_or_else(|e| die(format!("failed to convert {dataset}: {e}")));
+    print!("{}", ring_templates(&mols));
+}
+
+fn torsion_lib(args: &[String]) {
+    let [library, dataset] = args else {
+        die(USAGE);
+    };
+    let library = TorsionLibrary::load(library)
+        .unwrap_or_else(|e| die(format!("failed to load {library}: {e}")));
+    let mols = load_dataset(dataset)
+        .molecules()
+        .unwrap_or_else(|e| die(format!("failed to convert {dataset}: {e}")));
+    print!("{}", library.class_counts(&mols));
+}
+
+fn watch(args: &[String]) {
+    let (preset, args) = preset(args);
+    let mut config = WatchConfig {
+        options: preset.matching,
+        ..Default::default()
+    };
+    let mut dir = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| die(USAGE));
+        match arg.as_str() {
+            "--catalog" => {
+                let path = value();
+                config.catalog =
+                    Some(PatternCatalog::load(path).unwrap_or_else(|e| {
+                        die(format!("failed to load {path}: {e}"))
+                    }));
+            }
+            "--interval" => {
+                let secs: f64 = value().parse().unwrap_or_else(|_| die(USAGE));
+                config.interval = Duration::try_from_secs_f64(secs)
+                    .unwrap_or_else(|_| die(USAGE));
+            }
+            _ if dir.is_none() => dir = Some(arg),
+            _ => die(USAGE),
+        }
+    }
+    let Some(dir) = dir else {
+        die(USAGE);
+    };
+    let mut watcher = Watcher::new(dir, config);
+    let res = watcher.run(|path, outcome| match outcome {
+        Ok(outputs) => {
+            for out in outputs {
+                eprintln!("{} -> {}", path.display(), out.display());
+            }
+        }
+        Err(e) => eprintln!("failed to process {}: {e}", path.display()),
+    });
+    if let Err(e) = res {
+        die(format!("failed to watch {dir}: {e}"));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("report") => report(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("convert") => convert(&args[1..]),
+        Some("diff") => diff_cmd(&args[1..]),
+        Some("export-graphs") => export_graphs(&args[1..]),
+        Some("filter") => filter(&args[1..]),
+        Some("fetch") => fetch(&args[1..]),
+        Some("format") => format_cmd(&args[1..]),
+        Some("merge") => merge(&args[1..]),
+        Some("parse") => parse(&args[1..]),
+        Some("presets") => presets(&args[1..]),
+        Some("primitives") => primitives(&args[1..]),
+        Some("ring-sizes") => ring_sizes_cmd(&args[1..]),
+        Some("ring-templates") => ring_templates_cmd(&args[1..]),
+        Some("scan") => scan(&args[1..]),
+        Some("select") => select(&args[1..]),
+        Some("split") => split(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        Some("tag") => tag(&args[1..]),
+        Some("tags") => tags(&args[1..]),
+        Some("torsion-lib") => torsion_lib(&args[1..]),
+        Some("watch") => watch(&args[1..]),
+        Some("-h" | "--help") => println!("{USAGE}"),
+        Some(cmd) => die(format!("unknown command {cmd}\n\n{USAGE}")),
+        None => die(USAGE),
     }
 }
 
 // main idea is to read a dataset to get SMILES, convert the smiles to smarts
 // with rdkit (MolToSmarts), then start processing the smarts
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_dataset() {
+        let ds = Dataset::load("testfiles/small.json.gz").unwrap();
+        let path = std::env::temp_dir().join("chomper_cli.msgpack");
+        ds.save(&path, DatasetFormat::MessagePack).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(DatasetFormat::detect(path).is_some());
+        assert_eq!(
+            DatasetFormat::detect("data.msgpack.zst"),
+            Some(DatasetFormat::MessagePack)
+        );
+        assert_eq!(load_dataset(path).to_smiles(), ds.to_smiles());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,650 @@
+//! Substructure matching of a [Query] against a concrete molecule, prepared
+//! as a [Target]. This is a plain backtracking search in the spirit of VF2:
+//! query atoms are visited in depth-first order so that each one after the
+//! first in a component has a mapped neighbor, which limits its candidates to
+//! that neighbor's neighbors in the target. Atoms and bonds are tested with
+//! [AtomExpr::matches] and [BondExpr::matches], so every primitive, including
+//! ring membership and recursive SMARTS, means the same thing here as in
+//! [Query::find_matches], which is built on this search
+
+use std::collections::HashSet;
+
+use crate::{
+    molecule::Molecule,
+    query::{AtomExpr, AtomPrimitive, BondExpr, Expr, Query, Target},
+    timing::{Stage, Timings},
+    Provenance,
+};
+
+/// Options controlling which matches are reported by [find_matches]. The
+/// default mirrors RDKit's `GetSubstructMatches`: matches covering the same set
+/// of target atoms are only reported once, and charges are compared but
+/// chirality is not
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchOptions {
+    /// report only one match for each distinct set of target atoms
+    pub unique: bool,
+    /// require chirality tags to agree when the query atom has one
+    pub use_chirality: bool,
+    /// require formal charges to agree when the query atom has one
+    pub use_charge: bool,
+    /// stop after this many matches have been found
+    pub max_matches: Option<usize>,
+    /// treat matches whose mapped (with a `mol_index`) query atoms land on
+    /// the same target atoms, either forwards or reversed, as equivalent. this
+    /// is how the OpenFF toolkit collapses, for example, the two directions of
+    /// a torsion match
+    pub dedup_symmetric: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            unique: true,
+            use_chirality: false,
+            use_charge: true,
+            max_matches: None,
+            dedup_symmetric: false,
+        }
+    }
+}
+
+impl MatchOptions {
+    /// report every mapping and compare every primitive of the query, as
+    /// [Query::find_matches] does
+    pub fn exhaustive() -> Self {
+        Self {
+            unique: false,
+            use_chirality: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// The result of matching a query against a single target
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Matches {
+    /// the provenance of the target molecule, if any
+    pub provenance: Option<Provenance>,
+    /// each match maps the position of an atom in the query's `atoms` to the
+    /// position of the matching atom in the target's `atoms`
+    pub matches: Vec<Vec<usize>>,
+}
+
+impl Matches {
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+}
+
+/// return a depth-first ordering of the atoms of the graph with adjacency
+/// lists `adj`, along with the already visited neighbor used to reach each
+/// one, if any. the first atom is always atom 0
+fn dfs_order(adj: &[Vec<(usize, usize)>]) -> Vec<(usize, Option<usize>)> {
+    let mut seen = vec![false; adj.len()];
+    let mut ret = Vec::with_capacity(adj.len());
+    for start in 0..adj.len() {
+        if seen[start] {
+            continue;
+        }
+        let mut stack = vec![(start, None)];
+        while let Some((i, parent)) = stack.pop() {
+            if seen[i] {
+                continue;
+            }
+            seen[i] = true;
+            ret.push((i, parent));
+            for &(j, _) in adj[i].iter().rev() {
+                if !seen[j] {
+                    stack.push((j, Some(i)));
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// the bit for atomic number `n` in an element mask. elements beyond 127 all
+/// share the top bit, which is fine for a prefilter
+fn element_bit(n: usize) -> u128 {
+    1 << n.min(127)
+}
+
+/// the atomic number any atom matching `expr` must have, if `expr` says so
+/// outside of any `Or` or `Not`
+fn required_element(expr: &AtomExpr) -> Option<usize> {
+    match expr {
+        Expr::Primitive(AtomPrimitive::AtomicNumber(n)) => Some(*n),
+        Expr::And(es) => es.iter().find_map(required_element),
+        _ => None,
+    }
+}
+
+/// A [Query] prepared for matching against many targets: its atom
+/// expressions with the primitives [MatchOptions] ignores left out, and the
+/// search order over its atoms. Building one once and calling
+/// [CompiledQuery::find_matches] on each target avoids redoing this work
+/// when matching against a whole dataset
+pub struct CompiledQuery {
+    atoms: Vec<AtomExpr>,
+    bonds: Vec<BondExpr>,
+    /// query adjacency as (neighbor, index into `bonds`)
+    adj: Vec<Vec<(usize, usize)>>,
+    order: Vec<(usize, Option<usize>)>,
+    /// whether each query atom carries an atom map
+    tagged: Vec<bool>,
+    /// elements that must be present in any target
+    elements: u128,
+    options: MatchOptions,
+}
+
+impl CompiledQuery {
+    pub fn new(query: &Query, options: MatchOptions) -> Self {
+        let atoms: Vec<AtomExpr> = query
+            .atoms
+            .iter()
+            .map(|a| {
+                a.expr
+                    .without(&|p| match p {
+                        AtomPrimitive::Charge(_) => !options.use_charge,
+                        AtomPrimitive::Chirality(_) => !options.use_chirality,
+                        _ => false,
+                    })
+                    .unwrap_or(Expr::And(Vec::new()))
+            })
+            .collect();
+        let mut adj = vec![Vec::new(); query.atoms.len()];
+        for (k, b) in query.bonds.iter().enumerate() {
+            adj[b.atom1].push((b.atom2, k));
+            adj[b.atom2].push((b.atom1, k));
+        }
+        Self {
+            elements: atoms
+                .iter()
+                .filter_map(required_element)
+                .fold(0, |acc, n| acc | element_bit(n)),
+            atoms,
+            bonds: query.bonds.iter().map(|b| b.expr.clone()).collect(),
+            order: dfs_order(&adj),
+            adj,
+            tagged: query.atoms.iter().map(|a| a.mol_index.is_some()).collect(),
+            options,
+        }
+    }
+
+    /// find all of the matches of `self` in `target`
+    pub fn find_matches(&self, target: &Target) -> Matches {
+        Matches {
+            provenance: target.mol.provenance.clone(),
+            matches: self.search(target, None),
+        }
+    }
+
+    /// whether `self` matches `target` with its first atom on atom `atom`, as
+    /// a recursive SMARTS requires. an empty query never matches
+    pub fn matches_at(&self, target: &Target, atom: usize) -> bool {
+        !self.search(target, Some(atom)).is_empty()
+    }
+
+    /// the matches of `self` in `target`, with the first query atom on
+    /// `root`, if given
+    fn search(&self, target: &Target, root: Option<usize>) -> Vec<Vec<usize>> {
+        let mask = target
+            .mol
+            .atoms
+            .iter()
+            .fold(0, |acc, a| acc | element_bit(a.atomic_number));
+        if self.order.is_empty()
+            || self.atoms.len() > target.mol.atoms.len()
+            || self.elements & mask != self.elements
+        {
+            return Vec::new();
+        }
+        let mut state = State {
+            query: self,
+            target,
+            root,
+            mapping: vec![None; self.atoms.len()],
+            used: vec![false; target.mol.atoms.len()],
+            seen: HashSet::new(),
+            matches: Vec::new(),
+        };
+        state.search(0);
+        state.matches
+    }
+}
+
+struct State<'a, 't> {
+    query: &'a CompiledQuery,
+    target: &'a Target<'t>,
+    /// the only target atom the first query atom may land on, if any
+    root: Option<usize>,
+    /// query position -> target position
+    mapping: Vec<Option<usize>>,
+    used: Vec<bool>,
+    seen: HashSet<Vec<usize>>,
+    matches: Vec<Vec<usize>>,
+}
+
+impl State<'_, '_> {
+    fn done(&self) -> bool {
+        self.query
+            .options
+            .max_matches
+            .is_some_and(|n| self.matches.len() >= n)
+    }
+
+    fn feasible(&self, q: usize, t: usize) -> bool {
+        if self.used[t] || !self.query.atoms[q].matches(self.target, t) {
+            return false;
+        }
+        self.query.adj[q].iter().all(|&(qn, b)| {
+            let Some(tn) = self.mapping[qn] else {
+                return true;
+            };
+            self.target.adj[t].iter().any(|&(u, k)| {
+                u == tn && self.query.bonds[b].matches(self.target, k)
+            })
+        })
+    }
+
+    /// the key used to decide whether `m` duplicates an earlier match
+    fn key(&self, m: &[usize]) -> Vec<usize> {
+        let options = &self.query.options;
+        if options.dedup_symmetric {
+            let tagged: Vec<usize> = m
+                .iter()
+                .zip(&self.query.tagged)
+                .filter(|(_, tagged)| **tagged)
+                .map(|(t, _)| *t)
+                .collect();
+            let mut rev = tagged.clone();
+            rev.reverse();
+            tagged.min(rev)
+        } else if options.unique {
+            let mut k = m.to_vec();
+            k.sort();
+            k
+        } else {
+            m.to_vec()
+        }
+    }
+
+    fn search(&mut self, depth: usize) {
+        if self.done() {
+            return;
+        }
+        if depth == self.query.order.len() {
+            let m: Vec<usize> =
+                self.mapping.iter().flatten().copied().collect();
+            if self.seen.insert(self.key(&m)) {
+                self.matches.push(m);
+            }
+            return;
+        }
+        let (q, parent) = self.query.order[depth];
+        let candidates: Vec<usize> = match (parent, self.root) {
+            (Some(p), _) => {
+                let tp = self.mapping[p].unwrap();
+                self.target.adj[tp].iter().map(|(t, _)| *t).collect()
+            }
+            (None, Some(r)) if depth == 0 => vec![r],
+            (None, _) => (0..self.target.mol.atoms.len()).collect(),
+        };
+        for t in candidates {
+            if self.feasible(q, t) {
+                self.mapping[q] = Some(t);
+                self.used[t] = true;
+                self.search(depth + 1);
+                self.mapping[q] = None;
+                self.used[t] = false;
+            }
+        }
+    }
+}
+
+/// find all of the matches of `query` in `target` subject to `options`. when
+/// matching the same query against many targets, prefer building a
+/// [CompiledQuery] once instead
+pub fn find_matches(
+    query: &Query,
+    target: &Target,
+    options: &MatchOptions,
+) -> Matches {
+    CompiledQuery::new(query, options.clone()).find_matches(target)
+}
+
+/// A sparse matrix of match counts between a list of queries and a list of
+/// molecules. Only nonzero entries are stored, as `(query, molecule, count)`
+/// triples sorted by query and then molecule
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MatchMatrix {
+    pub n_queries: usize,
+    pub n_molecules: usize,
+    pub entries: Vec<(usize, usize, usize)>,
+}
+
+impl MatchMatrix {
+    /// the number of times query `q` matched molecule `m`
+    pub fn get(&self, q: usize, m: usize) -> usize {
+        self.entries
+            .binary_search_by_key(&(q, m), |&(q, m, _)| (q, m))
+            .map(|i| self.entries[i].2)
+            .unwrap_or(0)
+    }
+
+    /// the (molecule, count) pairs for every molecule matched by query `q`
+    pub fn query_hits(
+        &self,
+        q: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let start = self.entries.partition_point(|e| e.0 < q);
+        self.entries[start..]
+            .iter()
+            .take_while(move |e| e.0 == q)
+            .map(|&(_, m, n)| (m, n))
+    }
+
+    /// the (query, count) pairs for every query matching molecule `m`
+    pub fn molecule_hits(
+        &self,
+        m: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.entries
+            .iter()
+            .filter(move |e| e.1 == m)
+            .map(|&(q, _, n)| (q, n))
+    }
+}
+
+/// match every query in `queries` against every molecule in `molecules`,
+/// splitting the molecules across the available threads
+pub fn match_matrix<'a>(
+    queries: impl IntoIterator<Item = &'a Query>,
+    molecules: &[Molecule],
+    options: &MatchOptions,
+) -> MatchMatrix {
+    match_matrix_timed(queries, molecules, options, &mut Timings::default())
+}
+
+/// like [match_matrix], but record the time spent matching all of the queries
+/// against each molecule in `timings`, as one [Stage::Match] sample per
+/// molecule
+pub fn match_matrix_timed<'a>(
+    queries: impl IntoIterator<Item = &'a Query>,
+    molecules: &[Molecule],
+    options: &MatchOptions,
+    timings: &mut Timings,
+) -> MatchMatrix {
+    let compiled: Vec<_> = queries
+        .into_iter()
+        .map(|q| CompiledQuery::new(q, options.clone()))
+        .collect();
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = molecules.len().div_ceil(threads).max(1);
+    let mut entries: Vec<(usize, usize, usize)> = std::thread::scope(|s| {
+        let handles: Vec<_> = molecules
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(c, chunk)| {
+                let compiled = &compiled;
+                s.spawn(move || {
+                    let mut ret = Vec::new();
+                    let mut timings = Timings::default();
+                    for (i, mol) in chunk.iter().enumerate() {
+                        let m = c * chunk_size + i;
+                        timings.time(Stage::Match, || {
+                            let target = Target::new(mol);
+                            for (q, query) in compiled.iter().enumerate() {
+                                let n = query.find_matches(&target).len();
+                                if n > 0 {
+                                    ret.push((q, m, n));
+                                }
+                            }
+                        });
+                    }
+                    (ret, timings)
+                })
+            })
+            .collect();
+        let mut ret = Vec::new();
+        for h in handles {
+            let (entries, t) = h.join().unwrap();
+            ret.extend(entries);
+            timings.merge(t);
+        }
+        ret
+    });
+    entries.sort_unstable();
+    MatchMatrix {
+        n_queries: compiled.len(),
+        n_molecules: molecules.len(),
+        entries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        molecule::mol as parse,
+        smarts::{InputKind, Smarts},
+    };
+
+    use super::*;
+
+    fn query(s: &str) -> Query {
+        Query::from(&Smarts::parse_as(s.to_owned(), InputKind::Smarts))
+    }
+
+    fn find_matches(
+        query: &Query,
+        target: &Molecule,
+        options: &MatchOptions,
+    ) -> Matches {
+        super::find_matches(query, &Target::new(target), options)
+    }
+
+    #[test]
+    fn unique() {
+        let query = query("[#6:1]-[#6:2]");
+        let target = parse("[#6H3:1]-[#6H2:2]-[#8H:3]");
+        let got = find_matches(&query, &target, &MatchOptions::default());
+        assert_eq!(got.matches, vec![vec![0, 1]]);
+
+        let opts = MatchOptions {
+            unique: false,
+            ..Default::default()
+        };
+        let got = find_matches(&query, &target, &opts);
+        assert_eq!(got.matches, vec![vec![0, 1], vec![1, 0]]);
+    }
+
+    #[test]
+    fn wildcard() {
+        let query = query("[#6:1]-[*:2]");
+        let target = parse("[#6H3:1]-[#6H2:2]-[#8H:3]");
+        let got = find_matches(&query, &target, &MatchOptions::default());
+        assert_eq!(got.matches, vec![vec![0, 1], vec![1, 2]]);
+        let got = CompiledQuery::new(&query, MatchOptions::default())
+            .find_matches(&Target::new(&target));
+        assert_eq!(got.matches.len(), 2);
+    }
+
+    #[test]
+    fn bond_expressions() {
+        let target = parse("[#6H3:1]-[#6H2:2]=[#8:3]");
+        let opts = MatchOptions::default();
+        let got = find_matches(&query("[#6:1]~[*:2]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![0, 1], vec![1, 2]]);
+        let got = find_matches(&query("[*:1]-,=[#8:2]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![1, 2]]);
+        assert!(
+            find_matches(&query("[*:1]!=[#8:2]"), &target, &opts).is_empty()
+        );
+    }
+
+    #[test]
+    fn aliphatic() {
+        let benzene = parse("[cH]1:[cH]:[cH]:[cH]:[cH]:[cH]:1");
+        let opts = MatchOptions::default();
+        assert!(find_matches(&query("[C:1]"), &benzene, &opts).is_empty());
+        assert_eq!(find_matches(&query("[c:1]"), &benzene, &opts).len(), 6);
+        assert_eq!(find_matches(&query("[#6:1]"), &benzene, &opts).len(), 6);
+        let got = find_matches(&query("[C,N:1]"), &benzene, &opts);
+        assert!(got.is_empty());
+    }
+
+    #[test]
+    fn logical() {
+        let target = parse("[#6H3:1]-[#7H2:2]-[#8H:3]");
+        let opts = MatchOptions::default();
+        let got = find_matches(&query("[#6,#7:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![0], vec![1]]);
+        let got = find_matches(&query("[!#6;H1,H2:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![1], vec![2]]);
+        // charges are dropped from the expression when they're ignored
+        let q = query("[#8,+:1]");
+        assert_eq!(find_matches(&q, &target, &opts).len(), 1);
+        let opts = MatchOptions {
+            use_charge: false,
+            ..Default::default()
+        };
+        assert_eq!(find_matches(&q, &target, &opts).len(), 3);
+    }
+
+    #[test]
+    fn recursive() {
+        let target = parse("[#6H3:1]-[#6H:2]=[#8:3]");
+        let opts = MatchOptions::default();
+        let got = find_matches(&query("[#6$([#6]=[#8]):1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![1]]);
+        let got = find_matches(&query("[!$([#6]=[#8]);#6:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![0]]);
+    }
+
+    #[test]
+    fn rings_and_degree() {
+        let target = parse("[#6H2:1]1-[#6H2:2]-[#6H:3]-1-[#6H3:4]");
+        let opts = MatchOptions::default();
+        let got = find_matches(&query("[#6R:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![0], vec![1], vec![2]]);
+        let got = find_matches(&query("[#6!R:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![3]]);
+        let got = find_matches(&query("[#6r3;D3:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![2]]);
+        let got = find_matches(&query("[#6X4;D1:1]"), &target, &opts);
+        assert_eq!(got.matches, vec![vec![3]]);
+        let got = find_matches(&query("[#6:1]@[#6:2]"), &target, &opts);
+        assert_eq!(got.len(), 3);
+    }
+
+    #[test]
+    fn isotopes() {
+        let target = parse("[2H]-[#8H]-[#1]");
+        let opts = MatchOptions::default();
+        assert_eq!(find_matches(&query("[2#1:1]"), &target, &opts).len(), 1);
+        assert_eq!(find_matches(&query("[#1:1]"), &target, &opts).len(), 2);
+        assert!(find_matches(&query("[3H]"), &target, &opts).is_empty());
+    }
+
+    #[test]
+    fn hydrogens_and_charge() {
+        let target = parse("[#6H3:1]-[#7H3+:2]");
+        let opts = MatchOptions::default();
+        assert_eq!(find_matches(&query("[#7:1]"), &target, &opts).len(), 1);
+        assert_eq!(find_matches(&query("[#7+:1]"), &target, &opts).len(), 1);
+        assert!(find_matches(&query("[#7+0:1]"), &target, &opts).is_empty());
+        assert!(find_matches(&query("[#6H2:1]"), &target, &opts).is_empty());
+        // in a concrete molecule, a missing H count means H0
+        let exact = Query::from(&parse("[#7+:1]"));
+        assert!(find_matches(&exact, &target, &opts).is_empty());
+
+        let opts = MatchOptions {
+            use_charge: false,
+            ..Default::default()
+        };
+        assert_eq!(find_matches(&query("[#7:1]"), &target, &opts).len(), 1);
+    }
+
+    #[test]
+    fn chirality() {
+        let target = parse("[#6@H:1](-[#9:2])-[#17:3]");
+        let query = query("[#6@@:1]");
+        let opts = MatchOptions::default();
+        assert_eq!(find_matches(&query, &target, &opts).len(), 1);
+        let opts = MatchOptions {
+            use_chirality: true,
+            ..Default::default()
+        };
+        assert!(find_matches(&query, &target, &opts).is_empty());
+    }
+
+    #[test]
+    fn max_and_symmetric() {
+        let query = query("[#6:1]-[#6:2]-[#6:3]");
+        let target = parse("[#6H3:1]-[#6H2:2]-[#6H3:3]");
+        let opts = MatchOptions {
+            unique: false,
+            ..Default::default()
+        };
+        assert_eq!(find_matches(&query, &target, &opts).len(), 2);
+        let opts = MatchOptions {
+            unique: false,
+            max_matches: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(find_matches(&query, &target, &opts).len(), 1);
+        let opts = MatchOptions {
+            unique: false,
+            dedup_symmetric: true,
+            ..Default::default()
+        };
+        assert_eq!(find_matches(&query, &target, &opts).len(), 1);
+    }
+
+    #[test]
+    fn compiled() {
+        let query = CompiledQuery::new(
+            &query("[#6:1]=[#8:2]"),
+            MatchOptions::default(),
+        );
+        let targets = [
+            parse("[#6H3:1]-[#6H:2]=[#8:3]"),
+            parse("[#6H3:1]-[#6H2:2]-[#8H:3]"),
+            parse("[#6H3:1]-[#7H2:2]"),
+        ];
+        let got: Vec<_> = targets
+            .iter()
+            .map(|t| query.find_matches(&Target::new(t)).matches)
+            .collect();
+        assert_eq!(got, vec![vec![vec![1, 2]], vec![], vec![]]);
+    }
+
+    #[test]
+    fn matrix() {
+        let queries =
+            [query("[#6:1]-[#8:2]"), query("[#7:1]"), query("[#6:1]")];
+        let molecules = [
+            parse("[#6H3:1]-[#6H2:2]-[#8H:3]"),
+            parse("[#6H3:1]-[#7H2:2]"),
+            parse("[#8H2:1]"),
+        ];
+        let got = match_matrix(&queries, &molecules, &MatchOptions::default());
+        let want = MatchMatrix {
+            n_queries: 3,
+            n_molecules: 3,
+            entries: vec![(0, 0, 1), (1, 1, 1), (2, 0, 2), (2, 1, 1)],
+        };
+        assert_eq!(got, want);
+        assert_eq!(got.get(2, 0), 2);
+        assert_eq!(got.get(1, 2), 0);
+        assert_eq!(got.query_hits(2).collect::<Vec<_>>(), [(0, 2), (1, 1)]);
+        assert_eq!(got.molecule_hits(1).collect::<Vec<_>>(), [(1, 1), (2, 1)]);
+    }
+}
@@ -0,0 +1,716 @@
+//! Concrete molecules, as opposed to the query patterns in [crate::query].
+//!
+//! A [Molecule] has the same shape as a parsed [Smarts], but every atom has a
+//! definite H count and every bond a definite type, so properties like
+//! valences can be computed from it
+
+use std::fmt::Display;
+
+use crate::{
+    conformer::Conformer,
+    elements,
+    rings::RingInfo,
+    smarts::{Atom, AtomMaps, Bond, BondOrder, Chiral, Smarts},
+    Provenance,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MolAtom {
+    pub atomic_number: usize,
+    /// the mass number, or `None` for the natural mixture of isotopes
+    pub isotope: Option<usize>,
+    pub n_hydrogens: usize,
+    pub charge: isize,
+    pub chirality: Chiral,
+    pub aromatic: bool,
+    pub mol_index: Option<usize>,
+}
+
+/// The type of a bond in a concrete molecule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BondType {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+}
+
+impl BondType {
+    /// the numeric bond order, with 1.5 for aromatic bonds
+    pub fn order(&self) -> f64 {
+        match self {
+            BondType::Single => 1.0,
+            BondType::Double => 2.0,
+            BondType::Triple => 3.0,
+            BondType::Aromatic => 1.5,
+        }
+    }
+}
+
+/// The direction of a single bond adjacent to a stereo double bond, pointing
+/// from `atom1` to `atom2`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MolBond {
+    pub atom1: usize,
+    pub atom2: usize,
+    pub bond_type: BondType,
+    pub direction: Option<Direction>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Molecule {
+    pub atoms: Vec<MolAtom>,
+    pub bonds: Vec<MolBond>,
+    /// the positions in `bonds`, in increasing order, of the bonds that were
+    /// written with ring-closure labels, as in [Smarts::ring_closures]. these
+    /// fix the neighbor order that chirality tags refer to, as described in
+    /// [crate::stereo]
+    pub ring_closures: Vec<usize>,
+    /// the net charge, which is the sum of the formal charges unless
+    /// overridden
+    pub charge: isize,
+    /// the spin multiplicity. this is computed as the lowest multiplicity
+    /// consistent with the number of electrons unless overridden
+    pub multiplicity: usize,
+    pub provenance: Option<Provenance>,
+    /// geometries for the molecule, each with one position per atom
+    pub conformers: Vec<Conformer>,
+    /// one partial charge per atom, in atom order, from an external source
+    /// like AM1-BCC
+    pub partial_charges: Option<Vec<f64>>,
+}
+
+/// The reasons a [Smarts] can fail to describe a concrete molecule
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoleculeError {
+    /// the atom at this position has an unconstrained H count
+    UnknownHydrogens(usize),
+    /// the atom at this position is a wildcard, with no element
+    Wildcard(usize),
+    /// the atom at this position is a logical expression, which only makes
+    /// sense in a query
+    QueryAtom(usize),
+    /// the bond at this position only makes sense in a query, like a ring bond
+    /// or a single-or-aromatic bond
+    QueryBond(usize),
+    /// the atom at this position has implicit hydrogens where only explicit
+    /// ones can be represented
+    ImplicitHydrogens(usize),
+    /// a conformer had a different number of positions than the molecule has
+    /// atoms
+    ConformerSize { n_atoms: usize, n_positions: usize },
+    /// a list of partial charges had a different length than the molecule has
+    /// atoms
+    PartialChargeSize { n_atoms: usize, n_charges: usize },
+    /// no arrangement of single and double bonds fits the aromatic bonds
+    /// around the atom at this position
+    Kekulize(usize),
+}
+
+impl Display for MoleculeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoleculeError::UnknownHydrogens(i) => {
+                write!(f, "atom {i} has an unconstrained H count")
+            }
+            MoleculeError::Wildcard(i) => write!(f, "atom {i} is a wildcard"),
+            MoleculeError::QueryAtom(i) => {
+                write!(f, "atom {i} is a query atom")
+            }
+            MoleculeError::QueryBond(i) => {
+                write!(f, "bond {i} is a query bond")
+            }
+            MoleculeError::ImplicitHydrogens(i) => {
+                write!(f, "atom {i} has implicit hydrogens")
+            }
+            MoleculeError::ConformerSize {
+                n_atoms,
+                n_positions,
+            } => write!(
+                f,
+                "conformer has {n_positions} positions for {n_atoms} atoms"
+            ),
+            MoleculeError::PartialChargeSize { n_atoms, n_charges } => {
+                write!(f, "got {n_charges} partial charges for {n_atoms} atoms")
+            }
+            MoleculeError::Kekulize(i) => {
+                write!(f, "failed to kekulize the aromatic bonds at atom {i}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoleculeError {}
+
+impl TryFrom<&Smarts> for Molecule {
+    type Error = MoleculeError;
+
+    fn try_from(s: &Smarts) -> Result<Self, Self::Error> {
+        let atoms: Vec<_> = s
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                if a.expr.is_some() {
+                    return Err(MoleculeError::QueryAtom(i));
+                }
+                Ok(MolAtom {
+                    atomic_number: a
+                        .atomic_number
+                        .ok_or(MoleculeError::Wildcard(i))?,
+                    isotope: a.isotope,
+                    n_hydrogens: a
+                        .n_hydrogens
+                        .ok_or(MoleculeError::UnknownHydrogens(i))?,
+                    charge: a.charge,
+                    chirality: a.chirality.clone(),
+                    aromatic: a.aromatic,
+                    mol_index: a.mol_index,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let bonds: Vec<_> = s
+            .bonds
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                use BondOrder as B;
+                let (bond_type, direction) = match b.order {
+                    B::Single => (BondType::Single, None),
+                    B::Double => (BondType::Double, None),
+                    B::Triple => (BondType::Triple, None),
+                    B::Aromatic => (BondType::Aromatic, None),
+                    B::Up => (BondType::Single, Some(Direction::Up)),
+                    B::Down => (BondType::Single, Some(Direction::Down)),
+                    B::Ring | B::SingleOrAromatic | B::Any | B::Expr(_) => {
+                        return Err(MoleculeError::QueryBond(i))
+                    }
+                };
+                Ok(MolBond {
+                    atom1: b.atom1,
+                    atom2: b.atom2,
+                    bond_type,
+                    direction,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let mut ret = Self::new(atoms, bonds);
+        ret.ring_closures = s.ring_closures.clone();
+        ret.provenance = s.provenance.clone();
+        Ok(ret)
+    }
+}
+
+impl From<&Molecule> for Smarts {
+    fn from(m: &Molecule) -> Self {
+        let atoms = m
+            .atoms
+            .iter()
+            .map(|a| {
+                Atom::new(
+                    a.atomic_number,
+                    a.n_hydrogens,
+                    a.charge,
+                    a.chirality.clone(),
+                    a.mol_index,
+                )
+                .with_isotope(a.isotope)
+                .with_aromatic(a.aromatic)
+            })
+            .collect();
+        let bonds = m
+            .bonds
+            .iter()
+            .map(|b| {
+                let order = match (b.bond_type, b.direction) {
+                    (_, Some(Direction::Up)) => BondOrder::Up,
+                    (_, Some(Direction::Down)) => BondOrder::Down,
+                    (BondType::Single, None) => BondOrder::Single,
+                    (BondType::Double, None) => BondOrder::Double,
+                    (BondType::Triple, None) => BondOrder::Triple,
+                    (BondType::Aromatic, None) => BondOrder::Aromatic,
+                };
+                Bond::new(b.atom1, b.atom2, order)
+            })
+            .collect();
+        let mut ret = Smarts::from_parts(atoms, bonds).unwrap();
+        ret.ring_closures = m.ring_closures.clone();
+        ret.provenance = m.provenance.clone();
+        ret
+    }
+}
+
+impl Molecule {
+    /// build a molecule from `atoms` and `bonds`, computing its charge and
+    /// multiplicity from the atoms
+    pub fn new(atoms: Vec<MolAtom>, bonds: Vec<MolBond>) -> Self {
+        let charge = atoms.iter().map(|a| a.charge).sum();
+        let mut ret = Self {
+            atoms,
+            bonds,
+            ring_closures: Vec::new(),
+            charge,
+            multiplicity: 1,
+            provenance: None,
+            conformers: Vec::new(),
+            partial_charges: None,
+        };
+        ret.multiplicity = 1 + ret.n_electrons() % 2;
+        ret
+    }
+
+    /// the number of electrons, counting every implicit hydrogen and
+    /// adjusting for the net charge
+    pub fn n_electrons(&self) -> usize {
+        let nuclear: usize = self
+            .atoms
+            .iter()
+            .map(|a| a.atomic_number + a.n_hydrogens)
+            .sum();
+        (nuclear as isize - self.charge).max(0) as usize
+    }
+
+    /// override the computed charge, as when a dataset record specifies it.
+    /// the multiplicity is recomputed from the new number of electrons unless
+    /// it was already overridden with [Molecule::with_multiplicity]
+    pub fn with_charge(mut self, charge: isize) -> Self {
+        let computed = self.multiplicity == 1 + self.n_electrons() % 2;
+        self.charge = charge;
+        if computed {
+            self.multiplicity = 1 + self.n_electrons() % 2;
+        }
+        self
+    }
+
+    pub fn with_multiplicity(mut self, multiplicity: usize) -> Self {
+        self.multiplicity = multiplicity;
+        self
+    }
+
+    /// attach `conformer` to `self`, checking that it has a position for each
+    /// atom
+    pub fn add_conformer(
+        &mut self,
+        conformer: Conformer,
+    ) -> Result<(), MoleculeError> {
+        if conformer.len() != self.atoms.len() {
+            return Err(MoleculeError::ConformerSize {
+                n_atoms: self.atoms.len(),
+                n_positions: conformer.len(),
+            });
+        }
+        self.conformers.push(conformer);
+        Ok(())
+    }
+
+    pub fn with_conformer(
+        mut self,
+        conformer: Conformer,
+    ) -> Result<Self, MoleculeError> {
+        self.add_conformer(conformer)?;
+        Ok(self)
+    }
+
+    /// attach `charges` to `self`, one per atom in atom order
+    pub fn with_partial_charges(
+        mut self,
+        charges: Vec<f64>,
+    ) -> Result<Self, MoleculeError> {
+        if charges.len() != self.atoms.len() {
+            return Err(MoleculeError::PartialChargeSize {
+                n_atoms: self.atoms.len(),
+                n_charges: charges.len(),
+            });
+        }
+        self.partial_charges = Some(charges);
+        Ok(self)
+    }
+
+    /// the position of each atom when the atoms are sorted by atom map, for
+    /// lining up per-atom data given in map order, like conformers from
+    /// QCArchive. the maps are only used if they are exactly `1..=n`,
+    /// otherwise this is the atom order
+    pub fn map_order(&self) -> Vec<usize> {
+        let by_map: Option<Vec<usize>> = self
+            .atoms
+            .iter()
+            .map(|a| a.mol_index?.checked_sub(1))
+            .collect();
+        match by_map {
+            Some(order) if is_permutation(&order) => order,
+            _ => (0..self.atoms.len()).collect(),
+        }
+    }
+
+    /// the table between the atom maps in `self` and atom positions
+    pub fn atom_maps(&self) -> AtomMaps {
+        AtomMaps::new(self.atoms.iter().map(|a| a.mol_index))
+    }
+
+    /// the positions of the atoms bonded to atom `i`
+    pub fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        self.bonds.iter().filter_map(move |b| {
+            if b.atom1 == i {
+                Some(b.atom2)
+            } else if b.atom2 == i {
+                Some(b.atom1)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// whether each bond is part of a ring, in the same order as `bonds`. a
+    /// bond is in a ring when its atoms are still connected without it
+    pub fn ring_bonds(&self) -> Vec<bool> {
+        (0..self.bonds.len())
+            .map(|skip| {
+                let MolBond { atom1, atom2, .. } = self.bonds[skip];
+                let mut seen = vec![false; self.atoms.len()];
+                let mut stack = vec![atom1];
+                seen[atom1] = true;
+                while let Some(i) = stack.pop() {
+                    for (k, b) in self.bonds.iter().enumerate() {
+                        let j = match (b.atom1 == i, b.atom2 == i) {
+                            _ if k == skip => continue,
+                            (true, _) => b.atom2,
+                            (_, true) => b.atom1,
+                            _ => continue,
+                        };
+                        if !seen[j] {
+                            seen[j] = true;
+                            stack.push(j);
+                        }
+                    }
+                }
+                seen[atom2]
+            })
+            .collect()
+    }
+
+    /// whether each atom is part of a ring, in the same order as `atoms`
+    pub fn ring_atoms(&self) -> Vec<bool> {
+        let mut ret = vec![false; self.atoms.len()];
+        for (b, ring) in self.bonds.iter().zip(self.ring_bonds()) {
+            if ring {
+                ret[b.atom1] = true;
+                ret[b.atom2] = true;
+            }
+        }
+        ret
+    }
+
+    /// the number of explicit bonds to atom `i`, not counting hydrogens
+    pub fn degree(&self, i: usize) -> usize {
+        self.neighbors(i).count()
+    }
+
+    /// the number of connections to atom `i`, including hydrogens
+    pub fn total_degree(&self, i: usize) -> usize {
+        self.degree(i) + self.atoms[i].n_hydrogens
+    }
+
+    /// the number of bonds from atom `i` to atoms other than hydrogen, so
+    /// explicit hydrogens don't count either
+    pub fn heavy_degree(&self, i: usize) -> usize {
+        self.neighbors(i)
+            .filter(|&j| self.atoms[j].atomic_number != 1)
+            .count()
+    }
+
+    /// perceive the rings of `self`. see [RingInfo] for the details
+    pub fn ring_info(&self) -> RingInfo {
+        RingInfo::new(self)
+    }
+
+    /// the valence of each atom: the sum of its bond orders in the Kekulé
+    /// structure from [Molecule::kekule_bonds], plus one for each hydrogen
+    pub fn valences(&self) -> Result<Vec<usize>, MoleculeError> {
+        let mut ret: Vec<_> =
+            self.atoms.iter().map(|a| a.n_hydrogens).collect();
+        for (bond, t) in self.bonds.iter().zip(self.kekule_bonds()?) {
+            let order = t.order() as usize;
+            ret[bond.atom1] += order;
+            ret[bond.atom2] += order;
+        }
+        Ok(ret)
+    }
+
+    /// the valence of atom `i`, as in [Molecule::valences]
+    pub fn valence(&self, i: usize) -> Result<usize, MoleculeError> {
+        Ok(self.valences()?[i])
+    }
+
+    /// the number of atoms other than hydrogen
+    pub fn n_heavy_atoms(&self) -> usize {
+        self.atoms.iter().filter(|a| a.atomic_number != 1).count()
+    }
+
+    /// the molecular weight in Daltons, including implicit hydrogens. atoms
+    /// without a known mass contribute nothing
+    pub fn molecular_weight(&self) -> f64 {
+        let h = elements::mass(1).unwrap();
+        self.atoms
+            .iter()
+            .map(|a| {
+                elements::mass(a.atomic_number).unwrap_or(0.0)
+                    + h * a.n_hydrogens as f64
+            })
+            .sum()
+    }
+
+    /// the total number of hydrogens, whether they are explicit atoms or
+    /// counted on their neighbors
+    pub fn n_hydrogens(&self) -> usize {
+        self.atoms
+            .iter()
+            .map(|a| a.n_hydrogens + usize::from(a.atomic_number == 1))
+            .sum()
+    }
+
+    /// the type of each bond with every aromatic bond replaced by a single or
+    /// double bond, so that each aromatic atom with a free valence gets
+    /// exactly one double bond
+    pub fn kekule_bonds(&self) -> Result<Vec<BondType>, MoleculeError> {
+        let mut ret: Vec<_> = self.bonds.iter().map(|b| b.bond_type).collect();
+        let aromatic: Vec<usize> = (0..self.bonds.len())
+            .filter(|&b| ret[b] == BondType::Aromatic)
+            .collect();
+        if aromatic.is_empty() {
+            return Ok(ret);
+        }
+        // atoms with a free valence once every aromatic bond is single
+        let mut needs = vec![false; self.atoms.len()];
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let used: usize = self
+                .bonds
+                .iter()
+                .filter(|b| b.atom1 == i || b.atom2 == i)
+                .map(|b| match b.bond_type {
+                    BondType::Double => 2,
+                    BondType::Triple => 3,
+                    _ => 1,
+                })
+                .sum::<usize>()
+                + atom.n_hydrogens;
+            let is_aromatic = aromatic
+                .iter()
+                .any(|&b| self.bonds[b].atom1 == i || self.bonds[b].atom2 == i);
+            needs[i] = is_aromatic
+                && default_valence(atom.atomic_number, atom.charge)
+                    .is_some_and(|v| v > used);
+        }
+        let mut matched = vec![false; self.atoms.len()];
+        if !self.match_double(&aromatic, &needs, &mut matched, &mut ret) {
+            let i = (0..needs.len()).find(|&i| needs[i] && !matched[i]);
+            return Err(MoleculeError::Kekulize(i.unwrap_or_default()));
+        }
+        for b in aromatic {
+            if ret[b] == BondType::Aromatic {
+                ret[b] = BondType::Single;
+            }
+        }
+        Ok(ret)
+    }
+
+    /// assign a double bond from `aromatic` to the first atom that `needs`
+    /// one and is not yet `matched`, backtracking until every such atom has
+    /// one. returns whether this succeeded
+    fn match_double(
+        &self,
+        aromatic: &[usize],
+        needs: &[bool],
+        matched: &mut [bool],
+        types: &mut [BondType],
+    ) -> bool {
+        let Some(i) = (0..needs.len()).find(|&i| needs[i] && !matched[i])
+        else {
+            return true;
+        };
+        for &b in aromatic {
+            let bond = &self.bonds[b];
+            let j = match (bond.atom1, bond.atom2) {
+                (a, j) if a == i => j,
+                (j, a) if a == i => j,
+                _ => continue,
+            };
+            if !needs[j] || matched[j] {
+                continue;
+            }
+            (matched[i], matched[j]) = (true, true);
+            types[b] = BondType::Double;
+            if self.match_double(aromatic, needs, matched, types) {
+                return true;
+            }
+            (matched[i], matched[j]) = (false, false);
+            types[b] = BondType::Aromatic;
+        }
+        false
+    }
+}
+
+/// the usual number of bonds to an atom of `atomic_number` with `charge` in
+/// an aromatic ring, or `None` for elements that don't usually appear in one
+fn default_valence(atomic_number: usize, charge: isize) -> Option<usize> {
+    let v: isize = match atomic_number {
+        // boron gains a bond with each negative charge
+        5 => 3 - charge,
+        6 | 14 => 4 - charge.abs(),
+        7 | 15 | 33 => 3 + charge,
+        8 | 16 | 34 | 52 => 2 + charge,
+        _ => return None,
+    };
+    usize::try_from(v).ok()
+}
+
+fn is_permutation(order: &[usize]) -> bool {
+    let mut seen = vec![false; order.len()];
+    order
+        .iter()
+        .all(|&i| i < seen.len() && !std::mem::replace(&mut seen[i], true))
+}
+
+/// parse `s` as SMILES into a [Molecule] for tests, panicking if it can't be
+/// converted
+#[cfg(test)]
+pub(crate) fn mol(s: &str) -> Molecule {
+    Molecule::try_from(&Smarts::parse(s.to_owned())).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::InputKind;
+
+    use super::*;
+
+    #[test]
+    fn convert() {
+        let s = Smarts::parse("[#6H3:1]-[#6H:2]=[#8:3]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert_eq!(mol.atoms[2].n_hydrogens, 0);
+        assert_eq!(mol.bonds[1].bond_type, BondType::Double);
+        assert_eq!(Smarts::from(&mol), s);
+
+        let q =
+            Smarts::parse_as("[#6:1]-[#8H:2]".to_owned(), InputKind::Smarts);
+        assert_eq!(
+            Molecule::try_from(&q),
+            Err(MoleculeError::UnknownHydrogens(0))
+        );
+        let q = Smarts::parse_as("[#6H3]@[#8H]".to_owned(), InputKind::Smarts);
+        assert_eq!(Molecule::try_from(&q), Err(MoleculeError::QueryBond(0)));
+        let q = Smarts::parse("[#6H3]-[#6,#7H2]".to_owned());
+        assert_eq!(Molecule::try_from(&q), Err(MoleculeError::QueryAtom(1)));
+        let q = Smarts::parse("[2H]-[#8H]".to_owned());
+        let mol = Molecule::try_from(&q).unwrap();
+        assert_eq!(mol.atoms[0].isotope, Some(2));
+        assert_eq!(Smarts::from(&mol).atoms[0].isotope, Some(2));
+    }
+
+    #[test]
+    fn kekulize() {
+        let n_double = |s: &str| {
+            let mol = Molecule::try_from(&Smarts::parse(s.to_owned())).unwrap();
+            let got = mol.kekule_bonds().unwrap();
+            assert!(!got.contains(&BondType::Aromatic));
+            for i in 0..mol.atoms.len() {
+                let doubles = mol
+                    .bonds
+                    .iter()
+                    .zip(&got)
+                    .filter(|(b, t)| {
+                        **t == BondType::Double
+                            && (b.atom1 == i || b.atom2 == i)
+                    })
+                    .count();
+                assert!(doubles <= 1, "{s}");
+            }
+            got.iter().filter(|&&t| t == BondType::Double).count()
+        };
+        assert_eq!(n_double("[#7]1:[#6H]:[#6H]:[#6H]:[#6H]:[#6H]:1"), 3);
+        // the NH in pyrrole takes no double bond
+        assert_eq!(n_double("[#7H]1:[#6H]:[#6H]:[#6H]:[#6H]:1"), 2);
+        assert_eq!(n_double("[#6H3]-[#6H]=[#8]"), 1);
+
+        // a five-membered ring of CH atoms has an odd number of free valences
+        let s = Smarts::parse("[#6H]1:[#6H]:[#6H]:[#6H]:[#6H]:1".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert!(matches!(
+            mol.kekule_bonds(),
+            Err(MoleculeError::Kekulize(_))
+        ));
+    }
+
+    #[test]
+    fn aromatic_valence() {
+        let thiophene = mol("[#16]1:[#6H]:[#6H]:[#6H]:[#6H]:1");
+        assert_eq!(thiophene.valences(), Ok(vec![2, 4, 4, 4, 4]));
+        let pyrrole = mol("[#7H]1:[#6H]:[#6H]:[#6H]:[#6H]:1");
+        assert_eq!(pyrrole.valence(0), Ok(3));
+        let pyridine = mol("[#7]1:[#6H]:[#6H]:[#6H]:[#6H]:[#6H]:1");
+        assert_eq!(pyridine.valence(0), Ok(3));
+    }
+
+    #[test]
+    fn properties() {
+        let s = Smarts::parse("[#6H3:1]-[#6H:2]=[#8:3]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert_eq!(mol.neighbors(1).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(mol.degree(1), 2);
+        assert_eq!(mol.total_degree(0), 4);
+        assert_eq!(mol.valence(1), Ok(4));
+        assert_eq!(mol.valence(2), Ok(2));
+        assert_eq!(mol.n_hydrogens(), 4);
+        assert_eq!(mol.n_heavy_atoms(), 3);
+        assert!((mol.molecular_weight() - 44.053).abs() < 1e-9);
+        assert_eq!(mol.charge, 0);
+        assert_eq!(mol.multiplicity, 1);
+
+        // a methyl radical and an ammonium cation
+        let s = Smarts::parse("[#6H3]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert_eq!((mol.n_electrons(), mol.multiplicity), (9, 2));
+        let s = Smarts::parse("[#7H4+]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert_eq!(
+            (mol.n_electrons(), mol.charge, mol.multiplicity),
+            (10, 1, 1)
+        );
+        let mol = mol.with_charge(0);
+        assert_eq!((mol.charge, mol.multiplicity), (0, 2));
+        let mol = mol.with_multiplicity(4).with_charge(1);
+        assert_eq!((mol.charge, mol.multiplicity), (1, 4));
+    }
+
+    #[test]
+    fn rings() {
+        // methylcyclopropane
+        let s = Smarts::parse("[#6H3]-[#6H]1-[#6H2]-[#6H2]-1".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        assert_eq!(mol.ring_bonds(), [false, true, true, true]);
+        assert_eq!(mol.ring_atoms(), [false, true, true, true]);
+    }
+
+    #[test]
+    fn conformers() {
+        let s = Smarts::parse("[#8H2]".to_owned());
+        let mut mol = Molecule::try_from(&s).unwrap();
+        let c = Conformer::new(vec![[0.0; 3]], Default::default());
+        mol.add_conformer(c.with_energy(-76.0)).unwrap();
+        assert_eq!(mol.conformers[0].energy(), Some(-76.0));
+        let c = Conformer::new(vec![[0.0; 3]; 3], Default::default());
+        assert_eq!(
+            mol.add_conformer(c),
+            Err(MoleculeError::ConformerSize {
+                n_atoms: 1,
+                n_positions: 3
+            })
+        );
+    }
+}
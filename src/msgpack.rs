@@ -0,0 +1,424 @@
+//! A MessagePack encoding of datasets, as a binary cache that loads faster
+//! than JSON for large collections.
+//!
+//! Values are written from a [serde_json::Value], so anything that can be
+//! written as JSON can be written here, and read back through a serde
+//! [Deserializer] straight from the bytes, borrowing strings where it can.
+//! Integers are written in the smallest encoding that holds them and floats
+//! always as 64-bit floats. Of enums, only unit variants written as strings
+//! can be read
+
+use std::fmt::Display;
+
+use serde::{
+    de::{
+        self, value::BorrowedStrDeserializer, DeserializeOwned,
+        DeserializeSeed, MapAccess, SeqAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MsgpackError {
+    /// the input ended in the middle of a value
+    Eof,
+    /// the byte at this offset doesn't start a supported value
+    Marker { offset: usize, marker: u8 },
+    /// the string at this offset is not UTF-8
+    Utf8(usize),
+    /// the input continues after the value, from this offset
+    Trailing(usize),
+    /// a value didn't have the type or layout the caller expected
+    Custom(String),
+}
+
+impl Display for MsgpackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MsgpackError::Eof => write!(f, "unexpected end of MessagePack"),
+            MsgpackError::Marker { offset, marker } => {
+                write!(f, "unsupported marker {marker:#04x} at byte {offset}")
+            }
+            MsgpackError::Utf8(offset) => {
+                write!(f, "invalid UTF-8 in the string at byte {offset}")
+            }
+            MsgpackError::Trailing(offset) => {
+                write!(f, "trailing bytes after the value at byte {offset}")
+            }
+            MsgpackError::Custom(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl std::error::Error for MsgpackError {}
+
+impl de::Error for MsgpackError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::Custom(msg.to_string())
+    }
+}
+
+/// encode `value` as MessagePack
+pub fn to_vec(value: &Value) -> Vec<u8> {
+    let mut ret = Vec::new();
+    encode(value, &mut ret);
+    ret
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                encode_uint(u, out);
+            } else if let Some(i) = n.as_i64() {
+                encode_int(i, out);
+            } else {
+                out.push(0xcb);
+                out.extend(n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(s) => {
+            let n = s.len();
+            match n {
+                0..=31 => out.push(0xa0 | n as u8),
+                32..=0xff => out.extend([0xd9, n as u8]),
+                0x100..=0xffff => {
+                    out.push(0xda);
+                    out.extend((n as u16).to_be_bytes());
+                }
+                _ => {
+                    out.push(0xdb);
+                    out.extend((n as u32).to_be_bytes());
+                }
+            }
+            out.extend(s.as_bytes());
+        }
+        Value::Array(a) => {
+            encode_len(a.len(), [0x90, 0xdc, 0xdd], out);
+            for v in a {
+                encode(v, out);
+            }
+        }
+        Value::Object(m) => {
+            encode_len(m.len(), [0x80, 0xde, 0xdf], out);
+            for (k, v) in m {
+                encode(&Value::String(k.clone()), out);
+                encode(v, out);
+            }
+        }
+    }
+}
+
+fn encode_uint(u: u64, out: &mut Vec<u8>) {
+    match u {
+        0..=0x7f => out.push(u as u8),
+        0x80..=0xff => out.extend([0xcc, u as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend((u as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend((u as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend(u.to_be_bytes());
+        }
+    }
+}
+
+/// encode the negative integer `i`
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+    match i {
+        -32..=-1 => out.push(i as u8),
+        -0x80..=-33 => out.extend([0xd0, i as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend((i as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend((i as i32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend(i.to_be_bytes());
+        }
+    }
+}
+
+/// encode the length `n` of an array or map with the `fix`, 16-bit, and
+/// 32-bit `markers`
+fn encode_len(n: usize, markers: [u8; 3], out: &mut Vec<u8>) {
+    match n {
+        0..=15 => out.push(markers[0] | n as u8),
+        16..=0xffff => {
+            out.push(markers[1]);
+            out.extend((n as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(markers[2]);
+            out.extend((n as u32).to_be_bytes());
+        }
+    }
+}
+
+/// decode a `T` from the MessagePack in `bytes`, which must hold exactly one
+/// value
+pub fn from_slice<T: DeserializeOwned>(
+    bytes: &[u8],
+) -> Result<T, MsgpackError> {
+    let mut de = MsgpackDeserializer {
+        input: bytes,
+        pos: 0,
+    };
+    let ret = T::deserialize(&mut de)?;
+    if de.pos != bytes.len() {
+        return Err(MsgpackError::Trailing(de.pos));
+    }
+    Ok(ret)
+}
+
+struct MsgpackDeserializer<'de> {
+    input: &'de [u8],
+    pos: usize,
+}
+
+impl<'de> MsgpackDeserializer<'de> {
+    fn take(&mut self, n: usize) -> Result<&'de [u8], MsgpackError> {
+        let end = self.pos.checked_add(n).ok_or(MsgpackError::Eof)?;
+        let ret = self.input.get(self.pos..end).ok_or(MsgpackError::Eof)?;
+        self.pos = end;
+        Ok(ret)
+    }
+
+    fn byte(&mut self) -> Result<u8, MsgpackError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8, MsgpackError> {
+        self.input.get(self.pos).copied().ok_or(MsgpackError::Eof)
+    }
+
+    /// read a big-endian unsigned integer of `n` bytes
+    fn uint(&mut self, n: usize) -> Result<u64, MsgpackError> {
+        Ok(self.take(n)?.iter().fold(0, |acc, &b| acc << 8 | b as u64))
+    }
+
+    /// read a big-endian signed integer of `n` bytes
+    fn int(&mut self, n: usize) -> Result<i64, MsgpackError> {
+        let shift = 64 - 8 * n as u32;
+        Ok(((self.uint(n)? << shift) as i64) >> shift)
+    }
+
+    /// read a string of `n` bytes
+    fn str(&mut self, n: usize) -> Result<&'de str, MsgpackError> {
+        let offset = self.pos;
+        std::str::from_utf8(self.take(n)?)
+            .map_err(|_| MsgpackError::Utf8(offset))
+    }
+}
+
+impl<'de> Deserializer<'de> for &mut MsgpackDeserializer<'de> {
+    type Error = MsgpackError;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let offset = self.pos;
+        let marker = self.byte()?;
+        match marker {
+            0x00..=0x7f => visitor.visit_u64(marker as u64),
+            0x80..=0x8f => visitor.visit_map(Items::new(self, marker & 0x0f)),
+            0x90..=0x9f => visitor.visit_seq(Items::new(self, marker & 0x0f)),
+            0xa0..=0xbf => {
+                visitor.visit_borrowed_str(self.str((marker & 0x1f) as usize)?)
+            }
+            0xc0 => visitor.visit_unit(),
+            0xc2 => visitor.visit_bool(false),
+            0xc3 => visitor.visit_bool(true),
+            0xc4..=0xc6 => {
+                let n = self.uint(1 << (marker - 0xc4))? as usize;
+                visitor.visit_borrowed_bytes(self.take(n)?)
+            }
+            0xca => {
+                let bits = self.uint(4)? as u32;
+                visitor.visit_f64(f32::from_bits(bits) as f64)
+            }
+            0xcb => visitor.visit_f64(f64::from_bits(self.uint(8)?)),
+            0xcc..=0xcf => visitor.visit_u64(self.uint(1 << (marker - 0xcc))?),
+            0xd0..=0xd3 => visitor.visit_i64(self.int(1 << (marker - 0xd0))?),
+            0xd9..=0xdb => {
+                let n = self.uint(1 << (marker - 0xd9))? as usize;
+                visitor.visit_borrowed_str(self.str(n)?)
+            }
+            0xdc | 0xdd => {
+                let n = self.uint(2 << (marker - 0xdc))? as usize;
+                visitor.visit_seq(Items::new(self, n))
+            }
+            0xde | 0xdf => {
+                let n = self.uint(2 << (marker - 0xde))? as usize;
+                visitor.visit_map(Items::new(self, n))
+            }
+            0xe0..=0xff => visitor.visit_i64(marker as i8 as i64),
+            _ => Err(MsgpackError::Marker { offset, marker }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.peek()? == 0xc0 {
+            self.pos += 1;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    /// only unit variants, written as their names, are supported
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let offset = self.pos;
+        let marker = self.byte()?;
+        let n = match marker {
+            0xa0..=0xbf => (marker & 0x1f) as usize,
+            0xd9..=0xdb => self.uint(1 << (marker - 0xd9))? as usize,
+            _ => return Err(MsgpackError::Marker { offset, marker }),
+        };
+        visitor.visit_enum(BorrowedStrDeserializer::new(self.str(n)?))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+/// The remaining elements of an array, or entries of a map
+struct Items<'a, 'de> {
+    de: &'a mut MsgpackDeserializer<'de>,
+    left: usize,
+}
+
+impl<'a, 'de> Items<'a, 'de> {
+    fn new(
+        de: &'a mut MsgpackDeserializer<'de>,
+        left: impl Into<usize>,
+    ) -> Self {
+        Self {
+            de,
+            left: left.into(),
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Items<'_, 'de> {
+    type Error = MsgpackError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left)
+    }
+}
+
+impl<'de> MapAccess<'de> for Items<'_, 'de> {
+    type Error = MsgpackError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = json!({
+            "null": null,
+            "bools": [true, false],
+            "ints": [0, 127, 128, 65536, u64::MAX, -1, -33, -129, -40000, i64::MIN],
+            "float": -1.17,
+            "short": "C",
+            "long": "x".repeat(300),
+            "many": (0..20).collect::<Vec<_>>(),
+        });
+        let bytes = to_vec(&value);
+        assert_eq!(bytes[0], 0x87);
+        assert_eq!(from_slice::<Value>(&bytes).unwrap(), value);
+
+        // optional fields and structs come through a derived Deserialize
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct S {
+            a: Option<usize>,
+            b: Option<String>,
+        }
+        let bytes = to_vec(&json!({"a": null, "b": "x"}));
+        let got: S = from_slice(&bytes).unwrap();
+        assert_eq!(
+            got,
+            S {
+                a: None,
+                b: Some("x".to_owned())
+            }
+        );
+        let got: crate::stereo::Cip = from_slice(&to_vec(&json!("R"))).unwrap();
+        assert_eq!(got, crate::stereo::Cip::R);
+
+        assert_eq!(from_slice::<Value>(&bytes[..3]), Err(MsgpackError::Eof));
+        assert_eq!(
+            from_slice::<Value>(&[0xc1]),
+            Err(MsgpackError::Marker {
+                offset: 0,
+                marker: 0xc1
+            })
+        );
+        assert_eq!(
+            from_slice::<Value>(&[0xc0, 0xc0]),
+            Err(MsgpackError::Trailing(1))
+        );
+    }
+}
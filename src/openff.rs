@@ -0,0 +1,329 @@
+//! Conversion between [Molecule]s and the JSON layout of the OpenFF toolkit's
+//! `Molecule.to_dict`, so the output of chomper can be read back with
+//! `Molecule.from_dict` and the other way around.
+//!
+//! OpenFF molecules store every hydrogen as an atom, give each bond an
+//! integer Kekulé order alongside its aromaticity flag, and label
+//! stereochemistry with CIP descriptors rather than SMILES chirality tags and
+//! bond directions. The labels are converted with [crate::stereo], and atom
+//! map numbers go in the `atom_map` property, as they do when the toolkit
+//! reads a mapped SMILES
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    conformer::{Conformer, LengthUnit},
+    molecule::{BondType, MolAtom, MolBond, Molecule, MoleculeError},
+    smarts::Chiral,
+    stereo::{self, BondStereo, Cip},
+};
+
+/// The name of the property holding the atom map numbers
+pub const ATOM_MAP: &str = "atom_map";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OpenffAtom {
+    pub atomic_number: usize,
+    /// the formal charge in units of the elementary charge
+    pub formal_charge: isize,
+    pub is_aromatic: bool,
+    pub stereochemistry: Option<Cip>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OpenffBond {
+    pub atom1: usize,
+    pub atom2: usize,
+    /// the Kekulé bond order, even for aromatic bonds
+    pub bond_order: usize,
+    pub is_aromatic: bool,
+    pub stereochemistry: Option<BondStereo>,
+    #[serde(default)]
+    pub fractional_bond_order: Option<f64>,
+}
+
+/// An OpenFF toolkit molecule. The virtual sites and hierarchy schemes are
+/// kept as they are read but not used by chomper
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OpenffMolecule {
+    #[serde(default)]
+    pub name: String,
+    pub atoms: Vec<OpenffAtom>,
+    #[serde(default)]
+    pub virtual_sites: Vec<Value>,
+    pub bonds: Vec<OpenffBond>,
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+    /// one list of positions per conformer, in `conformers_unit`
+    pub conformers: Option<Vec<Vec<[f64; 3]>>>,
+    #[serde(default = "angstrom")]
+    pub conformers_unit: String,
+    pub partial_charges: Option<Vec<f64>>,
+    #[serde(default = "elementary_charge")]
+    pub partial_charge_unit: String,
+    #[serde(default)]
+    pub hierarchy_schemes: Map<String, Value>,
+}
+
+fn angstrom() -> String {
+    "angstrom".to_owned()
+}
+
+fn elementary_charge() -> String {
+    "elementary_charge".to_owned()
+}
+
+/// The reasons a conversion to or from an [OpenffMolecule] can fail
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpenffError {
+    Molecule(MoleculeError),
+    /// the bond at this position has an order other than 1, 2, or 3
+    BondOrder {
+        bond: usize,
+        order: usize,
+    },
+    /// the atom at this position has stereochemistry that can't be converted
+    /// between a chirality tag and a CIP label
+    AtomStereo(usize),
+    /// the bond at this position has stereochemistry that can't be converted
+    /// between bond directions and an E or Z label
+    BondStereo(usize),
+    /// the conformers were in a unit other than angstroms or bohr
+    Unit(String),
+}
+
+impl Display for OpenffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenffError::Molecule(e) => write!(f, "{e}"),
+            OpenffError::BondOrder { bond, order } => {
+                write!(f, "bond {bond} has unsupported order {order}")
+            }
+            OpenffError::AtomStereo(i) => {
+                write!(f, "failed to label the stereocenter at atom {i}")
+            }
+            OpenffError::BondStereo(i) => {
+                write!(f, "failed to label the stereo double bond {i}")
+            }
+            OpenffError::Unit(u) => write!(f, "unsupported length unit {u}"),
+        }
+    }
+}
+
+impl std::error::Error for OpenffError {}
+
+impl From<MoleculeError> for OpenffError {
+    fn from(e: MoleculeError) -> Self {
+        Self::Molecule(e)
+    }
+}
+
+impl OpenffMolecule {
+    /// build an OpenFF molecule from `mol`. every hydrogen in `mol` must be an
+    /// explicit atom, and every chirality tag must be on an atom whose
+    /// neighbors can be ranked
+    pub fn new(mol: &Molecule) -> Result<Self, OpenffError> {
+        if let Some(i) = mol.atoms.iter().position(|a| a.n_hydrogens > 0) {
+            return Err(MoleculeError::ImplicitHydrogens(i).into());
+        }
+        let orders = mol.kekule_bonds()?;
+        let atoms = mol
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let stereochemistry = stereo::cip_label(mol, i);
+                if a.chirality != Chiral::None && stereochemistry.is_none() {
+                    return Err(OpenffError::AtomStereo(i));
+                }
+                Ok(OpenffAtom {
+                    atomic_number: a.atomic_number,
+                    formal_charge: a.charge,
+                    is_aromatic: a.aromatic,
+                    stereochemistry,
+                    name: String::new(),
+                    metadata: Map::new(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let bonds = mol
+            .bonds
+            .iter()
+            .zip(orders)
+            .enumerate()
+            .map(|(b, (bond, order))| OpenffBond {
+                atom1: bond.atom1,
+                atom2: bond.atom2,
+                bond_order: order.order() as usize,
+                is_aromatic: bond.bond_type == BondType::Aromatic,
+                stereochemistry: stereo::bond_label(mol, b),
+                fractional_bond_order: None,
+            })
+            .collect();
+        let atom_map: Map<String, Value> = mol
+            .atoms
+            .iter()
+            .enumerate()
+            .filter_map(|(i, a)| Some((i.to_string(), a.mol_index?.into())))
+            .collect();
+        let mut properties = Map::new();
+        if !atom_map.is_empty() {
+            properties.insert(ATOM_MAP.to_owned(), atom_map.into());
+        }
+        let conformers = (!mol.conformers.is_empty()).then(|| {
+            mol.conformers
+                .iter()
+                .map(|c| c.coordinates().to_vec())
+                .collect()
+        });
+        Ok(Self {
+            name: String::new(),
+            atoms,
+            virtual_sites: Vec::new(),
+            bonds,
+            properties,
+            conformers,
+            conformers_unit: angstrom(),
+            partial_charges: mol.partial_charges.clone(),
+            partial_charge_unit: elementary_charge(),
+            hierarchy_schemes: Map::new(),
+        })
+    }
+
+    /// the map number of each atom from the `atom_map` property
+    fn atom_map(&self) -> BTreeMap<usize, usize> {
+        let Some(Value::Object(map)) = self.properties.get(ATOM_MAP) else {
+            return BTreeMap::new();
+        };
+        map.iter()
+            .filter_map(|(k, v)| Some((k.parse().ok()?, v.as_u64()? as usize)))
+            .collect()
+    }
+}
+
+impl TryFrom<&OpenffMolecule> for Molecule {
+    type Error = OpenffError;
+
+    fn try_from(m: &OpenffMolecule) -> Result<Self, Self::Error> {
+        let maps = m.atom_map();
+        let atoms = m
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(i, a)| MolAtom {
+                atomic_number: a.atomic_number,
+                isotope: None,
+                n_hydrogens: 0,
+                charge: a.formal_charge,
+                chirality: Chiral::None,
+                aromatic: a.is_aromatic,
+                mol_index: maps.get(&i).copied(),
+            })
+            .collect();
+        let bonds = m
+            .bonds
+            .iter()
+            .enumerate()
+            .map(|(b, bond)| {
+                let bond_type = match (bond.is_aromatic, bond.bond_order) {
+                    (true, _) => BondType::Aromatic,
+                    (false, 1) => BondType::Single,
+                    (false, 2) => BondType::Double,
+                    (false, 3) => BondType::Triple,
+                    (false, order) => {
+                        return Err(OpenffError::BondOrder { bond: b, order })
+                    }
+                };
+                Ok(MolBond {
+                    atom1: bond.atom1,
+                    atom2: bond.atom2,
+                    bond_type,
+                    direction: None,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let mut ret = Molecule::new(atoms, bonds);
+        for (i, a) in m.atoms.iter().enumerate() {
+            if let Some(label) = a.stereochemistry {
+                ret.atoms[i].chirality = stereo::chirality_for(&ret, i, label)
+                    .ok_or(OpenffError::AtomStereo(i))?;
+            }
+        }
+        for (b, bond) in m.bonds.iter().enumerate() {
+            if let Some(label) = bond.stereochemistry {
+                if !stereo::set_bond_label(&mut ret, b, label) {
+                    return Err(OpenffError::BondStereo(b));
+                }
+            }
+        }
+        let unit = match m.conformers_unit.as_str() {
+            "angstrom" => LengthUnit::Angstrom,
+            "bohr" => LengthUnit::Bohr,
+            u => return Err(OpenffError::Unit(u.to_owned())),
+        };
+        for conf in m.conformers.iter().flatten() {
+            ret.add_conformer(Conformer::new(conf.clone(), unit))?;
+        }
+        if let Some(charges) = &m.partial_charges {
+            ret = ret.with_partial_charges(charges.clone())?;
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::Smarts;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        // (R)-CHFClBr with its hydrogen first, then trans-1,2-difluoroethene
+        let s =
+            Smarts::parse("[#1:1][#6@@:2]([#9:3])([#17:4])[#35:5]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        let got = OpenffMolecule::new(&mol).unwrap();
+        assert_eq!(got.atoms[1].stereochemistry, Some(Cip::R));
+        let json = serde_json::to_value(&got).unwrap();
+        assert_eq!(json["atoms"][1]["stereochemistry"], "R");
+        assert_eq!(json["properties"][ATOM_MAP]["4"], 5);
+        assert_eq!(json["conformers"], Value::Null);
+
+        let back: OpenffMolecule = serde_json::from_value(json).unwrap();
+        let back = Molecule::try_from(&back).unwrap();
+        assert_eq!(back.atoms, mol.atoms);
+        assert_eq!(back.bonds, mol.bonds);
+
+        let s = Smarts::parse("[#9]/[#6](-[#1])=[#6](-[#1])/[#9]".to_owned());
+        let got = OpenffMolecule::new(&Molecule::try_from(&s).unwrap());
+        let got = got.unwrap();
+        assert_eq!(got.bonds[2].stereochemistry, Some(BondStereo::E));
+        let back = Molecule::try_from(&got).unwrap();
+        assert_eq!(stereo::bond_label(&back, 2), Some(BondStereo::E));
+
+        // benzene comes back with its Kekulé orders and aromatic flags
+        let s = Smarts::parse(
+            "[#6]1(-[#1]):[#6](-[#1]):[#6](-[#1]):[#6](-[#1]):[#6](-[#1]):[#6]:1-[#1]"
+                .to_owned(),
+        );
+        let got = OpenffMolecule::new(&Molecule::try_from(&s).unwrap());
+        let got = got.unwrap();
+        let doubles = got.bonds.iter().filter(|b| b.bond_order == 2).count();
+        let aromatic = got.bonds.iter().filter(|b| b.is_aromatic).count();
+        assert_eq!((doubles, aromatic), (3, 6));
+
+        let s = Smarts::parse("[#8H2]".to_owned());
+        assert_eq!(
+            OpenffMolecule::new(&Molecule::try_from(&s).unwrap()),
+            Err(MoleculeError::ImplicitHydrogens(0).into())
+        );
+    }
+}
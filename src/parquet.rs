@@ -0,0 +1,182 @@
+//! Reading and writing Parquet tables through `pyarrow` in the embedded
+//! Python interpreter.
+//!
+//! Tables are read column by column: only the columns asked for are loaded,
+//! and each one is extracted from its Python list straight into a Rust
+//! vector. They are written from JSON-like rows, one map from column name to
+//! value per row, with the column types inferred by pyarrow
+
+use std::path::Path;
+
+use pyo3::{
+    exceptions::PyImportError,
+    prelude::PyAnyMethods,
+    types::{PyDict, PyModule},
+    Bound, FromPyObject, Py, PyAny, Python,
+};
+use serde_json::{Map, Value};
+
+use crate::error::ChomperError;
+
+/// One row of a table, keyed by column name
+pub type Row = Map<String, Value>;
+
+/// A Parquet table read by [read_parquet], held by the Python interpreter
+/// until its columns are extracted
+pub struct Table {
+    table: Py<PyAny>,
+
+    /// the names of the columns that were read
+    pub columns: Vec<String>,
+
+    /// the number of rows in the table
+    pub num_rows: usize,
+}
+
+impl Table {
+    /// the list of values in column `name`, or `None` if it wasn't read
+    fn values<'py>(
+        &self,
+        py: Python<'py>,
+        name: &str,
+    ) -> Result<Option<Bound<'py, PyAny>>, ChomperError> {
+        if !self.columns.iter().any(|c| c == name) {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.table
+                .bind(py)
+                .call_method1("column", (name,))?
+                .call_method0("to_pylist")?,
+        ))
+    }
+
+    /// the values in column `name`, one per row with `None` for nulls, or
+    /// all `None` if the column wasn't read. fails if a value can't be
+    /// extracted as a `T`
+    pub fn column<T>(&self, name: &str) -> Result<Vec<Option<T>>, ChomperError>
+    where
+        T: for<'py> FromPyObject<'py>,
+    {
+        Python::with_gil(|py| {
+            let Some(values) = self.values(py, name)? else {
+                return Ok(self.nulls());
+            };
+            values.extract().map_err(|e| {
+                ChomperError::Format(format!("in column {name}: {e}"))
+            })
+        })
+    }
+
+    /// like [Table::column] for strings, but converting values of any other
+    /// type to strings with Python's `str`, so integer IDs come through too
+    pub fn strings(
+        &self,
+        name: &str,
+    ) -> Result<Vec<Option<String>>, ChomperError> {
+        Python::with_gil(|py| {
+            let Some(values) = self.values(py, name)? else {
+                return Ok(self.nulls());
+            };
+            let mut ret = Vec::with_capacity(self.num_rows);
+            for v in values.iter()? {
+                let v = v?;
+                ret.push(if v.is_none() {
+                    None
+                } else if let Ok(s) = v.extract() {
+                    Some(s)
+                } else {
+                    Some(v.str()?.to_string())
+                });
+            }
+            Ok(ret)
+        })
+    }
+
+    /// the values in column `name` as JSON, one per row with
+    /// [Value::Null] for nulls, or all nulls if the column wasn't read.
+    /// values JSON can't represent, like timestamps, are converted to strings
+    /// with Python's `str`
+    pub fn json(&self, name: &str) -> Result<Vec<Value>, ChomperError> {
+        Python::with_gil(|py| {
+            let Some(values) = self.values(py, name)? else {
+                return Ok(vec![Value::Null; self.num_rows]);
+            };
+            let json = PyModule::import_bound(py, "json")?;
+            let kwargs = PyDict::new_bound(py);
+            let str = PyModule::import_bound(py, "builtins")?.getattr("str")?;
+            kwargs.set_item("default", str)?;
+            let s: String = json
+                .call_method("dumps", (values,), Some(&kwargs))?
+                .extract()?;
+            Ok(serde_json::from_str(&s)?)
+        })
+    }
+
+    /// a column of nulls, standing in for one that wasn't read
+    fn nulls<T>(&self) -> Vec<Option<T>> {
+        std::iter::repeat_with(|| None)
+            .take(self.num_rows)
+            .collect()
+    }
+}
+
+fn pyarrow<'py>(
+    py: Python<'py>,
+    module: &str,
+) -> Result<Bound<'py, PyModule>, ChomperError> {
+    PyModule::import_bound(py, module).map_err(|e| {
+        PyImportError::new_err(format!(
+            "Parquet support requires the Python module pyarrow: {e}"
+        ))
+        .into()
+    })
+}
+
+/// read the Parquet file at `path`, loading only those of `columns` that it
+/// has, or every column if `columns` is `None`
+pub fn read_parquet(
+    path: impl AsRef<Path>,
+    columns: Option<&[&str]>,
+) -> Result<Table, ChomperError> {
+    let path = path.as_ref();
+    Python::with_gil(|py| {
+        let pq = pyarrow(py, "pyarrow.parquet")?;
+        let names: Vec<String> = pq
+            .call_method1("read_schema", (path,))?
+            .getattr("names")?
+            .extract()?;
+        let columns: Vec<String> = names
+            .into_iter()
+            .filter(|n| columns.is_none_or(|c| c.contains(&n.as_str())))
+            .collect();
+        let kwargs = PyDict::new_bound(py);
+        kwargs.set_item("columns", &columns)?;
+        let table = pq.call_method("read_table", (path,), Some(&kwargs))?;
+        Ok(Table {
+            num_rows: table.getattr("num_rows")?.extract()?,
+            table: table.unbind(),
+            columns,
+        })
+    })
+}
+
+/// write `rows` to `path` as a Parquet file, with the column types inferred
+/// by pyarrow from the values
+pub fn write_parquet(
+    path: impl AsRef<Path>,
+    rows: &[Row],
+) -> Result<(), ChomperError> {
+    let path = path.as_ref();
+    let s = serde_json::to_string(rows)?;
+    Python::with_gil(|py| -> Result<_, ChomperError> {
+        let pa = pyarrow(py, "pyarrow")?;
+        let pq = pyarrow(py, "pyarrow.parquet")?;
+        let json = PyModule::import_bound(py, "json")?;
+        let rows = json.call_method1("loads", (s,))?;
+        let table =
+            pa.getattr("Table")?.call_method1("from_pylist", (rows,))?;
+        pq.call_method1("write_table", (table, path))?;
+        Ok(())
+    })
+}
@@ -0,0 +1,172 @@
+//! Perceiving bonds from 3D geometries and checking them against the graph a
+//! molecule was built from.
+//!
+//! Two atoms are bonded when they are closer than the sum of their covalent
+//! radii plus a tolerance. This is crude compared to the perception done by
+//! quantum chemistry packages, but it is enough to catch an optimization that
+//! moved a proton or opened a ring, which is what the validator is for
+
+use std::fmt::Display;
+
+use crate::{
+    conformer::Conformer,
+    elements,
+    molecule::{Molecule, MoleculeError},
+};
+
+/// The default allowance, in Ångström, beyond the sum of two covalent radii
+/// for two atoms to be considered bonded
+pub const DEFAULT_TOLERANCE: f64 = 0.4;
+
+/// return the pairs of positions in `conformer` close enough to be bonded,
+/// with the first index of each pair less than the second. `atomic_numbers`
+/// gives the element at each position, and atoms without a known covalent
+/// radius are never bonded
+pub fn perceive_bonds(
+    atomic_numbers: &[usize],
+    conformer: &Conformer,
+    tolerance: f64,
+) -> Vec<(usize, usize)> {
+    let radii: Vec<_> = atomic_numbers
+        .iter()
+        .map(|&n| elements::covalent_radius(n))
+        .collect();
+    let mut ret = Vec::new();
+    for i in 0..radii.len() {
+        for j in i + 1..radii.len() {
+            let (Some(a), Some(b)) = (radii[i], radii[j]) else {
+                continue;
+            };
+            if conformer.distance(i, j) < a + b + tolerance {
+                ret.push((i, j));
+            }
+        }
+    }
+    ret
+}
+
+/// A disagreement between the bonds of a molecule and those perceived from a
+/// geometry. Atom indices are positions in the molecule
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BondMismatch {
+    /// a bond in the molecule whose atoms are too far apart in the geometry
+    Broken(usize, usize),
+    /// a pair of atoms close enough to be bonded without a bond in the
+    /// molecule
+    Formed(usize, usize),
+}
+
+impl Display for BondMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BondMismatch::Broken(i, j) => write!(f, "bond {i}-{j} is broken"),
+            BondMismatch::Formed(i, j) => {
+                write!(f, "unexpected bond {i}-{j}")
+            }
+        }
+    }
+}
+
+/// compare the bonds of `mol` to those perceived from `conformer`. if every
+/// atom in `mol` is mapped, atom map `n` is taken to be at position `n - 1` in
+/// the conformer, as in QCArchive records ordered by their cmiles, otherwise
+/// the conformer is in the same order as the atoms. bonds to atoms without a
+/// known covalent radius are not compared. an empty result means the geometry
+/// still matches the molecule
+pub fn validate(
+    mol: &Molecule,
+    conformer: &Conformer,
+    tolerance: f64,
+) -> Result<Vec<BondMismatch>, MoleculeError> {
+    let n = mol.atoms.len();
+    if conformer.len() != n {
+        return Err(MoleculeError::ConformerSize {
+            n_atoms: n,
+            n_positions: conformer.len(),
+        });
+    }
+    // molecule position -> conformer position
+    let order = mol.map_order();
+    let mut atomic_numbers = vec![0; n];
+    let mut inverse = vec![0; n];
+    for (i, &j) in order.iter().enumerate() {
+        atomic_numbers[j] = mol.atoms[i].atomic_number;
+        inverse[j] = i;
+    }
+
+    let sorted = |i: usize, j: usize| (i.min(j), i.max(j));
+    let mut perceived: Vec<_> =
+        perceive_bonds(&atomic_numbers, conformer, tolerance)
+            .into_iter()
+            .map(|(i, j)| sorted(inverse[i], inverse[j]))
+            .collect();
+    perceived.sort();
+    let known = |i: usize| {
+        elements::covalent_radius(mol.atoms[i].atomic_number).is_some()
+    };
+    let mut graph: Vec<_> = mol
+        .bonds
+        .iter()
+        .filter(|b| known(b.atom1) && known(b.atom2))
+        .map(|b| sorted(b.atom1, b.atom2))
+        .collect();
+    graph.sort();
+
+    let mut ret: Vec<_> = graph
+        .iter()
+        .filter(|b| perceived.binary_search(b).is_err())
+        .map(|&(i, j)| BondMismatch::Broken(i, j))
+        .chain(
+            perceived
+                .iter()
+                .filter(|b| graph.binary_search(b).is_err())
+                .map(|&(i, j)| BondMismatch::Formed(i, j)),
+        )
+        .collect();
+    ret.sort();
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{conformer::LengthUnit, smarts::Smarts};
+
+    use super::*;
+
+    fn water() -> Molecule {
+        let s = Smarts::parse("[#1:2]-[#8:1]-[#1:3]".to_owned());
+        Molecule::try_from(&s).unwrap()
+    }
+
+    #[test]
+    fn perceive() {
+        // map order: O, H, H
+        let conf = Conformer::new(
+            vec![[0.0, 0.0, 0.0], [0.96, 0.0, 0.0], [-0.24, 0.93, 0.0]],
+            LengthUnit::Angstrom,
+        );
+        let got = perceive_bonds(&[8, 1, 1], &conf, DEFAULT_TOLERANCE);
+        assert_eq!(got, [(0, 1), (0, 2)]);
+        assert!(validate(&water(), &conf, DEFAULT_TOLERANCE)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn mismatches() {
+        // one H has wandered off and the other is bonded to it instead
+        let conf = Conformer::new(
+            vec![[0.0, 0.0, 0.0], [0.96, 0.0, 0.0], [1.6, 0.0, 0.0]],
+            LengthUnit::Angstrom,
+        );
+        let got = validate(&water(), &conf, DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(
+            got,
+            [BondMismatch::Broken(1, 2), BondMismatch::Formed(0, 2)]
+        );
+        assert_eq!(got[0].to_string(), "bond 1-2 is broken");
+
+        let conf = Conformer::new(vec![[0.0; 3]], LengthUnit::Angstrom);
+        assert!(validate(&water(), &conf, DEFAULT_TOLERANCE).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+//! Processing a [Dataset] in fixed-size chunks, so that only one chunk of
+//! converted and parsed molecules is alive at a time, no matter how large the
+//! dataset is.
+//!
+//! Each record goes through the same stages as [Dataset::parse]: its cmiles
+//! is converted to SMARTS, which is then parsed into a [Smarts]. Once a chunk
+//! is full, it is handed to the callback and dropped before the next one is
+//! started. A record that fails to convert or parse shows up in its chunk as
+//! a [ChomperError] naming the record, and the rest of the run carries on.
+//!
+//! Each chunk is split into one contiguous batch per thread, and each thread
+//! converts its whole batch before parsing it, so that the default rdkit
+//! conversion takes the GIL once per batch with [rdkit::to_smarts_batch].
+//! The conversions still take turns on the GIL, but the parsing runs in
+//! parallel. The molecules come out in the same order whatever the number of
+//! threads
+
+use std::{path::Path, thread};
+
+use crate::{
+    error::ChomperError,
+    rdkit,
+    smarts::{InputKind, Smarts},
+    timing::{Stage, Timings},
+    Dataset, DatasetFormat, Provenance, Record,
+};
+
+/// The default number of records in each chunk
+pub const CHUNK_SIZE: usize = 1000;
+
+/// Converts the cmiles of each record into the string that is parsed
+#[derive(Clone, Copy, Debug)]
+enum Converter {
+    Each(fn(String) -> Result<String, ChomperError>),
+    Batch(fn(Vec<String>) -> Vec<Result<String, ChomperError>>),
+}
+
+impl Converter {
+    fn convert(&self, batch: Vec<String>) -> Vec<Result<String, ChomperError>> {
+        match self {
+            Converter::Each(f) => batch.into_iter().map(f).collect(),
+            Converter::Batch(f) => f(batch),
+        }
+    }
+}
+
+/// Chunked conversion and parsing of a dataset. See the module docs
+#[derive(Clone, Debug)]
+pub struct Pipeline {
+    chunk_size: usize,
+    threads: usize,
+    kind: InputKind,
+    convert: Converter,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            chunk_size: CHUNK_SIZE,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            kind: InputKind::Smiles,
+            convert: Converter::Batch(rdkit::to_smarts_batch),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// process `chunk_size` records at a time. panics if `chunk_size` is 0
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// split each chunk across `threads` threads, instead of one for each
+    /// available CPU. panics if `threads` is 0
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "thread count must be positive");
+        self.threads = threads;
+        self
+    }
+
+    /// parse the converted records with `kind` semantics
+    pub fn with_kind(mut self, kind: InputKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// convert each cmiles with `convert` instead of [rdkit::to_smarts], for
+    /// example to parse records that already hold SMARTS
+    pub fn with_converter(
+        mut self,
+        convert: fn(String) -> Result<String, ChomperError>,
+    ) -> Self {
+        self.convert = Converter::Each(convert);
+        self
+    }
+
+    /// like [Pipeline::with_converter], but with `convert` called once on
+    /// each thread's whole batch of cmiles, returning one string for each
+    pub fn with_batch_converter(
+        mut self,
+        convert: fn(Vec<String>) -> Vec<Result<String, ChomperError>>,
+    ) -> Self {
+        self.convert = Converter::Batch(convert);
+        self
+    }
+
+    /// convert and parse `dataset` one chunk at a time, in the order of
+    /// [Dataset::parse], passing each chunk to `f`. the last chunk may be
+    /// short. stops at the first error returned by `f`, and otherwise returns
+    /// the number of records processed
+    pub fn run<E>(
+        &self,
+        dataset: &Dataset,
+        f: impl FnMut(Vec<Result<Smarts, ChomperError>>) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        self.run_timed(dataset, &mut Timings::default(), f)
+    }
+
+    /// like [Pipeline::run], but record the time spent in each stage in
+    /// `timings`. time spent in `f` is not recorded, and the times of the
+    /// threads are added together, so they can exceed the wall-clock time
+    pub fn run_timed<E>(
+        &self,
+        dataset: &Dataset,
+        timings: &mut Timings,
+        mut f: impl FnMut(Vec<Result<Smarts, ChomperError>>) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        let mut n = 0;
+        for (key, recs) in &dataset.entries {
+            for rec in recs {
+                chunk.push((key.as_str(), rec));
+                n += 1;
+                if chunk.len() == self.chunk_size {
+                    f(self.process(&chunk, timings))?;
+                    chunk.clear();
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            f(self.process(&chunk, timings))?;
+        }
+        Ok(n)
+    }
+
+    /// convert and parse the records of `chunk`, given with the names of
+    /// their entries, across the threads
+    fn process(
+        &self,
+        chunk: &[(&str, &Record)],
+        timings: &mut Timings,
+    ) -> Vec<Result<Smarts, ChomperError>> {
+        let size = chunk.len().div_ceil(self.threads);
+        if size == chunk.len() {
+            return self.process_batch(chunk, timings);
+        }
+        thread::scope(|s| {
+            let handles: Vec<_> = chunk
+                .chunks(size)
+                .map(|batch| {
+                    s.spawn(move || {
+                        let mut timings = Timings::default();
+                        (self.process_batch(batch, &mut timings), timings)
+                    })
+                })
+                .collect();
+            let mut ret = Vec::with_capacity(chunk.len());
+            for h in handles {
+                let (mols, t) = h.join().unwrap();
+                ret.extend(mols);
+                timings.merge(t);
+            }
+            ret
+        })
+    }
+
+    /// convert and parse `batch` on the current thread
+    fn process_batch(
+        &self,
+        batch: &[(&str, &Record)],
+        timings: &mut Timings,
+    ) -> Vec<Result<Smarts, ChomperError>> {
+        let cmiles = batch.iter().map(|(_, rec)| rec.cmiles.clone()).collect();
+        let converted =
+            timings.time(Stage::Convert, || self.convert.convert(cmiles));
+        batch
+            .iter()
+            .zip(converted)
+            .map(|(&(key, rec), smarts)| {
+                let provenance = Provenance {
+                    file: rec.file.clone(),
+                    dataset_key: key.to_owned(),
+                    record_id: rec.record_id.clone(),
+                };
+                smarts
+                    .and_then(|s| Smarts::parse_timed(s, self.kind, timings))
+                    .map(|s| s.with_provenance(provenance.clone()))
+                    .map_err(|e| e.in_record(provenance))
+            })
+            .collect()
+    }
+
+    /// like [Pipeline::run], but read the dataset at `path` with
+    /// [Dataset::stream], so that only one chunk of records is in memory at a
+    /// time. the records come in the order of the file rather than of
+    /// [Dataset::parse]. only JSON datasets can be streamed, so the other
+    /// formats are loaded whole and [Pipeline::run] instead. an error reading
+    /// the file stops the run like an error from `f`
+    pub fn run_file<E: From<ChomperError>>(
+        &self,
+        path: impl AsRef<Path>,
+        mut f: impl FnMut(Vec<Result<Smarts, ChomperError>>) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        let path = path.as_ref();
+        if DatasetFormat::from_path(path) != DatasetFormat::Json {
+            return self.run(&Dataset::load(path)?, f);
+        }
+        let mut timings = Timings::default();
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        let mut n = 0;
+        let mut flush = |chunk: &mut Vec<(String, Record)>| {
+            let refs: Vec<_> =
+                chunk.iter().map(|(key, rec)| (key.as_str(), rec)).collect();
+            let ret = f(self.process(&refs, &mut timings));
+            chunk.clear();
+            ret
+        };
+        for item in Dataset::stream(path)? {
+            chunk.push(item?);
+            n += 1;
+            if chunk.len() == self.chunk_size {
+                flush(&mut chunk)?;
+            }
+        }
+        if !chunk.is_empty() {
+            flush(&mut chunk)?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset() -> Dataset {
+        serde_json::from_str(
+            r#"{"entries": {
+                "b": [
+                    {"cmiles": "[#6H4]", "record_id": "1"},
+                    {"cmiles": "[#7H3]", "record_id": "2"}
+                ],
+                "a": [
+                    {"cmiles": "[#8H2]", "record_id": "3"},
+                    {"cmiles": "[#6H3]-[#8H]", "record_id": "4"},
+                    {"cmiles": "[#16H2]", "record_id": "5"}
+                ]
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn chunks() {
+        let pipeline = Pipeline::new().with_chunk_size(2).with_converter(Ok);
+        let mut sizes = Vec::new();
+        let mut ids = Vec::new();
+        let n = pipeline
+            .run(&dataset(), |chunk| {
+                sizes.push(chunk.len());
+                ids.extend(chunk.into_iter().map(|s| {
+                    s.unwrap().provenance.unwrap().record_id.unwrap()
+                }));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(n, 5);
+        assert_eq!(sizes, [2, 2, 1]);
+        assert_eq!(ids, ["3", "4", "5", "1", "2"]);
+
+        // the same molecules in the same order on any number of threads
+        let parse = |pipeline: Pipeline| {
+            let mut ret = Vec::new();
+            pipeline
+                .run(&dataset(), |chunk| {
+                    ret.extend(chunk.into_iter().map(Result::unwrap));
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+            ret
+        };
+        let serial = parse(pipeline.clone().with_threads(1));
+        let parallel =
+            parse(pipeline.clone().with_chunk_size(5).with_threads(3));
+        assert_eq!(parallel, serial);
+        let batched = pipeline
+            .clone()
+            .with_batch_converter(|b| b.into_iter().map(Ok).collect())
+            .with_threads(4);
+        assert_eq!(parse(batched), serial);
+
+        // an error from the callback stops the run
+        let mut calls = 0;
+        let got = pipeline.run(&dataset(), |_| {
+            calls += 1;
+            Err("stop")
+        });
+        assert_eq!(got, Err("stop"));
+        assert_eq!(calls, 1);
+
+        // a bad record is reported in its chunk without stopping the run
+        let dataset: Dataset = serde_json::from_str(
+            r#"{"entries": {"a": [{"cmiles": "[#6H4]"}, {"cmiles": "[#6H4]~"}]}}"#,
+        )
+        .unwrap();
+        let mut failed = Vec::new();
+        let n = pipeline
+            .run(&dataset, |chunk| {
+                failed.extend(chunk.into_iter().filter_map(Result::err));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].to_string().starts_with("record ? of a: "));
+    }
+
+    #[test]
+    fn streamed() {
+        let path = std::env::temp_dir().join("chomper_pipeline_streamed.json");
+        std::fs::write(&path, serde_json::to_string(&dataset()).unwrap())
+            .unwrap();
+        let pipeline = Pipeline::new().with_chunk_size(2).with_converter(Ok);
+        let mut sizes = Vec::new();
+        let mut smarts = Vec::new();
+        let n = pipeline
+            .run_file(&path, |chunk| {
+                sizes.push(chunk.len());
+                smarts.extend(chunk.into_iter().map(|s| s.unwrap()));
+                Ok::<_, ChomperError>(())
+            })
+            .unwrap();
+        assert_eq!(n, 5);
+        assert_eq!(sizes, [2, 2, 1]);
+        let mut want = Vec::new();
+        pipeline
+            .run(&Dataset::load(&path).unwrap(), |chunk| {
+                want.extend(chunk.into_iter().map(|s| s.unwrap()));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        // the same molecules, though not necessarily in the same order
+        for s in &want {
+            assert!(smarts.contains(s));
+        }
+
+        // a truncated file stops the run
+        std::fs::write(&path, r#"{"entries": {"a": [{"cmiles": "C"}"#).unwrap();
+        assert!(pipeline
+            .run_file(&path, |_| Ok::<_, ChomperError>(()))
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
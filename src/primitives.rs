@@ -0,0 +1,260 @@
+//! Statistics on the primitives decorating the atoms of a set of SMARTS or
+//! SMIRKS patterns, grouped by element.
+//!
+//! This works on the [crate::syntax] token stream rather than parsed
+//! [crate::smarts::Smarts], so it accepts every primitive in the query syntax,
+//! including the ring and connectivity primitives that chomper's own parser
+//! doesn't support. The contents of recursive SMARTS are counted only as a
+//! single `$` primitive on the atom containing them. Reviewing these counts
+//! shows whether a generated hierarchy repeats decorators, like `+0` or `X4`,
+//! that carry no information for the elements they are attached to
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{
+    elements,
+    syntax::{tokenize, Token, TokenKind},
+};
+
+/// The label for atoms with no element, like `*` or `[X4]`
+pub const ANY_ELEMENT: &str = "*";
+
+/// The primitives used on the atoms of one element
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ElementStats {
+    /// the number of atoms of this element
+    pub n_atoms: usize,
+    /// primitive name -> the number of atoms using it. the names are `H` for
+    /// H counts, `charge`, `@` for chirality, `$` for recursive SMARTS, `!`
+    /// for negations, `wildcard` for `*`, `a`, and `A` alongside an element,
+    /// and the letter of any other primitive, like `X` or `r`
+    pub primitives: BTreeMap<String, usize>,
+    /// primitive text -> the number of atoms using it, like `H1` or `X4`
+    pub values: BTreeMap<String, usize>,
+}
+
+impl ElementStats {
+    /// the mean number of distinct primitives on each atom
+    pub fn mean_primitives(&self) -> f64 {
+        if self.n_atoms == 0 {
+            return 0.0;
+        }
+        let total: usize = self.primitives.values().sum();
+        total as f64 / self.n_atoms as f64
+    }
+}
+
+/// The result of [primitive_stats], keyed by element symbol, or
+/// [ANY_ELEMENT]. An atom listing several elements, like `[#6,#7]`, counts
+/// once for each of them, but a negated element, like the `#7` in `[#6;!#7]`,
+/// only counts as a `!` primitive, so `[!#6]` is an atom of [ANY_ELEMENT]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrimitiveStats {
+    pub n_patterns: usize,
+    pub elements: BTreeMap<String, ElementStats>,
+}
+
+/// the name of the primitive category for `tok`, or `None` if it is not a
+/// primitive
+fn primitive_name(tok: &Token) -> Option<String> {
+    use TokenKind as K;
+    let name = match tok.kind {
+        K::HCount => "H".to_owned(),
+        K::Charge => "charge".to_owned(),
+        K::Chirality => "@".to_owned(),
+        K::Recursive => "$".to_owned(),
+        K::Wildcard => "wildcard".to_owned(),
+        K::Operator if tok.text == "!" => "!".to_owned(),
+        K::Primitive => tok.text[..1].to_owned(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// the element symbol for an element token, like `C` for `#6`, `C`, or `c`
+fn element_symbol(text: &str) -> String {
+    match text.strip_prefix('#') {
+        Some(n) => n
+            .parse()
+            .ok()
+            .and_then(elements::symbol)
+            .unwrap_or(text)
+            .to_owned(),
+        None => {
+            let mut chars = text.chars();
+            let first = chars.next().unwrap_or_default().to_ascii_uppercase();
+            format!("{first}{}", chars.as_str())
+        }
+    }
+}
+
+impl PrimitiveStats {
+    /// tally the primitives in `pattern`
+    pub fn add(&mut self, pattern: &str) {
+        use TokenKind as K;
+        self.n_patterns += 1;
+        let tokens = tokenize(pattern);
+        let mut tokens = tokens.iter().filter(|t| !t.kind.is_trivia());
+        while let Some(tok) = tokens.next() {
+            match tok.kind {
+                K::Element | K::AromaticElement => {
+                    let sym = element_symbol(tok.text);
+                    self.elements.entry(sym).or_default().n_atoms += 1;
+                }
+                K::Wildcard => {
+                    let stats = self.elements.entry(ANY_ELEMENT.to_owned());
+                    stats.or_default().n_atoms += 1;
+                }
+                K::Bracket if tok.text == "[" => {
+                    let mut elems = Vec::new();
+                    // every value of each category, since an atom can list
+                    // several, like the X3 and X4 in `[#6;X3,X4]`
+                    let mut prims: BTreeMap<_, Vec<_>> = BTreeMap::new();
+                    let mut depth = 0;
+                    let mut not = false;
+                    for tok in tokens.by_ref() {
+                        let is_not = tok.kind == K::Operator && tok.text == "!";
+                        let negated = std::mem::replace(&mut not, is_not);
+                        match tok.kind {
+                            K::Paren if tok.text == "(" => depth += 1,
+                            K::Paren => depth -= 1,
+                            _ if depth > 0 => {}
+                            K::Bracket => break,
+                            K::Element | K::AromaticElement if negated => {}
+                            K::Element | K::AromaticElement => {
+                                elems.push(element_symbol(tok.text))
+                            }
+                            _ => {
+                                if let Some(name) = primitive_name(tok) {
+                                    prims
+                                        .entry(name)
+                                        .or_default()
+                                        .push(tok.text);
+                                }
+                            }
+                        }
+                    }
+                    if elems.is_empty() {
+                        elems.push(ANY_ELEMENT.to_owned());
+                        prims.remove("wildcard");
+                    }
+                    elems.sort();
+                    elems.dedup();
+                    for texts in prims.values_mut() {
+                        texts.sort();
+                        texts.dedup();
+                    }
+                    for elem in elems {
+                        let stats = self.elements.entry(elem).or_default();
+                        stats.n_atoms += 1;
+                        for (name, texts) in &prims {
+                            let n = stats.primitives.entry(name.clone());
+                            *n.or_default() += 1;
+                            for text in texts {
+                                let n = stats.values.entry(text.to_string());
+                                *n.or_default() += 1;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// count the primitives on each element across `patterns`
+pub fn primitive_stats<'a>(
+    patterns: impl IntoIterator<Item = &'a str>,
+) -> PrimitiveStats {
+    let mut ret = PrimitiveStats::default();
+    for p in patterns {
+        ret.add(p);
+    }
+    ret
+}
+
+/// Display one block per element with its atom count, the mean number of
+/// primitives per atom, and the number and share of atoms using each
+/// primitive value, most common first
+impl Display for PrimitiveStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} patterns", self.n_patterns)?;
+        for (elem, stats) in &self.elements {
+            writeln!(
+                f,
+                "{elem}: {} atoms, {:.2} primitives per atom",
+                stats.n_atoms,
+                stats.mean_primitives()
+            )?;
+            let mut values: Vec<_> = stats.values.iter().collect();
+            values.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (value, count) in values {
+                let pct = 100.0 * *count as f64 / stats.n_atoms as f64;
+                writeln!(f, "    {value:<8} {count:>8} {pct:>6.1}%")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_element() {
+        let got = primitive_stats([
+            "[#6X4:1]-[#6X4+0:2]",
+            "[#6H1:1]=[#8:2]",
+            "[#7,#8;!$(*=O):1]~[*:2]C",
+            "[c:1]:[X3:2]",
+        ]);
+        assert_eq!(got.n_patterns, 4);
+
+        let c = &got.elements["C"];
+        assert_eq!(c.n_atoms, 5);
+        assert_eq!(
+            c.primitives,
+            BTreeMap::from([
+                ("H".to_owned(), 1),
+                ("X".to_owned(), 2),
+                ("charge".to_owned(), 1),
+            ])
+        );
+        assert_eq!(c.values["X4"], 2);
+        assert_eq!(c.mean_primitives(), 0.8);
+
+        // the recursive SMARTS counts once on each listed element, without
+        // its own atoms
+        let n = &got.elements["N"];
+        assert_eq!(n.n_atoms, 1);
+        assert_eq!(n.primitives["$"], 1);
+        assert_eq!(n.primitives["!"], 1);
+        assert_eq!(got.elements["O"].n_atoms, 2);
+
+        let any = &got.elements[ANY_ELEMENT];
+        assert_eq!(any.n_atoms, 2);
+        assert_eq!(any.primitives, BTreeMap::from([("X".to_owned(), 1)]));
+
+        let s = got.to_string();
+        assert!(s.starts_with("4 patterns\n*: 2 atoms, 0.50 primitives"));
+        assert!(s.contains("\n    X4              2   40.0%\n"));
+
+        // every value in a list is counted, but the category only once
+        let got = primitive_stats(["[#6;X3,X4:1]"]);
+        let c = &got.elements["C"];
+        assert_eq!(c.primitives, BTreeMap::from([("X".to_owned(), 1)]));
+        assert_eq!((c.values["X3"], c.values["X4"]), (1, 1));
+
+        // negated elements are not atoms of that element
+        let got = primitive_stats(["[!#6:1]-[#6;!#7:2]", "[!#1]"]);
+        assert_eq!(got.elements.keys().collect::<Vec<_>>(), ["*", "C"]);
+        let any = &got.elements[ANY_ELEMENT];
+        assert_eq!(any.n_atoms, 2);
+        assert_eq!(any.primitives, BTreeMap::from([("!".to_owned(), 2)]));
+        let c = &got.elements["C"];
+        assert_eq!(c.n_atoms, 1);
+        assert_eq!(c.primitives, BTreeMap::from([("!".to_owned(), 1)]));
+    }
+}
@@ -0,0 +1,383 @@
+//! A client for fetching datasets from a [QCFractal] server, like the public
+//! QCArchive instance, by collection name.
+//!
+//! Requests go through `urllib` in the embedded Python interpreter, like the
+//! rest of chomper's I/O that needs more than the standard library, and use
+//! the JSON form of the QCFractal v1 REST API:
+//!
+//! 1. `POST api/v1/datasets/query` to look up the dataset ID from its type and
+//!    name
+//! 2. `GET api/v1/datasets/{type}/{id}/entry_names` for its entries
+//! 3. `POST .../entries/bulkFetch` for the molecule of each entry
+//! 4. `POST .../records/bulkFetch` for the record computed for each entry
+//!    under a specification. The computed data, like the status and energies,
+//!    sits in the nested `record` object, which the server only fills in when
+//!    it is asked for with `include`
+//! 5. `POST api/v1/molecules/bulkGet` for the final molecule of each
+//!    optimization, which a record only refers to by ID
+//!
+//! The resulting [Dataset] has the same layout as one exported from QCArchive:
+//! a single entry named by the server address, with one record for each
+//! entry and specification pair whose computation is complete
+//!
+//! [QCFractal]: https://github.com/MolSSI/QCFractal
+
+use std::collections::HashMap;
+
+use pyo3::{prelude::PyAnyMethods, types::PyModule, Python};
+use serde_json::{json, Map, Value};
+
+use crate::{error::ChomperError, Dataset, Record};
+
+/// The address of the public QCArchive server
+pub const QCARCHIVE: &str = "https://api.qcarchive.molssi.org:443/";
+
+/// The number of entries requested at once in the bulk fetches
+pub const BATCH_SIZE: usize = 250;
+
+/// The identifier holding the cmiles of a QCArchive molecule
+const CMILES: &str = "canonical_isomeric_explicit_hydrogen_mapped_smiles";
+
+/// A connection to one QCFractal server
+pub struct Client {
+    address: String,
+}
+
+impl Client {
+    /// a client for the server at `address`, like [QCARCHIVE]
+    pub fn new(address: impl Into<String>) -> Self {
+        let mut address = address.into();
+        if !address.ends_with('/') {
+            address.push('/');
+        }
+        Self { address }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// send a request for `path` on the server, with `body` as its JSON
+    /// payload if given, and return the decoded JSON response
+    fn request(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, ChomperError> {
+        let url = format!("{}{path}", self.address);
+        let text: String = Python::with_gil(|py| -> Result<_, ChomperError> {
+            let request = PyModule::import_bound(py, "urllib.request")?;
+            let data = body.map(|b| b.to_string().into_bytes());
+            let method = if data.is_some() { "POST" } else { "GET" };
+            let headers = HashMap::from([
+                ("Accept", "application/json"),
+                ("Content-Type", "application/json"),
+            ]);
+            let req = request.call_method1(
+                "Request",
+                (&url, data.as_deref(), headers, None::<&str>, false, method),
+            )?;
+            let resp =
+                request.call_method1("urlopen", (req,)).map_err(|e| {
+                    ChomperError::Request {
+                        url: url.clone(),
+                        message: e.to_string(),
+                    }
+                })?;
+            Ok(resp
+                .call_method0("read")?
+                .call_method1("decode", ("utf-8",))?
+                .extract()?)
+        })?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// the ID of the dataset of type `dataset_type`, like `optimization` or
+    /// `torsiondrive`, named `name`
+    fn dataset_id(
+        &self,
+        dataset_type: &str,
+        name: &str,
+    ) -> Result<u64, ChomperError> {
+        let body = json!({"dataset_type": dataset_type, "dataset_name": name});
+        let resp = self.request("api/v1/datasets/query", Some(&body))?;
+        resp.as_array()
+            .and_then(|ds| ds.first())
+            .and_then(|d| d["id"].as_u64())
+            .ok_or_else(|| {
+                ChomperError::Format(format!(
+                    "no {dataset_type} dataset named {name} on {}",
+                    self.address
+                ))
+            })
+    }
+
+    /// fetch the `dataset_type` dataset named `name` from the server, keeping
+    /// the records computed with the specification `spec`, like `default`
+    pub fn fetch(
+        &self,
+        dataset_type: &str,
+        name: &str,
+        spec: &str,
+    ) -> Result<Dataset, ChomperError> {
+        let id = self.dataset_id(dataset_type, name)?;
+        let base = format!("api/v1/datasets/{dataset_type}/{id}");
+        let names = self.request(&format!("{base}/entry_names"), None)?;
+        let names: Vec<String> = serde_json::from_value(names)?;
+        let mut entries = Vec::new();
+        let mut records = Vec::new();
+        for batch in names.chunks(BATCH_SIZE) {
+            let body = json!({"names": batch, "missing_ok": false});
+            let resp = self
+                .request(&format!("{base}/entries/bulkFetch"), Some(&body))?;
+            entries.extend(as_array(resp)?);
+            let body = json!({
+                "entry_names": batch,
+                "specification_names": [spec],
+                "include": ["*", "record"],
+            });
+            let resp = self
+                .request(&format!("{base}/records/bulkFetch"), Some(&body))?;
+            records.extend(as_array(resp)?);
+        }
+        let ids: Vec<u64> = records
+            .iter()
+            .filter(|r| is_complete(r))
+            .filter_map(|r| r["record"]["final_molecule_id"].as_u64())
+            .collect();
+        let mut molecules = HashMap::new();
+        for batch in ids.chunks(BATCH_SIZE) {
+            let body = json!({"ids": batch, "missing_ok": true});
+            let resp = self.request("api/v1/molecules/bulkGet", Some(&body))?;
+            molecules.extend(batch.iter().copied().zip(as_array(resp)?));
+        }
+        to_dataset(&self.address, &entries, &records, &molecules)
+    }
+}
+
+/// whether the record in the dataset record item `item` has finished
+/// successfully
+fn is_complete(item: &Value) -> bool {
+    item["record"]["status"] == "complete"
+}
+
+fn as_array(v: Value) -> Result<Vec<Value>, ChomperError> {
+    match v {
+        Value::Array(v) => Ok(v),
+        v => Err(ChomperError::Format(format!(
+            "expected a list in the response, found {v}"
+        ))),
+    }
+}
+
+/// the molecule of a dataset entry: the initial molecule of an optimization
+/// or the first of a torsion drive's, or the molecule of a singlepoint
+fn entry_molecule(entry: &Value) -> Option<&Value> {
+    [
+        &entry["initial_molecule"],
+        &entry["initial_molecules"][0],
+        &entry["molecule"],
+    ]
+    .into_iter()
+    .find(|m| m.is_object())
+}
+
+/// the dihedrals driven by a torsion drive `rec`, from its specification's
+/// keywords or else the additional keywords of its `entry`. other records
+/// have none. `rec` is the nested `record` of a dataset record item
+fn dihedrals(
+    entry: &Value,
+    rec: &Value,
+) -> Result<Vec<[usize; 4]>, ChomperError> {
+    let d = [
+        &rec["specification"]["keywords"]["dihedrals"],
+        &entry["additional_keywords"]["dihedrals"],
+    ]
+    .into_iter()
+    .find(|d| d.is_array());
+    match d {
+        Some(d) => Ok(serde_json::from_value(d.clone())?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// build a [Dataset] named by `address` from the JSON of the dataset
+/// `entries`, the dataset record items for them in `records`, and the final
+/// `molecules` of the records by ID. entries without a complete record are
+/// skipped, and the cmiles comes from the entry's attributes or else its
+/// molecule's identifiers
+fn to_dataset(
+    address: &str,
+    entries: &[Value],
+    records: &[Value],
+    molecules: &HashMap<u64, Value>,
+) -> Result<Dataset, ChomperError> {
+    let entries: HashMap<&str, &Value> = entries
+        .iter()
+        .filter_map(|e| Some((e["name"].as_str()?, e)))
+        .collect();
+    let mut recs = Vec::new();
+    for item in records {
+        let Some(name) = item["entry_name"].as_str() else {
+            return Err(ChomperError::Format(format!(
+                "record without an entry name: {item}"
+            )));
+        };
+        let Some(entry) = entries.get(name) else {
+            return Err(ChomperError::Format(format!(
+                "record for unknown entry {name}"
+            )));
+        };
+        let rec = &item["record"];
+        if !rec.is_object() {
+            return Err(ChomperError::Format(format!(
+                "no record data for entry {name}"
+            )));
+        }
+        if !is_complete(item) {
+            continue;
+        }
+        let mol = entry_molecule(entry);
+        let identifiers = mol.map(|m| &m["identifiers"]);
+        let cmiles = entry["attributes"][CMILES]
+            .as_str()
+            .or_else(|| identifiers?[CMILES].as_str())
+            .ok_or_else(|| {
+                ChomperError::Format(format!("no cmiles for entry {name}"))
+            })?;
+        let inchi_key = entry["attributes"]["inchi_key"]
+            .as_str()
+            .or_else(|| identifiers?["inchikey"].as_str());
+        let record_id = match &item["record_id"] {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        };
+        recs.push(Record {
+            cmiles: cmiles.to_owned(),
+            record_id,
+            molecular_charge: mol
+                .and_then(|m| m["molecular_charge"].as_f64())
+                .map(|q| q.round() as isize),
+            molecular_multiplicity: mol
+                .and_then(|m| m["molecular_multiplicity"].as_u64())
+                .map(|m| m as usize),
+            inchi_key: inchi_key.map(str::to_owned),
+            tags: Default::default(),
+            dihedrals: dihedrals(entry, rec)?,
+            geometry: match rec["final_molecule_id"]
+                .as_u64()
+                .and_then(|id| molecules.get(&id))
+            {
+                Some(m @ Value::Object(_)) => {
+                    Some(serde_json::from_value(m.clone())?)
+                }
+                _ => None,
+            },
+            energies: match &rec["energies"] {
+                e @ Value::Array(_) => serde_json::from_value(e.clone())?,
+                _ => Vec::new(),
+            },
+            file: None,
+            extras: Default::default(),
+        });
+    }
+    Ok(Dataset {
+        entries: [(address.to_owned(), recs)].into(),
+        extras: Map::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the JSON array in `testfiles/qcfractal/{name}.json`, laid out like a
+    /// response from the v1 API
+    fn response(name: &str) -> Vec<Value> {
+        let path = format!("testfiles/qcfractal/{name}.json");
+        let s = std::fs::read_to_string(path).unwrap();
+        as_array(serde_json::from_str(&s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn responses() {
+        let molecules: HashMap<u64, Value> = response("molecules")
+            .into_iter()
+            .map(|m| (m["id"].as_u64().unwrap(), m))
+            .collect();
+        let got = to_dataset(
+            QCARCHIVE,
+            &response("entries"),
+            &response("records"),
+            &molecules,
+        )
+        .unwrap();
+        // the methane record failed and the ammonia one is still running
+        let recs = &got.entries[QCARCHIVE];
+        assert_eq!(recs.len(), 1);
+        assert_eq!(recs[0].cmiles(), "[H:2][O:1][H:3]");
+        assert_eq!(recs[0].record_id(), Some("104321073"));
+        assert_eq!(recs[0].molecular_charge, Some(0));
+        assert_eq!(recs[0].molecular_multiplicity, Some(1));
+        assert_eq!(
+            recs[0].inchi_key.as_deref(),
+            Some("XLYOFNOQVPJJNP-UHFFFAOYSA-N")
+        );
+        assert_eq!(recs[0].energies.len(), 3);
+        let geometry = recs[0].geometry.as_ref().unwrap();
+        assert_eq!(geometry.symbols, ["O", "H", "H"]);
+        assert_eq!(geometry.geometry[2], -0.1242);
+        assert!(recs[0].dihedrals().is_empty());
+
+        // torsion drives take their molecule and dihedrals from the entry,
+        // and their cmiles from the molecule when the entry has none
+        let entries = json!([{
+            "name": "methane",
+            "initial_molecules": [
+                {"identifiers": {CMILES: "[C:1]([H:2])([H:3])([H:4])[H:5]"}}
+            ],
+            "additional_keywords": {"dihedrals": [[2, 1, 0, 3]]}
+        }]);
+        let records = json!([{
+            "entry_name": "methane",
+            "record_id": 8,
+            "record": {"id": 8, "status": "complete"}
+        }]);
+        let got = to_dataset(
+            QCARCHIVE,
+            entries.as_array().unwrap(),
+            records.as_array().unwrap(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let recs = &got.entries[QCARCHIVE];
+        assert_eq!(recs[0].cmiles(), "[C:1]([H:2])([H:3])([H:4])[H:5]");
+        assert_eq!(recs[0].dihedrals(), [[2, 1, 0, 3]]);
+        assert!(recs[0].geometry.is_none());
+
+        // a record item without the nested record was fetched without
+        // `include`
+        let bad = json!([{"entry_name": "methane", "record_id": 8}]);
+        let got = to_dataset(
+            QCARCHIVE,
+            entries.as_array().unwrap(),
+            bad.as_array().unwrap(),
+            &HashMap::new(),
+        );
+        assert!(got.is_err());
+        let bad = json!([{"entry_name": "missing", "record_id": 9}]);
+        let got = to_dataset(
+            QCARCHIVE,
+            &[],
+            bad.as_array().unwrap(),
+            &HashMap::new(),
+        );
+        assert!(got.is_err());
+
+        assert_eq!(
+            Client::new("http://localhost:7777").address(),
+            "http://localhost:7777/"
+        );
+    }
+}
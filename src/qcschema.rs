@@ -0,0 +1,99 @@
+//! Export of molecules in the [QCSchema] molecule format used by QCArchive.
+//!
+//! [QCSchema]: https://molssi-qc-schema.readthedocs.io
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conformer::{Conformer, LengthUnit},
+    elements,
+    molecule::{Molecule, MoleculeError},
+};
+
+/// A QCSchema molecule. Only the fields chomper can fill in are included
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QcMolecule {
+    pub schema_name: String,
+    pub schema_version: u32,
+    pub symbols: Vec<String>,
+    /// flattened coordinates in Bohr
+    pub geometry: Vec<f64>,
+    pub molecular_charge: f64,
+    pub molecular_multiplicity: usize,
+    /// `(atom1, atom2, bond order)` for each bond
+    pub connectivity: Vec<(usize, usize, f64)>,
+}
+
+impl QcMolecule {
+    /// build a QCSchema molecule from `mol` and `conformer`, whose positions
+    /// are in the same order as the atoms. QCSchema has no notion of implicit
+    /// hydrogens, so every hydrogen in `mol` must be an explicit atom
+    pub fn new(
+        mol: &Molecule,
+        conformer: &Conformer,
+    ) -> Result<Self, MoleculeError> {
+        if let Some(i) = mol.atoms.iter().position(|a| a.n_hydrogens > 0) {
+            return Err(MoleculeError::ImplicitHydrogens(i));
+        }
+        if conformer.len() != mol.atoms.len() {
+            return Err(MoleculeError::ConformerSize {
+                n_atoms: mol.atoms.len(),
+                n_positions: conformer.len(),
+            });
+        }
+        Ok(Self {
+            schema_name: "qcschema_molecule".to_owned(),
+            schema_version: 2,
+            symbols: mol
+                .atoms
+                .iter()
+                .map(|a| {
+                    elements::symbol(a.atomic_number).unwrap_or("X").to_owned()
+                })
+                .collect(),
+            geometry: conformer
+                .coordinates_in(LengthUnit::Bohr)
+                .into_iter()
+                .flatten()
+                .collect(),
+            molecular_charge: mol.charge as f64,
+            molecular_multiplicity: mol.multiplicity,
+            connectivity: mol
+                .bonds
+                .iter()
+                .map(|b| (b.atom1, b.atom2, b.bond_type.order()))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::Smarts;
+
+    use super::*;
+
+    #[test]
+    fn export() {
+        let s = Smarts::parse("[#8:1]-[#1:2]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap().with_charge(-1);
+        let conf =
+            Conformer::new(vec![[0.0; 3], [0.0, 0.0, 2.0]], LengthUnit::Bohr);
+        let got = QcMolecule::new(&mol, &conf).unwrap();
+        assert_eq!(got.symbols, ["O", "H"]);
+        assert_eq!(got.molecular_charge, -1.0);
+        assert_eq!(got.molecular_multiplicity, 1);
+        assert_eq!(got.connectivity, [(0, 1, 1.0)]);
+        assert!((got.geometry[5] - 2.0).abs() < 1e-12);
+        let json = serde_json::to_value(&got).unwrap();
+        assert_eq!(json["schema_name"], "qcschema_molecule");
+
+        let s = Smarts::parse("[#8H2]".to_owned());
+        let mol = Molecule::try_from(&s).unwrap();
+        let conf = Conformer::new(vec![[0.0; 3]], LengthUnit::Bohr);
+        assert_eq!(
+            QcMolecule::new(&mol, &conf),
+            Err(MoleculeError::ImplicitHydrogens(0))
+        );
+    }
+}
@@ -0,0 +1,1237 @@
+//! Query patterns, as opposed to the concrete molecules in [crate::molecule].
+//!
+//! Each query atom and bond holds a logical expression over primitives, which
+//! is evaluated against the atoms and bonds of a [Molecule]. A parsed [Smarts]
+//! converts to a [Query] that is the conjunction of everything it specifies
+
+use std::fmt::{Display, Write};
+
+use crate::{
+    canonical::dfs_tree,
+    matcher::{CompiledQuery, MatchOptions},
+    molecule::{BondType, Direction, MolAtom, MolBond, Molecule},
+    rings::RingInfo,
+    smarts::{BondOrder, Chiral, Smarts},
+};
+
+/// A single test on an atom
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AtomPrimitive {
+    AtomicNumber(usize),
+    /// the mass number, like the 13 in `[13C]`
+    Isotope(usize),
+    /// total H count
+    Hydrogens(usize),
+    /// the number of hydrogens not present as atoms in the graph, `h`
+    ImplicitHydrogens(usize),
+    Charge(isize),
+    Chirality(Chiral),
+    Aromatic,
+    /// not aromatic, `A`, which uppercase element symbols like `C` imply
+    Aliphatic,
+    /// the number of explicit connections, `D`
+    Degree(usize),
+    /// the number of connections including hydrogens, `X`
+    Connectivity(usize),
+    /// the total bond order including hydrogens, `v`, with aromatic bonds
+    /// taken from the Kekulé structure
+    Valence(usize),
+    /// membership in any ring, a bare `R`
+    InRing,
+    /// membership in exactly this many SSSR rings, `R<n>`
+    RingCount(usize),
+    /// the size of the smallest SSSR ring containing the atom, `r<n>`
+    SmallestRing(usize),
+    /// the number of ring bonds to the atom, `x<n>`
+    RingConnectivity(usize),
+    /// a recursive SMARTS, `$(...)`, which holds when the query matches with
+    /// its first atom on this one
+    Recursive(Box<Query>),
+}
+
+impl AtomPrimitive {
+    pub fn matches(&self, target: &Target, atom: usize) -> bool {
+        let (mol, a) = (target.mol, &target.mol.atoms[atom]);
+        match self {
+            AtomPrimitive::AtomicNumber(n) => a.atomic_number == *n,
+            AtomPrimitive::Isotope(n) => a.isotope == Some(*n),
+            AtomPrimitive::Hydrogens(h) => {
+                let graph_hs = mol
+                    .neighbors(atom)
+                    .filter(|&j| mol.atoms[j].atomic_number == 1)
+                    .count();
+                a.n_hydrogens + graph_hs == *h
+            }
+            AtomPrimitive::ImplicitHydrogens(h) => a.n_hydrogens == *h,
+            AtomPrimitive::Charge(c) => a.charge == *c,
+            AtomPrimitive::Chirality(c) => a.chirality == *c,
+            AtomPrimitive::Aromatic => a.aromatic,
+            AtomPrimitive::Aliphatic => !a.aromatic,
+            AtomPrimitive::Degree(d) => mol.degree(atom) == *d,
+            AtomPrimitive::Connectivity(x) => mol.total_degree(atom) == *x,
+            AtomPrimitive::Valence(v) => {
+                target.valences.as_ref().is_some_and(|vs| vs[atom] == *v)
+            }
+            AtomPrimitive::InRing => target.rings.n_atom_rings(atom) > 0,
+            AtomPrimitive::RingCount(n) => {
+                target.rings.n_atom_rings(atom) == *n
+            }
+            AtomPrimitive::SmallestRing(r) => {
+                target.rings.smallest_ring(atom) == Some(*r)
+            }
+            AtomPrimitive::RingConnectivity(x) => {
+                let ring_bonds =
+                    mol.bonds.iter().enumerate().filter(|(k, b)| {
+                        (b.atom1 == atom || b.atom2 == atom)
+                            && target.rings.is_ring_bond(*k)
+                    });
+                ring_bonds.count() == *x
+            }
+            AtomPrimitive::Recursive(q) => q.matches_at(target, atom),
+        }
+    }
+}
+
+/// A single test on a bond
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BondPrimitive {
+    /// a bond of the given type, with any direction
+    Type(BondType),
+    /// a directional single bond
+    Direction(Direction),
+    /// any bond in a ring, `@`
+    Ring,
+    Any,
+}
+
+impl BondPrimitive {
+    pub fn matches(&self, target: &Target, bond: usize) -> bool {
+        let b = &target.mol.bonds[bond];
+        match self {
+            BondPrimitive::Type(t) => b.bond_type == *t,
+            BondPrimitive::Direction(d) => b.direction == Some(*d),
+            BondPrimitive::Ring => target.rings.is_ring_bond(bond),
+            BondPrimitive::Any => true,
+        }
+    }
+}
+
+/// A [Molecule] prepared for evaluating primitives against, along with the
+/// derived properties some of them need, like its rings
+#[derive(Clone, Debug)]
+pub struct Target<'a> {
+    pub mol: &'a Molecule,
+    pub rings: RingInfo,
+    /// the neighbors of each atom, paired with the position of the bond to
+    /// them
+    pub adj: Vec<Vec<(usize, usize)>>,
+    /// the valence of each atom, from [Molecule::valences], or `None` if
+    /// `mol` can't be kekulized, in which case no atom matches
+    /// [AtomPrimitive::Valence]
+    pub valences: Option<Vec<usize>>,
+}
+
+impl<'a> Target<'a> {
+    pub fn new(mol: &'a Molecule) -> Self {
+        Self {
+            mol,
+            rings: mol.ring_info(),
+            adj: adjacency(mol),
+            valences: mol.valences().ok(),
+        }
+    }
+}
+
+/// The logical relationships between primitives of the same kind, used to
+/// decide whether one expression implies another. Both methods may return
+/// false when unsure, which only makes [Query::is_more_specific_than] more
+/// conservative
+pub trait Primitive: PartialEq {
+    /// whether anything satisfying `self` also satisfies `other`
+    fn implies(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    /// whether nothing can satisfy both `self` and `other`
+    fn excludes(&self, other: &Self) -> bool;
+}
+
+impl Primitive for AtomPrimitive {
+    fn excludes(&self, other: &Self) -> bool {
+        use AtomPrimitive as P;
+        match (self, other) {
+            (P::AtomicNumber(a), P::AtomicNumber(b)) => a != b,
+            (P::Hydrogens(a), P::Hydrogens(b)) => a != b,
+            (P::ImplicitHydrogens(a), P::ImplicitHydrogens(b)) => a != b,
+            (P::Charge(a), P::Charge(b)) => a != b,
+            (P::Chirality(a), P::Chirality(b)) => a != b,
+            (P::Degree(a), P::Degree(b)) => a != b,
+            (P::Connectivity(a), P::Connectivity(b)) => a != b,
+            (P::Valence(a), P::Valence(b)) => a != b,
+            (P::RingCount(a), P::RingCount(b)) => a != b,
+            (P::SmallestRing(a), P::SmallestRing(b)) => a != b,
+            (P::RingConnectivity(a), P::RingConnectivity(b)) => a != b,
+            (
+                P::RingCount(0) | P::RingConnectivity(0),
+                P::InRing | P::SmallestRing(_),
+            )
+            | (
+                P::InRing | P::SmallestRing(_),
+                P::RingCount(0) | P::RingConnectivity(0),
+            ) => true,
+            _ => false,
+        }
+    }
+
+    fn implies(&self, other: &Self) -> bool {
+        use AtomPrimitive as P;
+        self == other
+            || matches!((self, other), (P::SmallestRing(_), P::InRing))
+            || matches!((self, other), (P::RingCount(n), P::InRing) if *n > 0)
+            || matches!(
+                (self, other),
+                (P::RingConnectivity(n), P::InRing) if *n > 0
+            )
+    }
+}
+
+impl Primitive for BondPrimitive {
+    fn implies(&self, other: &Self) -> bool {
+        use BondPrimitive as P;
+        self == other
+            || *other == P::Any
+            || matches!(
+                (self, other),
+                (P::Direction(_), P::Type(BondType::Single))
+            )
+    }
+
+    fn excludes(&self, other: &Self) -> bool {
+        use BondPrimitive as P;
+        match (self, other) {
+            (P::Type(a), P::Type(b)) => a != b,
+            (P::Direction(a), P::Direction(b)) => a != b,
+            (P::Direction(_), P::Type(t)) | (P::Type(t), P::Direction(_)) => {
+                *t != BondType::Single
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A logical expression over primitives of type `P`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Expr<P> {
+    Primitive(P),
+    Not(Box<Expr<P>>),
+    And(Vec<Expr<P>>),
+    Or(Vec<Expr<P>>),
+}
+
+pub type AtomExpr = Expr<AtomPrimitive>;
+pub type BondExpr = Expr<BondPrimitive>;
+
+impl<P> Expr<P> {
+    /// evaluate `self`, using `f` to evaluate the primitives. an empty `And`
+    /// is true and an empty `Or` is false
+    pub fn eval(&self, f: &impl Fn(&P) -> bool) -> bool {
+        match self {
+            Expr::Primitive(p) => f(p),
+            Expr::Not(e) => !e.eval(f),
+            Expr::And(es) => es.iter().all(|e| e.eval(f)),
+            Expr::Or(es) => es.iter().any(|e| e.eval(f)),
+        }
+    }
+
+    /// `self` with the primitives `drop` selects treated as unknown, so that
+    /// whatever they took part in is widened to constrain nothing: a `Not`
+    /// or `Or` over one is dropped too, while an `And` keeps its other
+    /// members unless none are left. `None` if all of `self` is dropped
+    pub fn without(&self, drop: &impl Fn(&P) -> bool) -> Option<Self>
+    where
+        P: Clone,
+    {
+        match self {
+            Expr::Primitive(p) => (!drop(p)).then(|| self.clone()),
+            Expr::Not(e) => Some(Expr::Not(Box::new(e.without(drop)?))),
+            Expr::And(es) => {
+                let kept: Vec<_> =
+                    es.iter().filter_map(|e| e.without(drop)).collect();
+                (es.is_empty() || !kept.is_empty()).then_some(Expr::And(kept))
+            }
+            Expr::Or(es) => Some(Expr::Or(
+                es.iter().map(|e| e.without(drop)).collect::<Option<_>>()?,
+            )),
+        }
+    }
+
+    /// the primitives of `self` if it is a conjunction of primitives, or a
+    /// single primitive
+    fn conjuncts(&self) -> Option<Vec<&P>> {
+        match self {
+            Expr::Primitive(p) => Some(vec![p]),
+            Expr::And(es) => es
+                .iter()
+                .map(|e| match e {
+                    Expr::Primitive(p) => Some(p),
+                    _ => None,
+                })
+                .collect(),
+            Expr::Not(_) | Expr::Or(_) => None,
+        }
+    }
+}
+
+impl<P: Primitive> Expr<P> {
+    /// whether everything satisfying `self` also satisfies `other`. this is
+    /// decided structurally, so it can miss implications that only follow
+    /// from rewriting either expression, but it never reports a false one
+    pub fn implies(&self, other: &Self) -> bool {
+        match (self, other) {
+            (_, Expr::And(bs)) => bs.iter().all(|b| self.implies(b)),
+            (Expr::Or(es), _) => es.iter().all(|e| e.implies(other)),
+            (_, Expr::Or(bs)) if bs.iter().any(|b| self.implies(b)) => true,
+            (Expr::And(es), _) => es.iter().any(|e| e.implies(other)),
+            (Expr::Primitive(a), Expr::Primitive(b)) => a.implies(b),
+            (Expr::Primitive(a), Expr::Not(b)) => match b.as_ref() {
+                Expr::Primitive(b) => a.excludes(b),
+                _ => false,
+            },
+            (Expr::Not(a), Expr::Not(b)) => b.implies(a),
+            _ => false,
+        }
+    }
+}
+
+impl AtomExpr {
+    /// whether atom `atom` of `target` satisfies `self`
+    pub fn matches(&self, target: &Target, atom: usize) -> bool {
+        self.eval(&|p: &AtomPrimitive| p.matches(target, atom))
+    }
+}
+
+impl BondExpr {
+    /// whether bond `bond` of `target` satisfies `self`
+    pub fn matches(&self, target: &Target, bond: usize) -> bool {
+        self.eval(&|p: &BondPrimitive| p.matches(target, bond))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QueryAtom {
+    pub expr: AtomExpr,
+    pub mol_index: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QueryBond {
+    pub atom1: usize,
+    pub atom2: usize,
+    pub expr: BondExpr,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Query {
+    pub atoms: Vec<QueryAtom>,
+    pub bonds: Vec<QueryBond>,
+}
+
+fn bond_expr(order: &BondOrder) -> BondExpr {
+    use BondPrimitive as P;
+    let t = |t| Expr::Primitive(P::Type(t));
+    match order {
+        // as in the matcher, directional bonds match any single bond
+        BondOrder::Single | BondOrder::Up | BondOrder::Down => {
+            t(BondType::Single)
+        }
+        BondOrder::Double => t(BondType::Double),
+        BondOrder::Triple => t(BondType::Triple),
+        BondOrder::Aromatic => t(BondType::Aromatic),
+        BondOrder::Ring => Expr::Primitive(P::Ring),
+        BondOrder::SingleOrAromatic => {
+            Expr::Or(vec![t(BondType::Single), t(BondType::Aromatic)])
+        }
+        BondOrder::Any => Expr::Primitive(P::Any),
+        BondOrder::Expr(e) => e.clone(),
+    }
+}
+
+/// return the positions of `queries` in an order where no query comes before
+/// one it is more specific than, as needed for force-field parameter lists
+/// where later parameters take precedence. queries that are not comparable
+/// keep their relative order
+pub fn order_by_specificity(queries: &[Query]) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..queries.len()).collect();
+    let mut ret = Vec::with_capacity(queries.len());
+    while !remaining.is_empty() {
+        // there is always a query that specializes nothing remaining, since
+        // the relation is acyclic
+        let k = remaining
+            .iter()
+            .position(|&i| {
+                !remaining
+                    .iter()
+                    .any(|&j| queries[i].is_more_specific_than(&queries[j]))
+            })
+            .unwrap_or(0);
+        ret.push(remaining.remove(k));
+    }
+    ret
+}
+
+/// How the H counts of a concrete molecule are written when it is turned into
+/// a query. Force fields differ in which convention they use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HydrogenPolicy {
+    /// the total H count, `H<n>`, including any hydrogens present as atoms
+    #[default]
+    Total,
+    /// the implicit H count, `h<n>`, which leaves out hydrogens present as
+    /// atoms
+    Implicit,
+    /// no H count, so that any number of hydrogens matches
+    Drop,
+}
+
+impl HydrogenPolicy {
+    /// the primitive for an atom with `total` hydrogens, `implicit` of which
+    /// are not present as atoms, if the policy keeps one
+    pub fn primitive(
+        &self,
+        total: usize,
+        implicit: usize,
+    ) -> Option<AtomPrimitive> {
+        match self {
+            HydrogenPolicy::Total => Some(AtomPrimitive::Hydrogens(total)),
+            HydrogenPolicy::Implicit => {
+                Some(AtomPrimitive::ImplicitHydrogens(implicit))
+            }
+            HydrogenPolicy::Drop => None,
+        }
+    }
+}
+
+/// Every atomic number, H count, and given charge in `s` becomes a primitive,
+/// along with isotopes, chirality, and aromaticity when they are present.
+/// Directional bonds become plain single bonds. H counts are written with
+/// [HydrogenPolicy::Total], like [Query::from_smarts]
+impl From<&Smarts> for Query {
+    fn from(s: &Smarts) -> Self {
+        Self::from_smarts(s, HydrogenPolicy::Total)
+    }
+}
+
+impl Query {
+    /// the conjunction of everything `s` specifies, with its H counts written
+    /// according to `hydrogens`. atoms without an H count get none either way,
+    /// and atoms with a logical expression, like `[#6,#7]`, get that
+    /// expression as written. an isotope comes first in either case, and a
+    /// charge is only included if `s` gives one, see
+    /// [crate::smarts::Atom::explicit_charge]
+    pub fn from_smarts(s: &Smarts, hydrogens: HydrogenPolicy) -> Self {
+        use AtomPrimitive as P;
+        let mut graph_hs = vec![0; s.atoms.len()];
+        for b in &s.bonds {
+            graph_hs[b.atom1] +=
+                usize::from(s.atoms[b.atom2].atomic_number == Some(1));
+            graph_hs[b.atom2] +=
+                usize::from(s.atoms[b.atom1].atomic_number == Some(1));
+        }
+        let atoms = s
+            .atoms
+            .iter()
+            .zip(graph_hs)
+            .map(|(a, graph_hs)| {
+                let isotope = a.isotope.map(P::Isotope);
+                if let Some(expr) = &a.expr {
+                    let expr = match isotope {
+                        Some(p) => {
+                            Expr::And(vec![Expr::Primitive(p), expr.clone()])
+                        }
+                        None => expr.clone(),
+                    };
+                    return QueryAtom {
+                        expr,
+                        mol_index: a.mol_index,
+                    };
+                }
+                let mut prims: Vec<_> = isotope.into_iter().collect();
+                prims.extend(a.atomic_number.map(P::AtomicNumber));
+                prims.extend(
+                    a.n_hydrogens
+                        .and_then(|h| hydrogens.primitive(h + graph_hs, h)),
+                );
+                if a.explicit_charge {
+                    prims.push(P::Charge(a.charge));
+                }
+                if a.chirality != Chiral::None {
+                    prims.push(P::Chirality(a.chirality.clone()));
+                }
+                if a.aromatic {
+                    prims.push(P::Aromatic);
+                }
+                if a.aliphatic {
+                    prims.push(P::Aliphatic);
+                }
+                QueryAtom {
+                    expr: Expr::And(
+                        prims.into_iter().map(Expr::Primitive).collect(),
+                    ),
+                    mol_index: a.mol_index,
+                }
+            })
+            .collect();
+        let bonds = s
+            .bonds
+            .iter()
+            .map(|b| QueryBond {
+                atom1: b.atom1,
+                atom2: b.atom2,
+                expr: bond_expr(&b.order),
+            })
+            .collect();
+        Self { atoms, bonds }
+    }
+}
+
+/// The exact query for `m`, which only matches molecules with identical atoms
+/// and bonds
+impl From<&Molecule> for Query {
+    fn from(m: &Molecule) -> Self {
+        Self::from(&Smarts::from(m))
+    }
+}
+
+impl Query {
+    /// whether every molecule matched by `self` is also matched by `other`,
+    /// with `other` strictly more general. this holds when `other` can be
+    /// mapped onto a subgraph of `self` such that each atom and bond
+    /// expression of `self` implies the one it is paired with in `other`,
+    /// and every atom map in `other` is paired with the same map in `self`,
+    /// but no such mapping exists in the other direction
+    pub fn is_more_specific_than(&self, other: &Query) -> bool {
+        self.specializes(other) && !other.specializes(self)
+    }
+
+    /// the non-strict version of [Query::is_more_specific_than]
+    fn specializes(&self, general: &Query) -> bool {
+        if general.atoms.len() > self.atoms.len() {
+            return false;
+        }
+        let mut mapping = vec![None; general.atoms.len()];
+        let mut used = vec![false; self.atoms.len()];
+        self.search(general, 0, &mut mapping, &mut used)
+    }
+
+    /// extend `mapping`, from positions in `general` to positions in `self`,
+    /// starting at atom `i` of `general`
+    fn search(
+        &self,
+        general: &Query,
+        i: usize,
+        mapping: &mut Vec<Option<usize>>,
+        used: &mut Vec<bool>,
+    ) -> bool {
+        if i == general.atoms.len() {
+            return true;
+        }
+        let g = &general.atoms[i];
+        for (j, s) in self.atoms.iter().enumerate() {
+            if used[j]
+                || g.mol_index.is_some_and(|m| s.mol_index != Some(m))
+                || !s.expr.implies(&g.expr)
+            {
+                continue;
+            }
+            mapping[i] = Some(j);
+            // every bond of `general` from atom `i` to an atom already
+            // mapped needs a matching bond in `self`
+            let bonds_ok = general.bonds.iter().all(|gb| {
+                if gb.atom1 != i && gb.atom2 != i {
+                    return true;
+                }
+                let (Some(a), Some(b)) = (mapping[gb.atom1], mapping[gb.atom2])
+                else {
+                    return true;
+                };
+                self.bonds.iter().any(|sb| {
+                    ((sb.atom1, sb.atom2) == (a, b)
+                        || (sb.atom1, sb.atom2) == (b, a))
+                        && sb.expr.implies(&gb.expr)
+                })
+            });
+            if bonds_ok {
+                used[j] = true;
+                if self.search(general, i + 1, mapping, used) {
+                    return true;
+                }
+                used[j] = false;
+            }
+            mapping[i] = None;
+        }
+        false
+    }
+
+    /// every mapping of the atoms of `self` onto distinct atoms of `target`
+    /// such that each atom and bond expression matches what it lands on. each
+    /// match is indexed by query atom position, as in [Matches]. symmetric
+    /// matches covering the same target atoms are all reported. this is
+    /// [CompiledQuery::find_matches] with [MatchOptions::exhaustive]
+    ///
+    /// [Matches]: crate::matcher::Matches
+    pub fn find_matches(&self, target: &Target) -> Vec<Vec<usize>> {
+        CompiledQuery::new(self, MatchOptions::exhaustive())
+            .find_matches(target)
+            .matches
+    }
+
+    /// whether `self` matches `target` with its first atom on atom `atom`, as
+    /// a recursive SMARTS requires. an empty query never matches
+    pub fn matches_at(&self, target: &Target, atom: usize) -> bool {
+        let options = MatchOptions {
+            max_matches: Some(1),
+            ..MatchOptions::exhaustive()
+        };
+        CompiledQuery::new(self, options).matches_at(target, atom)
+    }
+
+    /// whether `self` matches anywhere in `target`
+    pub fn matches(&self, target: &Target) -> bool {
+        let options = MatchOptions {
+            max_matches: Some(1),
+            ..MatchOptions::exhaustive()
+        };
+        !CompiledQuery::new(self, options)
+            .find_matches(target)
+            .is_empty()
+    }
+
+    /// convert `self` back into a concrete [Molecule], if every atom is a
+    /// conjunction that specifies at least an atomic number and an H count
+    /// and every bond is a lone [BondPrimitive::Type]. returns `None` otherwise
+    pub fn to_molecule(&self) -> Option<Molecule> {
+        let mut atoms = Vec::new();
+        for qa in &self.atoms {
+            let mut atom = MolAtom {
+                atomic_number: 0,
+                isotope: None,
+                n_hydrogens: 0,
+                charge: 0,
+                chirality: Chiral::None,
+                aromatic: false,
+                mol_index: qa.mol_index,
+            };
+            let (mut elem, mut hs) = (false, false);
+            for p in qa.expr.conjuncts()? {
+                match p {
+                    AtomPrimitive::AtomicNumber(n) => {
+                        atom.atomic_number = *n;
+                        elem = true;
+                    }
+                    AtomPrimitive::Isotope(n) => atom.isotope = Some(*n),
+                    AtomPrimitive::Hydrogens(h) => {
+                        atom.n_hydrogens = *h;
+                        hs = true;
+                    }
+                    AtomPrimitive::Charge(c) => atom.charge = *c,
+                    AtomPrimitive::Chirality(c) => atom.chirality = c.clone(),
+                    AtomPrimitive::Aromatic => atom.aromatic = true,
+                    // the rest constrain the graph rather than the atom
+                    _ => {}
+                }
+            }
+            if !(elem && hs) {
+                return None;
+            }
+            atoms.push(atom);
+        }
+        let mut bonds = Vec::new();
+        for qb in &self.bonds {
+            let [BondPrimitive::Type(t)] = qb.expr.conjuncts()?[..] else {
+                return None;
+            };
+            bonds.push(MolBond {
+                atom1: qb.atom1,
+                atom2: qb.atom2,
+                bond_type: *t,
+                direction: None,
+            });
+        }
+        Some(Molecule::new(atoms, bonds))
+    }
+}
+
+/// Display a primitive in SMARTS syntax, like `#6`, `X4`, or `+0`. an
+/// explicit [Chiral::None], which SMARTS has no primitive for, is written as
+/// `@0`
+impl Display for AtomPrimitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use AtomPrimitive as P;
+        match self {
+            P::AtomicNumber(n) => write!(f, "#{n}"),
+            P::Isotope(n) => write!(f, "{n}"),
+            P::Hydrogens(h) => write!(f, "H{h}"),
+            P::ImplicitHydrogens(h) => write!(f, "h{h}"),
+            P::Charge(c) => write!(f, "{c:+}"),
+            P::Chirality(Chiral::Cw) => write!(f, "@@"),
+            P::Chirality(Chiral::Acw) => write!(f, "@"),
+            P::Chirality(Chiral::None) => write!(f, "@0"),
+            P::Aromatic => write!(f, "a"),
+            P::Aliphatic => write!(f, "A"),
+            P::Degree(d) => write!(f, "D{d}"),
+            P::Connectivity(x) => write!(f, "X{x}"),
+            P::Valence(v) => write!(f, "v{v}"),
+            P::InRing => write!(f, "R"),
+            P::RingCount(n) => write!(f, "R{n}"),
+            P::SmallestRing(r) => write!(f, "r{r}"),
+            P::RingConnectivity(x) => write!(f, "x{x}"),
+            P::Recursive(q) => write!(f, "$({q})"),
+        }
+    }
+}
+
+/// Display a primitive in SMARTS syntax, like `-` or `@`
+impl Display for BondPrimitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            BondPrimitive::Type(BondType::Single) => "-",
+            BondPrimitive::Type(BondType::Double) => "=",
+            BondPrimitive::Type(BondType::Triple) => "#",
+            BondPrimitive::Type(BondType::Aromatic) => ":",
+            BondPrimitive::Direction(Direction::Up) => "/",
+            BondPrimitive::Direction(Direction::Down) => "\\",
+            BondPrimitive::Ring => "@",
+            BondPrimitive::Any => "~",
+        };
+        f.write_str(s)
+    }
+}
+
+/// a conjunction of disjunctions of possibly negated primitives
+type Cnf<'a, P> = Vec<Vec<(bool, &'a P)>>;
+
+impl<P: Display> Expr<P> {
+    /// rewrite `self`, or its negation if `negate` is set, in conjunctive
+    /// normal form, with each literal paired with whether it is negated
+    fn cnf(&self, negate: bool) -> Cnf<'_, P> {
+        match (self, negate) {
+            (Expr::Primitive(p), _) => vec![vec![(negate, p)]],
+            (Expr::Not(e), _) => e.cnf(!negate),
+            (Expr::And(es), false) | (Expr::Or(es), true) => {
+                es.iter().flat_map(|e| e.cnf(negate)).collect()
+            }
+            (Expr::Or(es), false) | (Expr::And(es), true) => {
+                let mut ret = vec![Vec::new()];
+                for e in es {
+                    let clauses = e.cnf(negate);
+                    ret = ret
+                        .iter()
+                        .flat_map(|a| {
+                            clauses.iter().map(move |b| {
+                                a.iter().chain(b).copied().collect()
+                            })
+                        })
+                        .collect();
+                }
+                ret
+            }
+        }
+    }
+
+    /// write `self` in SMARTS syntax, using `any` for an expression that is
+    /// always true. the expression is put in conjunctive normal form, since
+    /// SMARTS has no parentheses, so nested expressions can grow
+    pub(crate) fn write_smarts(
+        &self,
+        any: &str,
+        f: &mut impl Write,
+    ) -> std::fmt::Result {
+        let cnf = self.cnf(false);
+        if cnf.is_empty() {
+            return f.write_str(any);
+        }
+        let simple = cnf.iter().all(|c| c.len() == 1);
+        for (i, clause) in cnf.iter().enumerate() {
+            if i > 0 {
+                f.write_char(if simple { '&' } else { ';' })?;
+            }
+            if clause.is_empty() {
+                // an empty disjunction is never true
+                write!(f, "!{any}")?;
+            }
+            for (j, (negated, p)) in clause.iter().enumerate() {
+                if j > 0 {
+                    f.write_char(',')?;
+                }
+                if *negated {
+                    f.write_char('!')?;
+                }
+                write!(f, "{p}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Display a query as a SMARTS pattern, with the atoms in depth-first order
+/// from the first atom and each ring closure's bond written at the closing
+/// label. atoms keep their maps, and separate components are joined by `.`
+impl Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.atoms.len();
+        let mut adj = vec![Vec::new(); n];
+        for (k, b) in self.bonds.iter().enumerate() {
+            adj[b.atom1].push((b.atom2, k));
+            adj[b.atom2].push((b.atom1, k));
+        }
+        // mark the bonds of a DFS forest, so the rest close rings
+        let mut tree = vec![false; self.bonds.len()];
+        let mut seen = vec![false; n];
+        for root in 0..n {
+            if !seen[root] {
+                seen[root] = true;
+                dfs_tree(&adj, root, &mut seen, &mut tree);
+            }
+        }
+        let mut written = vec![false; n];
+        let mut open = Vec::new();
+        for root in 0..n {
+            if written[root] {
+                continue;
+            }
+            if root > 0 {
+                f.write_char('.')?;
+            }
+            self.write_atom(f, &adj, &tree, root, &mut written, &mut open)?;
+        }
+        Ok(())
+    }
+}
+
+/// the neighbors of each atom in `mol`, paired with the position of the bond
+/// to them
+fn adjacency(mol: &Molecule) -> Vec<Vec<(usize, usize)>> {
+    let mut adj = vec![Vec::new(); mol.atoms.len()];
+    for (k, b) in mol.bonds.iter().enumerate() {
+        adj[b.atom1].push((b.atom2, k));
+        adj[b.atom2].push((b.atom1, k));
+    }
+    adj
+}
+
+impl Query {
+    /// write atom `u`, its ring closures, and then its subtree. `open` holds
+    /// the bond behind each open ring-closure label, with `None` for labels
+    /// that are free to reuse
+    fn write_atom(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        adj: &[Vec<(usize, usize)>],
+        tree: &[bool],
+        u: usize,
+        written: &mut [bool],
+        open: &mut Vec<Option<usize>>,
+    ) -> std::fmt::Result {
+        written[u] = true;
+        let atom = &self.atoms[u];
+        f.write_char('[')?;
+        // an isotope can only be read at the start of a bracket atom, so a
+        // leading one is written there without a separator
+        match &atom.expr {
+            Expr::And(es) => match es.split_first() {
+                Some((Expr::Primitive(AtomPrimitive::Isotope(n)), rest)) => {
+                    write!(f, "{n}")?;
+                    Expr::And(rest.to_vec()).write_smarts("*", f)?;
+                }
+                _ => atom.expr.write_smarts("*", f)?,
+            },
+            _ => atom.expr.write_smarts("*", f)?,
+        }
+        if let Some(map) = atom.mol_index {
+            write!(f, ":{map}")?;
+        }
+        f.write_char(']')?;
+        for &(_, k) in adj[u].iter().filter(|&&(_, k)| !tree[k]) {
+            let label = match open.iter().position(|&b| b == Some(k)) {
+                Some(l) => {
+                    self.bonds[k].expr.write_smarts("~", f)?;
+                    open[l] = None;
+                    l
+                }
+                None => match open.iter().position(Option::is_none) {
+                    Some(l) => {
+                        open[l] = Some(k);
+                        l
+                    }
+                    None => {
+                        open.push(Some(k));
+                        open.len() - 1
+                    }
+                },
+            };
+            let label = label + 1;
+            if label < 10 {
+                write!(f, "{label}")?;
+            } else {
+                write!(f, "%{label}")?;
+            }
+        }
+        let children: Vec<_> = adj[u]
+            .iter()
+            .filter(|&&(v, k)| tree[k] && !written[v])
+            .copied()
+            .collect();
+        for (i, &(v, k)) in children.iter().enumerate() {
+            let branch = i + 1 < children.len();
+            if branch {
+                f.write_char('(')?;
+            }
+            self.bonds[k].expr.write_smarts("~", f)?;
+            self.write_atom(f, adj, tree, v, written, open)?;
+            if branch {
+                f.write_char(')')?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{molecule::mol, smarts::InputKind};
+
+    use super::*;
+
+    #[test]
+    fn expressions() {
+        let m = mol("[#6H3:1]-[#7H3+:2]");
+        use AtomPrimitive as P;
+        let p = |p| Expr::Primitive(p);
+        let t = Target::new(&m);
+        let nitrogen = p(P::AtomicNumber(7));
+        assert!(!nitrogen.matches(&t, 0));
+        assert!(nitrogen.matches(&t, 1));
+        let e = Expr::And(vec![
+            nitrogen.clone(),
+            Expr::Not(Box::new(p(P::Charge(0)))),
+        ]);
+        assert!(e.matches(&t, 1));
+        let e = Expr::Or(vec![p(P::AtomicNumber(8)), p(P::Hydrogens(3))]);
+        assert!(e.matches(&t, 0));
+        assert!(!AtomExpr::Or(vec![]).matches(&t, 0));
+
+        let single = Expr::Primitive(BondPrimitive::Type(BondType::Single));
+        assert!(single.matches(&t, 0));
+    }
+
+    #[test]
+    fn convert() {
+        let q = Query::from(&Smarts::parse_as(
+            "[#6:1][#8H:2]".to_owned(),
+            InputKind::Smarts,
+        ));
+        let m = mol("[#6H3:1]-[#8H:2]");
+        let t = Target::new(&m);
+        assert!(q.atoms[0].expr.matches(&t, 0));
+        assert!(q.atoms[1].expr.matches(&t, 1));
+        assert!(q.bonds[0].expr.matches(&t, 0));
+        // a query with an unconstrained H count has no concrete molecule
+        assert!(q.to_molecule().is_none());
+
+        let m = mol("[#6H3:1]-[#6H:2]=[#8:3]");
+        assert_eq!(Query::from(&m).to_molecule(), Some(m));
+    }
+
+    fn query(s: &str) -> Query {
+        Query::from(&Smarts::parse_as(s.to_owned(), InputKind::Smarts))
+    }
+
+    #[test]
+    fn hydrogen_policy() {
+        let s = Smarts::parse("[#6H2]([#1])-[#8H]".to_owned());
+        let write = |h| Query::from_smarts(&s, h).to_string();
+        assert_eq!(Query::from(&s).to_string(), write(HydrogenPolicy::Total));
+        assert_eq!(
+            write(HydrogenPolicy::Total),
+            "[#6&H3&+0](-[#1&H0&+0])-[#8&H1&+0]"
+        );
+        assert_eq!(
+            write(HydrogenPolicy::Implicit),
+            "[#6&h2&+0](-[#1&h0&+0])-[#8&h1&+0]"
+        );
+        assert_eq!(write(HydrogenPolicy::Drop), "[#6&+0](-[#1&+0])-[#8&+0]");
+    }
+
+    #[test]
+    fn specificity() {
+        let generic = query("[#6:1][#6:2]");
+        let single = query("[#6:1]-[#6:2]");
+        let methyl = query("[#6H3:1]-[#6:2]");
+        assert!(single.is_more_specific_than(&generic));
+        assert!(methyl.is_more_specific_than(&single));
+        assert!(methyl.is_more_specific_than(&generic));
+        assert!(!generic.is_more_specific_than(&single));
+        assert!(!single.is_more_specific_than(&single));
+        // the maps have to line up
+        assert!(!query("[#6:2]-[#6H3:1]").is_more_specific_than(&methyl));
+        // a larger pattern containing a smaller one is more specific
+        let ether = query("[#6:1]-[#8:2]-[#6:3]");
+        assert!(ether.is_more_specific_than(&query("[#6:1]-[#8:2]")));
+        assert!(!ether.is_more_specific_than(&query("[#6:1]-[#7:2]")));
+
+        let order = order_by_specificity(&[methyl, generic, single]);
+        assert_eq!(order, [1, 2, 0]);
+    }
+
+    #[test]
+    fn implication() {
+        use AtomPrimitive as P;
+        let p = |p| AtomExpr::Primitive(p);
+        let carbon = p(P::AtomicNumber(6));
+        let ch3 = Expr::And(vec![carbon.clone(), p(P::Hydrogens(3))]);
+        let c_or_n = Expr::Or(vec![carbon.clone(), p(P::AtomicNumber(7))]);
+        assert!(ch3.implies(&carbon));
+        assert!(!carbon.implies(&ch3));
+        assert!(ch3.implies(&c_or_n));
+        assert!(!c_or_n.implies(&carbon));
+        let not_n = Expr::Not(Box::new(p(P::AtomicNumber(7))));
+        assert!(carbon.implies(&not_n));
+        assert!(!p(P::Hydrogens(3)).implies(&not_n));
+        assert!(p(P::SmallestRing(6)).implies(&p(P::InRing)));
+        let acyclic = Expr::Not(Box::new(p(P::InRing)));
+        assert!(p(P::RingCount(0)).implies(&acyclic));
+    }
+
+    #[test]
+    fn primitives() {
+        use AtomPrimitive::*;
+        // each row is a molecule, a primitive, and the atoms it matches, as
+        // reported by rdkit's GetSubstructMatches for the equivalent SMILES
+        // and single-atom SMARTS
+        let cases = [
+            // CC(=O)O, [D1] and [D3]
+            ("[#6H3]-[#6](=[#8])-[#8H]", Degree(1), vec![0, 2, 3]),
+            ("[#6H3]-[#6](=[#8])-[#8H]", Degree(3), vec![1]),
+            // CC(=O)O, [X4], [X2], and [v2]
+            ("[#6H3]-[#6](=[#8])-[#8H]", Connectivity(4), vec![0]),
+            ("[#6H3]-[#6](=[#8])-[#8H]", Connectivity(2), vec![3]),
+            ("[#6H3]-[#6](=[#8])-[#8H]", Valence(2), vec![2, 3]),
+            // c1ccccc1O, [v4], [h1], and [h0]
+            (PHENOL, Valence(4), vec![0, 1, 2, 3, 4, 5]),
+            (PHENOL, ImplicitHydrogens(1), vec![0, 1, 2, 3, 4, 6]),
+            (PHENOL, ImplicitHydrogens(0), vec![5]),
+            // c1ccsc1 and c1cc[nH]c1, [v2] and [v3]
+            ("[#6H]1:[#6H]:[#16]:[#6H]:[#6H]:1", Valence(2), vec![2]),
+            ("[#6H]1:[#6H]:[#7H]:[#6H]:[#6H]:1", Valence(3), vec![2]),
+            // C[NH3+], [+1] and [-1]
+            ("[#6H3]-[#7H3+]", Charge(1), vec![1]),
+            ("[#6H3]-[#7H3+]", Charge(-1), vec![]),
+            // c1ccccc1O, [a] and [#8]
+            (PHENOL, Aromatic, vec![0, 1, 2, 3, 4, 5]),
+            (PHENOL, AtomicNumber(8), vec![6]),
+            // c1ccc2ccccc2c1, [R2] and [R1]
+            (NAPHTHALENE, RingCount(2), vec![4, 5]),
+            (NAPHTHALENE, RingCount(1), vec![0, 1, 2, 3, 6, 7, 8, 9]),
+            // CC1CC12CC2, [R], [R0], [r3], and [r4]
+            (SPIRO, InRing, vec![1, 2, 3, 4, 5]),
+            (SPIRO, RingCount(0), vec![0]),
+            (SPIRO, SmallestRing(3), vec![1, 2, 3, 4, 5]),
+            (SPIRO, SmallestRing(4), vec![]),
+        ];
+        for (s, p, want) in cases {
+            let m = mol(s);
+            let t = Target::new(&m);
+            let got: Vec<_> =
+                (0..m.atoms.len()).filter(|&i| p.matches(&t, i)).collect();
+            assert_eq!(got, want, "{p:?} in {s}");
+        }
+
+        // CC1CC12CC2, [#6]@[#6] and [#6]!@[#6]
+        let m = mol(SPIRO);
+        let t = Target::new(&m);
+        let ring: Vec<_> = (0..m.bonds.len())
+            .filter(|&b| BondPrimitive::Ring.matches(&t, b))
+            .collect();
+        assert_eq!(ring.len(), 6);
+        assert!(!BondPrimitive::Ring.matches(&t, 0));
+    }
+
+    /// the primitives rdkit perceives for each atom of `smiles`, keeping its
+    /// explicit hydrogens, labeled by atom map number
+    fn rdkit_primitives(smiles: &str) -> Vec<(usize, Vec<AtomPrimitive>)> {
+        use pyo3::{prelude::PyAnyMethods, types::PyModule, Python};
+        const CODE: &str = "
+from rdkit import Chem
+
+def primitives(smiles):
+    params = Chem.SmilesParserParams()
+    params.removeHs = False
+    mol = Chem.MolFromSmiles(smiles, params)
+    rings = mol.GetRingInfo()
+    return [
+        (
+            a.GetAtomMapNum(),
+            a.GetAtomicNum(),
+            a.GetTotalNumHs(includeNeighbors=True),
+            a.GetNumImplicitHs(),
+            a.GetFormalCharge(),
+            a.GetIsAromatic(),
+            a.GetDegree(),
+            a.GetTotalDegree(),
+            a.GetTotalValence(),
+            rings.NumAtomRings(a.GetIdx()),
+            rings.MinAtomRingSize(a.GetIdx()),
+        )
+        for a in mol.GetAtoms()
+    ]
+";
+        type Row = (
+            usize,
+            usize,
+            usize,
+            usize,
+            isize,
+            bool,
+            usize,
+            usize,
+            usize,
+            usize,
+            usize,
+        );
+        let rows: Vec<Row> = Python::with_gil(|py| {
+            PyModule::from_code_bound(py, CODE, "primitives.py", "primitives")
+                .unwrap()
+                .call_method1("primitives", (smiles,))
+                .unwrap()
+                .extract()
+                .unwrap()
+        });
+        use AtomPrimitive as P;
+        rows.into_iter()
+            .map(|(map, z, h, ih, q, arom, d, x, v, nr, r)| {
+                let mut prims = vec![
+                    P::AtomicNumber(z),
+                    P::Hydrogens(h),
+                    P::ImplicitHydrogens(ih),
+                    P::Charge(q),
+                    if arom { P::Aromatic } else { P::Aliphatic },
+                    P::Degree(d),
+                    P::Connectivity(x),
+                    P::Valence(v),
+                    P::RingCount(nr),
+                ];
+                if r > 0 {
+                    prims.extend([P::InRing, P::SmallestRing(r)]);
+                }
+                (map, prims)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rdkit_agreement() {
+        let ds = crate::Dataset::load("testfiles/opt.json").unwrap();
+        let smiles = ds.clone().to_smiles();
+        let mols = ds.molecules().unwrap();
+        let mut failed = Vec::new();
+        for (s, m) in smiles.iter().zip(&mols) {
+            let t = Target::new(m);
+            for (map, prims) in rdkit_primitives(s) {
+                let i = m.atoms.iter().position(|a| a.mol_index == Some(map));
+                let i = i.unwrap_or_else(|| panic!("no atom {map} in {s}"));
+                for p in prims.into_iter().filter(|p| !p.matches(&t, i)) {
+                    failed.push((s, map, p));
+                }
+            }
+        }
+        assert!(failed.is_empty(), "{failed:#?}");
+    }
+
+    #[test]
+    fn find_matches() {
+        let m = mol("[#6H3]-[#6H2]-[#8H]");
+        let t = Target::new(&m);
+        assert_eq!(query("[#6:1]-[#8:2]").find_matches(&t), [[1, 2]]);
+        // both orientations of a symmetric pattern are reported
+        assert_eq!(query("[#6]-[#6]").find_matches(&t), [[0, 1], [1, 0]]);
+        assert!(!query("[#6]=[#8]").matches(&t));
+        let m = mol(SPIRO);
+        let ring = query("[#6]1-[#6]-[#6]-1");
+        assert_eq!(ring.find_matches(&Target::new(&m)).len(), 12);
+    }
+
+    #[test]
+    fn bond_expressions() {
+        // methylcyclopropane
+        let m = mol("[#6H3]-[#6H]1-[#6H2]-[#6H2]-1");
+        let t = Target::new(&m);
+        // each bond is matched in both directions
+        let q = query("[#6:1]!@[#6:2]");
+        assert_eq!(q.find_matches(&t), [[0, 1], [1, 0]]);
+        assert_eq!(query("[#6:1]-;@[#6:2]").find_matches(&t).len(), 6);
+        assert_eq!(query("[*:1]~[*:2]").find_matches(&t).len(), 8);
+        let q = query("[#6:1]-;!@[#6:2]");
+        assert_eq!(q.to_string(), "[#6:1]-&!@[#6:2]");
+    }
+
+    #[test]
+    fn aliphatic() {
+        let m = mol("[cH]1:[cH]:[cH]:[cH]:[c](:[cH]:1)-[#6](=[#8])-[#6H3]");
+        let t = Target::new(&m);
+        assert_eq!(query("[C]").find_matches(&t), [[6], [8]]);
+        assert_eq!(query("[c]").find_matches(&t).len(), 6);
+        // the carbonyl carbon, but not the ring carbon bonded to it
+        assert_eq!(query("[#6$(C=O)]").find_matches(&t), [[6]]);
+        assert_eq!(query("[#6$(C-C=O)]").find_matches(&t), [[8]]);
+    }
+
+    #[test]
+    fn isotopes() {
+        let m = mol("[2H]-[#8]-[#1]");
+        let t = Target::new(&m);
+        assert_eq!(m.atoms[0].isotope, Some(2));
+        let q = query("[2#1]");
+        assert_eq!(q.find_matches(&t), [[0]]);
+        assert_eq!(q.to_string(), "[2#1]");
+        assert_eq!(query("[2]").to_string(), "[2*]");
+        assert_eq!(query("[#1]").find_matches(&t).len(), 2);
+        assert_eq!(query("[13#1]").find_matches(&t), Vec::<Vec<usize>>::new());
+        // the isotope survives the round trip through a query
+        let m = mol("[13CH3]-[#8H]");
+        assert_eq!(Query::from(&m).to_molecule(), Some(m));
+    }
+
+    #[test]
+    fn recursive() {
+        let m = mol("[#6H3]-[#6](=[#8])-[#6H3]");
+        let t = Target::new(&m);
+        let q = query("[#6$(C=O)]");
+        assert_eq!(q.find_matches(&t), [[1]]);
+        assert_eq!(q.to_string(), "[#6&$([#6&A]=[#8&A])]");
+        assert_eq!(query("[#6;!$(C=O)]").find_matches(&t), [[0], [3]]);
+        // the first atom of the recursive pattern is the one being tested
+        assert_eq!(query("[$(O=C)]").find_matches(&t), [[2]]);
+    }
+
+    #[test]
+    fn write_query() {
+        let q = query("[#6:1]1-[#6:2]-[#6H2:3]-1");
+        assert_eq!(q.to_string(), "[#6:1]1-[#6:2]-[#6&H2:3]-1");
+        // only a charge that was written is kept
+        assert_eq!(query("[#6+0]-[#8]").to_string(), "[#6&+0]-[#8]");
+        use AtomPrimitive as P;
+        let p = |p| Expr::Primitive(p);
+        let mut q = query("[#6]-[#8]");
+        q.atoms[0].expr = Expr::And(vec![
+            Expr::Or(vec![p(P::AtomicNumber(6)), p(P::AtomicNumber(7))]),
+            Expr::Not(Box::new(Expr::And(vec![p(P::Aromatic), p(P::InRing)]))),
+        ]);
+        q.bonds[0].expr = Expr::And(vec![]);
+        assert_eq!(q.to_string(), "[#6,#7;!a,!R]~[#8]");
+    }
+
+    const PHENOL: &str = "[cH]1:[cH]:[cH]:[cH]:[cH]:[cH0]:1-[#8H]";
+    const NAPHTHALENE: &str =
+        "[cH]1:[cH]:[cH]:[cH]:[cH0]2:[cH0]:1:[cH]:[cH]:[cH]:[cH]:2";
+    const SPIRO: &str = "[#6H3]-[#6H]1-[#6H2]-[#6]-12-[#6H2]-[#6H2]-2";
+}
@@ -1,12 +1,446 @@
-use pyo3::{prelude::PyAnyMethods, types::PyModule, Python};
+//! Calls into rdkit through an embedded Python interpreter.
+//!
+//! Every function here may be called from any thread. Each call holds the GIL
+//! only for its own duration, and the Python modules it needs are imported
+//! once into process-wide [GILOnceCell]s, whose handles are `Send` and `Sync`,
+//! so concurrent callers share them instead of importing or compiling them
+//! again. Calls from different threads are serialized by the GIL, so they are
+//! safe but do not run in parallel. The one way to deadlock is to hold the GIL
+//! while waiting on another thread that calls in here, so these functions must
+//! not be called from inside `Python::with_gil` while joining threads that
+//! also use them. None of them panic: when rdkit can't read its input, or a
+//! module fails to load, they return a [ChomperError::Rdkit] naming the input
 
-pub fn to_smarts(smiles: String) -> String {
+use pyo3::{
+    prelude::{PyAnyMethods, PyDictMethods},
+    sync::GILOnceCell,
+    types::{PyAny, PyDict, PyModule},
+    Bound, Py, PyResult, Python,
+};
+
+use crate::{
+    error::ChomperError,
+    highlight::{Color, Highlight},
+    smarts::{Atom, Bond, BondOrder, Chiral, Smarts},
+};
+
+static CHEM: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+static DRAW: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+static CHARGE_STATES: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+
+/// the module in `cell`, calling `load` to fill it on first use. if two threads
+/// race to fill it, both load the module but only one copy is kept
+fn cached<'py>(
+    py: Python<'py>,
+    cell: &'static GILOnceCell<Py<PyModule>>,
+    load: impl FnOnce() -> PyResult<Bound<'py, PyModule>>,
+) -> PyResult<Bound<'py, PyModule>> {
+    let m = cell.get_or_try_init(py, || load().map(Bound::unbind))?;
+    Ok(m.bind(py).clone())
+}
+
+fn chem(py: Python<'_>) -> PyResult<Bound<'_, PyModule>> {
+    cached(py, &CHEM, || PyModule::import_bound(py, "rdkit.Chem"))
+}
+
+/// the [ChomperError::Rdkit] for the input `smiles` with `message`
+fn error(smiles: &str, message: impl ToString) -> ChomperError {
+    ChomperError::Rdkit {
+        smiles: smiles.to_owned(),
+        message: message.to_string(),
+    }
+}
+
+/// the rdkit molecule for `smiles`, failing if rdkit can't read it
+fn mol_from_smiles<'py>(
+    chem: &Bound<'py, PyModule>,
+    smiles: &str,
+) -> Result<Bound<'py, PyAny>, ChomperError> {
+    let mol = chem
+        .call_method1("MolFromSmiles", (smiles,))
+        .map_err(|e| error(smiles, e))?;
+    if mol.is_none() {
+        return Err(error(smiles, "invalid SMILES"));
+    }
+    Ok(mol)
+}
+
+/// the rdkit query molecule for `smarts`, failing if rdkit can't read it
+fn mol_from_smarts<'py>(
+    chem: &Bound<'py, PyModule>,
+    smarts: &str,
+) -> Result<Bound<'py, PyAny>, ChomperError> {
+    let mol = chem
+        .call_method1("MolFromSmarts", (smarts,))
+        .map_err(|e| error(smarts, e))?;
+    if mol.is_none() {
+        return Err(error(smarts, "invalid SMARTS"));
+    }
+    Ok(mol)
+}
+
+/// convert `smiles` to SMARTS with rdkit, failing if rdkit can't read it
+pub fn to_smarts(smiles: String) -> Result<String, ChomperError> {
     Python::with_gil(|py| {
-        let chem = PyModule::import_bound(py, "rdkit.Chem").unwrap();
-        let mol = chem.call_method1("MolFromSmiles", (smiles,)).unwrap();
-        chem.call_method1("MolToSmarts", (mol, true))
-            .unwrap()
+        let chem = chem(py).map_err(|e| error(&smiles, e))?;
+        smiles_to_smarts(&chem, &smiles)
+    })
+}
+
+/// like [to_smarts] for each of `smiles`, but holding the GIL once for the
+/// whole batch instead of once per molecule
+pub fn to_smarts_batch(
+    smiles: Vec<String>,
+) -> Vec<Result<String, ChomperError>> {
+    Python::with_gil(|py| match chem(py) {
+        Ok(chem) => smiles.iter().map(|s| smiles_to_smarts(&chem, s)).collect(),
+        Err(e) => smiles.iter().map(|s| Err(error(s, &e))).collect(),
+    })
+}
+
+fn smiles_to_smarts(
+    chem: &Bound<'_, PyModule>,
+    smiles: &str,
+) -> Result<String, ChomperError> {
+    let mol = mol_from_smiles(chem, smiles)?;
+    chem.call_method1("MolToSmarts", (mol, true))
+        .and_then(|s| s.extract())
+        .map_err(|e| error(smiles, e))
+}
+
+/// generate the standard InChI for `smiles`, failing if rdkit can't read it.
+/// with the `inchi` feature, the InChI is generated by libinchi from rdkit's
+/// mol block for `smiles` instead of by rdkit itself
+pub fn to_inchi(smiles: &str) -> Result<String, ChomperError> {
+    #[cfg(feature = "inchi")]
+    let inchi =
+        crate::inchi::from_mol_block(&mol_text(smiles, "MolToMolBlock")?)
+            .map_err(|e| error(smiles, e));
+    #[cfg(not(feature = "inchi"))]
+    let inchi = mol_text(smiles, "MolToInchi");
+    inchi
+}
+
+/// generate the standard InChIKey for `smiles`, failing if rdkit can't read
+/// it. like [to_inchi], this goes through libinchi with the `inchi` feature
+pub fn to_inchikey(smiles: &str) -> Result<String, ChomperError> {
+    #[cfg(feature = "inchi")]
+    let key =
+        crate::inchi::key(&to_inchi(smiles)?).map_err(|e| error(smiles, e));
+    #[cfg(not(feature = "inchi"))]
+    let key = mol_text(smiles, "MolToInchiKey");
+    key
+}
+
+/// the text written by the rdkit function `method`, like `MolToInchi`, for
+/// the molecule read from `smiles`
+fn mol_text(smiles: &str, method: &str) -> Result<String, ChomperError> {
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(smiles, e))?;
+        let mol = mol_from_smiles(&chem, smiles)?;
+        chem.call_method1(method, (mol,))
+            .and_then(|s| s.extract())
+            .map_err(|e| error(smiles, e))
+    })
+}
+
+/// rdkit's canonical isomeric SMILES for `smiles`, with atom maps cleared and
+/// hydrogens made implicit, for comparing molecules written with different
+/// atom orders. fails if rdkit can't read `smiles`
+pub fn canonical_smiles(smiles: &str) -> Result<String, ChomperError> {
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(smiles, e))?;
+        let mol = mol_from_smiles(&chem, smiles)?;
+        let canonical = || -> PyResult<String> {
+            for atom in mol.call_method0("GetAtoms")?.iter()? {
+                atom?.call_method1("SetAtomMapNum", (0,))?;
+            }
+            let mol = chem.call_method1("RemoveHs", (&mol,))?;
+            chem.call_method1("MolToSmiles", (mol,))?.extract()
+        };
+        canonical().map_err(|e| error(smiles, e))
+    })
+}
+
+/// enumerate up to `max_states` protonation states of `smiles` that are likely
+/// near physiological pH, using the rules in charge_states.py. the states are
+/// mapped, explicit-hydrogen SMILES like the cmiles in a QCArchive dataset,
+/// starting with the most ionized states. the input state is always kept as
+/// the last state, even when `max_states` cuts off the ionized ones, so a
+/// `max_states` of 0 gives only the input state. fails if rdkit can't read
+/// `smiles`
+pub fn charge_states(
+    smiles: &str,
+    max_states: usize,
+) -> Result<Vec<String>, ChomperError> {
+    Python::with_gil(|py| {
+        let m = cached(py, &CHARGE_STATES, || {
+            PyModule::from_code_bound(
+                py,
+                include_str!("rdkit/charge_states.py"),
+                "charge_states.py",
+                "charge_states",
+            )
+        })?;
+        m.call_method1("enumerate_states", (smiles, max_states))?
             .extract()
-            .unwrap()
     })
+    .map_err(|e| error(smiles, e))
+}
+
+/// convert a molfile block to a SMILES string with explicit hydrogens and atom
+/// map numbers matching the atom order in the block, like the cmiles in a
+/// QCArchive dataset. fails if rdkit can't read the block
+pub fn mol_block_to_smiles(block: &str) -> Result<String, ChomperError> {
+    let title = block.lines().next().unwrap_or_default().trim();
+    let input = format!("the mol block titled {title:?}");
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(&input, e))?;
+        let mol = chem
+            .call_method1("MolFromMolBlock", (block,))
+            .map_err(|e| error(&input, e))?;
+        if mol.is_none() {
+            return Err(error(&input, "invalid mol block"));
+        }
+        let smiles = || -> PyResult<String> {
+            let mol = chem.call_method1("AddHs", (&mol,))?;
+            for (i, atom) in mol.call_method0("GetAtoms")?.iter()?.enumerate() {
+                atom?.call_method1("SetAtomMapNum", (i + 1,))?;
+            }
+            chem.call_method1("MolToSmiles", (mol,))?.extract()
+        };
+        smiles().map_err(|e| error(&input, e))
+    })
+}
+
+/// every match rdkit finds for the SMARTS `pattern` in the molecule for
+/// `smiles`, or an error if rdkit can't read either of them. each match holds
+/// the target atoms of the mapped pattern atoms in map order, and the same
+/// atoms matched in different orders are reported separately
+pub fn substructure_matches(
+    pattern: &str,
+    smiles: &str,
+) -> Result<Vec<Vec<usize>>, ChomperError> {
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(pattern, e))?;
+        let query = mol_from_smarts(&chem, pattern)?;
+        let mol = mol_from_smiles(&chem, smiles)?;
+        let matches = || -> PyResult<Vec<Vec<usize>>> {
+            // the positions of the mapped query atoms, in map order
+            let mut mapped = Vec::new();
+            for (i, atom) in query.call_method0("GetAtoms")?.iter()?.enumerate()
+            {
+                let map: usize =
+                    atom?.call_method0("GetAtomMapNum")?.extract()?;
+                if map != 0 {
+                    mapped.push((map, i));
+                }
+            }
+            mapped.sort();
+            let kwargs = PyDict::new_bound(py);
+            kwargs.set_item("uniquify", false)?;
+            let matches: Vec<Vec<usize>> = mol
+                .call_method("GetSubstructMatches", (&query,), Some(&kwargs))?
+                .extract()?;
+            Ok(matches
+                .into_iter()
+                .map(|m| mapped.iter().map(|&(_, i)| m[i]).collect())
+                .collect())
+        };
+        matches().map_err(|e| error(smiles, e))
+    })
+}
+
+/// draw the query molecule for `smarts` as an SVG image, without the XML
+/// declaration so that the result can be embedded in HTML. fails if rdkit
+/// can't read `smarts`
+pub fn smarts_to_svg(smarts: &str) -> Result<String, ChomperError> {
+    draw(smarts, &[], &[])
+}
+
+/// like [smarts_to_svg], but highlight the atoms and bonds in `highlight`.
+/// `target` should be the result of parsing `smarts`, and is used to look up
+/// the atoms of each highlighted bond, since rdkit may number the bonds
+/// differently
+pub fn draw_highlighted(
+    smarts: &str,
+    target: &Smarts,
+    highlight: &Highlight,
+) -> Result<String, ChomperError> {
+    let atoms: Vec<_> = highlight.atoms.iter().map(|(&a, &c)| (a, c)).collect();
+    let bonds: Vec<_> = highlight
+        .bonds
+        .iter()
+        .map(|(&b, &c)| (target.bonds[b].atom1, target.bonds[b].atom2, c))
+        .collect();
+    draw(smarts, &atoms, &bonds)
+}
+
+fn draw(
+    smarts: &str,
+    atoms: &[(usize, Color)],
+    bonds: &[(usize, usize, Color)],
+) -> Result<String, ChomperError> {
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(smarts, e))?;
+        let mol = mol_from_smarts(&chem, smarts)?;
+        let svg = || -> PyResult<String> {
+            let draw = cached(py, &DRAW, || {
+                PyModule::import_bound(py, "rdkit.Chem.Draw.rdMolDraw2D")
+            })?;
+            let d = draw.call_method1("MolDraw2DSVG", (250, 200))?;
+            let rgb = |Color(r, g, b): Color| (r, g, b);
+            let atom_colors = PyDict::new_bound(py);
+            for &(a, c) in atoms {
+                atom_colors.set_item(a, rgb(c))?;
+            }
+            let bond_colors = PyDict::new_bound(py);
+            for &(a, b, c) in bonds {
+                let bond = mol.call_method1("GetBondBetweenAtoms", (a, b))?;
+                let idx: usize = bond.call_method0("GetIdx")?.extract()?;
+                bond_colors.set_item(idx, rgb(c))?;
+            }
+            let kwargs = PyDict::new_bound(py);
+            kwargs.set_item("highlightAtoms", atom_colors.keys())?;
+            kwargs.set_item("highlightAtomColors", &atom_colors)?;
+            kwargs.set_item("highlightBonds", bond_colors.keys())?;
+            kwargs.set_item("highlightBondColors", &bond_colors)?;
+            d.call_method("DrawMolecule", (&mol,), Some(&kwargs))?;
+            d.call_method0("FinishDrawing")?;
+            d.call_method0("GetDrawingText")?.extract()
+        };
+        let svg = svg().map_err(|e| error(smarts, e))?;
+        Ok(match svg.find("<svg") {
+            Some(i) => svg[i..].to_owned(),
+            None => svg,
+        })
+    })
+}
+
+/// build the molecular graph rdkit perceives for `smiles` directly, without
+/// going through a SMARTS string. atoms are in rdkit's order and keep their
+/// atom map numbers, with 0 treated as unmapped, and their aromaticity.
+/// chirality is not recorded, since rdkit's tags depend on the neighbor order.
+/// fails if rdkit can't read `smiles` or repeats an atom map
+pub fn smiles_to_graph(smiles: &str) -> Result<Smarts, ChomperError> {
+    Python::with_gil(|py| {
+        let chem = chem(py).map_err(|e| error(smiles, e))?;
+        let mol = mol_from_smiles(&chem, smiles)?;
+        let graph = || -> PyResult<(Vec<Atom>, Vec<Bond>)> {
+            let mut atoms = Vec::new();
+            for atom in mol.call_method0("GetAtoms")?.iter()? {
+                let atom = atom?;
+                let get = |m: &str| atom.call_method0(m);
+                let map: usize = get("GetAtomMapNum")?.extract()?;
+                let a = Atom::new(
+                    get("GetAtomicNum")?.extract::<usize>()?,
+                    get("GetTotalNumHs")?.extract::<usize>()?,
+                    get("GetFormalCharge")?.extract()?,
+                    Chiral::None,
+                    (map != 0).then_some(map),
+                );
+                atoms.push(a.with_aromatic(get("GetIsAromatic")?.extract()?));
+            }
+            let mut bonds = Vec::new();
+            for bond in mol.call_method0("GetBonds")?.iter()? {
+                let bond = bond?;
+                let get = |m: &str| bond.call_method0(m);
+                let order: f64 = get("GetBondTypeAsDouble")?.extract()?;
+                bonds.push(Bond::new(
+                    get("GetBeginAtomIdx")?.extract()?,
+                    get("GetEndAtomIdx")?.extract()?,
+                    BondOrder::from_f64(order).unwrap_or(BondOrder::Single),
+                ));
+            }
+            Ok((atoms, bonds))
+        };
+        let (atoms, bonds) = graph().map_err(|e| error(smiles, e))?;
+        Smarts::from_parts(atoms, bonds).map_err(|e| error(smiles, e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_modules() {
+        static MATH: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+        let load = || {
+            Python::with_gil(|py| {
+                let m =
+                    cached(py, &MATH, || PyModule::import_bound(py, "math"))
+                        .unwrap();
+                let x: f64 =
+                    m.call_method1("sqrt", (4.0,)).unwrap().extract().unwrap();
+                (m.as_ptr() as usize, x)
+            })
+        };
+        let got: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8).map(|_| s.spawn(load)).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // every thread sees the same module object
+        assert!(got.iter().all(|&g| g == got[0]));
+        assert_eq!(got[0].1, 2.0);
+    }
+
+    #[test]
+    fn substructure() {
+        // the C-O bond of ethanol, in both directions
+        let got = substructure_matches("[#6:1]-[#8:2]", "CCO").unwrap();
+        assert_eq!(got, [[1, 2]]);
+        let got = substructure_matches("[#8:2]-[#6:1]", "CCO").unwrap();
+        assert_eq!(got, [[1, 2]]);
+        // unmapped atoms constrain the match but aren't reported
+        let got = substructure_matches("[#6:1]-[#6]", "CCO").unwrap();
+        assert_eq!(got, [[0], [1]]);
+        assert!(substructure_matches("[", "C").is_err());
+    }
+
+    #[test]
+    fn invalid_input() {
+        // rdkit returns no molecule for these, which is an error, not a panic
+        assert!(smiles_to_graph("C1CC").is_err());
+        assert!(to_smarts("C1CC".to_owned()).is_err());
+        assert!(smarts_to_svg("[").is_err());
+        assert!(mol_block_to_smiles("").is_err());
+    }
+
+    #[test]
+    fn inchi() {
+        let want = "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3";
+        assert_eq!(to_inchi("OCC").unwrap(), want);
+        let want = "LFQSCWFLJHTTHZ-UHFFFAOYSA-N";
+        assert_eq!(to_inchikey("[C:1]([C:2][O:3])").unwrap(), want);
+        assert!(to_inchikey("C1CC").is_err());
+    }
+
+    #[test]
+    fn glycine_states() {
+        let got = charge_states("NCC(=O)O", 10).unwrap();
+        // the zwitterion, each singly charged form, and the neutral input
+        assert_eq!(got.len(), 4);
+        let charge = |s: &str| {
+            smiles_to_graph(s)
+                .unwrap()
+                .atoms
+                .iter()
+                .map(|a| a.charge)
+                .sum::<isize>()
+        };
+        assert_eq!(charge(&got[0]), 0);
+        assert!(got[0].contains('+') && got[0].contains('-'));
+        // the input state is kept when the ionized states are cut off
+        let got = charge_states("NCC(=O)O", 2).unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(charge(&got[1]), 0);
+        assert!(!got[1].contains('+') && !got[1].contains('-'));
+        for max_states in [0, 1] {
+            let got = charge_states("NCC(=O)O", max_states).unwrap();
+            assert_eq!(got.len(), 1);
+            assert!(!got[0].contains('+') && !got[0].contains('-'));
+        }
+        assert!(charge_states("C1CC", 2).is_err());
+    }
 }
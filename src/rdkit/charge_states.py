@@ -0,0 +1,81 @@
+"""Rule-based enumeration of protonation states near physiological pH, in the
+spirit of Dimorphite-DL.
+
+Each rule is a SMARTS pattern, the position in the pattern of the ionizable
+atom, and the change in that atom's formal charge when it ionizes. The rules
+only cover the groups that are usually charged in water near pH 7.
+"""
+
+from itertools import combinations
+
+from rdkit import Chem
+
+RULES = [
+    # carboxylic, sulfonic, and phosphonic acids
+    ("[CX3](=O)[OX2H1]", 2, -1),
+    ("[SX4](=O)(=O)[OX2H1]", 3, -1),
+    ("[PX4](=O)[OX2H1]", 2, -1),
+    # aliphatic amines, excluding amides, anilines, sulfonamides, enamines,
+    # and hydrazines
+    (
+        "[NX3;H2,H1,H0;+0;!$(N-[C,S,P]=[O,S,N]);!$(N-a);!$(N-[#7,#8]);"
+        "!$(N-C=C)]",
+        0,
+        1,
+    ),
+    # amidines and guanidines, protonated on the imine nitrogen
+    ("[CX3](=[NX2;H1,H0;+0])[NX3]", 1, 1),
+]
+
+
+def ionize(mol, sites):
+    mol = Chem.RWMol(mol)
+    for idx, dq in sites:
+        atom = mol.GetAtomWithIdx(idx)
+        atom.SetFormalCharge(atom.GetFormalCharge() + dq)
+        atom.SetNumExplicitHs(atom.GetTotalNumHs() + dq)
+        atom.SetNoImplicit(True)
+    Chem.SanitizeMol(mol)
+    return mol
+
+
+def to_cmiles(mol):
+    mol = Chem.AddHs(mol)
+    for atom in mol.GetAtoms():
+        atom.SetAtomMapNum(atom.GetIdx() + 1)
+    return Chem.MolToSmiles(mol)
+
+
+def enumerate_states(smiles, max_states):
+    """Return up to `max_states` distinct protonation states of `smiles` as
+    mapped, explicit-hydrogen SMILES, starting with every ionizable site
+    ionized and ending with the input state. The input state is always
+    included, so at most `max_states - 1` ionized states come before it."""
+    parent = Chem.MolFromSmiles(smiles)
+    if parent is None:
+        raise ValueError("invalid SMILES")
+    for atom in parent.GetAtoms():
+        atom.SetAtomMapNum(0)
+    parent = Chem.RemoveHs(parent)
+    sites = {}
+    for pattern, idx, dq in RULES:
+        query = Chem.MolFromSmarts(pattern)
+        for match in parent.GetSubstructMatches(query):
+            sites.setdefault(match[idx], dq)
+    sites = sorted(sites.items())
+    parent = ionize(parent, ())
+    ret, seen = [], {Chem.MolToSmiles(parent)}
+    for n in range(len(sites), 0, -1):
+        for chosen in combinations(sites, n):
+            if len(ret) >= max_states - 1:
+                break
+            try:
+                mol = ionize(parent, chosen)
+            except ValueError:
+                continue
+            key = Chem.MolToSmiles(mol)
+            if key not in seen:
+                seen.add(key)
+                ret.append(to_cmiles(mol))
+    ret.append(to_cmiles(parent))
+    return ret
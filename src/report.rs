@@ -0,0 +1,492 @@
+//! Reports built from tables, rendered either as standalone HTML pages with
+//! sortable tables or as Markdown for pasting into pull requests
+
+use std::fmt::Write;
+
+use crate::{
+    catalog::PatternCatalog, diff::Diff, matcher::MatchMatrix, rdkit,
+    stats::DatasetStats,
+};
+
+/// A single table cell. [Cell::Svg] holds markup that is embedded directly in
+/// the page, while text is escaped
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Number(f64),
+    Svg(String),
+}
+
+impl Cell {
+    /// the value used when sorting by this cell's column
+    fn sort_key(&self) -> String {
+        match self {
+            Cell::Text(s) => escape(s),
+            Cell::Number(n) => n.to_string(),
+            Cell::Svg(_) => String::new(),
+        }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_owned())
+    }
+}
+
+impl From<String> for Cell {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<usize> for Cell {
+    fn from(value: usize) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    pub caption: String,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub title: String,
+    pub tables: Vec<Table>,
+}
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { background: #eee; cursor: pointer; user-select: none; }
+td.num { text-align: right; }
+caption { font-weight: bold; text-align: left; padding: 0.5em 0; }";
+
+/// clicking a header sorts its table by that column, toggling the direction
+/// on repeated clicks. cells with numeric sort keys compare as numbers
+const SCRIPT: &str = "document.querySelectorAll('th').forEach(th => {
+  th.addEventListener('click', () => {
+    const table = th.closest('table');
+    const body = table.tBodies[0];
+    const col = th.cellIndex;
+    const asc = th.dataset.order !== 'asc';
+    th.dataset.order = asc ? 'asc' : 'desc';
+    const key = r => r.cells[col].dataset.sort;
+    const rows = Array.from(body.rows).sort((a, b) => {
+      const [x, y] = [key(a), key(b)];
+      const [nx, ny] = [parseFloat(x), parseFloat(y)];
+      const c = isNaN(nx) || isNaN(ny) ? x.localeCompare(y) : nx - ny;
+      return asc ? c : -c;
+    });
+    rows.forEach(r => body.appendChild(r));
+  });
+});";
+
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+impl Report {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            tables: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, table: Table) {
+        self.tables.push(table);
+    }
+
+    /// render `self` as a complete HTML document with inline styles and
+    /// scripts, so it can be opened or shared as a single file
+    pub fn to_html(&self) -> String {
+        let mut s = String::new();
+        let title = escape(&self.title);
+        writeln!(s, "<!DOCTYPE html>\n<html>\n<head>").unwrap();
+        writeln!(s, "<meta charset=\"utf-8\">\n<title>{title}</title>")
+            .unwrap();
+        writeln!(s, "<style>\n{STYLE}\n</style>\n</head>\n<body>").unwrap();
+        writeln!(s, "<h1>{title}</h1>").unwrap();
+        for table in &self.tables {
+            writeln!(s, "<table>").unwrap();
+            writeln!(s, "<caption>{}</caption>", escape(&table.caption))
+                .unwrap();
+            write!(s, "<thead><tr>").unwrap();
+            for h in &table.headers {
+                write!(s, "<th>{}</th>", escape(h)).unwrap();
+            }
+            writeln!(s, "</tr></thead>\n<tbody>").unwrap();
+            for row in &table.rows {
+                write!(s, "<tr>").unwrap();
+                for cell in row {
+                    let key = cell.sort_key();
+                    match cell {
+                        Cell::Text(t) => write!(
+                            s,
+                            "<td data-sort=\"{key}\">{}</td>",
+                            escape(t)
+                        ),
+                        Cell::Number(n) => write!(
+                            s,
+                            "<td class=\"num\" data-sort=\"{key}\">{}</td>",
+                            format_number(*n)
+                        ),
+                        Cell::Svg(svg) => {
+                            write!(s, "<td data-sort=\"\">{svg}</td>")
+                        }
+                    }
+                    .unwrap();
+                }
+                writeln!(s, "</tr>").unwrap();
+            }
+            writeln!(s, "</tbody>\n</table>").unwrap();
+        }
+        writeln!(s, "<script>\n{SCRIPT}\n</script>\n</body>\n</html>").unwrap();
+        s
+    }
+}
+
+impl Table {
+    /// render `self` as a GitHub-flavored Markdown table. columns containing
+    /// SVG depictions are left out since they can't be embedded inline
+    pub fn to_markdown(&self) -> String {
+        let keep: Vec<usize> = (0..self.headers.len())
+            .filter(|&c| {
+                !self
+                    .rows
+                    .iter()
+                    .any(|r| matches!(r.get(c), Some(Cell::Svg(_))))
+            })
+            .collect();
+        let mut s = String::new();
+        if !self.caption.is_empty() {
+            writeln!(s, "**{}**\n", self.caption).unwrap();
+        }
+        let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+        writeln!(
+            s,
+            "{}",
+            line(keep.iter().map(|&c| md_escape(&self.headers[c])).collect())
+        )
+        .unwrap();
+        writeln!(
+            s,
+            "{}",
+            line(
+                keep.iter()
+                    .map(|&c| {
+                        let numeric = self
+                            .rows
+                            .iter()
+                            .all(|r| matches!(r.get(c), Some(Cell::Number(_))));
+                        if numeric && !self.rows.is_empty() {
+                            "---:".to_owned()
+                        } else {
+                            "---".to_owned()
+                        }
+                    })
+                    .collect()
+            )
+        )
+        .unwrap();
+        for row in &self.rows {
+            let cells = keep
+                .iter()
+                .map(|&c| match row.get(c) {
+                    Some(Cell::Text(t)) => md_escape(t),
+                    Some(Cell::Number(n)) => format_number(*n),
+                    Some(Cell::Svg(_)) | None => String::new(),
+                })
+                .collect();
+            writeln!(s, "{}", line(cells)).unwrap();
+        }
+        s
+    }
+}
+
+impl Report {
+    /// render `self` as Markdown, with the title as a heading followed by each
+    /// of the tables
+    pub fn to_markdown(&self) -> String {
+        let mut s = format!("# {}\n", self.title);
+        for table in &self.tables {
+            write!(s, "\n{}", table.to_markdown()).unwrap();
+        }
+        s
+    }
+}
+
+/// escape characters that would break a Markdown table cell
+fn md_escape(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 {
+        format!("{n:.0}")
+    } else {
+        format!("{n:.2}")
+    }
+}
+
+/// build a table summarizing how many of the molecules in `matrix` each
+/// pattern in `catalog` matched. if `depict` is true, each row includes an SVG
+/// depiction of the pattern drawn by rdkit
+pub fn coverage_table(
+    catalog: &PatternCatalog,
+    matrix: &MatchMatrix,
+    depict: bool,
+) -> Table {
+    let mut headers = vec!["Pattern", "SMARTS"];
+    if depict {
+        headers.push("Depiction");
+    }
+    headers.extend(["Molecules", "Matches", "Coverage (%)"]);
+    let rows = catalog
+        .iter()
+        .enumerate()
+        .map(|(q, p)| {
+            let (mols, matches) = matrix
+                .query_hits(q)
+                .fold((0, 0), |(m, n), (_, c)| (m + 1, n + c));
+            let mut row =
+                vec![Cell::from(p.name.as_str()), p.smarts.as_str().into()];
+            if depict {
+                // a pattern rdkit can't draw gets the reason instead
+                row.push(match rdkit::smarts_to_svg(&p.smarts) {
+                    Ok(svg) => Cell::Svg(svg),
+                    Err(e) => Cell::Text(e.to_string()),
+                });
+            }
+            let frac = if matrix.n_molecules == 0 {
+                0.0
+            } else {
+                100.0 * mols as f64 / matrix.n_molecules as f64
+            };
+            row.extend([mols.into(), matches.into(), frac.into()]);
+            row
+        })
+        .collect();
+    Table {
+        caption: format!("Coverage of {} molecules", matrix.n_molecules),
+        headers: headers.into_iter().map(String::from).collect(),
+        rows,
+    }
+}
+
+/// build the tables of `stats`: one with the molecule, duplicate, and
+/// unreadable counts, followed by one for each distribution, in the order
+/// they are displayed
+pub fn stats_tables(stats: &DatasetStats) -> Vec<Table> {
+    fn table<K: ToString>(
+        caption: &str,
+        key: &str,
+        value: &str,
+        counts: impl IntoIterator<Item = (K, usize)>,
+    ) -> Table {
+        Table {
+            caption: caption.to_owned(),
+            headers: vec![key.to_owned(), value.to_owned()],
+            rows: counts
+                .into_iter()
+                .map(|(k, n)| vec![k.to_string().into(), n.into()])
+                .collect(),
+        }
+    }
+    vec![
+        table(
+            "Summary",
+            "",
+            "Count",
+            [
+                ("Molecules", stats.n_molecules),
+                ("Duplicates", stats.n_duplicates),
+                ("Unreadable", stats.n_unreadable),
+            ],
+        ),
+        table(
+            "Elements",
+            "Element",
+            "Atoms",
+            stats.elements.iter().map(|(k, &n)| (k, n)),
+        ),
+        table(
+            "Heavy atoms",
+            "Heavy atoms",
+            "Molecules",
+            stats.heavy_atoms.iter().map(|(k, &n)| (k, n)),
+        ),
+        table(
+            "Net charges",
+            "Charge",
+            "Molecules",
+            stats.charges.iter().map(|(k, &n)| (k, n)),
+        ),
+    ]
+}
+
+/// build a table with one row for each change in `diffs`, labeled with the
+/// name of the pair of molecules it came from
+pub fn diff_table(diffs: &[(String, Diff)]) -> Table {
+    let rows = diffs
+        .iter()
+        .flat_map(|(name, d)| {
+            d.changes
+                .iter()
+                .map(move |c| vec![name.as_str().into(), c.to_string().into()])
+        })
+        .collect();
+    Table {
+        caption: "Differences".to_owned(),
+        headers: vec!["Entry".to_owned(), "Change".to_owned()],
+        rows,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diff::diff,
+        matcher::MatchOptions,
+        molecule::{mol, Molecule},
+        smarts::Smarts,
+    };
+
+    use super::*;
+
+    #[test]
+    fn coverage() {
+        let catalog = PatternCatalog::load("testfiles/catalog.txt").unwrap();
+        let mols = [
+            mol("[#6H3:1]-[#6H:2]=[#8:3]"),
+            mol("[#6H3:1]-[#7H2:2]"),
+            mol("[#6H3:1]-[#6H2:2]-[#7H2:3]"),
+        ];
+        let matrix = catalog.match_all(&mols, &MatchOptions::default());
+        let table = coverage_table(&catalog, &matrix, false);
+        assert_eq!(
+            table.headers,
+            ["Pattern", "SMARTS", "Molecules", "Matches", "Coverage (%)"]
+        );
+        assert_eq!(
+            table.rows[2],
+            vec![
+                Cell::from("amine"),
+                Cell::from("[#7:1]"),
+                Cell::Number(2.0),
+                Cell::Number(2.0),
+                Cell::Number(200.0 / 3.0),
+            ]
+        );
+
+        let mut report = Report::new("Coverage <test>");
+        report.push(table);
+        let html = report.to_html();
+        assert!(html.contains("<title>Coverage &lt;test&gt;</title>"));
+        assert!(html.contains("<td class=\"num\" data-sort=\"2\">2</td>"));
+        assert!(html.contains("<td data-sort=\"amine\">amine</td>"));
+        assert!(html.contains("66.67"));
+    }
+
+    #[test]
+    fn markdown() {
+        let mut report = Report::new("Coverage");
+        report.push(Table {
+            caption: "Patterns".to_owned(),
+            headers: vec!["Name".into(), "Depiction".into(), "Count".into()],
+            rows: vec![
+                vec!["a|b".into(), Cell::Svg("<svg/>".into()), 3.into()],
+                vec!["c".into(), Cell::Svg("<svg/>".into()), 0.5.into()],
+            ],
+        });
+        let want = "# Coverage
+
+**Patterns**
+
+| Name | Count |
+| --- | ---: |
+| a\\|b | 3 |
+| c | 0.50 |
+";
+        assert_eq!(report.to_markdown(), want);
+    }
+
+    #[test]
+    fn stats_markdown() {
+        let mols: Vec<_> = ["[#6H4]", "[#8H2]", "[#6H4]", "[#7H4+]"]
+            .map(|s| Molecule::try_from(&Smarts::parse(s.to_owned())).unwrap())
+            .into();
+        let mut report = Report::new("Statistics");
+        for table in stats_tables(&DatasetStats::new(&mols)) {
+            report.push(table);
+        }
+        let want = "# Statistics
+
+**Summary**
+
+|  | Count |
+| --- | ---: |
+| Molecules | 4 |
+| Duplicates | 1 |
+| Unreadable | 0 |
+
+**Elements**
+
+| Element | Atoms |
+| --- | ---: |
+| C | 2 |
+| H | 14 |
+| N | 1 |
+| O | 1 |
+
+**Heavy atoms**
+
+| Heavy atoms | Molecules |
+| --- | ---: |
+| 1 | 4 |
+
+**Net charges**
+
+| Charge | Molecules |
+| --- | ---: |
+| 0 | 3 |
+| 1 | 1 |
+";
+        assert_eq!(report.to_markdown(), want);
+    }
+
+    #[test]
+    fn diff_markdown() {
+        let parse = |s: &str| Smarts::parse(s.to_owned());
+        let d = diff(&parse("[#6H3:1]-[#8H:2]"), &parse("[#6H3:1]-[#7H2:2]"));
+        let table = diff_table(&[("entry 1".to_owned(), d)]);
+        let want = "**Differences**
+
+| Entry | Change |
+| --- | --- |
+| entry 1 | ~ atom 1: element O -> N, n_hydrogens 1 -> 2 |
+";
+        assert_eq!(table.to_markdown(), want);
+    }
+}
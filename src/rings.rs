@@ -0,0 +1,333 @@
+//! Ring perception and per-atom ring membership.
+//!
+//! [RingInfo] finds a smallest set of smallest rings (SSSR) by taking the
+//! smallest ring through each ring bond as a candidate and keeping candidates,
+//! shortest first, as long as they are independent of the rings already kept.
+//! This finds the SSSR for everything but unusual cages, where it can come up
+//! short. Membership is stored as one [BitSet] of ring indices per atom and per
+//! bond, so the `R`, `r`, and `@` primitives are cheap to answer
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::molecule::Molecule;
+
+/// A growable set of small integers
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        let (w, b) = (i / 64, i % 64);
+        if w >= self.words.len() {
+            self.words.resize(w + 1, 0);
+        }
+        self.words[w] |= 1 << b;
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        self.words
+            .get(i / 64)
+            .is_some_and(|w| w & (1 << (i % 64)) != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// whether `self` and `other` have any element in common
+    pub fn intersects(&self, other: &BitSet) -> bool {
+        self.words.iter().zip(&other.words).any(|(a, b)| a & b != 0)
+    }
+
+    /// the number of elements in both `self` and `other`
+    pub fn n_common(&self, other: &BitSet) -> usize {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    /// the elements of the set in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(w, &word)| {
+            (0..64)
+                .filter(move |b| word & (1 << b) != 0)
+                .map(move |b| w * 64 + b)
+        })
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.iter().next()
+    }
+
+    /// the symmetric difference of `self` and `other`, stored in `self`
+    fn xor(&mut self, other: &BitSet) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a ^= b;
+        }
+    }
+}
+
+impl FromIterator<usize> for BitSet {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut ret = BitSet::new();
+        for i in iter {
+            ret.insert(i);
+        }
+        ret
+    }
+}
+
+/// The rings of a [Molecule] and the rings each atom and bond belongs to
+#[derive(Clone, Debug, PartialEq)]
+pub struct RingInfo {
+    /// the atoms of each ring in order around it, with rings sorted by size
+    rings: Vec<Vec<usize>>,
+    /// atom -> the indices of the rings containing it
+    atom_rings: Vec<BitSet>,
+    /// bond -> the indices of the rings containing it
+    bond_rings: Vec<BitSet>,
+}
+
+impl RingInfo {
+    pub fn new(mol: &Molecule) -> Self {
+        let n = mol.atoms.len();
+        let mut adj = vec![Vec::new(); n];
+        for (k, b) in mol.bonds.iter().enumerate() {
+            adj[b.atom1].push((b.atom2, k));
+            adj[b.atom2].push((b.atom1, k));
+        }
+
+        // the smallest ring through each bond, as (atoms, bonds)
+        let mut candidates: Vec<(Vec<usize>, BitSet)> = Vec::new();
+        for (k, b) in mol.bonds.iter().enumerate() {
+            let Some((atoms, mut bonds)) =
+                shortest_path(&adj, b.atom1, b.atom2, k)
+            else {
+                continue;
+            };
+            bonds.insert(k);
+            if !candidates.iter().any(|(_, c)| *c == bonds) {
+                candidates.push((atoms, bonds));
+            }
+        }
+        candidates.sort_by(|a, b| (a.0.len(), &a.1).cmp(&(b.0.len(), &b.1)));
+
+        let n_rings = (mol.bonds.len() + n_components(&adj)).saturating_sub(n);
+        let mut basis: Vec<(usize, BitSet)> = Vec::new();
+        let mut rings = Vec::new();
+        let mut atom_rings = vec![BitSet::new(); n];
+        let mut bond_rings = vec![BitSet::new(); mol.bonds.len()];
+        for (atoms, bonds) in candidates {
+            if rings.len() == n_rings {
+                break;
+            }
+            let mut reduced = bonds.clone();
+            for (pivot, v) in &basis {
+                if reduced.contains(*pivot) {
+                    reduced.xor(v);
+                }
+            }
+            let Some(pivot) = reduced.first() else {
+                continue;
+            };
+            basis.push((pivot, reduced));
+            let r = rings.len();
+            for &a in &atoms {
+                atom_rings[a].insert(r);
+            }
+            for b in bonds.iter() {
+                bond_rings[b].insert(r);
+            }
+            rings.push(atoms);
+        }
+
+        Self {
+            rings,
+            atom_rings,
+            bond_rings,
+        }
+    }
+
+    /// the atoms of each ring, in order around the ring
+    pub fn rings(&self) -> &[Vec<usize>] {
+        &self.rings
+    }
+
+    pub fn n_rings(&self) -> usize {
+        self.rings.len()
+    }
+
+    /// the indices into [RingInfo::rings] of the rings containing `atom`
+    pub fn atom_rings(&self, atom: usize) -> &BitSet {
+        &self.atom_rings[atom]
+    }
+
+    /// the indices into [RingInfo::rings] of the rings containing `bond`
+    pub fn bond_rings(&self, bond: usize) -> &BitSet {
+        &self.bond_rings[bond]
+    }
+
+    /// the number of rings containing `atom`, as in the SMARTS `R` primitive
+    pub fn n_atom_rings(&self, atom: usize) -> usize {
+        self.atom_rings[atom].len()
+    }
+
+    pub fn is_ring_bond(&self, bond: usize) -> bool {
+        !self.bond_rings[bond].is_empty()
+    }
+
+    /// whether some ring contains both `a` and `b`
+    pub fn same_ring(&self, a: usize, b: usize) -> bool {
+        self.atom_rings[a].intersects(&self.atom_rings[b])
+    }
+
+    /// the size of the smallest ring containing `atom`, as in the SMARTS `r`
+    /// primitive, or `None` if it is not in a ring
+    pub fn smallest_ring(&self, atom: usize) -> Option<usize> {
+        self.atom_rings[atom]
+            .iter()
+            .map(|r| self.rings[r].len())
+            .min()
+    }
+
+    /// ring size -> the number of rings of that size containing `atom`
+    pub fn ring_size_counts(&self, atom: usize) -> BTreeMap<usize, usize> {
+        let mut ret = BTreeMap::new();
+        for r in self.atom_rings[atom].iter() {
+            *ret.entry(self.rings[r].len()).or_default() += 1;
+        }
+        ret
+    }
+}
+
+/// the shortest path from `from` to `to` in `adj` that doesn't use bond
+/// `skip`, as its atoms in order and its bonds
+fn shortest_path(
+    adj: &[Vec<(usize, usize)>],
+    from: usize,
+    to: usize,
+    skip: usize,
+) -> Option<(Vec<usize>, BitSet)> {
+    // atom -> (previous atom, bond used to reach it)
+    let mut prev = vec![None; adj.len()];
+    let mut seen = vec![false; adj.len()];
+    seen[from] = true;
+    let mut queue = VecDeque::from([from]);
+    while let Some(i) = queue.pop_front() {
+        if i == to {
+            break;
+        }
+        for &(j, k) in &adj[i] {
+            if k != skip && !seen[j] {
+                seen[j] = true;
+                prev[j] = Some((i, k));
+                queue.push_back(j);
+            }
+        }
+    }
+    if !seen[to] {
+        return None;
+    }
+    let mut atoms = vec![to];
+    let mut bonds = BitSet::new();
+    let mut cur = to;
+    while let Some((p, k)) = prev[cur] {
+        atoms.push(p);
+        bonds.insert(k);
+        cur = p;
+    }
+    atoms.reverse();
+    Some((atoms, bonds))
+}
+
+fn n_components(adj: &[Vec<(usize, usize)>]) -> usize {
+    let mut seen = vec![false; adj.len()];
+    let mut ret = 0;
+    for start in 0..adj.len() {
+        if seen[start] {
+            continue;
+        }
+        ret += 1;
+        seen[start] = true;
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            for &(j, _) in &adj[i] {
+                if !seen[j] {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn bitset() {
+        let s: BitSet = [3, 70, 3, 0].into_iter().collect();
+        assert_eq!(s.iter().collect::<Vec<_>>(), [0, 3, 70]);
+        assert_eq!(s.len(), 3);
+        assert!(s.contains(70) && !s.contains(71) && !s.contains(1000));
+        assert!(s.intersects(&[70].into_iter().collect()));
+        assert!(!s.intersects(&BitSet::new()));
+        assert_eq!(s.n_common(&[0, 70, 71].into_iter().collect()), 2);
+        assert!(BitSet::new().is_empty());
+    }
+
+    #[test]
+    fn fused() {
+        // naphthalene, with the fusion atoms at positions 4 and 5
+        let m =
+            mol("[cH]1:[cH]:[cH]:[cH]:[cH0]2:[cH0]:1:[cH]:[cH]:[cH]:[cH]:2");
+        let info = RingInfo::new(&m);
+        assert_eq!(info.n_rings(), 2);
+        assert!(info.rings().iter().all(|r| r.len() == 6));
+        assert_eq!(info.n_atom_rings(4), 2);
+        assert_eq!(info.n_atom_rings(5), 2);
+        assert_eq!(info.n_atom_rings(1), 1);
+        assert_eq!(info.ring_size_counts(4), BTreeMap::from([(6, 2)]));
+        assert!(info.same_ring(1, 5));
+        assert!(!info.same_ring(1, 7));
+        // the fusion bond is in both rings
+        let fusion = m
+            .bonds
+            .iter()
+            .position(|b| (b.atom1, b.atom2) == (4, 5))
+            .unwrap();
+        assert_eq!(info.bond_rings(fusion).len(), 2);
+    }
+
+    #[test]
+    fn chains_and_spiro() {
+        let m = mol("[#6H3]-[#6H]1-[#6H2]-[#6H2]-[#6]-12-[#6H2]-[#6H2]-2");
+        let info = RingInfo::new(&m);
+        assert_eq!(info.n_rings(), 2);
+        assert_eq!(info.smallest_ring(0), None);
+        assert_eq!(info.smallest_ring(1), Some(4));
+        assert_eq!(info.ring_size_counts(4), BTreeMap::from([(3, 1), (4, 1)]));
+        assert!(!info.is_ring_bond(0));
+        assert!(info.is_ring_bond(1));
+        assert!(!info.same_ring(1, 5));
+    }
+}
@@ -0,0 +1,161 @@
+//! The distribution of ring sizes across a set of molecules, and the
+//! macrocycles among them.
+//!
+//! Rings are the SSSR rings from [crate::rings::RingInfo], so a fused
+//! bicycle counts as its two smallest rings rather than also as the envelope
+//! around both. A molecule is a macrocycle if any of its rings has at least
+//! the threshold number of atoms, [MACROCYCLE_SIZE] unless given otherwise
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use crate::{molecule::Molecule, Dataset, Provenance};
+
+/// The smallest ring size that makes a molecule a macrocycle by default
+pub const MACROCYCLE_SIZE: usize = 12;
+
+/// A molecule with at least one ring of the macrocycle size or larger
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macrocycle {
+    /// the position of the molecule in the input
+    pub molecule: usize,
+    pub provenance: Option<Provenance>,
+    /// the sizes of its macrocyclic rings, largest first
+    pub sizes: Vec<usize>,
+}
+
+/// The ring sizes found by [ring_sizes]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RingSizes {
+    /// ring size -> the number of rings of that size
+    pub counts: BTreeMap<usize, usize>,
+    /// ring size -> the number of molecules with at least one such ring
+    pub n_molecules: BTreeMap<usize, usize>,
+    pub macrocycle_size: usize,
+    pub macrocycles: Vec<Macrocycle>,
+}
+
+/// count the rings of each size in `mols`, flagging every molecule with a
+/// ring of at least `macrocycle_size` atoms as a [Macrocycle]
+pub fn ring_sizes(mols: &[Molecule], macrocycle_size: usize) -> RingSizes {
+    let mut ret = RingSizes {
+        macrocycle_size,
+        ..Default::default()
+    };
+    for (i, mol) in mols.iter().enumerate() {
+        let info = mol.ring_info();
+        let mut seen = BTreeSet::new();
+        let mut macro_sizes = Vec::new();
+        for ring in info.rings() {
+            let size = ring.len();
+            *ret.counts.entry(size).or_default() += 1;
+            if seen.insert(size) {
+                *ret.n_molecules.entry(size).or_default() += 1;
+            }
+            if size >= macrocycle_size {
+                macro_sizes.push(size);
+            }
+        }
+        if !macro_sizes.is_empty() {
+            macro_sizes.sort_by(|a, b| b.cmp(a));
+            ret.macrocycles.push(Macrocycle {
+                molecule: i,
+                provenance: mol.provenance.clone(),
+                sizes: macro_sizes,
+            });
+        }
+    }
+    ret
+}
+
+impl RingSizes {
+    /// add `tag` to the records in `dataset` that the macrocycles came from,
+    /// matching them by the entry name and record ID in their provenance, and
+    /// return the number of records newly tagged
+    pub fn tag_macrocycles(&self, dataset: &mut Dataset, tag: &str) -> usize {
+        let sources: BTreeSet<(&str, Option<&str>)> = self
+            .macrocycles
+            .iter()
+            .filter_map(|m| m.provenance.as_ref())
+            .map(|p| (p.dataset_key.as_str(), p.record_id.as_deref()))
+            .collect();
+        dataset.tag(tag, |key, id| sources.contains(&(key, id)))
+    }
+}
+
+/// Display one `size rings molecules` line per ring size, followed by the
+/// number of macrocycles and a line for each one
+impl Display for RingSizes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>6} {:>8} {:>9}", "size", "rings", "molecules")?;
+        for (size, count) in &self.counts {
+            let n = self.n_molecules[size];
+            writeln!(f, "{size:>6} {count:>8} {n:>9}")?;
+        }
+        writeln!(
+            f,
+            "{} macrocycles with rings of {} or more atoms",
+            self.macrocycles.len(),
+            self.macrocycle_size
+        )?;
+        for m in &self.macrocycles {
+            match &m.provenance {
+                Some(p) => {
+                    write!(f, "    {}", p.dataset_key)?;
+                    if let Some(id) = &p.record_id {
+                        write!(f, " ({id})")?;
+                    }
+                }
+                None => write!(f, "    molecule {}", m.molecule)?,
+            }
+            writeln!(f, ": {:?}", m.sizes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    /// a ring of `n` CH2 groups
+    fn cycloalkane(n: usize) -> String {
+        let mut ret = "[#6H2]1".to_owned();
+        for _ in 1..n - 1 {
+            ret.push_str("-[#6H2]");
+        }
+        ret.push_str("-[#6H2]-1");
+        ret
+    }
+
+    #[test]
+    fn histogram() {
+        let mols = [
+            mol(&cycloalkane(6)),
+            // naphthalene counts as two six-membered rings
+            mol("[cH]1:[cH]:[cH]:[cH]:[cH0]2:[cH0]:1:[cH]:[cH]:[cH]:[cH]:2"),
+            mol(&cycloalkane(14)),
+            mol("[#6H3]-[#6H3]"),
+        ];
+        let got = ring_sizes(&mols, MACROCYCLE_SIZE);
+        assert_eq!(got.counts, BTreeMap::from([(6, 3), (14, 1)]));
+        assert_eq!(got.n_molecules, BTreeMap::from([(6, 2), (14, 1)]));
+        assert_eq!(
+            got.macrocycles,
+            [Macrocycle {
+                molecule: 2,
+                provenance: None,
+                sizes: vec![14],
+            }]
+        );
+        assert!(ring_sizes(&mols, 15).macrocycles.is_empty());
+        assert!(got.to_string().ends_with(
+            "1 macrocycles with rings of 12 or more atoms\n    molecule 2: \
+             [14]\n"
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! Ring systems and the distinct ring templates found across a dataset.
+//!
+//! A ring system is a maximal set of rings joined by shared atoms, so fused
+//! and spiro rings form one system, while rings joined by a chain or a single
+//! bond, like the two rings of biphenyl, form two. A template is a ring
+//! system with its substituents and hydrogens removed, identified by its
+//! [canonical_key] and written as a SMARTS pattern in canonical atom order
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use crate::{
+    canonical::{canonical_key, write_canonical},
+    elements,
+    molecule::{MolAtom, MolBond, Molecule},
+    smarts::Chiral,
+    symmetry::canonical_order,
+};
+
+/// the atoms of each ring system in `mol`, each in increasing order, with
+/// systems sorted by their first atom
+pub fn ring_systems(mol: &Molecule) -> Vec<Vec<usize>> {
+    let info = mol.ring_info();
+    let mut systems: Vec<BTreeSet<usize>> = Vec::new();
+    for ring in info.rings() {
+        let mut merged: BTreeSet<usize> = ring.iter().copied().collect();
+        systems.retain(|s| {
+            if s.is_disjoint(&merged) {
+                return true;
+            }
+            merged.extend(s);
+            false
+        });
+        systems.push(merged);
+    }
+    let mut ret: Vec<Vec<usize>> = systems
+        .into_iter()
+        .map(|s| s.into_iter().collect())
+        .collect();
+    ret.sort();
+    ret
+}
+
+/// the ring system made of `atoms` in `mol` as a molecule of its own, keeping
+/// only the ring bonds between them and dropping H counts, stereochemistry,
+/// and atom maps
+fn extract(mol: &Molecule, atoms: &[usize]) -> Molecule {
+    let info = mol.ring_info();
+    let index: BTreeMap<usize, usize> =
+        atoms.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+    let new_atoms = atoms
+        .iter()
+        .map(|&a| MolAtom {
+            isotope: None,
+            n_hydrogens: 0,
+            chirality: Chiral::None,
+            mol_index: None,
+            ..mol.atoms[a].clone()
+        })
+        .collect();
+    let bonds = mol
+        .bonds
+        .iter()
+        .enumerate()
+        .filter(|&(k, _)| info.is_ring_bond(k))
+        .filter_map(|(_, b)| {
+            Some(MolBond {
+                atom1: *index.get(&b.atom1)?,
+                atom2: *index.get(&b.atom2)?,
+                bond_type: b.bond_type,
+                direction: None,
+            })
+        })
+        .collect();
+    Molecule::new(new_atoms, bonds)
+}
+
+fn atom_smarts(atom: &MolAtom) -> String {
+    let mut ret = String::from("[");
+    match elements::symbol(atom.atomic_number) {
+        Some(sym @ ("B" | "C" | "N" | "O" | "P" | "S")) if atom.aromatic => {
+            ret.push_str(&sym.to_lowercase())
+        }
+        _ => ret.push_str(&format!("#{}", atom.atomic_number)),
+    }
+    match atom.charge {
+        0 => {}
+        1 => ret.push('+'),
+        -1 => ret.push('-'),
+        q => ret.push_str(&format!("{q:+}")),
+    }
+    ret.push(']');
+    ret
+}
+
+/// One distinct ring system, as counted by [ring_templates]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RingTemplate {
+    /// the system as a SMARTS pattern, without H counts or substituents
+    pub smarts: String,
+    pub n_rings: usize,
+    /// the number of times the system occurs, counting each occurrence
+    /// within a molecule
+    pub count: usize,
+    /// the number of molecules containing the system at least once
+    pub n_molecules: usize,
+}
+
+/// The ring templates found in a set of molecules, keyed by the
+/// [canonical_key] of each system
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RingTemplates {
+    pub templates: BTreeMap<String, RingTemplate>,
+}
+
+/// count the distinct ring systems across `mols`
+pub fn ring_templates(mols: &[Molecule]) -> RingTemplates {
+    let mut ret = RingTemplates::default();
+    for mol in mols {
+        let mut seen = BTreeSet::new();
+        for atoms in ring_systems(mol) {
+            let system = extract(mol, &atoms);
+            let key = canonical_key(&system);
+            let first = seen.insert(key.clone());
+            let template =
+                ret.templates.entry(key).or_insert_with(|| RingTemplate {
+                    smarts: write_canonical(
+                        &system,
+                        &canonical_order(&system),
+                        |a| atom_smarts(&system.atoms[a]),
+                    ),
+                    n_rings: system.ring_info().n_rings(),
+                    count: 0,
+                    n_molecules: 0,
+                });
+            template.count += 1;
+            template.n_molecules += usize::from(first);
+        }
+    }
+    ret
+}
+
+impl RingTemplates {
+    /// the templates from most to least common, breaking ties by SMARTS
+    pub fn sorted(&self) -> Vec<&RingTemplate> {
+        let mut ret: Vec<_> = self.templates.values().collect();
+        ret.sort_by(|a, b| b.count.cmp(&a.count).then(a.smarts.cmp(&b.smarts)));
+        ret
+    }
+}
+
+/// Display one `count molecules smarts` line per template, from most to least
+/// common
+impl Display for RingTemplates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for t in self.sorted() {
+            writeln!(f, "{:>8} {:>8} {}", t.count, t.n_molecules, t.smarts)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+    use crate::smarts::Smarts;
+
+    use super::*;
+
+    const NAPHTHALENE: &str =
+        "[cH]1:[cH]:[cH]:[cH]:[cH0]2:[cH0]:1:[cH]:[cH]:[cH]:[cH]:2";
+    const BIPHENYL: &str = "[cH]1:[cH]:[cH]:[cH]:[cH]:[cH0]:1-\
+        [cH0]1:[cH]:[cH]:[cH]:[cH]:[cH]:1";
+
+    #[test]
+    fn systems() {
+        assert!(ring_systems(&mol("[#6H3]-[#6H3]")).is_empty());
+        assert_eq!(
+            ring_systems(&mol(NAPHTHALENE)),
+            [(0..10).collect::<Vec<_>>()]
+        );
+        assert_eq!(
+            ring_systems(&mol(BIPHENYL)),
+            [(0..6).collect::<Vec<_>>(), (6..12).collect()]
+        );
+        // spiro rings share one atom, so they are one system
+        let spiro = mol("[#6H3]-[#6H]1-[#6H2]-[#6]-12-[#6H2]-[#6H2]-2");
+        assert_eq!(ring_systems(&spiro), [vec![1, 2, 3, 4, 5]]);
+    }
+
+    #[test]
+    fn templates() {
+        let mols = [
+            mol(BIPHENYL),
+            mol("[cH]1:[cH]:[cH]:[cH]:[cH]:[cH0]:1-[#8H]"),
+            mol(NAPHTHALENE),
+            mol("[cH]1:[cH]:[cH]:[nH0]:[cH]:[cH]:1"),
+            mol("[#8H]-[cH0]1:[cH]:[cH]:[cH]:[cH]:[cH]:1"),
+        ];
+        let got = ring_templates(&mols);
+        assert_eq!(got.templates.len(), 3);
+        let sorted = got.sorted();
+        assert_eq!(
+            sorted[0],
+            &RingTemplate {
+                smarts: "[c]:1:[c]:[c]:[c]:[c]:[c]:1".to_owned(),
+                n_rings: 1,
+                count: 4,
+                n_molecules: 3,
+            }
+        );
+        assert_eq!(sorted[1].n_rings, 2);
+        // each template parses back to the same system
+        for t in sorted {
+            let smarts = Smarts::parse(t.smarts.clone());
+            assert_eq!(
+                ring_systems(&Molecule::try_from(&smarts).unwrap()).len(),
+                1,
+                "{}",
+                t.smarts
+            );
+        }
+        assert_eq!(got.to_string().lines().count(), 3);
+    }
+}
@@ -0,0 +1,369 @@
+//! Versioned JSON output format.
+//!
+//! The types here are deliberately separate from the internal data model so
+//! that the serialized layout only changes when [SCHEMA_VERSION] does. Every
+//! output is wrapped in a [Document]:
+//!
+//! ```json
+//! {
+//!   "schema_version": 2,
+//!   "molecules": [
+//!     {
+//!       "atoms": [
+//!         {"atomic_number": 6, "n_hydrogens": 3, "charge": 0,
+//!          "chirality": "none", "map_index": 1}
+//!       ],
+//!       "bonds": [{"atom1": 0, "atom2": 1, "order": "single"}],
+//!       "provenance": {"file": "opt.json", "dataset_key": "...",
+//!                      "record_id": "123"}
+//!     }
+//!   ],
+//!   "matches": [
+//!     {"query": 0, "molecule": 0, "count": 1, "matches": [[0, 1]]}
+//!   ],
+//!   "labels": [{"molecule": 0, "pattern": "b1", "atoms": [0, 1]}]
+//! }
+//! ```
+//!
+//! Empty sections, missing atom maps and H counts, and `"aromatic": false` are
+//! omitted. H counts are only missing from query patterns, where they are
+//! unconstrained, and the wildcard atom `*` has atomic number 0. Atom indices
+//! in `bonds`, `matches`, and `labels` are all positions in the molecule's
+//! `atoms` list. A query bond written as a logical expression has an order
+//! like `{"expression": "-;@"}`
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ChomperError,
+    matcher::{MatchMatrix, Matches},
+    smarts::{Atom, Bond, BondOrder, Chiral, Smarts},
+    Provenance,
+};
+
+/// The current version of the output schema. This is bumped whenever a change
+/// would break existing consumers
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub molecules: Vec<MoleculeRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<MatchRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<LabelRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AtomRecord {
+    pub atomic_number: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n_hydrogens: Option<usize>,
+    pub charge: isize,
+    pub chirality: ChiralityRecord,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub aromatic: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map_index: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChiralityRecord {
+    None,
+    Cw,
+    Acw,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BondRecord {
+    pub atom1: usize,
+    pub atom2: usize,
+    pub order: BondOrderRecord,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BondOrderRecord {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+    Ring,
+    Up,
+    Down,
+    SingleOrAromatic,
+    Any,
+    /// a logical bond expression from a query, in SMARTS syntax
+    Expression(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProvenanceRecord {
+    pub file: Option<String>,
+    pub dataset_key: String,
+    pub record_id: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MoleculeRecord {
+    pub atoms: Vec<AtomRecord>,
+    pub bonds: Vec<BondRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<ProvenanceRecord>,
+}
+
+/// The matches of query `query` in molecule `molecule`, both given as indices
+/// into whatever lists produced them. `matches` may be omitted when only the
+/// count is known
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub query: usize,
+    pub molecule: usize,
+    pub count: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<Vec<usize>>,
+}
+
+impl MatchRecord {
+    pub fn new(query: usize, molecule: usize, matches: &Matches) -> Self {
+        Self {
+            query,
+            molecule,
+            count: matches.len(),
+            matches: matches.matches.clone(),
+        }
+    }
+}
+
+/// A single assignment of the named pattern `pattern` to `atoms` in molecule
+/// `molecule`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelRecord {
+    pub molecule: usize,
+    pub pattern: String,
+    pub atoms: Vec<usize>,
+}
+
+impl From<&Atom> for AtomRecord {
+    fn from(a: &Atom) -> Self {
+        Self {
+            atomic_number: a.atomic_number.unwrap_or(0),
+            n_hydrogens: a.n_hydrogens,
+            charge: a.charge,
+            chirality: match a.chirality {
+                Chiral::Cw => ChiralityRecord::Cw,
+                Chiral::Acw => ChiralityRecord::Acw,
+                Chiral::None => ChiralityRecord::None,
+            },
+            aromatic: a.aromatic,
+            map_index: a.mol_index,
+        }
+    }
+}
+
+impl From<&Bond> for BondRecord {
+    fn from(b: &Bond) -> Self {
+        use BondOrder as B;
+        use BondOrderRecord as R;
+        Self {
+            atom1: b.atom1,
+            atom2: b.atom2,
+            order: match b.order {
+                B::Single => R::Single,
+                B::Double => R::Double,
+                B::Triple => R::Triple,
+                B::Aromatic => R::Aromatic,
+                B::Ring => R::Ring,
+                B::Up => R::Up,
+                B::Down => R::Down,
+                B::SingleOrAromatic => R::SingleOrAromatic,
+                B::Any => R::Any,
+                ref e @ B::Expr(_) => R::Expression(format!("{e:?}")),
+            },
+        }
+    }
+}
+
+impl From<&Provenance> for ProvenanceRecord {
+    fn from(p: &Provenance) -> Self {
+        Self {
+            file: p.file.as_ref().map(|f| f.display().to_string()),
+            dataset_key: p.dataset_key.clone(),
+            record_id: p.record_id.clone(),
+        }
+    }
+}
+
+impl From<&Smarts> for MoleculeRecord {
+    fn from(s: &Smarts) -> Self {
+        Self {
+            atoms: s.atoms.iter().map(AtomRecord::from).collect(),
+            bonds: s.bonds.iter().map(BondRecord::from).collect(),
+            provenance: s.provenance.as_ref().map(ProvenanceRecord::from),
+        }
+    }
+}
+
+impl LabelRecord {
+    /// one label for each match in `matches`
+    pub fn from_matches(
+        molecule: usize,
+        pattern: &str,
+        matches: &Matches,
+    ) -> Vec<Self> {
+        matches
+            .matches
+            .iter()
+            .map(|m| Self {
+                molecule,
+                pattern: pattern.to_owned(),
+                atoms: m.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_molecules<'a>(
+        mut self,
+        molecules: impl IntoIterator<Item = &'a Smarts>,
+    ) -> Self {
+        self.molecules.extend(molecules.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_matches(mut self, matches: Vec<MatchRecord>) -> Self {
+        self.matches.extend(matches);
+        self
+    }
+
+    /// add the nonzero entries of `matrix` as count-only [MatchRecord]s
+    pub fn with_match_counts(mut self, matrix: &MatchMatrix) -> Self {
+        self.matches.extend(matrix.entries.iter().map(
+            |&(query, molecule, count)| MatchRecord {
+                query,
+                molecule,
+                count,
+                matches: Vec::new(),
+            },
+        ));
+        self
+    }
+
+    pub fn with_labels(mut self, labels: Vec<LabelRecord>) -> Self {
+        self.labels.extend(labels);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// deserialize a [Document] from `s`, rejecting documents written by a
+    /// newer version of the schema
+    pub fn from_json(s: &str) -> Result<Self, ChomperError> {
+        let doc: Self = serde_json::from_str(s)?;
+        if doc.schema_version > SCHEMA_VERSION {
+            return Err(ChomperError::Format(format!(
+                "schema version {} is newer than the supported version {}",
+                doc.schema_version, SCHEMA_VERSION
+            )));
+        }
+        Ok(doc)
+    }
+}
+
+/// write each of `molecules` to `w` as a compact [MoleculeRecord] on its own
+/// line, as JSON Lines. unlike a [Document], the lines carry no schema
+/// version, so consumers are expected to record it separately
+pub fn write_jsonl<'a>(
+    mut w: impl Write,
+    molecules: impl IntoIterator<Item = &'a Smarts>,
+) -> Result<(), ChomperError> {
+    for mol in molecules {
+        serde_json::to_writer(&mut w, &MoleculeRecord::from(mol))?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        matcher::{find_matches, MatchOptions},
+        molecule::Molecule,
+        query::{Query, Target},
+        smarts::InputKind,
+    };
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mol = Smarts::parse("[#6H3:1]-[#8H:2]".to_owned()).with_provenance(
+            Provenance {
+                file: Some("opt.json".into()),
+                dataset_key: "key".to_owned(),
+                record_id: Some("123".to_owned()),
+            },
+        );
+        let query = Smarts::parse_as("[#8:1]".to_owned(), InputKind::Smarts);
+        let matches = find_matches(
+            &Query::from(&query),
+            &Target::new(&Molecule::try_from(&mol).unwrap()),
+            &MatchOptions::default(),
+        );
+        let doc = Document::new()
+            .with_molecules([&mol])
+            .with_labels(LabelRecord::from_matches(0, "hydroxyl", &matches));
+        let json = doc.to_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], 2);
+        assert_eq!(value["molecules"][0]["atoms"][1]["atomic_number"], 8);
+        assert_eq!(value["molecules"][0]["bonds"][0]["order"], "single");
+        assert_eq!(value["molecules"][0]["provenance"]["record_id"], "123");
+        assert_eq!(value["labels"][0]["atoms"][0], 1);
+        assert!(value.get("matches").is_none());
+        assert_eq!(Document::from_json(&json).unwrap(), doc);
+
+        let doc = Document::new()
+            .with_matches(vec![MatchRecord::new(0, 0, &matches)]);
+        let value: serde_json::Value =
+            serde_json::from_str(&doc.to_json()).unwrap();
+        assert_eq!(value["matches"][0]["count"], 1);
+        assert_eq!(value["matches"][0]["matches"][0][0], 1);
+    }
+
+    #[test]
+    fn jsonl() {
+        let mols = [
+            Smarts::parse("[#6H3:1]-[#8H:2]".to_owned()),
+            Smarts::parse("[#8H2]".to_owned()),
+        ];
+        let mut out = Vec::new();
+        write_jsonl(&mut out, &mols).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let rec: MoleculeRecord = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(rec, MoleculeRecord::from(&mols[1]));
+    }
+
+    #[test]
+    fn newer_version() {
+        assert!(Document::from_json(r#"{"schema_version": 3}"#).is_err());
+        assert!(Document::from_json(r#"{"schema_version": 2}"#).is_ok());
+    }
+}
@@ -0,0 +1,116 @@
+//! Minimal reader for SD files. Only the record structure is handled here: the
+//! molfile blocks themselves are passed along untouched for rdkit to interpret
+
+use std::{collections::HashMap, io, path::Path};
+
+use crate::compress::read_to_string;
+
+/// A single record in an SD file, consisting of a molfile block and any data
+/// items following it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SdfRecord {
+    pub mol_block: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl SdfRecord {
+    /// return the title line from the header block of the molfile
+    pub fn title(&self) -> &str {
+        self.mol_block.lines().next().unwrap_or_default().trim()
+    }
+}
+
+/// read all of the records in the .sdf or .mol file at `path`
+pub fn read_sdf(path: impl AsRef<Path>) -> io::Result<Vec<SdfRecord>> {
+    Ok(parse_sdf(&read_to_string(path)?))
+}
+
+/// split the contents of an SD file into records. the molfile block of each
+/// record runs through the `M  END` line, and the remaining lines up to the
+/// `$$$$` delimiter are data items of the form
+///
+/// ```text
+/// > <name>
+/// value
+///
+/// ```
+///
+/// a .mol file is just an SD file containing a single record without the
+/// trailing delimiter, so it also works here
+pub fn parse_sdf(s: &str) -> Vec<SdfRecord> {
+    let mut ret = Vec::new();
+    let mut cur = SdfRecord::default();
+    let mut in_block = true;
+    let mut prop: Option<(String, Vec<&str>)> = None;
+    for line in s.lines() {
+        if line.starts_with("$$$$") {
+            if let Some((k, v)) = prop.take() {
+                cur.properties.insert(k, v.join("\n"));
+            }
+            ret.push(std::mem::take(&mut cur));
+            in_block = true;
+            continue;
+        }
+        if in_block {
+            cur.mol_block.push_str(line);
+            cur.mol_block.push('\n');
+            if line.starts_with("M  END") {
+                in_block = false;
+            }
+            continue;
+        }
+        if let Some((k, v)) = prop.as_mut() {
+            if line.trim().is_empty() {
+                cur.properties.insert(std::mem::take(k), v.join("\n"));
+                prop = None;
+            } else {
+                v.push(line);
+            }
+        } else if line.starts_with('>') {
+            // the field name is the text between the first pair of angle
+            // brackets
+            let name = line
+                .split_once('<')
+                .and_then(|(_, rest)| rest.split_once('>'))
+                .map(|(name, _)| name.to_owned())
+                .unwrap_or_default();
+            prop = Some((name, Vec::new()));
+        }
+    }
+    // a .mol file or an SD file missing its final delimiter
+    if let Some((k, v)) = prop.take() {
+        cur.properties.insert(k, v.join("\n"));
+    }
+    if !cur.mol_block.trim().is_empty() {
+        ret.push(cur);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_records() {
+        let s = std::fs::read_to_string("testfiles/sdf/ethanol.sdf").unwrap();
+        let got = parse_sdf(&s);
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].title(), "ethanol");
+        assert_eq!(got[0].properties["name"], "ethanol");
+        assert_eq!(got[0].properties["id"], "1");
+        assert!(got[0].mol_block.ends_with("M  END\n"));
+        assert_eq!(got[1].title(), "methanol");
+        assert_eq!(got[1].properties["name"], "methanol");
+    }
+
+    #[test]
+    fn parse_mol() {
+        let s =
+            std::fs::read_to_string("testfiles/sdf/nested/water.mol").unwrap();
+        let got = parse_sdf(&s);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].title(), "water");
+        assert!(got[0].properties.is_empty());
+    }
+}
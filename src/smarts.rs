@@ -1,16 +1,35 @@
 //! SMARTS pattern parser
 
-use std::fmt::Debug;
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    panic::{catch_unwind, AssertUnwindSafe},
+};
 
-use crate::smarts::parser::Parser;
+use crate::{
+    elements,
+    error::ChomperError,
+    query::{AtomExpr, BondExpr},
+    smarts::parser::Parser,
+    timing::{Stage, Timings},
+    Provenance,
+};
 
-use self::{evaluator::Evaluator, scanner::scan};
+use self::{
+    evaluator::Evaluator,
+    scanner::{scan, scan_lossy},
+};
 
 mod evaluator;
 mod parser;
 mod scanner;
 
-#[derive(Clone, Default, Debug, PartialEq)]
+pub use parser::{ParseError, ParseErrorKind};
+pub use scanner::ScanError;
+
+/// Chirality tags order as `Cw < Acw < None`
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Chiral {
     Cw,
     Acw,
@@ -18,34 +37,137 @@ pub enum Chiral {
     None,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// The language a string is parsed as, which controls how omitted parts of it
+/// are read. In SMILES, an atom written without an H count has no hydrogens,
+/// and a bond written without a symbol is aromatic between two aromatic atoms
+/// and single otherwise. In SMARTS, the same omissions mean that the H count
+/// is unconstrained and that the bond may be either single or aromatic
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputKind {
+    /// a concrete molecule, like the SMARTS rdkit writes for a SMILES string
+    #[default]
+    Smiles,
+    /// a query pattern
+    Smarts,
+}
+
+/// Atoms are ordered by comparing their fields in declaration order: atomic
+/// number, then isotope, H count, charge, whether the charge was given,
+/// chirality, aromaticity, aliphaticity, expression, and finally `mol_index`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Atom {
-    pub atomic_number: usize,
-    pub n_hydrogens: usize,
+    /// the atomic number, or `None` for the wildcard atom `*`, which matches
+    /// any element
+    pub atomic_number: Option<usize>,
+    /// the mass number, like the 13 in `[13C]`, or `None` for any isotope
+    pub isotope: Option<usize>,
+    /// the total H count, or `None` if it is unconstrained, as in a SMARTS
+    /// atom written without one
+    pub n_hydrogens: Option<usize>,
     pub charge: isize,
+    /// whether `charge` constrains the atom. it always does for atoms read
+    /// with [InputKind::Smiles] semantics, but a SMARTS atom written without
+    /// a charge, like `[#6]`, leaves it unconstrained
+    pub explicit_charge: bool,
     pub chirality: Chiral,
-    pub mol_index: usize,
+    /// whether the atom was written as an aromatic atom, like `[c]`
+    pub aromatic: bool,
+    /// whether the atom was written as an aliphatic atom, like `[C]` or the
+    /// `C` in `$(C=O)`, which can't match an aromatic atom. atoms written
+    /// by atomic number, like `[#6]`, are neither
+    pub aliphatic: bool,
+    /// the logical expression of an atom that isn't a plain conjunction, like
+    /// `[#6,#7;!H0]`, which takes the place of the fields above other than
+    /// the isotope. they are left unconstrained, with no H count
+    pub expr: Option<AtomExpr>,
+    /// the atom map number, if the atom has one
+    pub mol_index: Option<usize>,
 }
 
 impl Atom {
     pub fn new(
-        atomic_number: usize,
-        n_hydrogens: usize,
+        atomic_number: impl Into<Option<usize>>,
+        n_hydrogens: impl Into<Option<usize>>,
         charge: isize,
         chirality: Chiral,
-        mol_index: usize,
+        mol_index: impl Into<Option<usize>>,
     ) -> Self {
         Self {
-            atomic_number,
-            n_hydrogens,
+            atomic_number: atomic_number.into(),
+            isotope: None,
+            n_hydrogens: n_hydrogens.into(),
             charge,
+            explicit_charge: true,
             chirality,
-            mol_index,
+            aromatic: false,
+            aliphatic: false,
+            expr: None,
+            mol_index: mol_index.into(),
         }
     }
+
+    /// set the mass number of `self`
+    pub fn with_isotope(mut self, isotope: impl Into<Option<usize>>) -> Self {
+        self.isotope = isotope.into();
+        self
+    }
+
+    /// set the aromatic flag on `self`
+    pub fn with_aromatic(mut self, aromatic: bool) -> Self {
+        self.aromatic = aromatic;
+        self
+    }
+
+    /// return the element symbol for `self`, `*` for the wildcard, or `?` for
+    /// an unknown atomic number. the symbol is not lowercased for aromatic
+    /// atoms
+    pub fn symbol(&self) -> &'static str {
+        // index 0 of the element table is the wildcard
+        elements::symbol(self.atomic_number.unwrap_or(0)).unwrap_or("?")
+    }
 }
 
-#[derive(Clone, PartialEq)]
+/// Display an atom as its isotope, symbol, chirality, H count, charge, and
+/// atom map, like `[C @ H3 +0 :1]` or `[13C +0]`. the isotope, chirality, H
+/// count, and atom map are omitted when there are none, and aromatic atoms use
+/// lowercase symbols, like `[c H1 +0]`. an atom with an expression shows it in
+/// SMARTS syntax instead, like `[#6,#7 :1]`
+impl Display for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        if let Some(n) = self.isotope {
+            write!(f, "{n}")?;
+        }
+        if let Some(expr) = &self.expr {
+            expr.write_smarts("*", f)?;
+            if let Some(i) = self.mol_index {
+                write!(f, " :{i}")?;
+            }
+            return f.write_str("]");
+        }
+        if self.aromatic {
+            write!(f, "{}", self.symbol().to_lowercase())?;
+        } else {
+            write!(f, "{}", self.symbol())?;
+        }
+        match self.chirality {
+            Chiral::Cw => write!(f, " @@")?,
+            Chiral::Acw => write!(f, " @")?,
+            Chiral::None => {}
+        }
+        if let Some(h) = self.n_hydrogens {
+            write!(f, " H{h}")?;
+        }
+        write!(f, " {:+}", self.charge)?;
+        if let Some(i) = self.mol_index {
+            write!(f, " :{i}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Bond orders are ordered by declaration order, from `Single` to `Expr`
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum BondOrder {
     Single,
     Double,
@@ -54,23 +176,99 @@ pub enum BondOrder {
     Ring,
     Up,
     Down,
+    /// the default bond between two atoms in a SMARTS pattern, which matches
+    /// either a single or an aromatic bond
+    SingleOrAromatic,
+    /// the SMARTS bond `~`, which matches any bond
+    Any,
+    /// a bond written as a logical expression, like `-;@` or `!@`
+    Expr(BondExpr),
 }
 
 impl Debug for BondOrder {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                BondOrder::Single => "-",
-                BondOrder::Double => "=",
-                BondOrder::Triple => "#",
-                BondOrder::Aromatic => ":",
-                BondOrder::Ring => "@",
-                BondOrder::Up => "/",
-                BondOrder::Down => "\\",
-            }
-        )
+        let s = match self {
+            BondOrder::Single => "-",
+            BondOrder::Double => "=",
+            BondOrder::Triple => "#",
+            BondOrder::Aromatic => ":",
+            BondOrder::Ring => "@",
+            BondOrder::Up => "/",
+            BondOrder::Down => "\\",
+            BondOrder::SingleOrAromatic => "-,:",
+            BondOrder::Any => "~",
+            BondOrder::Expr(e) => return e.write_smarts("~", f),
+        };
+        f.write_str(s)
+    }
+}
+
+impl BondOrder {
+    /// a lowercase name for `self`, such as `single` or `aromatic`
+    pub fn name(&self) -> &'static str {
+        match self {
+            BondOrder::Single => "single",
+            BondOrder::Double => "double",
+            BondOrder::Triple => "triple",
+            BondOrder::Aromatic => "aromatic",
+            BondOrder::Ring => "ring",
+            BondOrder::Up => "up",
+            BondOrder::Down => "down",
+            BondOrder::SingleOrAromatic => "single_or_aromatic",
+            BondOrder::Any => "any",
+            BondOrder::Expr(_) => "expression",
+        }
+    }
+
+    /// return the equivalent order for the same bond traversed in the opposite
+    /// direction, which swaps [BondOrder::Up] and [BondOrder::Down] and leaves
+    /// everything else alone
+    pub fn reversed(&self) -> Self {
+        match self {
+            BondOrder::Up => BondOrder::Down,
+            BondOrder::Down => BondOrder::Up,
+            b => b.clone(),
+        }
+    }
+
+    /// return the numeric bond order for `self`, with 1.5 for aromatic bonds.
+    /// directional bonds are single bonds, but [BondOrder::Ring] only
+    /// constrains ring membership in a query and has no numeric order, and
+    /// neither do the other query bonds, like [BondOrder::SingleOrAromatic]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            BondOrder::Single | BondOrder::Up | BondOrder::Down => Some(1.0),
+            BondOrder::Double => Some(2.0),
+            BondOrder::Triple => Some(3.0),
+            BondOrder::Aromatic => Some(1.5),
+            BondOrder::Ring
+            | BondOrder::SingleOrAromatic
+            | BondOrder::Any
+            | BondOrder::Expr(_) => None,
+        }
+    }
+
+    /// the inverse of [BondOrder::as_f64], returning `None` if `order` is not
+    /// within 0.01 of 1.0, 1.5, 2.0, or 3.0. directional and ring bonds can't
+    /// be recovered from a bare number, so 1.0 is always
+    /// [BondOrder::Single]
+    pub fn from_f64(order: f64) -> Option<Self> {
+        const TOL: f64 = 0.01;
+        [
+            (1.0, BondOrder::Single),
+            (1.5, BondOrder::Aromatic),
+            (2.0, BondOrder::Double),
+            (3.0, BondOrder::Triple),
+        ]
+        .into_iter()
+        .find(|(n, _)| (order - n).abs() < TOL)
+        .map(|(_, b)| b)
+    }
+}
+
+impl Display for BondOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
     }
 }
 
@@ -80,7 +278,21 @@ impl Debug for Bond {
     }
 }
 
-#[derive(PartialEq)]
+/// Display a bond as its atom indices, bond symbol, and bond name, like
+/// `1-2 (single)`
+impl Display for Bond {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{:?}{} ({})",
+            self.atom1, self.order, self.atom2, self.order
+        )
+    }
+}
+
+/// A bond between the atoms at positions `atom1` and `atom2` in
+/// [Smarts::atoms]. Bonds are ordered by `atom1`, then `atom2`, then `order`
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Bond {
     pub atom1: usize,
     pub atom2: usize,
@@ -97,18 +309,847 @@ impl Bond {
     }
 }
 
+/// Something parsing a [Smarts] did on a best-effort basis instead of failing,
+/// collected in [Warnings]. atom and bond positions refer to
+/// [Smarts::atoms] and [Smarts::bonds]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    /// an unsupported atom decorator skipped by [Smarts::parse_lossy]
+    SkippedDecorator {
+        /// the text of the skipped decorator, like `^3`
+        decorator: String,
+        /// the character offset of the decorator in the input
+        offset: usize,
+    },
+    /// the atom has a chirality tag, but either its H count is unknown or it
+    /// has fewer than three neighbors, so the tag cannot be interpreted
+    AmbiguousChirality { atom: usize },
+    /// the atom was written without an H count in SMILES, so it was assumed
+    /// to have none
+    ImplicitHydrogens { atom: usize },
+    /// the bond was written without a bond symbol in SMILES, so `order` was
+    /// assumed from its atoms
+    ImplicitBond { bond: usize, order: BondOrder },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::SkippedDecorator { decorator, offset } => write!(
+                f,
+                "skipped unsupported decorator {decorator} at offset {offset}"
+            ),
+            Warning::AmbiguousChirality { atom } => {
+                write!(f, "atom {atom} has an ambiguous chirality tag")
+            }
+            Warning::ImplicitHydrogens { atom } => {
+                write!(f, "atom {atom} has no H count, assuming 0")
+            }
+            Warning::ImplicitBond { bond, order } => write!(
+                f,
+                "bond {bond} has no bond symbol, assuming {}",
+                order.name()
+            ),
+        }
+    }
+}
+
+/// The [Warning]s collected while parsing one input, in the order they were
+/// found
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, warning: Warning) {
+        self.warnings.push(warning);
+    }
+
+    /// add all of the warnings in `other` after those in `self`
+    pub fn extend(&mut self, other: Warnings) {
+        self.warnings.extend(other.warnings);
+    }
+
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter()
+    }
+
+    /// the decorators skipped by [Smarts::parse_lossy]
+    pub fn skipped_decorators(&self) -> impl Iterator<Item = &str> {
+        self.iter().filter_map(|w| match w {
+            Warning::SkippedDecorator { decorator, .. } => Some(&**decorator),
+            _ => None,
+        })
+    }
+}
+
+/// Display one warning per line
+impl Display for Warnings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for w in &self.warnings {
+            writeln!(f, "{w}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The ways that a set of atoms and bonds can fail to form a valid [Smarts]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// bond `bond` refers to atom position `atom`, but there are only
+    /// `n_atoms` atoms
+    AtomOutOfRange {
+        bond: usize,
+        atom: usize,
+        n_atoms: usize,
+    },
+    /// bond `bond` connects atom `atom` to itself
+    SelfBond { bond: usize, atom: usize },
+    /// bonds `first` and `second` connect the same pair of atoms
+    DuplicateBond { first: usize, second: usize },
+    /// atoms `first` and `second` share the atom map `map`
+    DuplicateMap {
+        map: usize,
+        first: usize,
+        second: usize,
+    },
+    /// the atom at this position has no atom map, but [Smarts::check_maps]
+    /// requires one
+    UnmappedAtom(usize),
+    /// the atom at this position has the atom map 0, which rdkit uses for an
+    /// unmapped atom
+    ZeroMap(usize),
+    /// the atom maps skip `missing`, although `max` is used
+    MapGap { missing: usize, max: usize },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::AtomOutOfRange {
+                bond,
+                atom,
+                n_atoms,
+            } => write!(
+                f,
+                "bond {bond} refers to atom {atom}, but there are only \
+                 {n_atoms} atoms"
+            ),
+            ValidationError::SelfBond { bond, atom } => {
+                write!(f, "bond {bond} connects atom {atom} to itself")
+            }
+            ValidationError::DuplicateBond { first, second } => write!(
+                f,
+                "bonds {first} and {second} connect the same pair of atoms"
+            ),
+            ValidationError::DuplicateMap { map, first, second } => {
+                write!(f, "atoms {first} and {second} share the atom map {map}")
+            }
+            ValidationError::UnmappedAtom(i) => {
+                write!(f, "atom {i} has no atom map")
+            }
+            ValidationError::ZeroMap(i) => {
+                write!(f, "atom {i} has the atom map 0")
+            }
+            ValidationError::MapGap { missing, max } => {
+                write!(f, "atom map {missing} is missing, but {max} is used")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Smarts {
     pub atoms: Vec<Atom>,
     pub bonds: Vec<Bond>,
+    /// the positions in `bonds`, in increasing order, of the bonds written
+    /// with ring-closure labels rather than between adjacent atoms. these are
+    /// the bonds that close each ring in the input string
+    pub ring_closures: Vec<usize>,
+    /// where this molecule came from, if it was loaded from a [Dataset]
+    ///
+    /// [Dataset]: crate::Dataset
+    pub provenance: Option<Provenance>,
 }
 
 impl Smarts {
-    pub fn parse(s: String) -> Self {
-        let tokens = scan(s);
+    /// parse `s` as a concrete molecule with [InputKind::Smiles] semantics,
+    /// panicking if `s` is malformed. only for tests, see [Smarts::try_parse]
+    #[cfg(test)]
+    pub(crate) fn parse(s: String) -> Self {
+        Self::parse_as(s, InputKind::Smiles)
+    }
+
+    /// like [Smarts::try_parse_as], but panic if `s` is malformed. only for
+    /// tests
+    #[cfg(test)]
+    pub(crate) fn parse_as(s: String, kind: InputKind) -> Self {
+        Self::try_parse_as(s, kind).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// parse `s` as a concrete molecule with [InputKind::Smiles] semantics
+    pub fn try_parse(s: String) -> Result<Self, ChomperError> {
+        Self::try_parse_as(s, InputKind::Smiles)
+    }
+
+    /// parse `s`, filling in omitted H counts and bonds according to `kind`
+    pub fn try_parse_as(
+        s: String,
+        kind: InputKind,
+    ) -> Result<Self, ChomperError> {
+        let tokens = scan(s)?;
+        let mut parser = Parser::new(tokens).with_kind(kind);
+        let exprs = parser.parse()?;
+        Evaluator::new(exprs).with_kind(kind).eval()
+    }
+
+    /// like [Smarts::try_parse_as], but record the time spent scanning,
+    /// parsing, and evaluating `s` in `timings`
+    pub fn parse_timed(
+        s: String,
+        kind: InputKind,
+        timings: &mut Timings,
+    ) -> Result<Self, ChomperError> {
+        let tokens = timings.time(Stage::Scan, || scan(s))?;
+        let exprs = timings.time(Stage::Parse, || {
+            Parser::new(tokens).with_kind(kind).parse()
+        })?;
+        timings
+            .time(Stage::Eval, || Evaluator::new(exprs).with_kind(kind).eval())
+    }
+
+    /// like [Smarts::try_parse_as], but also catch a panic in any stage,
+    /// returning the [Stage] that failed and the error or panic message. the
+    /// default panic hook still prints panic messages to stderr
+    pub fn parse_caught(
+        s: String,
+        kind: InputKind,
+    ) -> Result<Self, (Stage, String)> {
+        fn caught<T, E: Display>(
+            stage: Stage,
+            f: impl FnOnce() -> Result<T, E>,
+        ) -> Result<T, (Stage, String)> {
+            match catch_unwind(AssertUnwindSafe(f)) {
+                Ok(r) => r.map_err(|e| (stage, e.to_string())),
+                Err(e) => Err((stage, panic_message(&*e).to_owned())),
+            }
+        }
+        let tokens = caught(Stage::Scan, || scan(s))?;
+        let exprs = caught(Stage::Parse, || {
+            Parser::new(tokens).with_kind(kind).parse()
+        })?;
+        caught(Stage::Eval, || Evaluator::new(exprs).with_kind(kind).eval())
+    }
+
+    /// like [Smarts::try_parse_as], but also return [Warnings] for the
+    /// assumptions made along the way, such as bonds written without a bond
+    /// symbol
+    pub fn parse_with_warnings(
+        s: String,
+        kind: InputKind,
+    ) -> Result<(Self, Warnings), ChomperError> {
+        let tokens = scan(s)?;
+        let mut parser = Parser::new(tokens).with_kind(kind);
+        let exprs = parser.parse()?;
+        let mut warnings = parser.warnings;
+        let (smarts, w) =
+            Evaluator::new(exprs).with_kind(kind).eval_warned()?;
+        warnings.extend(w);
+        Ok((smarts, warnings))
+    }
+
+    /// like [Smarts::try_parse], but skip unrecognized atom decorators like
+    /// the hybridization `^3` that rdkit writes instead of failing, returning a [Warning::SkippedDecorator]
+    /// for each one along with any other [Warnings]. anything else that
+    /// [Smarts::try_parse] rejects, such as an unknown bond, is still an error
+    pub fn parse_lossy(s: String) -> Result<(Self, Warnings), ChomperError> {
+        let tokens = scan_lossy(s)?;
         let mut parser = Parser::new(tokens);
-        let exprs = parser.parse();
-        let eval = Evaluator::new(exprs);
-        let (atoms, bonds) = eval.eval();
-        Self { atoms, bonds }
+        let exprs = parser.parse()?;
+        let mut warnings = parser.warnings;
+        let (smarts, w) = Evaluator::new(exprs).eval_warned()?;
+        warnings.extend(w);
+        Ok((smarts, warnings))
+    }
+
+    /// build a [Smarts] from `atoms` and `bonds`, checking that every bond
+    /// refers to two distinct atoms in `atoms`, that no pair of atoms is
+    /// bonded twice, and that no atom map is used more than once
+    pub fn from_parts(
+        atoms: Vec<Atom>,
+        bonds: Vec<Bond>,
+    ) -> Result<Self, ValidationError> {
+        let n_atoms = atoms.len();
+        let mut maps = HashMap::new();
+        for (i, atom) in atoms.iter().enumerate() {
+            if let Some(map) = atom.mol_index {
+                if let Some(first) = maps.insert(map, i) {
+                    return Err(ValidationError::DuplicateMap {
+                        map,
+                        first,
+                        second: i,
+                    });
+                }
+            }
+        }
+        let mut pairs = HashMap::new();
+        for (i, bond) in bonds.iter().enumerate() {
+            for atom in [bond.atom1, bond.atom2] {
+                if atom >= n_atoms {
+                    return Err(ValidationError::AtomOutOfRange {
+                        bond: i,
+                        atom,
+                        n_atoms,
+                    });
+                }
+            }
+            if bond.atom1 == bond.atom2 {
+                return Err(ValidationError::SelfBond {
+                    bond: i,
+                    atom: bond.atom1,
+                });
+            }
+            let pair = (bond.atom1.min(bond.atom2), bond.atom1.max(bond.atom2));
+            if let Some(first) = pairs.insert(pair, i) {
+                return Err(ValidationError::DuplicateBond {
+                    first,
+                    second: i,
+                });
+            }
+        }
+        Ok(Self {
+            atoms,
+            bonds,
+            ring_closures: Vec::new(),
+            provenance: None,
+        })
+    }
+
+    /// put `self` into a canonical order independent of the order of the
+    /// input string: the atoms are sorted by their [Ord] implementation, the
+    /// bonds are updated to point at the new atom positions and oriented so
+    /// that `atom1 <= atom2`, and then the bonds are sorted too
+    pub fn sort_canonical(&mut self) {
+        let mut order: Vec<usize> = (0..self.atoms.len()).collect();
+        order.sort_by(|&i, &j| self.atoms[i].cmp(&self.atoms[j]));
+        // old position -> new position
+        let mut new = vec![0; order.len()];
+        for (n, &o) in order.iter().enumerate() {
+            new[o] = n;
+        }
+        self.atoms = order.iter().map(|&o| self.atoms[o].clone()).collect();
+        for bond in &mut self.bonds {
+            let (a, b) = (new[bond.atom1], new[bond.atom2]);
+            (bond.atom1, bond.atom2) = (a.min(b), a.max(b));
+        }
+        let mut order: Vec<usize> = (0..self.bonds.len()).collect();
+        order.sort_by(|&i, &j| self.bonds[i].cmp(&self.bonds[j]));
+        let mut new = vec![0; order.len()];
+        for (n, &o) in order.iter().enumerate() {
+            new[o] = n;
+        }
+        self.bonds = order.iter().map(|&o| self.bonds[o].clone()).collect();
+        for c in &mut self.ring_closures {
+            *c = new[*c];
+        }
+        self.ring_closures.sort();
+    }
+
+    /// the position in [Smarts::bonds] of the bond between the atoms at
+    /// positions `a` and `b`, in either direction
+    pub fn bond_between(&self, a: usize, b: usize) -> Option<usize> {
+        self.bonds.iter().position(|bond| {
+            (bond.atom1, bond.atom2) == (a, b)
+                || (bond.atom1, bond.atom2) == (b, a)
+        })
+    }
+
+    /// whether the bond at position `bond` in [Smarts::bonds] was written with
+    /// a ring-closure label
+    pub fn is_ring_closure(&self, bond: usize) -> bool {
+        self.ring_closures.binary_search(&bond).is_ok()
+    }
+
+    /// the bonds written with ring-closure labels, in the order of
+    /// [Smarts::ring_closures]
+    pub fn ring_closure_bonds(&self) -> impl Iterator<Item = &Bond> {
+        self.ring_closures.iter().map(|&i| &self.bonds[i])
+    }
+
+    /// keep only the bonds for which `keep` returns true, given each bond's
+    /// position and the bond itself, and update [Smarts::ring_closures] to
+    /// match
+    pub(crate) fn retain_bonds(
+        &mut self,
+        mut keep: impl FnMut(usize, &Bond) -> bool,
+    ) {
+        let mut new = Vec::with_capacity(self.bonds.len());
+        let mut n = 0;
+        let mut i = 0;
+        self.bonds.retain(|b| {
+            let k = keep(i, b);
+            new.push(k.then_some(n));
+            n += usize::from(k);
+            i += 1;
+            k
+        });
+        self.ring_closures =
+            self.ring_closures.iter().filter_map(|&c| new[c]).collect();
+    }
+
+    /// check that the atom maps of `self` are complete: every heavy atom,
+    /// and every hydrogen too if `require_hydrogens` is set, has a map, no
+    /// map is 0 or used twice, and the maps run from 1 without gaps. this
+    /// catches molecules whose maps were dropped or duplicated on the way
+    /// through rdkit
+    pub fn check_maps(
+        &self,
+        require_hydrogens: bool,
+    ) -> Result<(), ValidationError> {
+        let mut maps = HashMap::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            match atom.mol_index {
+                Some(0) => return Err(ValidationError::ZeroMap(i)),
+                Some(map) => {
+                    if let Some(first) = maps.insert(map, i) {
+                        return Err(ValidationError::DuplicateMap {
+                            map,
+                            first,
+                            second: i,
+                        });
+                    }
+                }
+                None if atom.atomic_number != Some(1) || require_hydrogens => {
+                    return Err(ValidationError::UnmappedAtom(i))
+                }
+                None => {}
+            }
+        }
+        let max = maps.keys().copied().max().unwrap_or(0);
+        if let Some(missing) = (1..=max).find(|m| !maps.contains_key(m)) {
+            return Err(ValidationError::MapGap { missing, max });
+        }
+        Ok(())
+    }
+
+    /// the table between the atom maps in `self` and atom positions
+    pub fn atom_maps(&self) -> AtomMaps {
+        AtomMaps::new(self.atoms.iter().map(|a| a.mol_index))
+    }
+
+    /// the atom maps in `self`, in increasing order
+    pub fn maps(&self) -> Vec<usize> {
+        let mut ret: Vec<_> =
+            self.atoms.iter().filter_map(|a| a.mol_index).collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// rewrite the atom maps of `self` to be contiguous from `start`, keeping
+    /// their relative order, and return the table from old maps to new maps
+    /// so related data, like the other side of a SMIRKS, can be updated with
+    /// [Smarts::remap]
+    pub fn renumber_maps(&mut self, start: usize) -> BTreeMap<usize, usize> {
+        let table = map_table(self.maps(), start);
+        self.remap(&table);
+        table
+    }
+
+    /// [Smarts::renumber_maps] starting from 1
+    pub fn compress_maps(&mut self) -> BTreeMap<usize, usize> {
+        self.renumber_maps(1)
+    }
+
+    /// replace each atom map in `self` found in `table` with its new value,
+    /// leaving the others alone
+    pub fn remap(&mut self, table: &BTreeMap<usize, usize>) {
+        for atom in &mut self.atoms {
+            if let Some(new) = atom.mol_index.and_then(|m| table.get(&m)) {
+                atom.mol_index = Some(*new);
+            }
+        }
+    }
+
+    /// attach `provenance` to `self`
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.provenance = Some(provenance);
+        self
+    }
+}
+
+/// the tokens the scanner splits `s` into, in their debug form, for seeing how
+/// an input is read. fails on unrecognized characters, like
+/// [Smarts::try_parse]
+pub fn scan_debug(s: String) -> Result<Vec<String>, ChomperError> {
+    Ok(scan(s)?.iter().map(|t| format!("{t:?}")).collect())
+}
+
+/// A two-way table between the atom maps of a molecule and the positions of
+/// the atoms carrying them. Graph algorithms in this crate work with
+/// positions, so this is the one place to translate to and from maps, such as
+/// when pairing atoms across the two sides of a SMIRKS or lining up with
+/// rdkit atom indices
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AtomMaps {
+    /// map -> position
+    positions: BTreeMap<usize, usize>,
+    /// position -> map
+    maps: Vec<Option<usize>>,
+}
+
+impl AtomMaps {
+    /// build the table from the atom map of each atom, in atom order. if a
+    /// map is used twice, it refers to its first atom
+    pub fn new(maps: impl IntoIterator<Item = Option<usize>>) -> Self {
+        let maps: Vec<_> = maps.into_iter().collect();
+        let mut positions = BTreeMap::new();
+        for (i, map) in maps.iter().enumerate() {
+            if let Some(map) = map {
+                positions.entry(*map).or_insert(i);
+            }
+        }
+        Self { positions, maps }
+    }
+
+    /// the position of the atom with atom map `map`
+    pub fn position(&self, map: usize) -> Option<usize> {
+        self.positions.get(&map).copied()
+    }
+
+    /// the atom map of the atom at position `position`, if it has one
+    pub fn map(&self, position: usize) -> Option<usize> {
+        self.maps.get(position).copied().flatten()
+    }
+
+    /// the number of distinct maps
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// whether every atom has a map
+    pub fn is_complete(&self) -> bool {
+        self.maps.iter().all(Option::is_some)
+    }
+
+    /// the `(map, position)` pairs in increasing order of map
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.positions.iter().map(|(&m, &p)| (m, p))
+    }
+}
+
+/// the table sending each of `maps`, which should be sorted and distinct,
+/// to consecutive values from `start`
+pub(crate) fn map_table(
+    maps: impl IntoIterator<Item = usize>,
+    start: usize,
+) -> BTreeMap<usize, usize> {
+    maps.into_iter().zip(start..).collect()
+}
+
+/// the message passed to `panic!`, or "unknown error" if the payload is not a
+/// string
+pub(crate) fn panic_message(e: &(dyn Any + Send)) -> &str {
+    e.downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| e.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let a = Atom::new(6, 3, 0, Chiral::None, 1);
+        assert_eq!(a.symbol(), "C");
+        assert_eq!(a.to_string(), "[C H3 +0 :1]");
+        let a = Atom::new(7, 0, -1, Chiral::Cw, 12);
+        assert_eq!(a.to_string(), "[N @@ H0 -1 :12]");
+        let a = Atom::new(8, 1, 0, Chiral::None, None);
+        assert_eq!(a.to_string(), "[O H1 +0]");
+        let a = Atom::new(7, 1, 0, Chiral::None, None).with_aromatic(true);
+        assert_eq!(a.to_string(), "[n H1 +0]");
+        let b = Bond::new(1, 2, BondOrder::Aromatic);
+        assert_eq!(b.to_string(), "1:2 (aromatic)");
+    }
+
+    #[test]
+    fn canonical() {
+        let mut a = Smarts::parse("[#8H:3]-[#6H2:2]-[#6H3:1]".to_owned());
+        let mut b = Smarts::parse("[#6H3:1]-[#6H2:2]-[#8H:3]".to_owned());
+        assert_ne!(a, b);
+        a.sort_canonical();
+        b.sort_canonical();
+        assert_eq!(a, b);
+        // the CH2 sorts first and is bonded to both of the others
+        assert_eq!(
+            a.bonds,
+            [
+                Bond::new(0, 1, BondOrder::Single),
+                Bond::new(0, 2, BondOrder::Single)
+            ]
+        );
+        let set: std::collections::HashSet<_> =
+            a.atoms.iter().chain(&b.atoms).collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn renumber() {
+        let mut s = Smarts::parse("[#6H3:7]-[#6H2:3]-[#8H:12]".to_owned());
+        let table = s.compress_maps();
+        assert_eq!(table, BTreeMap::from([(3, 1), (7, 2), (12, 3)]));
+        let maps: Vec<_> = s.atoms.iter().map(|a| a.mol_index).collect();
+        assert_eq!(maps, [Some(2), Some(1), Some(3)]);
+        s.renumber_maps(10);
+        assert_eq!(s.maps(), [10, 11, 12]);
+    }
+
+    #[test]
+    fn ring_closures() {
+        let mut s =
+            Smarts::parse("[#6H3:1]-[#6H:2]1-[#6H2:3]-[#6H2:4]-1".to_owned());
+        assert_eq!(s.ring_closures, [3]);
+        assert_eq!(s.bond_between(3, 1), Some(3));
+        assert_eq!(s.bond_between(0, 3), None);
+        assert!(s.is_ring_closure(3));
+        assert!(!s.is_ring_closure(0));
+        let closure = s.bonds[3].clone();
+        assert_eq!(s.ring_closure_bonds().collect::<Vec<_>>(), [&closure]);
+
+        // the closure follows its bond through reordering and removal
+        let maps = |s: &Smarts, b: &Bond| {
+            let mut m =
+                [s.atoms[b.atom1].mol_index, s.atoms[b.atom2].mol_index];
+            m.sort();
+            m
+        };
+        s.sort_canonical();
+        let closure = s.ring_closure_bonds().next().unwrap();
+        assert_eq!(maps(&s, closure), [Some(2), Some(4)]);
+        s.retain_bonds(|k, _| k != 0);
+        let closure = s.ring_closure_bonds().next().unwrap();
+        assert_eq!(maps(&s, closure), [Some(2), Some(4)]);
+        let c = s.ring_closures[0];
+        s.retain_bonds(|k, _| k != c);
+        assert!(s.ring_closures.is_empty());
+
+        assert!(Smarts::parse("[#6H3]-[#6H3]".to_owned())
+            .ring_closures
+            .is_empty());
+    }
+
+    #[test]
+    fn from_parts() {
+        use BondOrder as B;
+        use ValidationError as V;
+        let atoms = vec![
+            Atom::new(6, 3, 0, Chiral::None, 1),
+            Atom::new(8, 1, 0, Chiral::None, 2),
+        ];
+        let got =
+            Smarts::from_parts(atoms.clone(), vec![Bond::new(0, 1, B::Single)]);
+        assert_eq!(got.unwrap().bonds.len(), 1);
+
+        let err = |bonds| Smarts::from_parts(atoms.clone(), bonds).unwrap_err();
+        assert_eq!(
+            err(vec![Bond::new(0, 2, B::Single)]),
+            V::AtomOutOfRange {
+                bond: 0,
+                atom: 2,
+                n_atoms: 2
+            }
+        );
+        assert_eq!(
+            err(vec![Bond::new(1, 1, B::Single)]),
+            V::SelfBond { bond: 0, atom: 1 }
+        );
+        assert_eq!(
+            err(vec![Bond::new(0, 1, B::Single), Bond::new(1, 0, B::Double)]),
+            V::DuplicateBond {
+                first: 0,
+                second: 1
+            }
+        );
+        let mut dup = atoms.clone();
+        dup[1].mol_index = Some(1);
+        assert_eq!(
+            Smarts::from_parts(dup, Vec::new()).unwrap_err().to_string(),
+            "atoms 0 and 1 share the atom map 1"
+        );
+    }
+
+    #[test]
+    fn check_maps() {
+        use ValidationError as V;
+        let check = |s: &str, h| Smarts::parse(s.to_owned()).check_maps(h);
+        assert_eq!(check("[#6H3:1]-[#8H1:2]", true), Ok(()));
+        // explicit hydrogens only need maps when asked
+        assert_eq!(check("[#6H2:2]-[#1H0]-[#8H1:1]", false), Ok(()));
+        assert_eq!(
+            check("[#6H2:2]-[#1H0]-[#8H1:1]", true),
+            Err(V::UnmappedAtom(1))
+        );
+        assert_eq!(check("[#6H3:1]-[#8H1]", false), Err(V::UnmappedAtom(1)));
+        assert_eq!(check("[#6H3:0]-[#8H1:1]", false), Err(V::ZeroMap(0)));
+        assert_eq!(
+            check("[#6H3:1]-[#8H1:3]", false),
+            Err(V::MapGap { missing: 2, max: 3 })
+        );
+        assert_eq!(
+            check("[#6H3:1]-[#8H1:1]", false).unwrap_err().to_string(),
+            "atoms 0 and 1 share the atom map 1"
+        );
+    }
+
+    #[test]
+    fn parse_lossy() {
+        let (got, warnings) =
+            Smarts::parse_lossy("[#6^3H3:1]-[#8H1^2:2]".to_owned()).unwrap();
+        assert_eq!(got, Smarts::parse("[#6H3:1]-[#8H1:2]".to_owned()));
+        assert_eq!(
+            warnings.as_slice(),
+            [
+                Warning::SkippedDecorator {
+                    decorator: "^3".to_owned(),
+                    offset: 3
+                },
+                Warning::SkippedDecorator {
+                    decorator: "^2".to_owned(),
+                    offset: 16
+                },
+            ]
+        );
+        assert_eq!(
+            warnings.as_slice()[0].to_string(),
+            "skipped unsupported decorator ^3 at offset 3"
+        );
+    }
+
+    #[test]
+    fn debug_tokens() {
+        assert_eq!(
+            scan_debug("[#6H3:1]=1".to_owned()).unwrap(),
+            [
+                "LBrack",
+                "AtomicNumber(6)",
+                "HCount(3)",
+                "Colon",
+                "Digit(1)",
+                "RBrack",
+                "DoubleBond",
+                "Digit(1)",
+                "End"
+            ]
+        );
+    }
+
+    #[test]
+    fn atom_maps() {
+        let s = Smarts::parse("[#6H3:3]-[#8H]-[#6H2:1]-[#6H3:3]".to_owned());
+        let maps = s.atom_maps();
+        assert_eq!(maps.position(3), Some(0));
+        assert_eq!(maps.position(1), Some(2));
+        assert_eq!(maps.position(2), None);
+        assert_eq!(maps.map(2), Some(1));
+        assert_eq!(maps.map(1), None);
+        assert_eq!(maps.map(4), None);
+        assert_eq!(maps.len(), 2);
+        assert!(!maps.is_complete());
+        assert_eq!(maps.iter().collect::<Vec<_>>(), [(1, 2), (3, 0)]);
+        for (m, p) in maps.iter() {
+            assert_eq!(maps.map(p), Some(m));
+        }
+    }
+
+    #[test]
+    fn warnings() {
+        let parse = |s: &str, kind| {
+            Smarts::parse_with_warnings(s.to_owned(), kind).unwrap()
+        };
+        let (_, w) = parse("[#6H3:1]-[#8H:2]", InputKind::Smiles);
+        assert!(w.is_empty());
+
+        let (got, w) =
+            parse("[#6H3][#6@H](-[#7H2])1[#6]-[#6H2]1", InputKind::Smiles);
+        use BondOrder as B;
+        assert_eq!(
+            w.as_slice(),
+            [
+                Warning::ImplicitHydrogens { atom: 3 },
+                Warning::ImplicitBond {
+                    bond: 0,
+                    order: B::Single
+                },
+                Warning::ImplicitBond {
+                    bond: 2,
+                    order: B::Single
+                },
+                Warning::ImplicitBond {
+                    bond: 4,
+                    order: B::Single
+                },
+            ]
+        );
+        // the ring closure
+        assert_eq!(got.bonds[4], Bond::new(1, 4, B::Single));
+        assert_eq!(
+            w.as_slice()[0].to_string(),
+            "atom 3 has no H count, assuming 0"
+        );
+        assert_eq!(
+            w.to_string().lines().nth(1),
+            Some("bond 0 has no bond symbol, assuming single")
+        );
+
+        // in SMARTS, omissions are not assumptions, but chirality without an
+        // H count is ambiguous
+        let (_, w) = parse("[#6][#6@](-[#7])-[#8]", InputKind::Smarts);
+        assert_eq!(w.as_slice(), [Warning::AmbiguousChirality { atom: 1 }]);
+        let (_, w) = parse("[#6H3]-[#8@H]", InputKind::Smiles);
+        assert_eq!(w.as_slice(), [Warning::AmbiguousChirality { atom: 1 }]);
+
+        let (_, w) = Smarts::parse_lossy("[#6^3H3]-[#8^2]".to_owned()).unwrap();
+        assert_eq!(w.skipped_decorators().collect::<Vec<_>>(), ["^3", "^2"]);
+        assert_eq!(w.len(), 3);
+    }
+
+    #[test]
+    fn numeric_bond_order() {
+        use BondOrder as B;
+        for b in [B::Single, B::Double, B::Triple, B::Aromatic] {
+            assert_eq!(B::from_f64(b.as_f64().unwrap()), Some(b));
+        }
+        assert_eq!(B::Up.as_f64(), Some(1.0));
+        assert_eq!(B::Down.as_f64(), Some(1.0));
+        assert_eq!(B::Ring.as_f64(), None);
+        assert_eq!(B::from_f64(1.499), Some(B::Aromatic));
+        assert_eq!(B::from_f64(1.25), None);
+        assert_eq!(B::from_f64(0.0), None);
     }
 }
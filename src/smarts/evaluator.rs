@@ -1,29 +1,95 @@
-use std::collections::HashMap;
+//! Evaluation of the parsed expressions into atoms and bonds, in two passes.
+//!
+//! The first pass walks the expressions in input order, registering every atom
+//! and pairing the uses of each ring-closure label in a symbol table. It
+//! records each connection it finds as a [Link] between atom positions, with
+//! any bond symbol written for it. Once every atom and label is known, the
+//! second pass resolves the links into bonds, filling in default bond orders
+//! and checking ring closures. Bonds come out in the order their connections
+//! are completed in the input: a chain bond at its second atom and a ring bond
+//! at its closing label
 
-use super::{parser::Expr, Atom, Bond, BondOrder};
+use std::collections::HashMap;
 
-/// search `exprs` for the first atom occuring before `cur`
-fn prev_atom(exprs: &[Expr], cur: usize) -> Option<&Expr> {
-    if cur == 0 {
-        return None;
-    }
-    for p in (0..cur - 1).rev() {
-        let r = &exprs[p];
-        if r.is_atom() {
-            return Some(r);
-        }
-    }
-    None
-}
+use super::{
+    parser::Expr, Atom, Bond, BondOrder, Chiral, InputKind, Smarts, Warning,
+    Warnings,
+};
+use crate::error::ChomperError;
 
 pub(super) struct Evaluator {
     exprs: Vec<Expr>,
     atoms: Vec<Atom>,
     bonds: Vec<Bond>,
-    cur: usize,
-    /// connection table for ring bonds. used like a stack where labels are
-    /// pushed and then popped when used to allow repeats
-    ctab: HashMap<usize, Vec<usize>>,
+    /// determines the order of bonds written without a bond symbol
+    kind: InputKind,
+    /// the connections to resolve into bonds, in input order
+    links: Vec<Link>,
+    /// symbol table for ring-closure labels
+    labels: RingLabels,
+    /// positions in `bonds` of the bonds closed by ring-closure labels
+    closures: Vec<usize>,
+    /// implicit bonds and ambiguous chirality found so far
+    warnings: Warnings,
+}
+
+/// A connection between two atoms found by the first pass
+enum Link {
+    /// consecutive atoms in a chain or branch, from the earlier to the later
+    /// one, with the bond symbol written between them
+    Chain {
+        from: usize,
+        to: usize,
+        order: Option<BondOrder>,
+    },
+    /// a ring closure, from the atom that opened `label` to the atom that
+    /// closed it, with the bond symbols written at each end
+    Ring {
+        label: usize,
+        from: usize,
+        to: usize,
+        opened: Option<BondOrder>,
+        closed: Option<BondOrder>,
+    },
+}
+
+/// The ring-closure labels that are currently open. A label is open from its
+/// first use until the next use closes it, after which it is removed and may
+/// be reused for another ring, so labels missing from `open` are closed
+#[derive(Default)]
+struct RingLabels {
+    /// the position of the atom that opened each label, along with any bond
+    /// symbol written before the opening label
+    open: HashMap<usize, (usize, Option<BondOrder>)>,
+}
+
+impl RingLabels {
+    /// use `label` at the atom at position `atom`, returning the opening atom
+    /// and its bond symbol if this closes the ring
+    fn toggle(
+        &mut self,
+        label: usize,
+        atom: usize,
+        order: Option<BondOrder>,
+    ) -> Option<(usize, Option<BondOrder>)> {
+        let ret = self.open.remove(&label);
+        if ret.is_none() {
+            self.open.insert(label, (atom, order));
+        }
+        ret
+    }
+
+    /// fail if any labels are still open at the end of the input
+    fn check_closed(&self) -> Result<(), ChomperError> {
+        let mut open: Vec<_> = self.open.iter().collect();
+        open.sort();
+        if let Some((label, (atom, _))) = open.first() {
+            return Err(ChomperError::Eval(format!(
+                "ring label {label} opened at atom {atom} is never closed"
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl Evaluator {
@@ -32,146 +98,234 @@ impl Evaluator {
             exprs,
             atoms: Vec::new(),
             bonds: Vec::new(),
-            cur: 0,
-            ctab: HashMap::new(),
+            kind: InputKind::default(),
+            links: Vec::new(),
+            labels: RingLabels::default(),
+            closures: Vec::new(),
+            warnings: Warnings::new(),
         }
     }
 
-    fn at_end(&self) -> bool {
-        self.cur == self.exprs.len()
-    }
-
-    fn peek(&self) -> Option<&Expr> {
-        self.exprs.get(self.cur)
+    pub(super) fn with_kind(mut self, kind: InputKind) -> Self {
+        self.kind = kind;
+        self
     }
 
-    fn prev_atom(&self) -> &Expr {
-        prev_atom(&self.exprs, self.cur).unwrap()
+    pub(crate) fn eval(self) -> Result<Smarts, ChomperError> {
+        Ok(self.eval_warned()?.0)
     }
 
-    fn next(&mut self) -> Expr {
-        let ret = self.exprs[self.cur].clone();
-        if self.cur < self.exprs.len() {
-            self.cur += 1;
+    /// like [Evaluator::eval], but also return [Warnings] for each bond whose
+    /// order was assumed and each atom with an ambiguous chirality tag
+    pub(crate) fn eval_warned(
+        mut self,
+    ) -> Result<(Smarts, Warnings), ChomperError> {
+        let exprs = std::mem::take(&mut self.exprs);
+        self.register(&exprs, None)?;
+        self.labels.check_closed()?;
+        self.resolve()?;
+        self.check_chirality();
+        let Evaluator {
+            atoms,
+            bonds,
+            closures,
+            warnings,
+            ..
+        } = self;
+        let smarts = Smarts {
+            atoms,
+            bonds,
+            ring_closures: closures,
+            provenance: None,
         };
-        ret
+        Ok((smarts, warnings))
     }
 
-    // I think we're actually going to need to do the next/prev stuff from the
-    // parser so we can look ahead and behind as neede
-    pub(crate) fn eval(mut self) -> (Vec<Atom>, Vec<Bond>) {
-        while !self.at_end() {
-            let expr = self.next();
-            self.inner(expr);
+    /// the first pass: register the atoms in `exprs`, a chain or branch
+    /// starting from the atom at position `anchor`, the ring-closure labels
+    /// written after them, and the links between them. fails on a bond symbol
+    /// that is not followed by an atom or ring-closure label, and on a branch
+    /// or label with no atom before it
+    fn register(
+        &mut self,
+        exprs: &[Expr],
+        anchor: Option<usize>,
+    ) -> Result<(), ChomperError> {
+        // the last atom at this level, which is the start of the next link
+        let mut last = anchor;
+        // a bond symbol waiting for the atom or label it belongs to
+        let mut pending: Option<BondOrder> = None;
+        for expr in exprs {
+            match expr {
+                Expr::Atom(a) => {
+                    let pos = self.atoms.len();
+                    self.atoms.push(a.clone());
+                    match last {
+                        Some(from) => self.links.push(Link::Chain {
+                            from,
+                            to: pos,
+                            order: pending.take(),
+                        }),
+                        None if pending.is_some() => {
+                            return Err(ChomperError::Eval(
+                                "bond symbol before the first atom".to_owned(),
+                            ))
+                        }
+                        None => {}
+                    }
+                    last = Some(pos);
+                }
+                Expr::Bond(order) => {
+                    if pending.is_some() {
+                        return Err(ChomperError::Eval(format!(
+                            "two bond symbols in a row at atom {last:?}"
+                        )));
+                    }
+                    pending = Some(order.clone());
+                }
+                Expr::Grouping(g) => {
+                    let Some(a) = last else {
+                        return Err(ChomperError::Eval(
+                            "branch before the first atom".to_owned(),
+                        ));
+                    };
+                    if pending.is_some() {
+                        return Err(ChomperError::Eval(format!(
+                            "bond symbol before the branch at atom {a}"
+                        )));
+                    }
+                    self.register(g, last)?;
+                }
+                Expr::Connect(n) => {
+                    let Some(to) = last else {
+                        return Err(ChomperError::Eval(format!(
+                            "ring label {n} before the first atom"
+                        )));
+                    };
+                    let order = pending.take();
+                    if let Some((from, opened)) =
+                        self.labels.toggle(*n, to, order.clone())
+                    {
+                        self.links.push(Link::Ring {
+                            label: *n,
+                            from,
+                            to,
+                            opened,
+                            closed: order,
+                        });
+                    }
+                }
+            }
         }
-        let Evaluator { atoms, bonds, .. } = self;
-        (atoms, bonds)
+        if pending.is_some() {
+            return Err(ChomperError::Eval(format!(
+                "bond symbol with no atom after it at atom {last:?}"
+            )));
+        }
+        Ok(())
     }
 
-    fn inner(&mut self, expr: Expr) {
-        match expr {
-            Expr::Atom(a) => {
-                self.atom(a);
-            }
-            Expr::Bond(order) => {
-                self.bond(order);
-            }
-            Expr::Grouping(g) => {
-                self.grouping(g);
-            }
-            Expr::Connect(n) => {
-                // should only encounter this with adjacent Connects, use
-                // previous atom's index
-                let a = self.atoms.last().unwrap().mol_index;
-                self.add_connection(n, a);
+    /// the second pass: resolve the links found by [Evaluator::register] into
+    /// bonds
+    fn resolve(&mut self) -> Result<(), ChomperError> {
+        for link in std::mem::take(&mut self.links) {
+            match link {
+                Link::Chain { from, to, order } => {
+                    let order =
+                        order.unwrap_or_else(|| self.default_bond(from, to));
+                    self.bonds.push(Bond::new(from, to, order));
+                }
+                Link::Ring {
+                    label,
+                    from,
+                    to,
+                    opened,
+                    closed,
+                } => self.ring_bond(label, from, to, opened, closed)?,
             }
         }
+        Ok(())
     }
 
-    fn add_connection(&mut self, n: usize, a: usize) {
-        self.ctab.entry(n).or_default().push(a);
-    }
-
-    fn get_connection(&mut self, n: usize) -> usize {
-        self.ctab.get_mut(&n).unwrap().pop().unwrap()
+    /// add the bond closing ring label `n`, from the atom at position `from`
+    /// that opened it to the atom at position `to` that closed it, with the
+    /// bond symbols written at the opening and closing labels.
+    ///
+    /// ring bonds always point from the opening atom to the closing atom, so a
+    /// directional bond written at the closing label is reversed. a bond symbol
+    /// at the closing label takes precedence over one at the opening label,
+    /// and the default bond is used if neither has one. fails if the closure
+    /// would bond an atom to itself or duplicate an existing bond
+    fn ring_bond(
+        &mut self,
+        n: usize,
+        from: usize,
+        to: usize,
+        opened: Option<BondOrder>,
+        closed: Option<BondOrder>,
+    ) -> Result<(), ChomperError> {
+        if from == to {
+            return Err(ChomperError::Eval(format!(
+                "ring label {n} closes on atom {to}, which opened it"
+            )));
+        }
+        if self.bonds.iter().any(|bond| {
+            (bond.atom1, bond.atom2) == (from, to)
+                || (bond.atom1, bond.atom2) == (to, from)
+        }) {
+            return Err(ChomperError::Eval(format!(
+                "ring label {n} duplicates the bond between atoms {from} and \
+                 {to}"
+            )));
+        }
+        let order = match (closed, opened) {
+            (Some(o), _) => o.reversed(),
+            (None, Some(o)) => o,
+            (None, None) => self.default_bond(from, to),
+        };
+        self.closures.push(self.bonds.len());
+        self.bonds.push(Bond::new(from, to, order));
+        Ok(())
     }
 
-    fn bond(&mut self, order: BondOrder) {
-        let atom1 = match self.prev_atom() {
-            Expr::Atom(a) => a.mol_index,
-            Expr::Bond(_) => todo!("{}", self.cur),
-            Expr::Grouping(_) => todo!(),
-            Expr::Connect(_) => todo!(),
-        };
-        // cloning so we can remove below
-        let atom2 = match self.peek().unwrap().clone() {
-            Expr::Atom(a) => a.mol_index,
-            Expr::Bond(_) => todo!("{}", self.cur),
-            Expr::Grouping(_) => todo!(),
-            Expr::Connect(n) => {
-                self.next(); // advance over connection
-                self.get_connection(n)
-            }
+    /// the order of a bond between the atoms at positions `a` and `b` that was
+    /// written without a bond symbol. for SMILES, this is aromatic if both
+    /// atoms are aromatic and single otherwise, and a warning is recorded for
+    /// the bond, which must be the next one pushed. SMARTS allows either
+    fn default_bond(&mut self, a: usize, b: usize) -> BondOrder {
+        if self.kind == InputKind::Smarts {
+            return BondOrder::SingleOrAromatic;
+        }
+        let order = if self.atoms[a].aromatic && self.atoms[b].aromatic {
+            BondOrder::Aromatic
+        } else {
+            BondOrder::Single
         };
-        self.bonds.push(Bond {
-            atom1,
-            atom2,
-            order,
+        self.warnings.push(Warning::ImplicitBond {
+            bond: self.bonds.len(),
+            order: order.clone(),
         });
+        order
     }
 
-    fn atom(&mut self, a: Atom) {
-        if let Some(&Expr::Connect(n)) = self.peek() {
-            self.next();
-            self.add_connection(n, a.mol_index);
+    /// warn about chirality tags on atoms with an unknown H count or fewer
+    /// than three neighbors, counting hydrogens
+    fn check_chirality(&mut self) {
+        let mut degree = vec![0; self.atoms.len()];
+        for b in &self.bonds {
+            degree[b.atom1] += 1;
+            degree[b.atom2] += 1;
         }
-        self.atoms.push(a);
-    }
-
-    fn grouping(&mut self, g: Vec<Expr>) {
-        let mut giter = g.iter().enumerate().peekable();
-        while let Some((i, expr)) = giter.next() {
-            match expr {
-                Expr::Atom(a) => {
-                    if let Some((_, &Expr::Connect(n))) = giter.peek() {
-                        giter.next();
-                        self.add_connection(n, a.mol_index);
-                    }
-                    self.atoms.push(a.clone());
-                }
-                Expr::Bond(order) => {
-                    let atom1 = match prev_atom(&g, i + 1) {
-                        Some(Expr::Atom(a)) => a.mol_index,
-                        None => {
-                            // bond is to the atom before the group. can't use
-                            // self.prev_atom because we could be in a recursive
-                            // group
-                            self.atoms.last().unwrap().mol_index
-                        }
-                        _ => unreachable!(),
-                    };
-                    let atom2 = match giter.peek().unwrap().1 {
-                        Expr::Atom(a) => a.mol_index,
-                        Expr::Connect(n) => {
-                            giter.next(); // discard Connect expr
-                            self.get_connection(*n)
-                        }
-                        _ => unreachable!(),
-                    };
-                    let (atom1, atom2) = (atom1.min(atom2), atom1.max(atom2));
-                    let bond = Bond {
-                        atom1,
-                        atom2,
-                        order: order.clone(),
-                    };
-                    self.bonds.push(bond);
-                }
-                Expr::Grouping(h) => self.grouping(h.clone()),
-                Expr::Connect(n) => {
-                    // adjacent connects
-                    let a = self.atoms.last().unwrap().mol_index;
-                    self.add_connection(*n, a);
-                }
+        for (i, atom) in self.atoms.iter().enumerate() {
+            if atom.chirality == Chiral::None {
+                continue;
+            }
+            let ambiguous = match atom.n_hydrogens {
+                Some(h) => degree[i] + h < 3,
+                None => true,
+            };
+            if ambiguous {
+                self.warnings.push(Warning::AmbiguousChirality { atom: i });
             }
         }
     }
@@ -180,7 +334,8 @@ impl Evaluator {
 #[cfg(test)]
 mod tests {
     use crate::{
-        rdkit::to_smarts,
+        conformance::{compare, ConformanceOptions},
+        rdkit::{smiles_to_graph, to_smarts},
         smarts::{parser::Parser, scanner::scan, Chiral, Smarts},
         Dataset,
     };
@@ -209,26 +364,231 @@ mod tests {
                 Atom::new(8, 1, 0, Chiral::None, 11),
             ],
             bonds: vec![
+                Bond::new(0, 1, B::Single),
                 Bond::new(1, 2, B::Single),
-                Bond::new(2, 3, B::Single),
-                Bond::new(3, 4, B::Double),
-                Bond::new(3, 5, B::Single),
-                Bond::new(5, 6, B::Double),
-                Bond::new(6, 7, B::Single),
-                Bond::new(7, 8, B::Double),
-                Bond::new(7, 9, B::Single),
-                Bond::new(9, 10, B::Single),
-                Bond::new(5, 10, B::Single),
-                Bond::new(2, 11, B::Single),
+                Bond::new(2, 3, B::Double),
+                Bond::new(2, 4, B::Single),
+                Bond::new(4, 5, B::Double),
+                Bond::new(5, 6, B::Single),
+                Bond::new(6, 7, B::Double),
+                Bond::new(6, 8, B::Single),
+                Bond::new(8, 9, B::Single),
+                Bond::new(4, 9, B::Single),
+                Bond::new(1, 10, B::Single),
             ],
+            ring_closures: vec![9],
+            provenance: None,
         }];
-        for (smile, want) in smiles.into_iter().zip(wants.into_iter()) {
-            let smarts = to_smarts(smile.to_owned());
-            let tokens = scan(smarts);
-            let p = Parser::new(tokens).parse();
-            let (atoms, bonds) = Evaluator::new(p).eval();
+        for (smile, want) in smiles.into_iter().zip(wants) {
+            let smarts = to_smarts(smile.to_owned()).unwrap();
+            let tokens = scan(smarts).unwrap();
+            let p = Parser::new(tokens).parse().unwrap();
+            let Smarts {
+                atoms,
+                bonds,
+                ring_closures,
+                ..
+            } = Evaluator::new(p).eval().unwrap();
             assert_eq!(atoms, want.atoms);
             assert_eq!(bonds, want.bonds);
+            assert_eq!(ring_closures, want.ring_closures);
+        }
+    }
+
+    #[test]
+    fn unmapped() {
+        let s = "[#6](-[#8])(-[#7])-[#6]";
+        let Smarts { atoms, bonds, .. } = Evaluator::new(
+            Parser::new(scan(s.to_owned()).unwrap()).parse().unwrap(),
+        )
+        .eval()
+        .unwrap();
+        assert!(atoms.iter().all(|a| a.mol_index.is_none()));
+        use BondOrder as B;
+        assert_eq!(
+            bonds,
+            [
+                Bond::new(0, 1, B::Single),
+                Bond::new(0, 2, B::Single),
+                Bond::new(0, 3, B::Single),
+            ]
+        );
+    }
+
+    #[test]
+    fn implicit_bonds() {
+        let eval = |s: &str| {
+            Evaluator::new(
+                Parser::new(scan(s.to_owned()).unwrap()).parse().unwrap(),
+            )
+            .eval()
+            .unwrap()
+        };
+        use BondOrder as B;
+        let Smarts { atoms, bonds, .. } =
+            eval("[cH:1]1[cH:2][cH:3][cH:4][cH:5][cH:6]1");
+        assert!(atoms
+            .iter()
+            .all(|a| a.aromatic && a.atomic_number == Some(6)));
+        assert_eq!(
+            bonds,
+            [
+                Bond::new(0, 1, B::Aromatic),
+                Bond::new(1, 2, B::Aromatic),
+                Bond::new(2, 3, B::Aromatic),
+                Bond::new(3, 4, B::Aromatic),
+                Bond::new(4, 5, B::Aromatic),
+                Bond::new(0, 5, B::Aromatic),
+            ]
+        );
+
+        // toluene, where only the bond to the methyl group is single and an
+        // explicit bond symbol is respected
+        let Smarts { bonds, .. } = eval("[#6H3][c]1[cH]([cH]:[cH][cH][cH]1)");
+        assert_eq!(
+            bonds,
+            [
+                Bond::new(0, 1, B::Single),
+                Bond::new(1, 2, B::Aromatic),
+                Bond::new(2, 3, B::Aromatic),
+                Bond::new(3, 4, B::Aromatic),
+                Bond::new(4, 5, B::Aromatic),
+                Bond::new(5, 6, B::Aromatic),
+                Bond::new(1, 6, B::Aromatic),
+            ]
+        );
+    }
+
+    #[test]
+    fn smarts_defaults() {
+        let s = "[c:1]1[c:2][c:3][c:4][c:5][c:6]1";
+        let Smarts { atoms, bonds, .. } = Evaluator::new(
+            Parser::new(scan(s.to_owned()).unwrap())
+                .with_kind(InputKind::Smarts)
+                .parse()
+                .unwrap(),
+        )
+        .with_kind(InputKind::Smarts)
+        .eval()
+        .unwrap();
+        assert!(atoms.iter().all(|a| a.n_hydrogens.is_none()));
+        assert!(bonds.iter().all(|b| b.order == BondOrder::SingleOrAromatic));
+    }
+
+    #[test]
+    fn ring_bond_decorations() {
+        let eval = |s: &str| {
+            Evaluator::new(
+                Parser::new(scan(s.to_owned()).unwrap()).parse().unwrap(),
+            )
+            .eval()
+            .unwrap()
+            .bonds
+        };
+        use BondOrder as B;
+        // a bond symbol at either end of the ring closure
+        let want = [
+            Bond::new(0, 1, B::Single),
+            Bond::new(1, 2, B::Single),
+            Bond::new(0, 2, B::Double),
+        ];
+        assert_eq!(eval("[#6H:1]=1-[#6H2:2]-[#6H:3]1"), want);
+        assert_eq!(eval("[#6H:1]1-[#6H2:2]-[#6H:3]=1"), want);
+        assert_eq!(eval("[#6H:1]1-[#6H2:2](-[#6H:3]=1)"), want);
+
+        // directional bonds written at the closing label are reversed
+        let open = eval("[#6H2:1]/1-[#6H:2]=[#6H:3]-[#6H2:4]1");
+        let close = eval("[#6H2:1]1-[#6H:2]=[#6H:3]-[#6H2:4]\\1");
+        assert_eq!(open[3], Bond::new(0, 3, B::Up));
+        assert_eq!(open, close);
+    }
+
+    #[test]
+    fn polycyclic() {
+        let eval = |s: &str| {
+            Evaluator::new(
+                Parser::new(scan(s.to_owned()).unwrap()).parse().unwrap(),
+            )
+            .eval()
+            .unwrap()
+        };
+        // naphthalene, with two rings open at once
+        let Smarts { atoms, bonds, .. } = eval(
+            "[cH:1]1[cH:2][cH:3][c:4]2[cH:5][cH:6][cH:7][cH:8][c:9]2[cH:10]1",
+        );
+        assert_eq!(atoms.len(), 10);
+        assert_eq!(bonds.len(), 11);
+        assert!(bonds.contains(&Bond::new(3, 8, BondOrder::Aromatic)));
+        assert!(bonds.contains(&Bond::new(0, 9, BondOrder::Aromatic)));
+
+        // label 1 is closed inside the branch and then reused by a second ring
+        let Smarts { bonds, .. } =
+            eval("[#6:1]1-[#6:2](-[#6:3]-1)-[#6:4]1-[#6:5]-[#6:6]-1");
+        use BondOrder as B;
+        assert_eq!(
+            bonds,
+            [
+                Bond::new(0, 1, B::Single),
+                Bond::new(1, 2, B::Single),
+                Bond::new(0, 2, B::Single),
+                Bond::new(1, 3, B::Single),
+                Bond::new(3, 4, B::Single),
+                Bond::new(4, 5, B::Single),
+                Bond::new(3, 5, B::Single),
+            ]
+        );
+    }
+
+    fn eval_err(s: &str) -> String {
+        let tokens = scan(s.to_owned()).unwrap();
+        let exprs = Parser::new(tokens).parse().unwrap();
+        Evaluator::new(exprs).eval().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn unclosed_ring() {
+        assert!(eval_err("[#6]1-[#6]").ends_with("never closed"));
+    }
+
+    #[test]
+    fn duplicate_ring_bond() {
+        assert!(eval_err("[#6]1-[#6]-1").contains("duplicates the bond"));
+    }
+
+    #[test]
+    fn bond_before_branch() {
+        let exprs = vec![
+            Expr::Atom(Atom::new(6, 3, 0, Chiral::None, 1)),
+            Expr::Bond(BondOrder::Single),
+            Expr::Grouping(vec![Expr::Atom(Atom::new(
+                8,
+                1,
+                0,
+                Chiral::None,
+                2,
+            ))]),
+        ];
+        let got = Evaluator::new(exprs).eval().unwrap_err();
+        assert!(matches!(got, ChomperError::Eval(_)));
+        assert!(got.to_string().contains("before the branch"));
+    }
+
+    /// the polycyclic molecules in the opt dataset should agree with rdkit
+    #[test]
+    fn polycyclic_dataset() {
+        let mut smiles =
+            Dataset::load("testfiles/opt.json").unwrap().to_smiles();
+        smiles.dedup();
+        let opts = ConformanceOptions::default();
+        for smile in smiles {
+            let smarts = to_smarts(smile.clone()).unwrap();
+            if !smarts.contains("]2") {
+                continue;
+            }
+            let got = Smarts::parse(smarts);
+            let failures =
+                compare(&got, &smiles_to_graph(&smile).unwrap(), &opts);
+            assert!(failures.is_empty(), "{smile}: {failures:?}");
         }
     }
 
@@ -238,10 +598,10 @@ mod tests {
             Dataset::load("testfiles/opt.json").unwrap().to_smiles();
         smiles.dedup();
         for smile in smiles {
-            let smarts = to_smarts(smile);
-            let tokens = scan(smarts);
-            let p = Parser::new(tokens).parse();
-            Evaluator::new(p).eval();
+            let smarts = to_smarts(smile).unwrap();
+            let tokens = scan(smarts).unwrap();
+            let p = Parser::new(tokens).parse().unwrap();
+            Evaluator::new(p).eval().unwrap();
         }
     }
 }
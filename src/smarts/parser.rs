@@ -1,33 +1,56 @@
 //! Parser for SMARTS. Grammar:
 //!
 //! smarts -> atom | atom bond smarts
-//! atom -> "[" "#" DIGIT+ ("H" DIGIT*)* ":" DIGIT+ "]"
-//! bond -> DIGIT? grouping* ( "-" | "/" | "\" | "=" | "#" | ":" | "@" )
+//! atom -> ELEMENT | AROMATIC | "*" | "[" DIGIT* low_and? (":" DIGIT+)? "]"
+//! low_and -> or (";" or)*
+//! or -> high_and ("," high_and)*
+//! high_and -> not ("&"? not)*
+//! not -> "!"* primitive
+//! primitive -> "#" DIGIT+ | ELEMENT | AROMATIC | "*" | "H" DIGIT* | "+" DIGIT*
+//!     | "-" DIGIT* | "@" | "@@" | "$(" smarts ")" | COUNT DIGIT* | "a" | "A"
+//! COUNT -> "D" | "X" | "v" | "h" | "R" | "r" | "x"
+//! bond -> DIGIT? grouping* bond_and
+//! bond_and -> bond_or (";" bond_or)*
+//! bond_or -> bond_high_and ("," bond_high_and)*
+//! bond_high_and -> bond_not ("&"? bond_not)*
+//! bond_not -> "!"* ( "-" | "/" | "\" | "=" | "#" | ":" | "@" | "~" )
 //! grouping -> "(" bond smarts ")"
 //!
 //! a smarts is either just an atom or an atom followed by a bond and further
 //! smarts
 //!
-//! an atom is something inside of square brackets. again, this can be a lot
-//! more complicated but maybe not for the concrete patterns I intend to parse
+//! an atom is usually something inside of square brackets: a logical expression
+//! over primitives, with `!` binding tightest, then `&` or simply writing two
+//! primitives next to each other, then `,`, and finally `;`. most atoms are a
+//! plain conjunction like `[#6H3]`, which ends up in the fields of an [Atom],
+//! while anything else, like `[#6&H1,#7;!H0]`, is kept as an expression. a
+//! recursive SMARTS like `[#6$(C=O)]` nests a whole pattern in an atom, which
+//! is evaluated on the spot and kept as a [Query] in that expression. atoms
+//! outside of brackets, like the `C` and `O` there, only come up in SMARTS
+//! written by hand
 //!
 //! a bond is just one of the bond symbols optionally prefixed by a digit for
 //! connecting back later in the string. it can also be a grouping. digit and
 //! grouping might be mutually exclusive, but I'm not sure
 //!
-//! I'm pretty sure there are actually further complications to this. for
-//! example, I think you can apply modifiers to bonds, and there are logical
-//! operators in here somewhere too. I'm not running into these yet because I'm
-//! labeling concrete molecules. So this might only be of concern for generated
-//! Smarts structs, not actually parsing
+//! bonds take the same logical operators as atoms, as in the `-;@` and `!@`
+//! of the SMIRNOFF force fields. a lone bond symbol keeps its plain
+//! [BondOrder], while anything else is kept as a [BondOrder::Expr]
 //!
 //! Usually you would turn the sequence of tokens into an AST, which you can
 //! then evaluate, but I think I can turn my tokens directly into my desired
 //! Smarts struct
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
-use super::{scanner::Token, Atom, BondOrder, Chiral};
+use super::{
+    evaluator::Evaluator, scanner::Token, Atom, BondOrder, Chiral, InputKind,
+    Warning, Warnings,
+};
+use crate::{
+    molecule::BondType,
+    query::{AtomExpr, AtomPrimitive, BondExpr, BondPrimitive, Query},
+};
 
 #[derive(Clone, PartialEq)]
 pub enum Expr {
@@ -40,11 +63,25 @@ pub enum Expr {
 impl Debug for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expr::Atom(a) => write!(
-                f,
-                "[#{}H{}{:+}:{}]",
-                a.atomic_number, a.n_hydrogens, a.charge, a.mol_index
-            ),
+            Expr::Atom(a) if a.expr.is_some() => write!(f, "{a}"),
+            Expr::Atom(a) => {
+                f.write_str("[")?;
+                if let Some(n) = a.isotope {
+                    write!(f, "{n}")?;
+                }
+                match a.atomic_number {
+                    Some(n) => write!(f, "#{n}")?,
+                    None => write!(f, "*")?,
+                }
+                if let Some(h) = a.n_hydrogens {
+                    write!(f, "H{h}")?;
+                }
+                write!(f, "{:+}", a.charge)?;
+                if let Some(i) = a.mol_index {
+                    write!(f, ":{i}")?;
+                }
+                write!(f, "]")
+            }
             Expr::Bond(order) => write!(f, "{order:?}"),
             Expr::Grouping(g) => write!(f, "Grouping({g:?})"),
             Expr::Connect(n) => write!(f, "Connect({n})"),
@@ -52,31 +89,95 @@ impl Debug for Expr {
     }
 }
 
-impl Expr {
-    /// Returns `true` if the expr is [`Atom`].
-    ///
-    /// [`Atom`]: Expr::Atom
-    #[must_use]
-    pub fn is_atom(&self) -> bool {
-        matches!(self, Self::Atom(..))
+/// The kinds of [ParseError]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// the input ended inside a bracket atom
+    UnclosedAtom,
+    /// a token that can't appear inside a bracket atom
+    AtomComponent,
+    /// an atom map `:` not followed by a number
+    AtomMap,
+    /// a token that can't appear where a bond is expected
+    Bond,
+    /// a recursive SMARTS `$(...)` that is malformed or empty
+    Recursive,
+    /// a `)` without a matching `(`, or a branch that is never closed
+    UnbalancedParens,
+}
+
+impl ParseErrorKind {
+    fn description(&self) -> &'static str {
+        match self {
+            ParseErrorKind::UnclosedAtom => "EOF while parsing atom",
+            ParseErrorKind::AtomComponent => "unknown atom component",
+            ParseErrorKind::AtomMap => "unknown atom map component",
+            ParseErrorKind::Bond => "unknown bond component",
+            ParseErrorKind::Recursive => "invalid recursive SMARTS",
+            ParseErrorKind::UnbalancedParens => "unbalanced parentheses",
+        }
     }
 }
 
+/// A token the parser could not place, with where it was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// the position of the offending token in the token stream
+    pub index: usize,
+    /// the offending token and up to five tokens on either side of it, in
+    /// their debug form
+    pub context: Vec<String>,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at token {}: {}",
+            self.kind.description(),
+            self.index,
+            self.context.join(" ")
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 pub(super) struct Parser {
     /// `tokens` represents a single input SMARTS string decomposed into a
     /// sequence of tokens. We turn this sequence back into a [Smarts] struct
     tokens: Vec<Token>,
     cur: usize,
+    /// determines the H count of atoms written without one
+    kind: InputKind,
+    /// the number of atoms parsed so far, which is the position of the next
+    /// one once evaluated
+    n_atoms: usize,
+    /// [Token::Unknown] atom decorators skipped and H counts assumed so far
+    pub(super) warnings: Warnings,
 }
 
 impl Parser {
     pub(super) fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, cur: 0 }
+        Self {
+            tokens,
+            cur: 0,
+            kind: InputKind::default(),
+            n_atoms: 0,
+            warnings: Warnings::new(),
+        }
+    }
+
+    pub(super) fn with_kind(mut self, kind: InputKind) -> Self {
+        self.kind = kind;
+        self
     }
 
-    fn context(&self, n: usize) -> &[Token] {
-        let beg = self.cur.saturating_sub(n);
-        let end = self.cur + n;
+    /// the tokens within `n` of position `index`
+    fn context(&self, index: usize, n: usize) -> &[Token] {
+        let beg = index.saturating_sub(n);
+        let end = index + n + 1;
         &self.tokens[beg..end.min(self.tokens.len())]
     }
 
@@ -97,116 +198,491 @@ impl Parser {
         ret
     }
 
-    pub(super) fn parse(&mut self) -> Vec<Expr> {
+    pub(super) fn parse(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let ret = self.exprs()?;
+        if !self.at_end() {
+            // exprs only stops early at a `)`, which has nothing to close
+            let t = self.advance();
+            return Err(self.error(ParseErrorKind::UnbalancedParens, &t));
+        }
+        Ok(ret)
+    }
+
+    /// the expressions up to the end of the input or the `)` closing the
+    /// enclosing group, which is left for the caller
+    fn exprs(&mut self) -> Result<Vec<Expr>, ParseError> {
         let mut ret = Vec::new();
         while !self.at_end() {
             match self.peek() {
-                Token::LBrack => ret.push(self.atom()),
-                Token::LParen => ret.push(self.grouping()),
-                Token::RParen => break, // for recursive calls from grouping
+                Token::LBrack => ret.push(self.atom()?),
+                Token::Atom(_)
+                | Token::AtomicNumber(_)
+                | Token::AromaticAtom(_)
+                | Token::Wildcard => ret.push(self.bare_atom()),
+                Token::LParen => ret.push(self.grouping()?),
+                Token::RParen => break, // closing a group or recursive SMARTS
+                // the scanner splits adjacent labels like the 12 in -12-
                 Token::Digit(n) => {
-                    if *n > 10 {
-                        // handling two adjacent connections that look like
-                        // -12-, for example
-                        let fst = *n / 10;
-                        let snd = *n % 10;
-                        ret.push(Expr::Connect(fst));
-                        ret.push(Expr::Connect(snd));
-                    } else {
-                        ret.push(Expr::Connect(*n));
-                    }
+                    ret.push(Expr::Connect(*n));
                     self.advance();
                 }
-                _ => ret.push(self.bond()),
+                _ => ret.push(self.bond()?),
             }
         }
-        ret
+        Ok(ret)
     }
 
-    fn atom(&mut self) -> Expr {
+    fn atom(&mut self) -> Result<Expr, ParseError> {
         self.advance(); // discard LBrack signaling we're in here
-        let mut chirality = Chiral::None;
-        let mut atomic_number = 0;
-        let mut n_hydrogens = 0;
-        let mut mol_index = 0;
-        let mut charge = 0;
-        loop {
+        let pos = self.n_atoms;
+        self.n_atoms += 1;
+        let isotope = match *self.peek() {
+            Token::Isotope(n) => {
+                self.advance();
+                Some(n)
+            }
+            _ => None,
+        };
+        // an atom can also be empty, like [:1], which constrains nothing
+        let expr = match self.peek() {
+            Token::Colon | Token::RBrack => None,
+            _ => self.low_and()?,
+        };
+        let mut mol_index = None;
+        if *self.peek() == Token::Colon {
+            self.advance();
             match self.advance() {
-                Token::Atom(n) => atomic_number = n,
-                Token::HCount(n) => n_hydrogens = n,
-                Token::Colon => {
-                    let Token::Digit(i) = self.advance() else {
-                        unreachable!();
-                    };
-                    mol_index = i;
+                Token::Digit(i) => mol_index = Some(i),
+                x => return Err(self.error(ParseErrorKind::AtomMap, &x)),
+            }
+        }
+        match self.advance() {
+            Token::RBrack => {}
+            x @ Token::End => {
+                return Err(self.error(ParseErrorKind::UnclosedAtom, &x))
+            }
+            x => return Err(self.error(ParseErrorKind::AtomComponent, &x)),
+        }
+
+        let mut prims = Vec::new();
+        if let Some(expr) = expr.filter(|e| !conjuncts(e, &mut prims)) {
+            return Ok(Expr::Atom(Atom {
+                atomic_number: None,
+                isotope,
+                n_hydrogens: None,
+                charge: 0,
+                explicit_charge: false,
+                chirality: Chiral::None,
+                aromatic: false,
+                aliphatic: false,
+                expr: Some(expr),
+                mol_index,
+            }));
+        }
+        let mut atom = Atom::new(None, None, 0, Chiral::None, mol_index)
+            .with_isotope(isotope);
+        atom.explicit_charge = self.kind == InputKind::Smiles;
+        let mut explicit_h = false;
+        for p in prims {
+            match p {
+                AtomPrimitive::AtomicNumber(n) => atom.atomic_number = Some(n),
+                AtomPrimitive::Hydrogens(n) => {
+                    atom.n_hydrogens = Some(n);
+                    explicit_h = true;
                 }
-                Token::Plus(n) => charge = n as isize,
-                Token::Dash => {
-                    let t = self.peek().clone();
-                    let n = if let Token::Digit(n) = t {
-                        self.advance();
-                        n
-                    } else {
-                        1
-                    };
-                    charge = -(n as isize);
+                AtomPrimitive::Charge(c) => {
+                    atom.charge = c;
+                    atom.explicit_charge = true;
                 }
-                Token::At => chirality = Chiral::Acw,
-                Token::AtAt => chirality = Chiral::Cw,
-                Token::RBrack => break,
-                Token::End => panic!("EOF while parsing atom"),
-                x => self.error("atom", x),
-            };
-        }
-        Expr::Atom(Atom {
-            atomic_number,
-            n_hydrogens,
-            charge,
-            chirality,
-            mol_index,
-        })
-    }
-
-    fn error(&self, label: &str, t: Token) -> ! {
-        panic!(
-            "unknown {label} component {t:?} at {}: {:?}",
-            self.cur,
-            self.context(5)
-        )
+                AtomPrimitive::Chirality(c) => atom.chirality = c,
+                AtomPrimitive::Aromatic => atom.aromatic = true,
+                AtomPrimitive::Aliphatic => atom.aliphatic = true,
+                _ => unreachable!("not kept by conjuncts"),
+            }
+        }
+        if !explicit_h && self.kind == InputKind::Smiles {
+            atom.n_hydrogens = Some(0);
+            self.warnings.push(Warning::ImplicitHydrogens { atom: pos });
+        }
+        Ok(Expr::Atom(atom))
     }
 
-    fn grouping(&mut self) -> Expr {
-        self.advance(); // discard LParen
-        let ret = self.parse();
-        self.advance(); // discard closing RParen
-        Expr::Grouping(ret)
+    /// an atom written outside of brackets, like `C` or `c`. its H count is
+    /// left unconstrained, since implicit hydrogens aren't worked out from
+    /// valences
+    fn bare_atom(&mut self) -> Expr {
+        self.n_atoms += 1;
+        let mut atom = Atom::new(None, None, 0, Chiral::None, None);
+        atom.explicit_charge = self.kind == InputKind::Smiles;
+        match self.advance() {
+            Token::Atom(n) => {
+                atom.atomic_number = Some(n);
+                atom.aliphatic = self.kind == InputKind::Smarts;
+            }
+            Token::AtomicNumber(n) => atom.atomic_number = Some(n),
+            Token::AromaticAtom(n) => {
+                atom.atomic_number = Some(n);
+                atom.aromatic = true;
+            }
+            _ => {} // the wildcard
+        }
+        Expr::Atom(atom)
     }
 
-    fn bond(&mut self) -> Expr {
+    /// the pattern in a recursive SMARTS, after its `$`, as a query. it is
+    /// always read as SMARTS, and its atoms don't count towards the positions
+    /// of the atoms in the enclosing pattern
+    fn recursive(&mut self) -> Result<AtomExpr, ParseError> {
+        let dollar = self.cur - 1;
+        let t = self.advance();
+        if t != Token::LParen {
+            return Err(self.error(ParseErrorKind::Recursive, &t));
+        }
+        let (kind, n_atoms) = (self.kind, self.n_atoms);
+        self.kind = InputKind::Smarts;
+        let exprs = self.exprs()?;
+        (self.kind, self.n_atoms) = (kind, n_atoms);
+        let t = self.advance();
+        if t != Token::RParen {
+            return Err(self.error(ParseErrorKind::Recursive, &t));
+        }
+        let smarts = Evaluator::new(exprs)
+            .with_kind(InputKind::Smarts)
+            .eval()
+            .ok()
+            .filter(|s| !s.atoms.is_empty())
+            .ok_or_else(|| self.error_at(ParseErrorKind::Recursive, dollar))?;
+        Ok(AtomExpr::Primitive(AtomPrimitive::Recursive(Box::new(
+            Query::from(&smarts),
+        ))))
+    }
+
+    /// the operands of `;`, the lowest-precedence and. like the rest of the
+    /// atom expression methods, `None` means the expression was made up of
+    /// skipped decorators, so it constrains nothing
+    fn low_and(&mut self) -> Result<Option<AtomExpr>, ParseError> {
+        let mut terms = vec![self.or()?];
+        while *self.peek() == Token::Semicolon {
+            self.advance();
+            terms.push(self.or()?);
+        }
+        Ok(all(terms))
+    }
+
+    /// the operands of `,`
+    fn or(&mut self) -> Result<Option<AtomExpr>, ParseError> {
+        let mut terms = vec![self.high_and()?];
+        while *self.peek() == Token::Comma {
+            self.advance();
+            terms.push(self.high_and()?);
+        }
+        // a skipped decorator might have matched, so the whole disjunction
+        // might too
+        if terms.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let mut terms: Vec<_> = terms.into_iter().flatten().collect();
+        if terms.len() == 1 {
+            return Ok(terms.pop());
+        }
+        Ok(Some(AtomExpr::Or(terms)))
+    }
+
+    /// the operands of `&`, or of adjacent primitives, which are also joined by
+    /// a high-precedence and
+    fn high_and(&mut self) -> Result<Option<AtomExpr>, ParseError> {
+        let mut terms = vec![self.not()?];
+        loop {
+            match self.peek() {
+                Token::Ampersand => {
+                    self.advance();
+                }
+                Token::Comma
+                | Token::Semicolon
+                | Token::Colon
+                | Token::RBrack
+                | Token::End => break,
+                _ => {}
+            }
+            terms.push(self.not()?);
+        }
+        Ok(all(terms))
+    }
+
+    fn not(&mut self) -> Result<Option<AtomExpr>, ParseError> {
+        if *self.peek() == Token::Bang {
+            self.advance();
+            return Ok(self.not()?.map(|e| AtomExpr::Not(Box::new(e))));
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Option<AtomExpr>, ParseError> {
+        use AtomPrimitive as P;
+        let p = match self.advance() {
+            // an uppercase symbol is aliphatic. only SMARTS needs to say so,
+            // since every atom of a SMILES not written as aromatic isn't
+            Token::Atom(n) if self.kind == InputKind::Smarts => {
+                return Ok(Some(AtomExpr::And(vec![
+                    AtomExpr::Primitive(P::AtomicNumber(n)),
+                    AtomExpr::Primitive(P::Aliphatic),
+                ])))
+            }
+            Token::Atom(n) | Token::AtomicNumber(n) => P::AtomicNumber(n),
+            Token::Wildcard => return Ok(Some(AtomExpr::And(Vec::new()))),
+            Token::AromaticAtom(n) => {
+                return Ok(Some(AtomExpr::And(vec![
+                    AtomExpr::Primitive(P::AtomicNumber(n)),
+                    AtomExpr::Primitive(P::Aromatic),
+                ])))
+            }
+            Token::HCount(n) => P::Hydrogens(n),
+            Token::Count(c, n) => return Ok(Some(count(c, n))),
+            Token::Aromatic => P::Aromatic,
+            Token::Aliphatic => P::Aliphatic,
+            Token::Plus(n) => P::Charge(n as isize),
+            Token::Dash => {
+                let t = self.peek().clone();
+                let n = if let Token::Digit(n) = t {
+                    self.advance();
+                    n
+                } else {
+                    1
+                };
+                P::Charge(-(n as isize))
+            }
+            Token::At => P::Chirality(Chiral::Acw),
+            Token::AtAt => P::Chirality(Chiral::Cw),
+            Token::Dollar => return Ok(Some(self.recursive()?)),
+            Token::Unknown { text, offset } => {
+                self.warnings.push(Warning::SkippedDecorator {
+                    decorator: text,
+                    offset,
+                });
+                return Ok(None);
+            }
+            x @ Token::End => {
+                return Err(self.error(ParseErrorKind::UnclosedAtom, &x))
+            }
+            x => return Err(self.error(ParseErrorKind::AtomComponent, &x)),
+        };
+        Ok(Some(AtomExpr::Primitive(p)))
+    }
+
+    /// the error for `t`, which was just returned by [Parser::advance]
+    fn error(&self, kind: ParseErrorKind, t: &Token) -> ParseError {
+        // advance doesn't move past the End token
+        let index = if t.is_end() { self.cur } else { self.cur - 1 };
+        self.error_at(kind, index)
+    }
+
+    /// the error for the token at position `index`
+    fn error_at(&self, kind: ParseErrorKind, index: usize) -> ParseError {
+        ParseError {
+            kind,
+            index,
+            context: self
+                .context(index, 5)
+                .iter()
+                .map(|t| format!("{t:?}"))
+                .collect(),
+        }
+    }
+
+    fn grouping(&mut self) -> Result<Expr, ParseError> {
+        self.advance(); // discard LParen
+        let ret = self.exprs()?;
         match self.advance() {
-            Token::Dash => Expr::Bond(BondOrder::Single),
-            Token::DoubleBond => Expr::Bond(BondOrder::Double),
-            Token::Colon => Expr::Bond(BondOrder::Aromatic),
-            Token::TripleBond => Expr::Bond(BondOrder::Triple),
-            Token::At => Expr::Bond(BondOrder::Ring),
-            Token::DownBond => Expr::Bond(BondOrder::Down),
-            Token::UpBond => Expr::Bond(BondOrder::Up),
-            x => self.error("bond", x),
+            Token::RParen => Ok(Expr::Grouping(ret)),
+            t => Err(self.error(ParseErrorKind::UnbalancedParens, &t)),
+        }
+    }
+
+    fn bond(&mut self) -> Result<Expr, ParseError> {
+        let next = &self.tokens[(self.cur + 1).min(self.tokens.len() - 1)];
+        let order = match self.peek() {
+            _ if continues_bond(next) => None,
+            Token::Dash => Some(BondOrder::Single),
+            Token::DoubleBond => Some(BondOrder::Double),
+            Token::Colon => Some(BondOrder::Aromatic),
+            Token::TripleBond => Some(BondOrder::Triple),
+            Token::At => Some(BondOrder::Ring),
+            Token::DownBond => Some(BondOrder::Down),
+            Token::UpBond => Some(BondOrder::Up),
+            Token::Tilde => Some(BondOrder::Any),
+            _ => None,
+        };
+        if let Some(order) = order {
+            self.advance();
+            return Ok(Expr::Bond(order));
+        }
+        let expr = self.bond_low_and()?;
+        // the default SMARTS bond, written out
+        let t = |t| BondExpr::Primitive(BondPrimitive::Type(t));
+        if expr
+            == BondExpr::Or(vec![t(BondType::Single), t(BondType::Aromatic)])
+        {
+            return Ok(Expr::Bond(BondOrder::SingleOrAromatic));
+        }
+        Ok(Expr::Bond(BondOrder::Expr(expr)))
+    }
+
+    /// the operands of `;` in a bond expression. unlike in atoms, there are no
+    /// skipped decorators, so every operand constrains the bond
+    fn bond_low_and(&mut self) -> Result<BondExpr, ParseError> {
+        let mut terms = vec![self.bond_or()?];
+        while *self.peek() == Token::Semicolon {
+            self.advance();
+            terms.push(self.bond_or()?);
+        }
+        Ok(join(terms, BondExpr::And))
+    }
+
+    fn bond_or(&mut self) -> Result<BondExpr, ParseError> {
+        let mut terms = vec![self.bond_high_and()?];
+        while *self.peek() == Token::Comma {
+            self.advance();
+            terms.push(self.bond_high_and()?);
+        }
+        Ok(join(terms, BondExpr::Or))
+    }
+
+    fn bond_high_and(&mut self) -> Result<BondExpr, ParseError> {
+        let mut terms = vec![self.bond_not()?];
+        loop {
+            match self.peek() {
+                Token::Ampersand => {
+                    self.advance();
+                }
+                t if bond_primitive(t).is_some() || *t == Token::Bang => {}
+                _ => break,
+            }
+            terms.push(self.bond_not()?);
+        }
+        Ok(join(terms, BondExpr::And))
+    }
+
+    fn bond_not(&mut self) -> Result<BondExpr, ParseError> {
+        if *self.peek() == Token::Bang {
+            self.advance();
+            return Ok(BondExpr::Not(Box::new(self.bond_not()?)));
+        }
+        let t = self.advance();
+        match bond_primitive(&t) {
+            Some(p) => Ok(BondExpr::Primitive(p)),
+            None => Err(self.error(ParseErrorKind::Bond, &t)),
+        }
+    }
+}
+
+/// the bond primitive written as `t`, if it is one. directional bonds are
+/// single bonds here, as they are for a lone `/` or `\` in a [Query]
+fn bond_primitive(t: &Token) -> Option<BondPrimitive> {
+    use BondPrimitive as P;
+    Some(match t {
+        Token::Dash | Token::UpBond | Token::DownBond => {
+            P::Type(BondType::Single)
+        }
+        Token::DoubleBond => P::Type(BondType::Double),
+        Token::TripleBond => P::Type(BondType::Triple),
+        Token::Colon => P::Type(BondType::Aromatic),
+        Token::At => P::Ring,
+        Token::Tilde => P::Any,
+        _ => return None,
+    })
+}
+
+/// the primitive for the count primitive `c`, written with the count `n`, if
+/// any. `D`, `X`, `v`, and `h` default to 1, like `H`, while a bare `R`, `r`,
+/// or `x` means any ring atom, and `r0` any atom outside of rings
+fn count(c: char, n: Option<usize>) -> AtomExpr {
+    use AtomPrimitive as P;
+    let p = match (c, n) {
+        ('R' | 'r' | 'x', None) => P::InRing,
+        ('r', Some(0)) => {
+            return AtomExpr::Not(Box::new(AtomExpr::Primitive(P::InRing)))
+        }
+        ('R', Some(n)) => P::RingCount(n),
+        ('r', Some(n)) => P::SmallestRing(n),
+        ('x', Some(n)) => P::RingConnectivity(n),
+        ('D', n) => P::Degree(n.unwrap_or(1)),
+        ('X', n) => P::Connectivity(n.unwrap_or(1)),
+        ('v', n) => P::Valence(n.unwrap_or(1)),
+        ('h', n) => P::ImplicitHydrogens(n.unwrap_or(1)),
+        _ => unreachable!("not scanned as a count: {c}"),
+    };
+    AtomExpr::Primitive(p)
+}
+
+/// whether `t` can continue a bond expression after its first primitive
+fn continues_bond(t: &Token) -> bool {
+    bond_primitive(t).is_some()
+        || matches!(
+            t,
+            Token::Bang | Token::Ampersand | Token::Comma | Token::Semicolon
+        )
+}
+
+/// `terms` combined with `op`, or the lone term if there is only one
+fn join(
+    mut terms: Vec<BondExpr>,
+    op: fn(Vec<BondExpr>) -> BondExpr,
+) -> BondExpr {
+    if terms.len() == 1 {
+        terms.pop().unwrap()
+    } else {
+        op(terms)
+    }
+}
+
+/// the conjunction of `terms`, leaving out the skipped ones
+fn all(terms: Vec<Option<AtomExpr>>) -> Option<AtomExpr> {
+    let mut terms: Vec<_> = terms.into_iter().flatten().collect();
+    match terms.len() {
+        0 => None,
+        1 => terms.pop(),
+        _ => Some(AtomExpr::And(terms)),
+    }
+}
+
+/// push the primitives of `e` onto `out` and return true if it is a plain
+/// conjunction of primitives that fit in the fields of an [Atom]
+fn conjuncts(e: &AtomExpr, out: &mut Vec<AtomPrimitive>) -> bool {
+    match e {
+        AtomExpr::Primitive(
+            p @ (AtomPrimitive::AtomicNumber(_)
+            | AtomPrimitive::Hydrogens(_)
+            | AtomPrimitive::Charge(_)
+            | AtomPrimitive::Chirality(_)
+            | AtomPrimitive::Aromatic
+            | AtomPrimitive::Aliphatic),
+        ) => {
+            out.push(p.clone());
+            true
         }
+        AtomExpr::And(es) => es.iter().all(|e| conjuncts(e, out)),
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{rdkit::to_smarts, smarts::scanner::scan, Dataset};
+    use crate::{
+        rdkit::to_smarts,
+        smarts::{
+            scanner::{scan, scan_lossy},
+            Smarts,
+        },
+        Dataset,
+    };
 
     use super::*;
 
     #[test]
     fn parse_single() {
         let s = r#"[#6H3:1]-[#6H2:2]-[#7H:3]-[#7H:4]-[#6H3:5]"#;
-        let tokens = scan(s.to_owned());
-        let got = Parser::new(tokens).parse();
+        let tokens = scan(s.to_owned()).unwrap();
+        let got = Parser::new(tokens).parse().unwrap();
         let want = vec![
             Expr::Atom(Atom::new(6, 3, 0, Chiral::None, 1)),
             Expr::Bond(BondOrder::Single),
@@ -219,6 +695,235 @@ mod tests {
             Expr::Atom(Atom::new(6, 3, 0, Chiral::None, 5)),
         ];
         assert_eq!(got, want);
+
+        let parse = |s: &str| Parser::new(scan(s.to_owned()).unwrap()).parse();
+        let got = parse("[Cl-:1]-[cH:2]").unwrap();
+        assert_eq!(got[0], Expr::Atom(Atom::new(17, 0, -1, Chiral::None, 1)));
+        let c = Atom::new(6, 1, 0, Chiral::None, 2).with_aromatic(true);
+        assert_eq!(got[2], Expr::Atom(c));
+        let got = parse("[*:1]-[#0]").unwrap();
+        assert_eq!(got[0], Expr::Atom(Atom::new(None, 0, 0, Chiral::None, 1)));
+        assert_eq!(got[2], Expr::Atom(Atom::new(0, 0, 0, Chiral::None, None)));
+
+        let got = parse("[13CH3:1]-[2#1]").unwrap();
+        let c = Atom::new(6, 3, 0, Chiral::None, 1).with_isotope(13);
+        assert_eq!(got[0], Expr::Atom(c));
+        let h = Atom::new(1, 0, 0, Chiral::None, None).with_isotope(2);
+        assert_eq!(got[2], Expr::Atom(h));
+
+        let got = parse("[#6H3").unwrap_err();
+        assert_eq!(got.kind, ParseErrorKind::UnclosedAtom);
+        assert_eq!(got.index, 3);
+        assert_eq!(
+            got.to_string(),
+            "EOF while parsing atom at token 3: LBrack AtomicNumber(6) HCount(3) End"
+        );
+        let got = parse("[#6:H]").unwrap_err();
+        assert_eq!((got.kind, got.index), (ParseErrorKind::AtomMap, 3));
+        let got = parse("[#6]-]").unwrap_err();
+        assert_eq!((got.kind, got.index), (ParseErrorKind::Bond, 4));
+        assert_eq!(got.context.last().unwrap(), "End");
+    }
+
+    #[test]
+    fn parens() {
+        let parse = |s: &str| Parser::new(scan(s.to_owned()).unwrap()).parse();
+        let got = parse("[#6](-[#8])-[#7]").unwrap();
+        assert!(matches!(got[1], Expr::Grouping(_)));
+        assert!(parse("[#6]([#8])([#7])[#9]").is_ok());
+
+        let err = |s: &str| {
+            let e = parse(s).unwrap_err();
+            (e.kind, e.index)
+        };
+        use ParseErrorKind::UnbalancedParens as U;
+        // a stray ) would otherwise end the pattern early
+        assert_eq!(err("[#6])[#8]"), (U, 3));
+        assert_eq!(err("[#6](-[#8]))"), (U, 9));
+        // and an unclosed branch would be accepted
+        assert_eq!(err("[#6]("), (U, 4));
+        assert_eq!(err("[#6](("), (U, 5));
+        assert_eq!(err("[#6](-[#8]"), (U, 8));
+    }
+
+    #[test]
+    fn bond_expressions() {
+        use BondPrimitive as P;
+        let parse = |s: &str| Parser::new(scan(s.to_owned()).unwrap()).parse();
+        let p = |p| BondExpr::Primitive(p);
+        let bond = |s: &str| parse(s).unwrap().swap_remove(1);
+        assert_eq!(
+            bond("[#6]-;@[#6]"),
+            Expr::Bond(BondOrder::Expr(BondExpr::And(vec![
+                p(P::Type(BondType::Single)),
+                p(P::Ring)
+            ])))
+        );
+        assert_eq!(
+            bond("[#6]!@[#6]"),
+            Expr::Bond(BondOrder::Expr(BondExpr::Not(Box::new(p(P::Ring)))))
+        );
+        assert_eq!(bond("[#6]~[#6]"), Expr::Bond(BondOrder::Any));
+        assert_eq!(
+            bond("[#6]-,:[#6]"),
+            Expr::Bond(BondOrder::SingleOrAromatic)
+        );
+        assert_eq!(bond("[#6]=[#6]"), Expr::Bond(BondOrder::Double));
+        let got = parse("[#6]-;[#6]").unwrap_err();
+        assert_eq!((got.kind, got.index), (ParseErrorKind::Bond, 5));
+
+        // patterns from the Sage force field
+        let sage = |s: &str| {
+            let got =
+                Smarts::try_parse_as(s.to_owned(), InputKind::Smarts).unwrap();
+            got.bonds.into_iter().map(|b| b.order).collect::<Vec<_>>()
+        };
+        assert_eq!(sage("[*:1]~[#6X3:2]"), [BondOrder::Any]);
+        let not_ring = BondOrder::Expr(BondExpr::Not(Box::new(p(P::Ring))));
+        assert_eq!(sage("[#6:1]!@[#6:2]"), [not_ring]);
+        assert_eq!(
+            sage("[*:1]~[#6X3:2]-[#6X4:3]~[*:4]"),
+            [BondOrder::Any, BondOrder::Single, BondOrder::Any]
+        );
+    }
+
+    #[test]
+    fn logical() {
+        use AtomPrimitive as P;
+        let parse = |s: &str| {
+            let mut parser = Parser::new(scan_lossy(s.to_owned()).unwrap())
+                .with_kind(InputKind::Smarts);
+            (parser.parse().unwrap(), parser.warnings)
+        };
+        let p = |p| AtomExpr::Primitive(p);
+        let (got, _) = parse("[#6&H1,#7;!+:1]");
+        let expr = AtomExpr::And(vec![
+            AtomExpr::Or(vec![
+                AtomExpr::And(vec![p(P::AtomicNumber(6)), p(P::Hydrogens(1))]),
+                p(P::AtomicNumber(7)),
+            ]),
+            AtomExpr::Not(Box::new(p(P::Charge(1)))),
+        ]);
+        let Expr::Atom(a) = &got[0] else { panic!() };
+        assert_eq!((&a.expr, a.mol_index), (&Some(expr), Some(1)));
+        assert_eq!(a.n_hydrogens, None);
+
+        // plain conjunctions still fill in the fields
+        let (got, _) = parse("[#6;H1&-]");
+        assert_eq!(got[0], Expr::Atom(Atom::new(6, 1, -1, Chiral::None, None)));
+
+        // the example from the SMARTS logical operators request
+        let (got, _) = parse("[#6&H1,#7;!R]");
+        let Expr::Atom(a) = &got[0] else { panic!() };
+        assert_eq!(
+            a.expr,
+            Some(AtomExpr::And(vec![
+                AtomExpr::Or(vec![
+                    AtomExpr::And(vec![
+                        p(P::AtomicNumber(6)),
+                        p(P::Hydrogens(1))
+                    ]),
+                    p(P::AtomicNumber(7)),
+                ]),
+                AtomExpr::Not(Box::new(p(P::InRing))),
+            ]))
+        );
+
+        // skipped decorators constrain nothing, widening anything they're in
+        let (got, warnings) = parse("[#6;!^3]");
+        let want = Atom::new(6, None, 0, Chiral::None, None);
+        assert_eq!(
+            got[0],
+            Expr::Atom(Atom {
+                explicit_charge: false,
+                ..want
+            })
+        );
+        assert_eq!(warnings.len(), 1);
+        let (got, _) = parse("[#6,^3;+]");
+        assert_eq!(
+            got[0],
+            Expr::Atom(Atom::new(None, None, 1, Chiral::None, None))
+        );
+
+        let got = Parser::new(scan("[#6;]".to_owned()).unwrap()).parse();
+        assert_eq!(got.unwrap_err().kind, ParseErrorKind::AtomComponent);
+    }
+
+    #[test]
+    fn counts() {
+        use AtomPrimitive as P;
+        let expr = |s: &str| {
+            let got =
+                Smarts::try_parse_as(s.to_owned(), InputKind::Smarts).unwrap();
+            got.atoms[0].expr.clone().unwrap()
+        };
+        let p = |p| AtomExpr::Primitive(p);
+        assert_eq!(
+            expr("[#6X4:1]-[#1:2]"),
+            AtomExpr::And(vec![p(P::AtomicNumber(6)), p(P::Connectivity(4))])
+        );
+        assert_eq!(
+            expr("[#6;r6]"),
+            AtomExpr::And(vec![p(P::AtomicNumber(6)), p(P::SmallestRing(6))])
+        );
+        assert_eq!(
+            expr("[#6h1D3v4x2R]"),
+            AtomExpr::And(vec![
+                p(P::AtomicNumber(6)),
+                p(P::ImplicitHydrogens(1)),
+                p(P::Degree(3)),
+                p(P::Valence(4)),
+                p(P::RingConnectivity(2)),
+                p(P::InRing),
+            ])
+        );
+        assert_eq!(
+            expr("[#6R0r0]"),
+            AtomExpr::And(vec![
+                p(P::AtomicNumber(6)),
+                p(P::RingCount(0)),
+                AtomExpr::Not(Box::new(p(P::InRing))),
+            ])
+        );
+        // bare counts default to 1, like H
+        assert_eq!(
+            expr("[*D]"),
+            AtomExpr::And(vec![AtomExpr::And(Vec::new()), p(P::Degree(1))])
+        );
+        // aromaticity alone still fits in the atom fields
+        let got = Smarts::try_parse_as("[#6;a]".to_owned(), InputKind::Smarts)
+            .unwrap();
+        assert!(got.atoms[0].aromatic && got.atoms[0].expr.is_none());
+    }
+
+    #[test]
+    fn recursive() {
+        use AtomPrimitive as P;
+        let parse = |s: &str| Parser::new(scan(s.to_owned()).unwrap()).parse();
+        let got = parse("[#6$(C=O)H3]-[#8]").unwrap();
+        let carbonyl =
+            Smarts::parse_as("[C]=[O]".to_owned(), InputKind::Smarts);
+        let Expr::Atom(a) = &got[0] else { panic!() };
+        assert_eq!(
+            a.expr,
+            Some(AtomExpr::And(vec![
+                AtomExpr::Primitive(P::AtomicNumber(6)),
+                AtomExpr::Primitive(P::Recursive(Box::new(Query::from(
+                    &carbonyl
+                )))),
+                AtomExpr::Primitive(P::Hydrogens(3)),
+            ]))
+        );
+        // the nested atoms belong to the recursive pattern alone
+        let mut parser =
+            Parser::new(scan("[#6$(CO)]-[#8]".to_owned()).unwrap());
+        parser.parse().unwrap();
+        assert_eq!(parser.n_atoms, 2);
+
+        for s in ["[#6$()]", "[#6$C]", "[$(C1CC)]"] {
+            assert_eq!(parse(s).unwrap_err().kind, ParseErrorKind::Recursive);
+        }
     }
 
     #[test]
@@ -261,8 +966,8 @@ mod tests {
             Expr::Atom(Atom::new(8, 1, 0, Chiral::None, 11)),
         ]];
         for (i, smile) in smiles.into_iter().enumerate() {
-            let smarts = to_smarts(smile.to_owned());
-            let got = Parser::new(scan(smarts)).parse();
+            let smarts = to_smarts(smile.to_owned()).unwrap();
+            let got = Parser::new(scan(smarts).unwrap()).parse().unwrap();
             let want = wants[i].clone();
             assert_eq!(got, want);
         }
@@ -274,9 +979,9 @@ mod tests {
             Dataset::load("testfiles/opt.json").unwrap().to_smiles();
         smiles.dedup();
         for smile in smiles {
-            let smarts = to_smarts(smile);
-            let tokens = scan(smarts);
-            Parser::new(tokens).parse();
+            let smarts = to_smarts(smile).unwrap();
+            let tokens = scan(smarts).unwrap();
+            Parser::new(tokens).parse().unwrap();
         }
     }
 }
@@ -1,4 +1,6 @@
-use std::{iter::Peekable, str::Chars};
+use std::{fmt::Display, iter::Peekable, str::CharIndices};
+
+use crate::elements;
 
 #[derive(Clone, Debug, PartialEq)]
 pub(super) enum Token {
@@ -11,9 +13,34 @@ pub(super) enum Token {
     Dash, // could be bond or charge at this point
     At,
     AtAt,
+    /// the start of a recursive SMARTS, `$(`, whose `(` is its own token
+    Dollar,
+    // logical operators, from highest to lowest precedence
+    Bang,
+    Ampersand,
+    Comma,
+    Semicolon,
     // counts
+    /// an uppercase, aliphatic element symbol like `C` or `Cl`, holding its
+    /// atomic number
     Atom(usize),
+    /// an atomic number written as `#6`, which matches either aromatic or
+    /// aliphatic atoms
+    AtomicNumber(usize),
+    /// a lowercase aromatic element symbol, holding its atomic number
+    AromaticAtom(usize),
+    /// the wildcard atom `*`, which matches any element
+    Wildcard,
     HCount(usize),
+    /// one of the other count primitives inside brackets, `D`, `X`, `v`, `h`,
+    /// `R`, `r`, or `x`, holding its letter and its count, if one is written
+    Count(char, Option<usize>),
+    /// `a`, any aromatic atom
+    Aromatic,
+    /// `A`, any aliphatic atom
+    Aliphatic,
+    /// a mass number at the start of a bracket atom, like the 13 in `[13C]`
+    Isotope(usize),
     Digit(usize),
     Plus(usize),
     // bonds
@@ -21,6 +48,14 @@ pub(super) enum Token {
     TripleBond,
     UpBond,
     DownBond,
+    /// the any bond `~`
+    Tilde,
+    /// an unrecognized character and any digits following it, starting at
+    /// character `offset` of the input. only produced by [scan_lossy]
+    Unknown {
+        text: String,
+        offset: usize,
+    },
     // end
     End,
 }
@@ -35,19 +70,136 @@ impl Token {
     }
 }
 
-fn get_digits(chars: &mut Peekable<Chars<'_>>) -> String {
+/// The reasons scanning can fail, as reported by
+/// [crate::smarts::Smarts::try_parse]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanError {
+    /// `character`, at byte `offset` of the input, doesn't start any token
+    Unrecognized { character: char, offset: usize },
+    /// the count in the token at byte `offset` is too large to represent
+    Overflow { offset: usize },
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::Unrecognized { character, offset } => {
+                write!(
+                    f,
+                    "unrecognized character {character:?} at byte {offset}"
+                )
+            }
+            ScanError::Overflow { offset } => {
+                write!(f, "count too large at byte {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// The elements that may be written outside of brackets
+const ORGANIC: [&str; 10] =
+    ["B", "C", "N", "O", "P", "S", "F", "Cl", "Br", "I"];
+
+/// The aromatic element symbols and their atomic numbers. only the first six
+/// may be written outside of brackets
+const AROMATIC: [(&str, usize); 9] = [
+    ("b", 5),
+    ("c", 6),
+    ("n", 7),
+    ("o", 8),
+    ("p", 15),
+    ("s", 16),
+    ("as", 33),
+    ("se", 34),
+    ("te", 52),
+];
+
+/// The letters of the count primitives scanned as [Token::Count]
+const COUNTS: [char; 7] = ['D', 'X', 'v', 'h', 'R', 'r', 'x'];
+
+/// the element symbol starting with the letter `c`, extended by the next
+/// character if that makes a longer symbol, so `Cl` is chlorine rather than
+/// carbon. any element may be written inside brackets, but only the organic
+/// subset outside of them
+fn element(
+    c: char,
+    chars: &mut Peekable<CharIndices<'_>>,
+    in_bracket: bool,
+) -> Option<Token> {
+    if !c.is_ascii_alphabetic() {
+        return None;
+    }
+    let lookup = |sym: &str| {
+        if c.is_ascii_uppercase() {
+            let n = elements::atomic_number(sym)?;
+            (in_bracket || ORGANIC.contains(&sym)).then_some(Token::Atom(n))
+        } else {
+            let n = if in_bracket { AROMATIC.len() } else { 6 };
+            let (_, n) = AROMATIC[..n].iter().find(|(s, _)| *s == sym)?;
+            Some(Token::AromaticAtom(*n))
+        }
+    };
+    if let Some(&(_, d)) = chars.peek() {
+        if d.is_ascii_lowercase() {
+            if let Some(t) = lookup(&format!("{c}{d}")) {
+                chars.next();
+                return Some(t);
+            }
+        }
+    }
+    lookup(&c.to_string())
+}
+
+/// whether the next character after an H completes a two-letter element
+/// symbol, like the g in Hg
+fn h_element(chars: &Peekable<CharIndices<'_>>) -> bool {
+    chars.clone().next().is_some_and(|(_, d)| {
+        d.is_ascii_lowercase()
+            && elements::atomic_number(&format!("H{d}")).is_some()
+    })
+}
+
+fn get_digits(chars: &mut Peekable<CharIndices<'_>>) -> String {
     let mut digits = String::new();
-    while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
-        digits.push(chars.next().unwrap());
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        digits.push(c);
     }
     digits
 }
 
-pub(super) fn scan(s: String) -> Vec<Token> {
+/// parse `digits`, from the token at byte `offset`, as a count
+fn count(digits: &str, offset: usize) -> Result<usize, ScanError> {
+    digits.parse().map_err(|_| ScanError::Overflow { offset })
+}
+
+pub(super) fn scan(s: String) -> Result<Vec<Token>, ScanError> {
+    scan_inner(s, false)
+}
+
+/// like [scan], but emit [Token::Unknown] for unrecognized characters instead
+/// of failing, leaving it up to the parser to decide what to do with them
+pub(super) fn scan_lossy(s: String) -> Result<Vec<Token>, ScanError> {
+    scan_inner(s, true)
+}
+
+fn scan_inner(s: String, lossy: bool) -> Result<Vec<Token>, ScanError> {
     use Token as T;
-    let mut chars = s.chars().peekable();
+    let mut chars = s.char_indices().peekable();
     let mut ret = Vec::new();
-    while let Some(c) = chars.next() {
+    // the brackets, branches, and recursive SMARTS enclosing the current
+    // character, innermost last. a recursive SMARTS is a whole pattern, so
+    // the atoms in it are outside of brackets again
+    let mut nesting = Vec::new();
+    // whether the last token was the opening bracket of an atom or its
+    // isotope, where H is hydrogen rather than an H count and digits are a
+    // mass number
+    let mut atom_start = false;
+    // whether the last token was a logical operator, after which H followed
+    // by a lowercase letter can start another element, like the Hg in [C,Hg]
+    let mut after_op = false;
+    while let Some((start, c)) = chars.next() {
         let got = match c {
             '[' => T::LBrack,
             ']' => T::RBrack,
@@ -56,16 +208,22 @@ pub(super) fn scan(s: String) -> Vec<Token> {
             ':' => T::Colon,
             '-' => T::Dash,
             '@' => {
-                if chars.peek().is_some_and(|c| *c == '@') {
-                    chars.next();
+                if chars.next_if(|(_, c)| *c == '@').is_some() {
                     T::AtAt
                 } else {
                     T::At
                 }
             }
+            '$' => T::Dollar,
+            '!' => T::Bang,
+            '&' => T::Ampersand,
+            ',' => T::Comma,
+            ';' => T::Semicolon,
+            '*' => T::Wildcard,
             '=' => T::DoubleBond,
             '\\' => T::DownBond,
             '/' => T::UpBond,
+            '~' => T::Tilde,
             '#' => {
                 // # can either be a number inside of an atom, eg [#6], or a
                 // triple bond. at some point we might have to improve this
@@ -74,21 +232,107 @@ pub(super) fn scan(s: String) -> Vec<Token> {
                 if digits.is_empty() {
                     T::TripleBond
                 } else {
-                    T::Atom(digits.parse().unwrap())
+                    T::AtomicNumber(count(&digits, start)?)
+                }
+            }
+            // H right after the opening bracket is hydrogen, or another
+            // element like Hg, unless it's followed by a count. after a
+            // logical operator, it's only an element with a two-letter symbol
+            'H' | '+'
+                if c == '+'
+                    || !(atom_start || after_op && h_element(&chars))
+                    || chars
+                        .peek()
+                        .is_some_and(|(_, d)| d.is_ascii_digit()) =>
+            {
+                let digits = get_digits(&mut chars);
+                let n = if digits.is_empty() {
+                    1
+                } else {
+                    count(&digits, start)?
+                };
+                if c == 'H' {
+                    T::HCount(n)
+                } else {
+                    T::Plus(n)
                 }
             }
-            'H' => T::HCount(get_digits(&mut chars).parse().unwrap_or(1)),
-            '+' => T::Plus(get_digits(&mut chars).parse().unwrap_or(1)),
-            '0'..='9' => T::Digit(
+            // outside of brackets, each digit is its own ring closure label,
+            // and two-digit labels are written with %
+            '0'..='9' if nesting.last() != Some(&T::LBrack) => {
+                T::Digit(c.to_digit(10).unwrap() as usize)
+            }
+            '%' if nesting.last() != Some(&T::LBrack)
+                && chars
+                    .clone()
+                    .take(2)
+                    .filter(|(_, d)| d.is_ascii_digit())
+                    .count()
+                    == 2 =>
+            {
+                let digits: String =
+                    chars.by_ref().take(2).map(|(_, d)| d).collect();
+                T::Digit(count(&digits, start)?)
+            }
+            '0'..='9' => {
                 // combine the digit in c with any following digits
-                format!("{c}{}", get_digits(&mut chars)).parse().unwrap(),
-            ),
-            _ => panic!("unrecognized token {c} in \n{s}"),
+                let n =
+                    count(&format!("{c}{}", get_digits(&mut chars)), start)?;
+                if atom_start {
+                    T::Isotope(n)
+                } else {
+                    T::Digit(n)
+                }
+            }
+            _ => {
+                let in_bracket = nesting.last() == Some(&T::LBrack);
+                match element(c, &mut chars, in_bracket) {
+                    Some(t) => t,
+                    // element symbols take precedence, so the R in [Rb] is
+                    // rubidium rather than a ring count
+                    None if in_bracket && COUNTS.contains(&c) => {
+                        let digits = get_digits(&mut chars);
+                        let n = if digits.is_empty() {
+                            None
+                        } else {
+                            Some(count(&digits, start)?)
+                        };
+                        T::Count(c, n)
+                    }
+                    None if in_bracket && c == 'a' => T::Aromatic,
+                    None if in_bracket && c == 'A' => T::Aliphatic,
+                    None if lossy => T::Unknown {
+                        text: format!("{c}{}", get_digits(&mut chars)),
+                        offset: s[..start].chars().count(),
+                    },
+                    None => {
+                        return Err(ScanError::Unrecognized {
+                            character: c,
+                            offset: start,
+                        })
+                    }
+                }
+            }
         };
+        match got {
+            T::LBrack => nesting.push(T::LBrack),
+            T::LParen if ret.last() == Some(&T::Dollar) => {
+                nesting.push(T::Dollar)
+            }
+            T::LParen => nesting.push(T::LParen),
+            T::RBrack | T::RParen => {
+                nesting.pop();
+            }
+            _ => {}
+        }
+        // the element follows the isotope, so H is still hydrogen there
+        atom_start = matches!(got, T::LBrack | T::Isotope(_));
+        after_op =
+            matches!(got, T::Comma | T::Semicolon | T::Ampersand | T::Bang);
         ret.push(got);
     }
     ret.push(T::End);
-    ret
+    Ok(ret)
 }
 
 #[cfg(test)]
@@ -100,7 +344,182 @@ mod tests {
     #[test]
     fn simple_scan() {
         let s = r#"[#6H3:1]-[#6H2:2]-[#7H:3]-[#7H:4]-[#6H3:5]"#;
-        scan(s.to_owned());
+        scan(s.to_owned()).unwrap();
+        let got = scan("[#6H3]?".to_owned()).unwrap_err();
+        assert_eq!(
+            got,
+            ScanError::Unrecognized {
+                character: '?',
+                offset: 6
+            }
+        );
+        // multi-byte characters are reported whole
+        let got = scan("[#6H3]→".to_owned()).unwrap_err();
+        assert_eq!(got.to_string(), "unrecognized character '→' at byte 6");
+        // ring closures outside of brackets are single digits or % and two
+        // digits, while numbers inside of brackets can be longer
+        use Token as T;
+        assert_eq!(
+            scan("[#6]%10".to_owned()),
+            Ok(vec![
+                T::LBrack,
+                T::AtomicNumber(6),
+                T::RBrack,
+                T::Digit(10),
+                T::End
+            ])
+        );
+        assert_eq!(
+            scan("[#6:12]12".to_owned()).unwrap()[3..],
+            [T::Digit(12), T::RBrack, T::Digit(1), T::Digit(2), T::End]
+        );
+        assert_eq!(
+            scan("[#6]%1".to_owned()),
+            Err(ScanError::Unrecognized {
+                character: '%',
+                offset: 4
+            })
+        );
+        let got = scan("[#6][#99999999999999999999]".to_owned()).unwrap_err();
+        assert_eq!(got, ScanError::Overflow { offset: 5 });
+    }
+
+    #[test]
+    fn elements() {
+        use Token as T;
+        let scan = |s: &str| {
+            let mut got = scan(s.to_owned()).unwrap();
+            got.pop();
+            got
+        };
+        assert_eq!(
+            scan("[Cl-]Br"),
+            [T::LBrack, T::Atom(17), T::Dash, T::RBrack, T::Atom(35)]
+        );
+        assert_eq!(
+            scan("[nH]"),
+            [T::LBrack, T::AromaticAtom(7), T::HCount(1), T::RBrack]
+        );
+        assert_eq!(scan("[se]")[1], T::AromaticAtom(34));
+        // two-letter symbols outside of brackets are only Cl and Br
+        assert_eq!(
+            scan("[Sc]Sc")[1..],
+            [T::Atom(21), T::RBrack, T::Atom(16), T::AromaticAtom(6)]
+        );
+        // H is hydrogen at the start of an atom and an H count after it
+        assert_eq!(scan("[H+]")[1], T::Atom(1));
+        assert_eq!(scan("[Hg]")[1], T::Atom(80));
+        assert_eq!(scan("[CH3]")[1..3], [T::Atom(6), T::HCount(3)]);
+        assert_eq!(scan("[H2]")[1], T::HCount(2));
+        assert_eq!(scan("[13C]")[1..3], [T::Isotope(13), T::Atom(6)]);
+        assert_eq!(scan("[2H+]")[1..3], [T::Isotope(2), T::Atom(1)]);
+        assert_eq!(scan("[2#1]")[1..3], [T::Isotope(2), T::AtomicNumber(1)]);
+        // after a logical operator, H only starts an element with a
+        // two-letter symbol
+        assert_eq!(scan("[C,Hg]")[1..4], [T::Atom(6), T::Comma, T::Atom(80)]);
+        assert_eq!(scan("[!Hg]")[1..3], [T::Bang, T::Atom(80)]);
+        assert_eq!(scan("[#6;H1]")[3], T::HCount(1));
+        assert_eq!(scan("[#6&H]")[3], T::HCount(1));
+        assert!(super::scan("[#6]Na".to_owned()).is_err());
+        assert_eq!(
+            super::scan("[Jq]".to_owned()),
+            Err(ScanError::Unrecognized {
+                character: 'J',
+                offset: 1
+            })
+        );
+    }
+
+    #[test]
+    fn operators() {
+        use Token as T;
+        let got = scan("[#6&H1,#7;!-]".to_owned()).unwrap();
+        assert_eq!(
+            got,
+            [
+                T::LBrack,
+                T::AtomicNumber(6),
+                T::Ampersand,
+                T::HCount(1),
+                T::Comma,
+                T::AtomicNumber(7),
+                T::Semicolon,
+                T::Bang,
+                T::Dash,
+                T::RBrack,
+                T::End
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        use Token as T;
+        let got = scan("[$(CO),Na]".to_owned()).unwrap();
+        // the atoms of a recursive SMARTS are outside of brackets, so CO is
+        // carbon and oxygen rather than cobalt
+        assert_eq!(
+            got[1..],
+            [
+                T::Dollar,
+                T::LParen,
+                T::Atom(6),
+                T::Atom(8),
+                T::RParen,
+                T::Comma,
+                T::Atom(11),
+                T::RBrack,
+                T::End
+            ]
+        );
+    }
+
+    #[test]
+    fn counts() {
+        use Token as T;
+        let scan = |s: &str| {
+            let mut got = scan(s.to_owned()).unwrap();
+            got.pop();
+            got
+        };
+        assert_eq!(
+            scan("[#6X4:1]")[1..3],
+            [T::AtomicNumber(6), T::Count('X', Some(4))]
+        );
+        assert_eq!(
+            scan("[#6;r6;R;!a;A]")[3..],
+            [
+                T::Count('r', Some(6)),
+                T::Semicolon,
+                T::Count('R', None),
+                T::Semicolon,
+                T::Bang,
+                T::Aromatic,
+                T::Semicolon,
+                T::Aliphatic,
+                T::RBrack
+            ]
+        );
+        assert_eq!(
+            scan("[ch1]")[1..3],
+            [T::AromaticAtom(6), T::Count('h', Some(1))]
+        );
+        // element symbols come first, and counts are only read in brackets
+        assert_eq!(scan("[Rb]")[1], T::Atom(37));
+        assert!(super::scan("[#6]R".to_owned()).is_err());
+    }
+
+    #[test]
+    fn lossy_scan() {
+        let got = scan_lossy("[#6^3:1]".to_owned()).unwrap();
+        assert_eq!(
+            got[2],
+            Token::Unknown {
+                text: "^3".to_owned(),
+                offset: 3
+            }
+        );
+        assert_eq!(got.len(), 7);
     }
 
     #[test]
@@ -109,7 +528,7 @@ mod tests {
             Dataset::load("testfiles/opt.json").unwrap().to_smiles();
         smiles.dedup();
         for smile in smiles {
-            scan(to_smarts(smile));
+            scan(to_smarts(smile).unwrap()).unwrap();
         }
     }
 }
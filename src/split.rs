@@ -0,0 +1,155 @@
+//! Reproducible train/test splits of a set of molecules, for building machine
+//! learning benchmarks.
+//!
+//! A split shuffles groups of molecules with a seeded generator and fills
+//! the training set with whole groups until it holds the requested fraction,
+//! so every member of a group lands on the same side. A random split puts
+//! each molecule in a group of its own, while a scaffold split groups
+//! molecules by their Bemis-Murcko [scaffold], so that the test set holds
+//! ring frameworks the training set has never seen
+
+use std::collections::BTreeMap;
+
+use crate::{
+    binning::SplitMix64,
+    canonical::canonical_key,
+    molecule::{MolAtom, MolBond, Molecule},
+    smarts::Chiral,
+};
+
+/// the Bemis-Murcko scaffold of `mol`: its rings and the chains linking them,
+/// left after repeatedly removing atoms with at most one neighbor, as a
+/// [canonical_key] with H counts and stereochemistry dropped. molecules
+/// without rings have the empty scaffold
+pub fn scaffold(mol: &Molecule) -> String {
+    let n = mol.atoms.len();
+    let mut keep = vec![true; n];
+    let mut degree: Vec<usize> = (0..n).map(|i| mol.degree(i)).collect();
+    let mut stack: Vec<usize> = (0..n).filter(|&i| degree[i] <= 1).collect();
+    while let Some(i) = stack.pop() {
+        if !keep[i] {
+            continue;
+        }
+        keep[i] = false;
+        for j in mol.neighbors(i) {
+            if keep[j] {
+                degree[j] -= 1;
+                if degree[j] == 1 {
+                    stack.push(j);
+                }
+            }
+        }
+    }
+    let atoms: Vec<usize> = (0..n).filter(|&i| keep[i]).collect();
+    if atoms.is_empty() {
+        return String::new();
+    }
+    let index: BTreeMap<usize, usize> =
+        atoms.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+    let new_atoms = atoms
+        .iter()
+        .map(|&a| MolAtom {
+            isotope: None,
+            n_hydrogens: 0,
+            chirality: Chiral::None,
+            mol_index: None,
+            ..mol.atoms[a].clone()
+        })
+        .collect();
+    let bonds = mol
+        .bonds
+        .iter()
+        .filter_map(|b| {
+            Some(MolBond {
+                atom1: *index.get(&b.atom1)?,
+                atom2: *index.get(&b.atom2)?,
+                bond_type: b.bond_type,
+                direction: None,
+            })
+        })
+        .collect();
+    canonical_key(&Molecule::new(new_atoms, bonds))
+}
+
+/// split the items labeled by `groups` into a training and a test set,
+/// returning whether each item is in the training set. the groups are
+/// shuffled with `seed` and added to the training set whole until it holds
+/// at least `fraction` of the items, so with large groups it can overshoot.
+/// the same `groups` and `seed` always give the same split
+pub fn split_groups<K: Ord>(
+    groups: &[K],
+    fraction: f64,
+    seed: u64,
+) -> Vec<bool> {
+    let mut members: BTreeMap<&K, Vec<usize>> = BTreeMap::new();
+    for (i, g) in groups.iter().enumerate() {
+        members.entry(g).or_default().push(i);
+    }
+    let mut members: Vec<Vec<usize>> = members.into_values().collect();
+    let mut rng = SplitMix64(seed);
+    for i in (1..members.len()).rev() {
+        let j = (rng.next() % (i + 1) as u64) as usize;
+        members.swap(i, j);
+    }
+    let target = (fraction.clamp(0.0, 1.0) * groups.len() as f64).round();
+    let mut ret = vec![false; groups.len()];
+    let mut n = 0;
+    for group in members {
+        if n as f64 >= target {
+            break;
+        }
+        n += group.len();
+        for i in group {
+            ret[i] = true;
+        }
+    }
+    ret
+}
+
+/// like [split_groups], but with every molecule in `mols` grouped by its
+/// [scaffold]
+pub fn scaffold_split(
+    mols: &[Molecule],
+    fraction: f64,
+    seed: u64,
+) -> Vec<bool> {
+    let scaffolds: Vec<String> = mols.iter().map(scaffold).collect();
+    split_groups(&scaffolds, fraction, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn splits() {
+        let benzene = "[cH]1:[cH]:[cH]:[cH]:[cH]:[cH]:1";
+        let toluene = mol("[#6H3]-[c]1:[cH]:[cH]:[cH]:[cH]:[cH]:1");
+        assert_eq!(scaffold(&toluene), scaffold(&mol(benzene)));
+        let biphenyl = mol(
+            "[c]1(:[cH]:[cH]:[cH]:[cH]:[cH]:1)-[c]1:[cH]:[cH]:[cH]:[cH]:[cH]:1",
+        );
+        assert_ne!(scaffold(&biphenyl), scaffold(&toluene));
+        assert_eq!(scaffold(&mol("[#6H3]-[#6H2]-[#8H]")), "");
+
+        let items: Vec<usize> = (0..10).collect();
+        let got = split_groups(&items, 0.8, 42);
+        assert_eq!(got.iter().filter(|&&t| t).count(), 8);
+        assert_eq!(got, split_groups(&items, 0.8, 42));
+        assert_ne!(got, split_groups(&items, 0.8, 7));
+
+        // every member of a group lands on the same side
+        let groups = [0, 0, 0, 1, 1, 2, 3, 3, 3, 3];
+        for seed in 0..10 {
+            let got = split_groups(&groups, 0.5, seed);
+            for (i, g) in groups.iter().enumerate() {
+                let first = groups.iter().position(|h| h == g).unwrap();
+                assert_eq!(got[i], got[first]);
+            }
+        }
+        assert!(split_groups(&groups, 0.0, 1).iter().all(|&t| !t));
+        assert!(split_groups(&groups, 1.0, 1).iter().all(|&t| t));
+    }
+}
@@ -0,0 +1,141 @@
+//! Summary statistics of a set of molecules, for a quick look at what a
+//! dataset contains before working with it.
+//!
+//! Element counts include implicit hydrogens, and duplicates are the
+//! molecules [unique_smiles] would drop, so stereoisomers are distinct and
+//! atom maps and explicit hydrogens don't matter
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::{
+    canonical::{canonical_smiles, unique_smiles},
+    elements,
+    molecule::Molecule,
+};
+
+/// The statistics computed by [DatasetStats::new]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DatasetStats {
+    pub n_molecules: usize,
+    /// element symbol -> the number of atoms of that element
+    pub elements: BTreeMap<String, usize>,
+    /// heavy-atom count -> the number of molecules with that many heavy atoms
+    pub heavy_atoms: BTreeMap<usize, usize>,
+    /// net charge -> the number of molecules with that charge
+    pub charges: BTreeMap<isize, usize>,
+    /// the number of molecules that repeat an earlier one. molecules rdkit
+    /// can't read are never counted
+    pub n_duplicates: usize,
+    /// the number of molecules rdkit couldn't read while looking for
+    /// duplicates
+    pub n_unreadable: usize,
+}
+
+impl DatasetStats {
+    /// compute the statistics of `mols`
+    pub fn new(mols: &[Molecule]) -> Self {
+        let mut ret = Self {
+            n_molecules: mols.len(),
+            ..Default::default()
+        };
+        for mol in mols {
+            for a in &mol.atoms {
+                let sym = match elements::symbol(a.atomic_number) {
+                    Some(s) => s.to_owned(),
+                    None => format!("#{}", a.atomic_number),
+                };
+                *ret.elements.entry(sym).or_default() += 1;
+            }
+            let h: usize = mol.atoms.iter().map(|a| a.n_hydrogens).sum();
+            if h > 0 {
+                *ret.elements.entry("H".to_owned()).or_default() += h;
+            }
+            *ret.heavy_atoms.entry(mol.n_heavy_atoms()).or_default() += 1;
+            *ret.charges.entry(mol.charge).or_default() += 1;
+        }
+        let (_, n_duplicates, errors) =
+            unique_smiles(mols.iter().map(canonical_smiles));
+        ret.n_duplicates = n_duplicates;
+        ret.n_unreadable = errors.len();
+        ret
+    }
+}
+
+/// Display the molecule, duplicate, and unreadable counts, followed by a table
+/// for each distribution
+impl Display for DatasetStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} molecules", self.n_molecules)?;
+        writeln!(f, "{} duplicates", self.n_duplicates)?;
+        writeln!(f, "{} unreadable", self.n_unreadable)?;
+        writeln!(f, "\n{:>8} {:>9}", "element", "atoms")?;
+        for (sym, n) in &self.elements {
+            writeln!(f, "{sym:>8} {n:>9}")?;
+        }
+        writeln!(f, "\n{:>8} {:>9}", "heavy", "molecules")?;
+        for (size, n) in &self.heavy_atoms {
+            writeln!(f, "{size:>8} {n:>9}")?;
+        }
+        writeln!(f, "\n{:>8} {:>9}", "charge", "molecules")?;
+        for (charge, n) in &self.charges {
+            writeln!(f, "{charge:>8} {n:>9}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let mols = [
+            mol("[#6H3]-[#8H]"),
+            mol("[#8H]-[#6H3]"),
+            mol("[#6H3]-[#6](=[#8])-[#8-]"),
+        ];
+        let got = DatasetStats::new(&mols);
+        assert_eq!(got.n_molecules, 3);
+        assert_eq!(got.n_duplicates, 1);
+        assert_eq!(
+            got.elements,
+            BTreeMap::from([
+                ("C".to_owned(), 4),
+                ("H".to_owned(), 11),
+                ("O".to_owned(), 4),
+            ])
+        );
+        assert_eq!(got.heavy_atoms, BTreeMap::from([(2, 2), (4, 1)]));
+        assert_eq!(got.charges, BTreeMap::from([(-1, 1), (0, 2)]));
+        assert_eq!(got.n_unreadable, 0);
+        assert!(got
+            .to_string()
+            .starts_with("3 molecules\n1 duplicates\n0 unreadable\n"));
+
+        let json = serde_json::to_value(&got).unwrap();
+        assert_eq!(json["charges"]["-1"], 1);
+        assert_eq!(json["elements"]["H"], 11);
+    }
+
+    #[test]
+    fn duplicates() {
+        // L- and D-alanine, and L-alanine again with atom maps
+        let mols = [
+            mol("[#7H2][#6@@H]([#6H3])[#6](=[#8])[#8H]"),
+            mol("[#7H2][#6@H]([#6H3])[#6](=[#8])[#8H]"),
+            mol("[#7H2:1][#6@@H:2]([#6H3:3])[#6:4](=[#8:5])[#8H:6]"),
+            mol("[#6H3]-[#8]-[#1]"),
+            mol("[#6H3]-[#8H]"),
+            // pentavalent carbon, which rdkit rejects
+            mol("[#6H5]"),
+        ];
+        let got = DatasetStats::new(&mols);
+        assert_eq!(got.n_duplicates, 2);
+        assert_eq!(got.n_unreadable, 1);
+    }
+}
@@ -0,0 +1,513 @@
+//! Stereochemistry labels for [Molecule]s: R and S for tetrahedral centers
+//! and E and Z for double bonds.
+//!
+//! chomper stores stereochemistry the way SMILES writes it. A chirality tag
+//! says whether the neighbors of an atom, in the order they were written, go
+//! clockwise or anticlockwise, and a double bond's geometry is given by the
+//! directions of the single bonds next to it. [smiles_neighbors] recovers the
+//! written neighbor order from the bonds and ring closures of a molecule: the
+//! atom the center was reached from, then its implicit hydrogen, its ring
+//! closures, and finally its branches and the rest of its chain. When one
+//! atom opens several rings, they are taken to be written in the order they
+//! close.
+//!
+//! Neighbors are ranked by a simplified form of the Cahn-Ingold-Prelog rules:
+//! only the atomic numbers in the hierarchical digraph are compared, sphere by
+//! sphere, with duplicate atoms for double and triple bonds and ring
+//! closures. Within a sphere, the sets of atoms are compared one at a time, in
+//! the order of precedence of the atoms they branch from. Aromatic bonds count
+//! as single bonds. This settles most centers in drug-like molecules, but a
+//! center that can only be ranked by isotopes or by the stereochemistry of its
+//! neighbors gets no label
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    molecule::{BondType, Direction, MolBond, Molecule},
+    smarts::Chiral,
+};
+
+/// The stand-in for a center's implicit hydrogen in [smiles_neighbors]
+pub const IMPLICIT_H: usize = usize::MAX;
+
+/// The largest number of atoms of the digraph that [rank] expands at each
+/// depth before giving up
+const MAX_NODES: usize = 1 << 16;
+
+/// A tetrahedral CIP label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Cip {
+    R,
+    S,
+}
+
+/// A double-bond CIP label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BondStereo {
+    E,
+    Z,
+}
+
+fn is_closure(mol: &Molecule, bond: usize) -> bool {
+    mol.ring_closures.binary_search(&bond).is_ok()
+}
+
+/// the neighbors of atom `i` in the order they were written, as described in
+/// the module docs, with [IMPLICIT_H] for an implicit hydrogen
+pub fn smiles_neighbors(mol: &Molecule, i: usize) -> Vec<usize> {
+    let mut before = Vec::new();
+    let mut closures = Vec::new();
+    let mut after = Vec::new();
+    for (b, bond) in mol.bonds.iter().enumerate() {
+        let j = match (bond.atom1, bond.atom2) {
+            (a, j) if a == i => j,
+            (j, a) if a == i => j,
+            _ => continue,
+        };
+        if is_closure(mol, b) {
+            closures.push((j > i, b, j));
+        } else if j < i {
+            before.push(j);
+        } else {
+            after.push(j);
+        }
+    }
+    // closing labels come first, then the ones opened here in closing order
+    closures.sort();
+    after.sort();
+    let mut ret = before;
+    ret.extend(std::iter::repeat_n(IMPLICIT_H, mol.atoms[i].n_hydrogens));
+    ret.extend(closures.into_iter().map(|(_, _, j)| j));
+    ret.extend(after);
+    ret
+}
+
+/// A node in the hierarchical digraph: either a real atom with the path of
+/// atoms leading to it, or a duplicate or hydrogen with no children
+struct Node {
+    atomic_number: usize,
+    path: Option<Vec<usize>>,
+}
+
+/// the number of duplicate atoms a bond of type `t` adds at each end
+fn duplicates(t: BondType) -> usize {
+    match t {
+        BondType::Double => 1,
+        BondType::Triple => 2,
+        BondType::Single | BondType::Aromatic => 0,
+    }
+}
+
+/// the children of the real atom at the end of `path`
+fn children(mol: &Molecule, path: &[usize]) -> Vec<Node> {
+    let &atom = path.last().unwrap();
+    let parent = path.len().checked_sub(2).map(|p| path[p]);
+    let mut ret = Vec::new();
+    for bond in &mol.bonds {
+        let j = match (bond.atom1, bond.atom2) {
+            (a, j) if a == atom => j,
+            (j, a) if a == atom => j,
+            _ => continue,
+        };
+        let z = mol.atoms[j].atomic_number;
+        // a multiple bond adds duplicates of `j` here, including when `j` is
+        // the parent and has no other child on this side
+        ret.extend((0..duplicates(bond.bond_type)).map(|_| Node {
+            atomic_number: z,
+            path: None,
+        }));
+        if Some(j) == parent {
+            continue;
+        }
+        let path = (!path.contains(&j)).then(|| {
+            let mut p = path.to_vec();
+            p.push(j);
+            p
+        });
+        ret.push(Node {
+            atomic_number: z,
+            path,
+        });
+    }
+    ret.extend((0..mol.atoms[atom].n_hydrogens).map(|_| Node {
+        atomic_number: 1,
+        path: None,
+    }));
+    ret
+}
+
+/// The spheres of the digraph below a node, starting with the node itself.
+/// Each sphere holds a set of atomic numbers, from highest to lowest, for each
+/// atom in the sphere above, in their order of precedence
+type Key = Vec<Vec<Vec<usize>>>;
+
+/// compare two keys sphere by sphere and set by set. missing atoms, like the
+/// children of a duplicate, count as atomic number 0
+fn compare(a: &Key, b: &Key) -> Ordering {
+    let get = |k: &[usize], i: usize| k.get(i).copied().unwrap_or(0);
+    for d in 0..a.len().max(b.len()) {
+        let (sa, sb) = (a.get(d), b.get(d));
+        let n = sa.map_or(0, Vec::len).max(sb.map_or(0, Vec::len));
+        for g in 0..n {
+            let ga = sa.and_then(|s| s.get(g)).map_or(&[][..], Vec::as_slice);
+            let gb = sb.and_then(|s| s.get(g)).map_or(&[][..], Vec::as_slice);
+            for k in 0..ga.len().max(gb.len()) {
+                match get(ga, k).cmp(&get(gb, k)) {
+                    Ordering::Equal => {}
+                    o => return o,
+                }
+            }
+        }
+    }
+    Ordering::Equal
+}
+
+/// the [Key] of `node`, expanded `depth` spheres down. `budget` is the
+/// number of nodes left to expand, and `cut` is set if a real atom was left
+/// unexpanded at the last sphere
+fn key(
+    mol: &Molecule,
+    node: &Node,
+    depth: usize,
+    budget: &mut usize,
+    cut: &mut bool,
+) -> Option<Key> {
+    let mut ret = vec![vec![vec![node.atomic_number]]];
+    let Some(path) = &node.path else {
+        ret.push(vec![Vec::new()]);
+        return Some(ret);
+    };
+    if depth == 0 {
+        *cut = true;
+        return Some(ret);
+    }
+    let kids = children(mol, path);
+    *budget = budget.checked_sub(kids.len())?;
+    let mut keys = kids
+        .iter()
+        .map(|c| key(mol, c, depth - 1, budget, cut))
+        .collect::<Option<Vec<_>>>()?;
+    keys.sort_by(|a, b| compare(b, a));
+    ret.push(vec![keys.iter().map(|k| k[0][0][0]).collect()]);
+    for d in 1..depth {
+        let sphere: Vec<_> = keys
+            .iter()
+            .filter_map(|k| k.get(d))
+            .flatten()
+            .cloned()
+            .collect();
+        if sphere.is_empty() {
+            break;
+        }
+        ret.push(sphere);
+    }
+    Some(ret)
+}
+
+/// rank the `neighbors` of `center`, as returned by [smiles_neighbors] but in
+/// any order, returning the rank of each one with 0 for the highest priority,
+/// or `None` if any two of them can't be told apart
+pub fn rank(
+    mol: &Molecule,
+    center: usize,
+    neighbors: &[usize],
+) -> Option<Vec<usize>> {
+    let roots: Vec<Node> = neighbors
+        .iter()
+        .map(|&n| {
+            if n == IMPLICIT_H {
+                Node {
+                    atomic_number: 1,
+                    path: None,
+                }
+            } else {
+                Node {
+                    atomic_number: mol.atoms[n].atomic_number,
+                    path: Some(vec![center, n]),
+                }
+            }
+        })
+        .collect();
+    // the keys are only exact up to the depth they were expanded to, so go one
+    // sphere deeper until that tells the branches apart
+    for depth in 0.. {
+        let mut budget = MAX_NODES;
+        let mut cut = false;
+        let keys = roots
+            .iter()
+            .map(|r| key(mol, r, depth, &mut budget, &mut cut))
+            .collect::<Option<Vec<_>>>()?;
+        let keys: Vec<_> = keys
+            .into_iter()
+            .map(|k| k[..k.len().min(depth + 1)].to_vec())
+            .collect();
+        let distinct = (0..keys.len()).all(|i| {
+            (i + 1..keys.len())
+                .all(|j| compare(&keys[i], &keys[j]) != Ordering::Equal)
+        });
+        if distinct {
+            let ret = keys
+                .iter()
+                .map(|k| {
+                    keys.iter()
+                        .filter(|o| compare(o, k) == Ordering::Greater)
+                        .count()
+                })
+                .collect();
+            return Some(ret);
+        }
+        if !cut {
+            return None;
+        }
+    }
+    None
+}
+
+/// whether `ranks` is an odd permutation of `0..ranks.len()`
+pub fn is_odd(ranks: &[usize]) -> bool {
+    let mut inversions = 0;
+    for i in 0..ranks.len() {
+        for j in i + 1..ranks.len() {
+            inversions += usize::from(ranks[i] > ranks[j]);
+        }
+    }
+    inversions % 2 == 1
+}
+
+/// the opposite chirality tag to `c`
+pub fn flip(c: &Chiral) -> Chiral {
+    match c {
+        Chiral::Cw => Chiral::Acw,
+        Chiral::Acw => Chiral::Cw,
+        Chiral::None => Chiral::None,
+    }
+}
+
+/// the CIP label of atom `i` from its chirality tag, or `None` if it has no
+/// tag, does not have four neighbors, or its neighbors can't be ranked
+pub fn cip_label(mol: &Molecule, i: usize) -> Option<Cip> {
+    let tag = &mol.atoms[i].chirality;
+    if *tag == Chiral::None {
+        return None;
+    }
+    let neighbors = smiles_neighbors(mol, i);
+    if neighbors.len() != 4 {
+        return None;
+    }
+    let ranks = rank(mol, i, &neighbors)?;
+    let tag = if is_odd(&ranks) {
+        flip(tag)
+    } else {
+        tag.clone()
+    };
+    // anticlockwise from the highest priority neighbor, in priority order
+    match tag {
+        Chiral::Acw => Some(Cip::S),
+        Chiral::Cw => Some(Cip::R),
+        Chiral::None => None,
+    }
+}
+
+/// the chirality tag that gives atom `i` the CIP label `label`, or `None` if
+/// `i` can't be a tetrahedral center as in [cip_label]
+pub fn chirality_for(mol: &Molecule, i: usize, label: Cip) -> Option<Chiral> {
+    let neighbors = smiles_neighbors(mol, i);
+    if neighbors.len() != 4 {
+        return None;
+    }
+    let ranks = rank(mol, i, &neighbors)?;
+    let tag = match label {
+        Cip::S => Chiral::Acw,
+        Cip::R => Chiral::Cw,
+    };
+    Some(if is_odd(&ranks) { flip(&tag) } else { tag })
+}
+
+fn flip_dir(d: Direction) -> Direction {
+    match d {
+        Direction::Up => Direction::Down,
+        Direction::Down => Direction::Up,
+    }
+}
+
+/// the substituents of `a` other than `b`, and whether the first is the one
+/// with the higher priority, or `None` if they can't be ranked
+fn substituents(
+    mol: &Molecule,
+    a: usize,
+    b: usize,
+) -> Option<(Vec<usize>, bool)> {
+    let subs: Vec<usize> = smiles_neighbors(mol, a)
+        .into_iter()
+        .filter(|&n| n != b)
+        .collect();
+    match subs.len() {
+        1 => Some((subs, true)),
+        2 => {
+            let ranks = rank(mol, a, &subs)?;
+            Some((subs, ranks[0] < ranks[1]))
+        }
+        _ => None,
+    }
+}
+
+/// the direction of `bond` as seen going from atom `from` to its other atom
+fn direction_from(bond: &MolBond, from: usize) -> Option<Direction> {
+    let d = bond.direction?;
+    Some(if bond.atom1 == from { d } else { flip_dir(d) })
+}
+
+/// the position of the bond between `a` and `b` and the bond itself
+fn bond_to(mol: &Molecule, a: usize, b: usize) -> Option<(usize, &MolBond)> {
+    mol.bonds.iter().enumerate().find(|(_, bond)| {
+        (bond.atom1, bond.atom2) == (a, b) || (bond.atom1, bond.atom2) == (b, a)
+    })
+}
+
+/// the E or Z label of the double bond at position `bond`, from the
+/// directions of the single bonds around it, or `None` if it isn't a double
+/// bond, has no directional neighbors on one side, or has two substituents on
+/// one side that can't be ranked
+pub fn bond_label(mol: &Molecule, bond: usize) -> Option<BondStereo> {
+    let db = &mol.bonds[bond];
+    if db.bond_type != BondType::Double {
+        return None;
+    }
+    let (a, b) = (db.atom1, db.atom2);
+    let (left, left_high) = substituents(mol, a, b)?;
+    let (right, right_high) = substituents(mol, b, a)?;
+    // the first directional bond on each side, as a substituent index and its
+    // direction going left to right across the double bond
+    let side = |subs: &[usize], center: usize, toward: bool| {
+        subs.iter().enumerate().find_map(|(k, &x)| {
+            if x == IMPLICIT_H {
+                return None;
+            }
+            let (_, bond) = bond_to(mol, center, x)?;
+            let d = if toward {
+                direction_from(bond, x)?
+            } else {
+                direction_from(bond, center)?
+            };
+            Some((k, d))
+        })
+    };
+    let (l, dl) = side(&left, a, true)?;
+    let (r, dr) = side(&right, b, false)?;
+    let trans = dl == dr;
+    // whether each of the two substituents is the higher priority one
+    let lh = (l == 0) == left_high;
+    let rh = (r == 0) == right_high;
+    Some(if trans == (lh == rh) {
+        BondStereo::E
+    } else {
+        BondStereo::Z
+    })
+}
+
+/// set the directions of the single bonds around the double bond at position
+/// `bond` to give it the label `label`, returning whether that was possible.
+/// bonds that already have a direction, such as those shared with a
+/// neighboring double bond, are kept
+pub fn set_bond_label(
+    mol: &mut Molecule,
+    bond: usize,
+    label: BondStereo,
+) -> bool {
+    let db = mol.bonds[bond].clone();
+    if db.bond_type != BondType::Double {
+        return false;
+    }
+    let (a, b) = (db.atom1, db.atom2);
+    let Some((left, left_high)) = substituents(mol, a, b) else {
+        return false;
+    };
+    let Some((right, right_high)) = substituents(mol, b, a) else {
+        return false;
+    };
+    let real = |subs: &[usize]| subs.iter().position(|&x| x != IMPLICIT_H);
+    let (Some(l), Some(r)) = (real(&left), real(&right)) else {
+        return false;
+    };
+    let (lb, lbond) = bond_to(mol, a, left[l]).unwrap();
+    let (rb, rbond) = bond_to(mol, b, right[r]).unwrap();
+    let lh = (l == 0) == left_high;
+    let rh = (r == 0) == right_high;
+    let trans = (label == BondStereo::E) == (lh == rh);
+    let dl = direction_from(lbond, left[l]);
+    let dr = direction_from(rbond, b);
+    let (dl, dr) = match (dl, dr) {
+        (Some(dl), Some(dr)) => return (dl == dr) == trans,
+        (Some(dl), None) => (dl, if trans { dl } else { flip_dir(dl) }),
+        (None, Some(dr)) => (if trans { dr } else { flip_dir(dr) }, dr),
+        (None, None) => {
+            let dl = Direction::Up;
+            (dl, if trans { dl } else { flip_dir(dl) })
+        }
+    };
+    let orient = |bond: &MolBond, from: usize, d: Direction| {
+        if bond.atom1 == from {
+            d
+        } else {
+            flip_dir(d)
+        }
+    };
+    let ld = orient(&mol.bonds[lb], left[l], dl);
+    let rd = orient(&mol.bonds[rb], b, dr);
+    mol.bonds[lb].direction = Some(ld);
+    mol.bonds[rb].direction = Some(rd);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn labels() {
+        // L-alanine, N[C@@H](C)C(=O)O, is S
+        let ala = "[#7H2][#6@@H]([#6H3])[#6](=[#8])[#8H]";
+        let m = mol(ala);
+        assert_eq!(smiles_neighbors(&m, 1), [0, IMPLICIT_H, 2, 3]);
+        assert_eq!(cip_label(&m, 1), Some(Cip::S));
+        assert_eq!(chirality_for(&m, 1, Cip::S), Some(Chiral::Cw));
+        let m = mol(&ala.replace("@@", "@"));
+        assert_eq!(cip_label(&m, 1), Some(Cip::R));
+        assert_eq!(cip_label(&m, 0), None);
+
+        // the ring label on the center comes before its branch
+        let m = mol("[#6@@H]1([#9])[#6H2][#6H2]1");
+        assert_eq!(smiles_neighbors(&m, 0), [IMPLICIT_H, 3, 1, 2]);
+
+        // both ring neighbors are CH2, but one is next to the fluorine
+        let m = mol("[#6H2]1[#6H2][#6@H]([#9])[#6H2][#6H]1[#9]");
+        let ranks = rank(&m, 2, &smiles_neighbors(&m, 2)).unwrap();
+        assert_eq!(ranks[0] + ranks[3], 3);
+        // the CH(CH(OH)2)CH3 branch beats CH(CH2OMe)2 at sphere 3, since its
+        // higher branch has {O,O,H} against {O,H,H}, even though the spheres
+        // hold the same atoms overall
+        let m = mol("[#9][#6@H]([#6H]([#6H2][#8][#6H3])[#6H2][#8][#6H3])\
+             [#6H]([#6H]([#8H])[#8H])[#6H3]");
+        let neighbors = smiles_neighbors(&m, 1);
+        assert_eq!(neighbors, [0, IMPLICIT_H, 2, 9]);
+        assert_eq!(rank(&m, 1, &neighbors), Some(vec![0, 3, 2, 1]));
+        assert_eq!(cip_label(&m, 1), Some(Cip::R));
+        // two identical methyls can't be ranked
+        let m = mol("[#6H3][#6@H]([#6H3])[#9]");
+        assert_eq!(cip_label(&m, 1), None);
+
+        // F/C=C/F is trans, so E, and F/C=C\F is Z
+        let m = mol("[#9]/[#6H]=[#6H]/[#9]");
+        assert_eq!(bond_label(&m, 1), Some(BondStereo::E));
+        let m = mol("[#9]/[#6H]=[#6H]\\[#9]");
+        assert_eq!(bond_label(&m, 1), Some(BondStereo::Z));
+        let mut m = mol("[#9][#6H]=[#6H][#9]");
+        assert_eq!(bond_label(&m, 1), None);
+        assert!(set_bond_label(&mut m, 1, BondStereo::Z));
+        assert_eq!(bond_label(&m, 1), Some(BondStereo::Z));
+    }
+}
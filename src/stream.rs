@@ -0,0 +1,204 @@
+//! Reading a JSON [crate::Dataset] one record at a time.
+//!
+//! serde only offers a push interface, so the file is deserialized on a
+//! background thread that hands each record over a bounded channel as soon as
+//! it is read. At most [BUFFERED] records are in memory at once, however
+//! large the file is, and if the [RecordStream] is dropped early the thread
+//! stops reading at its next record
+
+use std::{
+    fmt,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+};
+
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserializer,
+};
+
+use crate::{compress, error::ChomperError, Record};
+
+/// The number of records read ahead of the consumer of a [RecordStream]
+pub const BUFFERED: usize = 64;
+
+type Item = Result<(String, Record), serde_json::Error>;
+
+/// An iterator over the records of a JSON dataset, yielding the name of the
+/// entry each record belongs to alongside the record itself. Records come in
+/// the order they appear in the file, not in the sorted order of
+/// [crate::Dataset]. After an error, the iterator ends
+pub struct RecordStream {
+    rx: Receiver<Item>,
+}
+
+impl RecordStream {
+    /// start reading the dataset at `path`, which may be compressed like the
+    /// input to [crate::Dataset::load]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ChomperError> {
+        let path = path.as_ref().to_owned();
+        let r = BufReader::with_capacity(1 << 16, compress::open(&path)?);
+        let (tx, rx) = sync_channel(BUFFERED);
+        thread::spawn(move || {
+            let mut de = serde_json::Deserializer::from_reader(r);
+            let res = de
+                .deserialize_map(DatasetVisitor {
+                    tx: &tx,
+                    file: &path,
+                })
+                .and_then(|_| de.end());
+            if let Err(e) = res {
+                // the receiver is gone if the error came from sending
+                let _ = tx.send(Err(e));
+            }
+        });
+        Ok(Self { rx })
+    }
+}
+
+impl Iterator for RecordStream {
+    type Item = Result<(String, Record), ChomperError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the reader sends nothing after an error, so the channel closes
+        Some(self.rx.recv().ok()?.map_err(Into::into))
+    }
+}
+
+/// The top level of the dataset, whose `entries` are sent to `tx` and whose
+/// other fields are skipped
+struct DatasetVisitor<'a> {
+    tx: &'a SyncSender<Item>,
+    file: &'a PathBuf,
+}
+
+impl<'de> Visitor<'de> for DatasetVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a dataset object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                map.next_value_seed(Entries {
+                    tx: self.tx,
+                    file: self.file,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The map from entry names to lists of records
+struct Entries<'a> {
+    tx: &'a SyncSender<Item>,
+    file: &'a PathBuf,
+}
+
+impl<'de> DeserializeSeed<'de> for Entries<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Entries<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of entry names to records")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            map.next_value_seed(Records {
+                key,
+                tx: self.tx,
+                file: self.file,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The records of the entry `key`
+struct Records<'a> {
+    key: String,
+    tx: &'a SyncSender<Item>,
+    file: &'a PathBuf,
+}
+
+impl<'de> DeserializeSeed<'de> for Records<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Records<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a list of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(mut rec) = seq.next_element::<Record>()? {
+            rec.file = Some(self.file.clone());
+            if self.tx.send(Ok((self.key.clone(), rec))).is_err() {
+                return Err(de::Error::custom("record stream dropped"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Dataset;
+
+    use super::*;
+
+    #[test]
+    fn stream() {
+        let path = "testfiles/opt.json";
+        let got: Vec<_> =
+            Dataset::stream(path).unwrap().map(|r| r.unwrap()).collect();
+        let want = Dataset::load(path).unwrap();
+        assert_eq!(
+            got.iter().map(|(_, r)| r.cmiles()).collect::<Vec<_>>(),
+            want.to_smiles()
+        );
+        let (key, rec) = &got[0];
+        assert_eq!(key, "https://api.qcarchive.molssi.org:443/");
+        assert_eq!(rec.record_id(), Some("104321073"));
+        assert_eq!(rec.file(), Some(Path::new(path)));
+
+        // compressed datasets stream too
+        let got = Dataset::stream("testfiles/small.json.gz").unwrap().count();
+        let want = Dataset::load("testfiles/small.json.gz").unwrap();
+        assert_eq!(got, want.to_smiles().len());
+
+        // dropping the stream early stops the reader without blocking
+        assert!(Dataset::stream(path).unwrap().next().is_some());
+
+        let dir = std::env::temp_dir().join("chomper-stream-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let bad = dir.join("bad.json");
+        std::fs::write(&bad, r#"{"entries": {"a": [{"cmiles": "C"}, 1]}}"#)
+            .unwrap();
+        let got: Vec<_> = Dataset::stream(&bad).unwrap().collect();
+        assert_eq!(got.len(), 2);
+        assert!(got[0].is_ok());
+        assert!(got[1].is_err());
+    }
+}
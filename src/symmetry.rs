@@ -0,0 +1,495 @@
+//! Automorphisms of molecular graphs and the orbits they induce.
+//!
+//! Candidate symmetry classes come from iteratively refining atom invariants
+//! by the classes of their neighbors, which groups together every pair of
+//! symmetric atoms but can also group atoms that are not symmetric, as in
+//! some regular graphs. Orbits are therefore confirmed by searching for an
+//! actual automorphism between each candidate pair. Chirality is ignored
+//! throughout, so these are automorphisms of the constitution
+
+use crate::molecule::{BondType, Molecule};
+
+/// return the refined class of each atom in `mol`. symmetric atoms always
+/// share a class, and classes are numbered in increasing order of the
+/// invariants they were refined from, so the numbering doesn't depend on the
+/// order of the atoms
+pub fn refined_classes(mol: &Molecule) -> Vec<usize> {
+    refined_classes_colored(mol, &vec![0; mol.atoms.len()])
+}
+
+/// like [refined_classes], but atoms with different entries in `colors` never
+/// share a class
+pub fn refined_classes_colored(mol: &Molecule, colors: &[usize]) -> Vec<usize> {
+    let adj = adjacency(mol);
+    refine(&adj, sorted_rank(&invariants(mol, colors)))
+}
+
+/// A source of extra invariants for the atoms of a molecule, like the force
+/// field parameters assigned to them, so that atoms the graph alone can't
+/// tell apart are kept apart. Each atom gets a color, and only atoms with the
+/// same color are treated as equivalent, so colors should mean the same thing
+/// in every molecule they are compared across. Any
+/// `Fn(&Molecule, usize) -> usize` is an invariant
+pub trait AtomInvariant {
+    /// the color of atom `atom` of `mol`
+    fn color(&self, mol: &Molecule, atom: usize) -> usize;
+}
+
+impl<F: Fn(&Molecule, usize) -> usize> AtomInvariant for F {
+    fn color(&self, mol: &Molecule, atom: usize) -> usize {
+        self(mol, atom)
+    }
+}
+
+/// Colors atoms by their partial charge, rounded to the nearest multiple of
+/// the width in elementary charges. Every atom of a molecule without partial
+/// charges gets color 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargeBin(pub f64);
+
+impl AtomInvariant for ChargeBin {
+    fn color(&self, mol: &Molecule, atom: usize) -> usize {
+        let Some(charges) = &mol.partial_charges else {
+            return 0;
+        };
+        let bin = (charges[atom] / self.0).round() as i64;
+        // interleave the negative bins with the positive ones, leaving 0 free
+        1 + ((bin << 1) ^ (bin >> 63)) as usize
+    }
+}
+
+/// the color `invariant` gives each atom of `mol`
+pub fn colors(mol: &Molecule, invariant: &impl AtomInvariant) -> Vec<usize> {
+    (0..mol.atoms.len())
+        .map(|i| invariant.color(mol, i))
+        .collect()
+}
+
+/// A color given to an atom by the caller, then its atomic number, isotope,
+/// H count, charge, aromaticity, and degree
+type Invariant = (usize, usize, Option<usize>, usize, isize, bool, usize);
+
+/// the properties of each atom that automorphisms have to preserve, starting
+/// with its entry in `colors`
+fn invariants(mol: &Molecule, colors: &[usize]) -> Vec<Invariant> {
+    (0..mol.atoms.len())
+        .map(|i| {
+            let a = &mol.atoms[i];
+            (
+                colors[i],
+                a.atomic_number,
+                a.isotope,
+                a.n_hydrogens,
+                a.charge,
+                a.aromatic,
+                mol.degree(i),
+            )
+        })
+        .collect()
+}
+
+/// split `classes` by the classes of each atom's neighbors until no class
+/// splits further
+fn refine(
+    adj: &[Vec<(usize, BondType)>],
+    mut classes: Vec<usize>,
+) -> Vec<usize> {
+    loop {
+        let keys: Vec<_> = (0..adj.len())
+            .map(|i| {
+                let mut nbrs: Vec<_> =
+                    adj[i].iter().map(|&(j, t)| (classes[j], t)).collect();
+                nbrs.sort();
+                (classes[i], nbrs)
+            })
+            .collect();
+        let next = sorted_rank(&keys);
+        if n_classes(&next) == n_classes(&classes) {
+            return next;
+        }
+        classes = next;
+    }
+}
+
+fn n_classes(classes: &[usize]) -> usize {
+    classes.iter().max().map_or(0, |m| m + 1)
+}
+
+/// number the distinct values of `keys` in increasing order
+fn sorted_rank<T: Ord + Clone>(keys: &[T]) -> Vec<usize> {
+    let mut sorted: Vec<T> = keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    keys.iter()
+        .map(|k| sorted.binary_search(k).unwrap())
+        .collect()
+}
+
+/// number the distinct values of `keys` in order of first appearance
+fn rank<T: Ord + Clone>(keys: &[T]) -> Vec<usize> {
+    let mut sorted: Vec<T> = keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    // renumber the sorted values by the position of their first occurrence
+    let mut first = vec![usize::MAX; sorted.len()];
+    for (i, k) in keys.iter().enumerate() {
+        let r = sorted.binary_search(k).unwrap();
+        first[r] = first[r].min(i);
+    }
+    let mut order: Vec<usize> = (0..sorted.len()).collect();
+    order.sort_by_key(|&r| first[r]);
+    let mut relabel = vec![0; sorted.len()];
+    for (new, &r) in order.iter().enumerate() {
+        relabel[r] = new;
+    }
+    keys.iter()
+        .map(|k| relabel[sorted.binary_search(k).unwrap()])
+        .collect()
+}
+
+fn adjacency(mol: &Molecule) -> Vec<Vec<(usize, BondType)>> {
+    let mut adj = vec![Vec::new(); mol.atoms.len()];
+    for b in &mol.bonds {
+        adj[b.atom1].push((b.atom2, b.bond_type));
+        adj[b.atom2].push((b.atom1, b.bond_type));
+    }
+    adj
+}
+
+/// Backtracking search for automorphisms
+struct Search<'a> {
+    adj: Vec<Vec<(usize, BondType)>>,
+    classes: &'a [usize],
+    /// atom -> image
+    mapping: Vec<Option<usize>>,
+    used: Vec<bool>,
+}
+
+impl<'a> Search<'a> {
+    fn new(mol: &Molecule, classes: &'a [usize]) -> Self {
+        let n = mol.atoms.len();
+        Search {
+            adj: adjacency(mol),
+            classes,
+            mapping: vec![None; n],
+            used: vec![false; n],
+        }
+    }
+
+    /// whether mapping `i` to `j` is consistent with the atoms already mapped
+    fn feasible(&self, i: usize, j: usize) -> bool {
+        if self.used[j] || self.classes[i] != self.classes[j] {
+            return false;
+        }
+        // each mapped neighbor of i must map to a neighbor of j with the same
+        // bond type, and vice versa. the degrees are equal since the classes
+        // are, so checking one direction plus a count is enough
+        let mut n_mapped = 0;
+        for &(k, t) in &self.adj[i] {
+            if let Some(m) = self.mapping[k] {
+                n_mapped += 1;
+                if !self.adj[j].contains(&(m, t)) {
+                    return false;
+                }
+            }
+        }
+        let n_used = self.adj[j].iter().filter(|&&(k, _)| self.used[k]).count();
+        n_mapped == n_used
+    }
+
+    /// extend the mapping to every atom from position `i` on, calling `found`
+    /// with each complete automorphism until it returns false. returns false
+    /// if the search was stopped
+    fn extend(
+        &mut self,
+        i: usize,
+        found: &mut impl FnMut(Vec<usize>) -> bool,
+    ) -> bool {
+        if i == self.mapping.len() {
+            return found(self.mapping.iter().map(|m| m.unwrap()).collect());
+        }
+        if self.mapping[i].is_some() {
+            return self.extend(i + 1, found);
+        }
+        for j in 0..self.mapping.len() {
+            if !self.feasible(i, j) {
+                continue;
+            }
+            self.mapping[i] = Some(j);
+            self.used[j] = true;
+            let keep_going = self.extend(i + 1, found);
+            self.mapping[i] = None;
+            self.used[j] = false;
+            if !keep_going {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// find an automorphism of `mol` taking atom `a` to atom `b`, as a list of
+/// the image of each atom, if there is one
+pub fn find_automorphism(
+    mol: &Molecule,
+    a: usize,
+    b: usize,
+) -> Option<Vec<usize>> {
+    let classes = refined_classes(mol);
+    find_with(mol, &classes, a, b)
+}
+
+fn find_with(
+    mol: &Molecule,
+    classes: &[usize],
+    a: usize,
+    b: usize,
+) -> Option<Vec<usize>> {
+    let mut search = Search::new(mol, classes);
+    if !search.feasible(a, b) {
+        return None;
+    }
+    search.mapping[a] = Some(b);
+    search.used[b] = true;
+    let mut ret = None;
+    search.extend(0, &mut |m| {
+        ret = Some(m);
+        false
+    });
+    ret
+}
+
+/// up to `max` automorphisms of `mol`, starting with the identity. the full
+/// group can be enormous, as in the 31104 automorphisms of neopentane with
+/// explicit hydrogens, so the search stops once `max` are found
+pub fn automorphisms(mol: &Molecule, max: usize) -> Vec<Vec<usize>> {
+    let classes = refined_classes(mol);
+    let mut search = Search::new(mol, &classes);
+    let mut ret = Vec::new();
+    if max == 0 {
+        return ret;
+    }
+    search.extend(0, &mut |m| {
+        ret.push(m);
+        ret.len() < max
+    });
+    ret
+}
+
+/// the orbit of each atom under the automorphism group of `mol`, numbered in
+/// order of each orbit's first atom. two atoms share an orbit exactly when
+/// some automorphism maps one to the other
+pub fn orbits(mol: &Molecule) -> Vec<usize> {
+    orbits_colored(mol, &vec![0; mol.atoms.len()])
+}
+
+/// like [orbits], but only counting automorphisms that map each atom to one
+/// with the same entry in `colors`
+pub fn orbits_colored(mol: &Molecule, colors: &[usize]) -> Vec<usize> {
+    let n = mol.atoms.len();
+    let classes = refined_classes_colored(mol, colors);
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for a in 0..n {
+        for b in a + 1..n {
+            if classes[a] != classes[b]
+                || find(&mut parent, a) == find(&mut parent, b)
+            {
+                continue;
+            }
+            // every cycle of the automorphism lies within an orbit, so merge
+            // them all rather than just a and b
+            if let Some(m) = find_with(mol, &classes, a, b) {
+                for (i, j) in m.into_iter().enumerate() {
+                    let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                    parent[ri.max(rj)] = ri.min(rj);
+                }
+            }
+        }
+    }
+    let roots: Vec<usize> = (0..n).map(|i| find(&mut parent, i)).collect();
+    rank(&roots)
+}
+
+/// A canonical labeling: atom invariants in canonical order, then bonds as
+/// pairs of canonical positions
+type Encoding = (Vec<Invariant>, Vec<(usize, usize, BondType)>);
+
+/// a canonical position for each atom of `mol`, so that any two molecules
+/// with the same constitution, whatever the order of their atoms, give the
+/// same molecule when their atoms are sorted by these positions. ties left by
+/// [refined_classes] are broken by trying each candidate atom in turn and
+/// keeping the labeling with the smallest encoding, skipping candidates that
+/// an automorphism shows will give the same result as one already tried
+pub fn canonical_order(mol: &Molecule) -> Vec<usize> {
+    canonical_order_colored(mol, &vec![0; mol.atoms.len()])
+}
+
+/// like [canonical_order], but only atoms with the same entry in `colors` are
+/// treated as interchangeable, and atoms with smaller colors come first. this
+/// pins down atoms that are distinguished by something outside the graph,
+/// like the mapped atoms of a pattern
+pub fn canonical_order_colored(mol: &Molecule, colors: &[usize]) -> Vec<usize> {
+    let adj = adjacency(mol);
+    let inv = invariants(mol, colors);
+    let mut best = None;
+    canonical_search(mol, &adj, &inv, sorted_rank(&inv), &mut best);
+    best.map(|(_, order)| order).unwrap_or_default()
+}
+
+fn canonical_search(
+    mol: &Molecule,
+    adj: &[Vec<(usize, BondType)>],
+    inv: &[Invariant],
+    classes: Vec<usize>,
+    best: &mut Option<(Encoding, Vec<usize>)>,
+) {
+    let classes = refine(adj, classes);
+    let n = classes.len();
+    if n_classes(&classes) == n {
+        let mut atoms = vec![inv[0]; n];
+        for (i, &c) in classes.iter().enumerate() {
+            atoms[c] = inv[i];
+        }
+        let mut bonds: Vec<_> = mol
+            .bonds
+            .iter()
+            .map(|b| {
+                let (i, j) = (classes[b.atom1], classes[b.atom2]);
+                (i.min(j), i.max(j), b.bond_type)
+            })
+            .collect();
+        bonds.sort();
+        let enc = (atoms, bonds);
+        if best.as_ref().is_none_or(|(b, _)| enc < *b) {
+            *best = Some((enc, classes));
+        }
+        return;
+    }
+    // individualize each atom of the first tied class in turn
+    let mut counts = vec![0; n];
+    for &c in &classes {
+        counts[c] += 1;
+    }
+    let cell = (0..n).find(|&c| counts[c] > 1).unwrap();
+    let mut tried: Vec<usize> = Vec::new();
+    for v in (0..n).filter(|&i| classes[i] == cell) {
+        if tried
+            .iter()
+            .any(|&u| find_with(mol, &classes, u, v).is_some())
+        {
+            continue;
+        }
+        tried.push(v);
+        let split: Vec<_> = (0..n)
+            .map(|i| 2 * classes[i] + usize::from(i != v))
+            .collect();
+        canonical_search(mol, adj, inv, split, best);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn propane() {
+        let m = mol("[#6H3]-[#6H2]-[#6H3]");
+        assert_eq!(orbits(&m), [0, 1, 0]);
+        assert_eq!(automorphisms(&m, 10), [vec![0, 1, 2], vec![2, 1, 0]]);
+        assert_eq!(find_automorphism(&m, 0, 2), Some(vec![2, 1, 0]));
+        assert_eq!(find_automorphism(&m, 0, 1), None);
+    }
+
+    #[test]
+    fn explicit_hydrogens() {
+        // methanol with explicit hydrogens: the three methyl hydrogens are
+        // equivalent, but not equivalent to the hydroxyl hydrogen
+        let m = mol("[#1]-[#6](-[#1])(-[#1])-[#8]-[#1]");
+        assert_eq!(orbits(&m), [0, 1, 0, 0, 2, 3]);
+        assert_eq!(automorphisms(&m, 100).len(), 6);
+        assert_eq!(automorphisms(&m, 4).len(), 4);
+    }
+
+    #[test]
+    fn canonical() {
+        let a = mol("[#8H]-[#6H2]-[#6H2]-[#6H2]-[#7H2]");
+        let b = mol("[#6H2](-[#6H2]-[#8H])-[#6H2]-[#7H2]");
+        let (oa, ob) = (canonical_order(&a), canonical_order(&b));
+        let sorted = |m: &Molecule, order: &[usize]| {
+            let mut atoms = vec![None; order.len()];
+            for (i, &c) in order.iter().enumerate() {
+                atoms[c] = Some(m.atoms[i].atomic_number);
+            }
+            atoms
+        };
+        assert_eq!(sorted(&a, &oa), sorted(&b, &ob));
+        // ties are broken the same way for symmetric atoms
+        let p = mol("[#6H3]-[#6H2]-[#6H3]");
+        let mut o = canonical_order(&p);
+        o.sort();
+        assert_eq!(o, [0, 1, 2]);
+    }
+
+    #[test]
+    fn refinement_is_not_enough() {
+        // two triangles and a hexagon are both 2-regular, so refinement can't
+        // tell their atoms apart, but there is no automorphism between them
+        let tri = mol("[#6H2]1-[#6H2]-[#6H2]-1");
+        let hex = mol("[#6H2]1-[#6H2]-[#6H2]-[#6H2]-[#6H2]-[#6H2]-1");
+        let mut atoms = tri.atoms;
+        atoms.extend(hex.atoms);
+        let mut bonds = tri.bonds;
+        bonds.extend(hex.bonds.into_iter().map(|mut b| {
+            b.atom1 += 3;
+            b.atom2 += 3;
+            b
+        }));
+        let m = Molecule::new(atoms, bonds);
+        let classes = refined_classes(&m);
+        assert!(classes.iter().all(|&c| c == 0));
+        assert_eq!(orbits(&m), [0, 0, 0, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn colored() {
+        use crate::{
+            canonical::canonical_key_colored, fingerprint::Fingerprint,
+        };
+
+        // charges that break the symmetry of the two ends of propane
+        let m = mol("[#6H3]-[#6H2]-[#6H3]")
+            .with_partial_charges(vec![-0.1, 0.0, 0.2])
+            .unwrap();
+        let c = colors(&m, &ChargeBin(0.1));
+        assert_eq!(c, [2, 1, 5]);
+        assert_eq!(orbits_colored(&m, &c), [0, 1, 2]);
+        assert_eq!(refined_classes_colored(&m, &c), [1, 0, 2]);
+        assert_eq!(colors(&mol("[#6H4]"), &ChargeBin(0.1)), [0]);
+
+        // the same molecule with its atoms reversed gets the same key
+        let r = mol("[#6H3]-[#6H2]-[#6H3]")
+            .with_partial_charges(vec![0.2, 0.0, -0.1])
+            .unwrap();
+        let key = canonical_key_colored(&m, &c);
+        assert_eq!(
+            key,
+            canonical_key_colored(&r, &colors(&r, &ChargeBin(0.1)))
+        );
+        assert_ne!(key, crate::canonical::canonical_key(&m));
+
+        // a closure works too, and zero colors change nothing
+        let zero = colors(&m, &|_: &Molecule, _| 0);
+        assert_eq!(orbits_colored(&m, &zero), orbits(&m));
+        assert_eq!(Fingerprint::colored(&m, 2, &zero), Fingerprint::new(&m, 2));
+        assert_ne!(Fingerprint::colored(&m, 2, &c), Fingerprint::new(&m, 2));
+    }
+}
@@ -0,0 +1,414 @@
+//! A lossless token stream for SMARTS, SMIRKS, and SMILES, for syntax
+//! highlighting and editor tooling.
+//!
+//! Unlike the scanner behind [crate::smarts::Smarts::try_parse], which drops
+//! positions and fails on the parts of the query syntax that the parser does
+//! not support, [tokenize] accepts any input and covers every character of it
+//! exactly once, including whitespace and unrecognized characters, so
+//! concatenating the text of the tokens gives back the input. Each token is
+//! classified by the role it plays at its position, so `#` is an atomic
+//! number inside a bracket atom and a triple bond outside of one, and `:` is
+//! an atom map at the end of a bracket atom and an aromatic bond elsewhere
+
+use std::ops::Range;
+
+use crate::elements;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// `[` or `]` around a bracket atom
+    Bracket,
+    /// `(` or `)` around a branch or recursive SMARTS
+    Paren,
+    /// an element symbol, like `C`, `Cl`, or `#6`
+    Element,
+    /// an aromatic element symbol, like `c` or `se`
+    AromaticElement,
+    /// `*`, `a`, or `A`
+    Wildcard,
+    /// the isotope at the start of a bracket atom
+    Isotope,
+    /// a chirality tag like `@` or `@@`
+    Chirality,
+    /// an H count, like `H3`
+    HCount,
+    /// a charge, like `+`, `--`, or `+2`
+    Charge,
+    /// a counted atom primitive, like `X4`, `D2`, `R`, or `r5`
+    Primitive,
+    /// the atom map at the end of a bracket atom, like `:1`
+    AtomMap,
+    /// a bond symbol, like `-`, `=`, `~`, or `@`
+    Bond,
+    /// a logical operator, `!`, `&`, `,`, or `;`
+    Operator,
+    /// a ring-closure label, like `1` or `%12`
+    RingClosure,
+    /// the `$` starting a recursive SMARTS
+    Recursive,
+    /// `.` between disconnected components
+    Dot,
+    /// `>` or `>>` in a reaction
+    Arrow,
+    /// a run of whitespace
+    Whitespace,
+    /// a character that cannot appear at this position
+    Unknown,
+}
+
+impl TokenKind {
+    /// whether this is trivia, which carries no meaning of its own
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Whitespace)
+    }
+}
+
+/// One token in the output of [tokenize]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    /// the byte range of the token in the input
+    pub span: Range<usize>,
+    pub text: &'a str,
+}
+
+/// The syntactic context a character is read in
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Bracket,
+    Branch,
+    /// the parentheses of a recursive SMARTS inside a bracket atom
+    Recursive,
+}
+
+struct Lexer<'a> {
+    s: &'a str,
+    pos: usize,
+    stack: Vec<Context>,
+    tokens: Vec<Token<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn peek2(&self) -> Option<char> {
+        self.s[self.pos..].chars().nth(1)
+    }
+
+    fn in_bracket(&self) -> bool {
+        self.stack.last() == Some(&Context::Bracket)
+    }
+
+    /// advance over `c` and every following character satisfying `f`
+    fn take_while(&mut self, c: char, f: impl Fn(char) -> bool) {
+        self.pos += c.len_utf8();
+        while let Some(c) = self.peek().filter(|&c| f(c)) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        self.tokens.push(Token {
+            kind,
+            span: start..self.pos,
+            text: &self.s[start..self.pos],
+        });
+    }
+
+    /// whether the previous non-trivia token is the `[` opening the current
+    /// bracket atom or its isotope, so that the next token is its element
+    fn at_atom_start(&self) -> bool {
+        let mut prev = self.tokens.iter().rev().filter(|t| !t.kind.is_trivia());
+        match prev.next() {
+            Some(t) if t.text == "[" => true,
+            Some(t) if t.kind == TokenKind::Isotope => true,
+            _ => false,
+        }
+    }
+
+    /// the element symbol of one or two letters starting with `c`, if any.
+    /// `organic` limits the symbols to the ones allowed outside of brackets
+    fn element(&self, c: char, organic: bool) -> Option<(usize, TokenKind)> {
+        use TokenKind as K;
+        let next = self.peek2().filter(char::is_ascii_lowercase);
+        if let Some(n) = next {
+            let sym = format!("{}{n}", c.to_ascii_uppercase());
+            let known = if organic {
+                ["Cl", "Br"].contains(&sym.as_str())
+            } else if c.is_ascii_uppercase() {
+                elements::atomic_number(&sym).is_some()
+            } else {
+                ["Se", "As", "Te"].contains(&sym.as_str())
+            };
+            if known {
+                let kind = if c.is_ascii_lowercase() {
+                    K::AromaticElement
+                } else {
+                    K::Element
+                };
+                return Some((2, kind));
+            }
+        }
+        match c {
+            'b' | 'c' | 'n' | 'o' | 'p' | 's' => Some((1, K::AromaticElement)),
+            'A'..='Z' if organic => {
+                "BCNOPSFI".contains(c).then_some((1, K::Element))
+            }
+            'A'..='Z' => {
+                elements::atomic_number(&c.to_string()).map(|_| (1, K::Element))
+            }
+            _ => None,
+        }
+    }
+
+    fn bracket_token(&mut self, c: char, start: usize) -> TokenKind {
+        use TokenKind as K;
+        match c {
+            '0'..='9' if self.tokens.last().is_some_and(|t| t.text == "[") => {
+                self.digits();
+                K::Isotope
+            }
+            '#' if self.peek2().is_some_and(|c| c.is_ascii_digit()) => {
+                self.pos += 1;
+                self.digits();
+                K::Element
+            }
+            ':' => {
+                self.pos += 1;
+                self.digits();
+                K::AtomMap
+            }
+            '@' => {
+                self.take_while(c, |c| c == '@' || c == '?');
+                K::Chirality
+            }
+            '+' | '-' => {
+                self.take_while(c, |n| n == c);
+                self.digits();
+                K::Charge
+            }
+            '!' | '&' | ',' | ';' => {
+                self.pos += 1;
+                K::Operator
+            }
+            '$' if self.peek2() == Some('(') => {
+                self.pos += 1;
+                K::Recursive
+            }
+            '*' | 'a' | 'A' if self.element(c, false).is_none() => {
+                self.pos += 1;
+                K::Wildcard
+            }
+            'H' if self.at_atom_start()
+                && !self.peek2().is_some_and(|c| c.is_ascii_digit()) =>
+            {
+                let (n, kind) = self.element(c, false).unwrap();
+                self.pos += n;
+                kind
+            }
+            'H' => {
+                self.take_while(c, |c| c.is_ascii_digit());
+                K::HCount
+            }
+            _ => {
+                if let Some((n, kind)) = self.element(c, false) {
+                    self.pos += n;
+                    return kind;
+                }
+                if "DXvhRrx".contains(c) {
+                    self.take_while(c, |c| c.is_ascii_digit());
+                    return K::Primitive;
+                }
+                self.pos = start + c.len_utf8();
+                K::Unknown
+            }
+        }
+    }
+
+    fn outer_token(&mut self, c: char) -> TokenKind {
+        use TokenKind as K;
+        match c {
+            '0'..='9' => {
+                self.pos += 1;
+                K::RingClosure
+            }
+            '%' if self.peek2().is_some_and(|c| c.is_ascii_digit()) => {
+                self.pos += 1;
+                self.digits();
+                K::RingClosure
+            }
+            '-' | '=' | '#' | ':' | '~' | '@' => {
+                self.pos += 1;
+                K::Bond
+            }
+            '/' | '\\' => {
+                self.take_while(c, |c| c == '?');
+                K::Bond
+            }
+            '!' | '&' | ',' | ';' => {
+                self.pos += 1;
+                K::Operator
+            }
+            '.' => {
+                self.pos += 1;
+                K::Dot
+            }
+            '>' => {
+                self.take_while(c, |c| c == '>');
+                K::Arrow
+            }
+            '*' => {
+                self.pos += 1;
+                K::Wildcard
+            }
+            _ => match self.element(c, true) {
+                Some((n, kind)) => {
+                    self.pos += n;
+                    kind
+                }
+                None => {
+                    self.pos += c.len_utf8();
+                    K::Unknown
+                }
+            },
+        }
+    }
+
+    fn run(mut self) -> Vec<Token<'a>> {
+        use TokenKind as K;
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            let kind = match c {
+                _ if c.is_whitespace() => {
+                    self.take_while(c, char::is_whitespace);
+                    K::Whitespace
+                }
+                '[' if !self.in_bracket() => {
+                    self.stack.push(Context::Bracket);
+                    self.pos += 1;
+                    K::Bracket
+                }
+                ']' if self.in_bracket() => {
+                    self.stack.pop();
+                    self.pos += 1;
+                    K::Bracket
+                }
+                '(' => {
+                    let ctx = match self.tokens.last() {
+                        Some(t) if t.kind == K::Recursive => Context::Recursive,
+                        _ => Context::Branch,
+                    };
+                    self.stack.push(ctx);
+                    self.pos += 1;
+                    K::Paren
+                }
+                ')' if matches!(
+                    self.stack.last(),
+                    Some(Context::Branch | Context::Recursive)
+                ) =>
+                {
+                    self.stack.pop();
+                    self.pos += 1;
+                    K::Paren
+                }
+                _ if self.in_bracket() => self.bracket_token(c, start),
+                _ => self.outer_token(c),
+            };
+            self.push(kind, start);
+        }
+        self.tokens
+    }
+}
+
+/// split `s` into tokens covering all of it, as described in the module docs.
+/// this never fails: characters that can't appear at their position become
+/// [TokenKind::Unknown] tokens
+pub fn tokenize(s: &str) -> Vec<Token<'_>> {
+    Lexer {
+        s,
+        pos: 0,
+        stack: Vec::new(),
+        tokens: Vec::new(),
+    }
+    .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(s: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(s).into_iter().map(|t| (t.kind, t.text)).collect()
+    }
+
+    #[test]
+    fn lossless() {
+        use TokenKind as K;
+        let s = "[#6X4:1]-[#6;!$(C=O)]1~[Cl,Br] >> [13CH2+:2]=1 Q";
+        let tokens = tokenize(s);
+        assert_eq!(tokens.iter().map(|t| t.text).collect::<String>(), s);
+        assert!(tokens.iter().all(|t| &s[t.span.clone()] == t.text));
+
+        assert_eq!(
+            kinds("[#6X4:1]-[#6;!$(C=O)]1"),
+            [
+                (K::Bracket, "["),
+                (K::Element, "#6"),
+                (K::Primitive, "X4"),
+                (K::AtomMap, ":1"),
+                (K::Bracket, "]"),
+                (K::Bond, "-"),
+                (K::Bracket, "["),
+                (K::Element, "#6"),
+                (K::Operator, ";"),
+                (K::Operator, "!"),
+                (K::Recursive, "$"),
+                (K::Paren, "("),
+                (K::Element, "C"),
+                (K::Bond, "="),
+                (K::Element, "O"),
+                (K::Paren, ")"),
+                (K::Bracket, "]"),
+                (K::RingClosure, "1"),
+            ]
+        );
+        assert_eq!(
+            kinds("[13CH2+:2] >>Q"),
+            [
+                (K::Bracket, "["),
+                (K::Isotope, "13"),
+                (K::Element, "C"),
+                (K::HCount, "H2"),
+                (K::Charge, "+"),
+                (K::AtomMap, ":2"),
+                (K::Bracket, "]"),
+                (K::Whitespace, " "),
+                (K::Arrow, ">>"),
+                (K::Unknown, "Q"),
+            ]
+        );
+        // a bare H at the start of a bracket is hydrogen, and the chomper
+        // scanner's consecutive ring digits are separate labels here
+        assert_eq!(
+            kinds("[H+]c12%10"),
+            [
+                (K::Bracket, "["),
+                (K::Element, "H"),
+                (K::Charge, "+"),
+                (K::Bracket, "]"),
+                (K::AromaticElement, "c"),
+                (K::RingClosure, "1"),
+                (K::RingClosure, "2"),
+                (K::RingClosure, "%10"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Tightening a query pattern until it stops matching a set of molecules, the
+//! counterpart to generalizing a cluster of environments into one pattern in
+//! [crate::generate].
+//!
+//! Tightening is greedy. While some excluded molecule still matches, each
+//! atom and bond of its first match suggests decorators that would reject it:
+//! the negation of each primitive the matched atom or bond satisfies, and any
+//! primitive shared by the matches in the molecules that must be kept. The
+//! candidate that leaves the fewest excluded molecules, and then the fewest
+//! matches, is added, as long as every kept molecule still matches
+
+use std::fmt::Display;
+
+use crate::{
+    molecule::Molecule,
+    query::{
+        AtomExpr, AtomPrimitive, BondExpr, BondPrimitive, Expr, Query, Target,
+    },
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TightenError {
+    /// the kept molecule at this position does not match the starting query
+    Unmatched(usize),
+    /// no decorator rejects the excluded molecule at this position without
+    /// also rejecting a kept molecule
+    Stuck(usize),
+}
+
+impl Display for TightenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TightenError::Unmatched(i) => {
+                write!(f, "kept molecule {i} does not match the query")
+            }
+            TightenError::Stuck(i) => write!(
+                f,
+                "excluded molecule {i} cannot be rejected without rejecting a \
+                 kept molecule"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TightenError {}
+
+/// One decorator added by [tighten]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Added {
+    /// `expr` was added to the atom at this position in [Query::atoms]
+    Atom(usize, AtomExpr),
+    /// `expr` was added to the bond at this position in [Query::bonds]
+    Bond(usize, BondExpr),
+}
+
+/// The result of [tighten]: the tightened query and the decorators added to
+/// it, in the order they were added
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tightened {
+    pub query: Query,
+    pub added: Vec<Added>,
+}
+
+/// the primitives satisfied by atom `atom` of `target`, in the order they are
+/// tried as decorators
+fn atom_primitives(target: &Target, atom: usize) -> Vec<AtomPrimitive> {
+    use AtomPrimitive as P;
+    let mol = target.mol;
+    let a = &mol.atoms[atom];
+    let hydrogens = a.n_hydrogens
+        + mol
+            .neighbors(atom)
+            .filter(|&j| mol.atoms[j].atomic_number == 1)
+            .count();
+    let mut ret = vec![
+        P::AtomicNumber(a.atomic_number),
+        P::Connectivity(mol.total_degree(atom)),
+        P::Hydrogens(hydrogens),
+        P::Charge(a.charge),
+        P::Degree(mol.degree(atom)),
+    ];
+    if let Some(valences) = &target.valences {
+        ret.push(P::Valence(valences[atom]));
+    }
+    ret.push(P::RingCount(target.rings.n_atom_rings(atom)));
+    if let Some(r) = target.rings.smallest_ring(atom) {
+        ret.push(P::SmallestRing(r));
+    }
+    if a.aromatic {
+        ret.push(P::Aromatic);
+    }
+    ret
+}
+
+/// the primitives satisfied by bond `bond` of `target`
+fn bond_primitives(target: &Target, bond: usize) -> Vec<BondPrimitive> {
+    let mut ret = vec![BondPrimitive::Type(target.mol.bonds[bond].bond_type)];
+    if target.rings.is_ring_bond(bond) {
+        ret.push(BondPrimitive::Ring);
+    }
+    ret
+}
+
+/// the position in `target` of the bond between the atoms at positions `a`
+/// and `b`
+fn target_bond(target: &Target, a: usize, b: usize) -> usize {
+    target
+        .mol
+        .bonds
+        .iter()
+        .position(|bond| {
+            (bond.atom1, bond.atom2) == (a, b)
+                || (bond.atom1, bond.atom2) == (b, a)
+        })
+        .expect("matched query bonds have target bonds")
+}
+
+/// the primitives among `prims` that `holds` for every match in `matches`,
+/// paired with its target in `targets`
+fn shared<P: PartialEq>(
+    targets: &[Target],
+    matches: &[Vec<Vec<usize>>],
+    prims: Vec<P>,
+    holds: impl Fn(&P, &Target, &[usize]) -> bool,
+) -> Vec<P> {
+    prims
+        .into_iter()
+        .filter(|p| {
+            targets
+                .iter()
+                .zip(matches)
+                .all(|(t, ms)| ms.iter().all(|m| holds(p, t, m)))
+        })
+        .collect()
+}
+
+fn not<P>(p: P) -> Expr<P> {
+    Expr::Not(Box::new(Expr::Primitive(p)))
+}
+
+/// `expr` and `extra` joined by a conjunction, flattening an existing one
+fn and<P: Clone>(expr: &Expr<P>, extra: Expr<P>) -> Expr<P> {
+    match expr {
+        Expr::And(es) => {
+            let mut es = es.clone();
+            es.push(extra);
+            Expr::And(es)
+        }
+        e => Expr::And(vec![e.clone(), extra]),
+    }
+}
+
+impl Added {
+    fn apply(&self, query: &Query) -> Query {
+        let mut ret = query.clone();
+        match self {
+            Added::Atom(i, e) => {
+                ret.atoms[*i].expr = and(&ret.atoms[*i].expr, e.clone())
+            }
+            Added::Bond(i, e) => {
+                ret.bonds[*i].expr = and(&ret.bonds[*i].expr, e.clone())
+            }
+        }
+        ret
+    }
+}
+
+/// the candidate decorators that reject `m`, a match of `query` in `target`,
+/// followed by the more specific primitives shared by every match in `keep`
+fn candidates(
+    query: &Query,
+    target: &Target,
+    m: &[usize],
+    keep: &[Target],
+    keep_matches: &[Vec<Vec<usize>>],
+) -> Vec<Added> {
+    let mut ret = Vec::new();
+    for (i, &a) in m.iter().enumerate() {
+        for p in atom_primitives(target, a) {
+            ret.push(Added::Atom(i, not(p)));
+        }
+    }
+    for (k, qb) in query.bonds.iter().enumerate() {
+        let b = target_bond(target, m[qb.atom1], m[qb.atom2]);
+        for p in bond_primitives(target, b) {
+            ret.push(Added::Bond(k, not(p)));
+        }
+    }
+    let (Some(first), Some(first_m)) =
+        (keep.first(), keep_matches.first().and_then(|ms| ms.first()))
+    else {
+        return ret;
+    };
+    for (i, &a) in first_m.iter().enumerate() {
+        let prims =
+            shared(keep, keep_matches, atom_primitives(first, a), |p, t, m| {
+                p.matches(t, m[i])
+            });
+        for p in prims {
+            if !p.matches(target, m[i]) {
+                ret.push(Added::Atom(i, Expr::Primitive(p)));
+            }
+        }
+    }
+    for (k, qb) in query.bonds.iter().enumerate() {
+        let bond =
+            |t: &Target, m: &[usize]| target_bond(t, m[qb.atom1], m[qb.atom2]);
+        let prims = shared(
+            keep,
+            keep_matches,
+            bond_primitives(first, bond(first, first_m)),
+            |p, t, m| p.matches(t, bond(t, m)),
+        );
+        for p in prims {
+            if !p.matches(target, bond(target, m)) {
+                ret.push(Added::Bond(k, Expr::Primitive(p)));
+            }
+        }
+    }
+    ret
+}
+
+/// tighten `query` by adding decorators until it matches none of `exclude`,
+/// while still matching every molecule in `keep`. see the module docs for
+/// how decorators are chosen. an empty `exclude` returns `query` unchanged
+pub fn tighten(
+    query: &Query,
+    exclude: &[Molecule],
+    keep: &[Molecule],
+) -> Result<Tightened, TightenError> {
+    let exclude: Vec<_> = exclude.iter().map(Target::new).collect();
+    let keep: Vec<_> = keep.iter().map(Target::new).collect();
+    let keep_matches = |q: &Query| -> Option<Vec<Vec<Vec<usize>>>> {
+        keep.iter()
+            .map(|t| Some(q.find_matches(t)).filter(|ms| !ms.is_empty()))
+            .collect()
+    };
+    // the number of excluded molecules still matched, then the total number
+    // of matches among them
+    let score = |q: &Query| {
+        exclude.iter().fold((0, 0), |(n, total), t| {
+            let ms = q.find_matches(t).len();
+            (n + usize::from(ms > 0), total + ms)
+        })
+    };
+
+    let mut query = query.clone();
+    let Some(mut kept) = keep_matches(&query) else {
+        let i = keep.iter().position(|t| !query.matches(t)).unwrap();
+        return Err(TightenError::Unmatched(i));
+    };
+    let mut current = score(&query);
+    let mut added = Vec::new();
+    while current.0 > 0 {
+        let (i, m) = exclude
+            .iter()
+            .enumerate()
+            .find_map(|(i, t)| {
+                Some((i, query.find_matches(t).into_iter().next()?))
+            })
+            .unwrap();
+        let mut best: Option<(Added, Query, _, (usize, usize))> = None;
+        for c in candidates(&query, &exclude[i], &m, &keep, &kept) {
+            let q = c.apply(&query);
+            let s = score(&q);
+            if s >= current || best.as_ref().is_some_and(|b| s >= b.3) {
+                continue;
+            }
+            let Some(k) = keep_matches(&q) else {
+                continue;
+            };
+            best = Some((c, q, k, s));
+        }
+        let Some((c, q, k, s)) = best else {
+            return Err(TightenError::Stuck(i));
+        };
+        added.push(c);
+        (query, kept, current) = (q, k, s);
+    }
+    Ok(Tightened { query, added })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        format::{format_smarts, FormatOptions},
+        molecule::mol,
+        smarts::{InputKind, Smarts},
+    };
+
+    use super::*;
+
+    fn query(s: &str) -> Query {
+        Query::from(&Smarts::parse_as(s.to_owned(), InputKind::Smarts))
+    }
+
+    #[test]
+    fn tighten_exclusions() {
+        let q = query("[#6:1]-[#8:2]");
+        // ethanol, dimethyl ether, and acetic acid
+        let mols = [
+            mol("[#6H3]-[#6H2]-[#8H]"),
+            mol("[#6H3]-[#8]-[#6H3]"),
+            mol("[#6H3]-[#6](=[#8])-[#8H]"),
+        ];
+        let (ethanol, ether) = (&mols[..1], &mols[1..2]);
+        let matches = |q: &Query, m| q.matches(&Target::new(m));
+
+        let got = tighten(&q, ether, ethanol).unwrap();
+        assert!(!matches(&got.query, &mols[1]));
+        assert!(matches(&got.query, &mols[0]));
+        assert_eq!(got.added.len(), 1);
+        // both of the ether's carbons are methyl groups, unlike the CH2 in
+        // ethanol
+        assert_eq!(got.query.to_string(), "[#6&!H3:1]-[#8:2]");
+
+        let got = tighten(&q, &mols[1..], ethanol).unwrap();
+        assert!(!matches(&got.query, &mols[1]));
+        assert!(!matches(&got.query, &mols[2]));
+        assert!(matches(&got.query, &mols[0]));
+        // the output is valid SMARTS
+        format_smarts(&got.query.to_string(), &FormatOptions::default())
+            .unwrap();
+
+        // nothing to exclude
+        let got = tighten(&q, &[], &[]).unwrap();
+        assert_eq!(got.query, q);
+        assert!(got.added.is_empty());
+
+        assert_eq!(tighten(&q, ether, ether), Err(TightenError::Stuck(0)));
+        assert_eq!(
+            tighten(&query("[#7]"), ether, ethanol),
+            Err(TightenError::Unmatched(0))
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Wall-time instrumentation for the stages of the pipeline.
+//!
+//! A [Timings] collects one sample per call to [Timings::time] or
+//! [Timings::record], so stages run once per molecule, like scanning and
+//! parsing, get per-molecule percentiles, while stages run once per dataset,
+//! like loading, just get their total
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// A stage of the pipeline, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    /// reading a dataset from disk
+    Load,
+    /// converting SMILES to SMARTS with rdkit
+    Convert,
+    Scan,
+    Parse,
+    Eval,
+    /// matching patterns against molecules
+    Match,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Load => "load",
+            Stage::Convert => "convert",
+            Stage::Scan => "scan",
+            Stage::Parse => "parse",
+            Stage::Eval => "eval",
+            Stage::Match => "match",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timings {
+    samples: BTreeMap<Stage, Vec<Duration>>,
+}
+
+/// The statistics for one [Stage], as returned by [Timings::summary]
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageSummary {
+    pub stage: Stage,
+    pub count: usize,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Timings {
+    pub fn record(&mut self, stage: Stage, duration: Duration) {
+        self.samples.entry(stage).or_default().push(duration);
+    }
+
+    /// run `f`, recording how long it took as a sample for `stage`
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let ret = f();
+        self.record(stage, start.elapsed());
+        ret
+    }
+
+    /// the samples recorded for `stage`, in the order they were recorded
+    pub fn samples(&self, stage: Stage) -> &[Duration] {
+        self.samples.get(&stage).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn total(&self, stage: Stage) -> Duration {
+        self.samples(stage).iter().sum()
+    }
+
+    /// the nearest-rank `p`th percentile of the samples for `stage`, for `p`
+    /// between 0 and 100. returns `None` if there are no samples
+    pub fn percentile(&self, stage: Stage, p: f64) -> Option<Duration> {
+        let mut samples = self.samples(stage).to_vec();
+        samples.sort();
+        percentile(&samples, p)
+    }
+
+    /// add the samples of `other` to `self`
+    pub fn merge(&mut self, other: Timings) {
+        for (stage, samples) in other.samples {
+            self.samples.entry(stage).or_default().extend(samples);
+        }
+    }
+
+    /// a summary of each stage with at least one sample, in pipeline order
+    pub fn summary(&self) -> Vec<StageSummary> {
+        self.samples
+            .iter()
+            .filter(|(_, s)| !s.is_empty())
+            .map(|(&stage, samples)| {
+                let mut sorted = samples.clone();
+                sorted.sort();
+                let p = |p| percentile(&sorted, p).unwrap();
+                StageSummary {
+                    stage,
+                    count: sorted.len(),
+                    total: sorted.iter().sum(),
+                    p50: p(50.0),
+                    p90: p(90.0),
+                    p95: p(95.0),
+                    p99: p(99.0),
+                    max: *sorted.last().unwrap(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Display a table with one row per stage, with times in milliseconds
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        writeln!(
+            f,
+            "{:<8} {:>8} {:>12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "stage", "count", "total (ms)", "p50", "p90", "p95", "p99", "max"
+        )?;
+        for s in self.summary() {
+            writeln!(
+                f,
+                "{:<8} {:>8} {:>12.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3} \
+                 {:>10.3}",
+                s.stage.name(),
+                s.count,
+                ms(s.total),
+                ms(s.p50),
+                ms(s.p90),
+                ms(s.p95),
+                ms(s.p99),
+                ms(s.max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut t = Timings::default();
+        for ms in (1..=100).rev() {
+            t.record(Stage::Parse, Duration::from_millis(ms));
+        }
+        assert_eq!(t.time(Stage::Load, || 3), 3);
+        let ms = Duration::from_millis;
+        assert_eq!(t.percentile(Stage::Parse, 50.0), Some(ms(50)));
+        assert_eq!(t.percentile(Stage::Parse, 99.0), Some(ms(99)));
+        assert_eq!(t.percentile(Stage::Parse, 0.0), Some(ms(1)));
+        assert_eq!(t.percentile(Stage::Scan, 50.0), None);
+        assert_eq!(t.total(Stage::Parse), ms(5050));
+
+        let summary = t.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].stage, Stage::Load);
+        assert_eq!(summary[1].p90, ms(90));
+        assert_eq!(summary[1].p95, ms(95));
+        assert_eq!(summary[1].max, ms(100));
+        let table = t.to_string();
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.lines().nth(2).unwrap().starts_with("parse"));
+    }
+}
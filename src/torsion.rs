@@ -0,0 +1,333 @@
+//! Proper torsions in a [Molecule] and their classification relative to its
+//! rings, for choosing which torsions to drive, and keys for comparing
+//! torsions across molecules
+
+use std::collections::BTreeMap;
+
+use crate::{
+    canonical::write_canonical,
+    molecule::{BondType, MolAtom, MolBond, Molecule},
+    smarts::Chiral,
+    symmetry::{self, canonical_order_colored},
+};
+
+/// Whether a bond is part of a ring
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BondClass {
+    Ring,
+    Chain,
+}
+
+/// Where a torsion sits relative to the rings of its molecule, decided by its
+/// central bond
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TorsionClass {
+    /// the central bond is in a ring, so the torsion can't rotate freely
+    InRing,
+    /// the central bond is a chain bond with at least one ring atom
+    RingAdjacent,
+    /// neither central atom is in a ring
+    Acyclic,
+}
+
+/// classify each bond of `mol` as ring or chain, in the same order as its
+/// bonds
+pub fn bond_classes(mol: &Molecule) -> Vec<BondClass> {
+    mol.ring_bonds()
+        .into_iter()
+        .map(|ring| {
+            if ring {
+                BondClass::Ring
+            } else {
+                BondClass::Chain
+            }
+        })
+        .collect()
+}
+
+/// the positions of the rotatable bonds in `mol`: single bonds outside of
+/// rings between two atoms that each have at least one other heavy-atom
+/// neighbor, so bonds to terminal groups like methyls aren't rotatable whether
+/// their hydrogens are explicit or not
+pub fn rotatable_bonds(mol: &Molecule) -> Vec<usize> {
+    mol.ring_bonds()
+        .into_iter()
+        .enumerate()
+        .filter(|&(i, ring)| {
+            let b = &mol.bonds[i];
+            !ring
+                && b.bond_type == BondType::Single
+                && mol.heavy_degree(b.atom1) > 1
+                && mol.heavy_degree(b.atom2) > 1
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// every proper torsion `i-j-k-l` in `mol`, listed once each with `j < k`.
+/// torsions in three-membered rings, where `i == l`, are excluded
+pub fn torsions(mol: &Molecule) -> Vec<[usize; 4]> {
+    let mut ret = Vec::new();
+    for b in &mol.bonds {
+        let (j, k) = (b.atom1.min(b.atom2), b.atom1.max(b.atom2));
+        for i in mol.neighbors(j).filter(|&i| i != k) {
+            for l in mol.neighbors(k).filter(|&l| l != j && l != i) {
+                ret.push([i, j, k, l]);
+            }
+        }
+    }
+    ret
+}
+
+/// classify `torsion` in `mol`. `ring_bonds` should be the result of
+/// [Molecule::ring_bonds], which is passed in so it can be shared across all the
+/// torsions in a molecule
+pub fn classify(
+    mol: &Molecule,
+    ring_bonds: &[bool],
+    torsion: [usize; 4],
+) -> TorsionClass {
+    let [_, j, k, _] = torsion;
+    let central = mol.bonds.iter().position(|b| {
+        (b.atom1, b.atom2) == (j, k) || (b.atom1, b.atom2) == (k, j)
+    });
+    if central.is_some_and(|c| ring_bonds[c]) {
+        return TorsionClass::InRing;
+    }
+    let in_ring = |a: usize| {
+        mol.bonds
+            .iter()
+            .zip(ring_bonds)
+            .any(|(b, &ring)| ring && (b.atom1 == a || b.atom2 == a))
+    };
+    if in_ring(j) || in_ring(k) {
+        TorsionClass::RingAdjacent
+    } else {
+        TorsionClass::Acyclic
+    }
+}
+
+/// every torsion in `mol` along with its class
+pub fn classified_torsions(mol: &Molecule) -> Vec<([usize; 4], TorsionClass)> {
+    let ring_bonds = mol.ring_bonds();
+    torsions(mol)
+        .into_iter()
+        .map(|t| (t, classify(mol, &ring_bonds, t)))
+        .collect()
+}
+
+/// group the torsions of `mol` into symmetry classes, returning each torsion
+/// from [torsions] with its class. two torsions share a class when their atoms
+/// fall in the same [symmetry::orbits] in the same order, read in either
+/// direction, so the size of a class is the multiplicity of that torsion
+pub fn symmetry_classes(mol: &Molecule) -> Vec<([usize; 4], usize)> {
+    let orbits = symmetry::orbits(mol);
+    let mut keys: Vec<[usize; 4]> = Vec::new();
+    torsions(mol)
+        .into_iter()
+        .map(|t| {
+            let key = t.map(|a| orbits[a]);
+            let mut rev = key;
+            rev.reverse();
+            let key = key.min(rev);
+            let class =
+                keys.iter().position(|k| *k == key).unwrap_or_else(|| {
+                    keys.push(key);
+                    keys.len() - 1
+                });
+            (t, class)
+        })
+        .collect()
+}
+
+/// a canonical key for the chemical environment of `torsion` in `mol`, so
+/// that torsions can be grouped by chemistry across molecules and datasets.
+/// the key is a mapped SMIRKS pattern of the four torsion atoms, written with
+/// their element, aromaticity, connectivity, total H count, and charge, along
+/// with the heavy atoms bonded to them, written with only their element,
+/// aromaticity, and charge. it is the same for a torsion and its reverse, and
+/// whether hydrogens are explicit atoms or counted on their neighbors
+pub fn torsion_key(mol: &Molecule, torsion: [usize; 4]) -> String {
+    let mut rev = torsion;
+    rev.reverse();
+    let a = environment_smirks(mol, torsion);
+    let b = environment_smirks(mol, rev);
+    a.min(b)
+}
+
+/// the positions in `mol` of the atoms of `dihedral`, given as 0-based
+/// QCArchive indices like [crate::Record::dihedrals], which are 1 less than
+/// the atom maps of the cmiles, so that the driven torsion of a record can be
+/// passed to [torsion_key]. returns `None` if an index has no matching atom
+/// map or the atoms are not bonded in a chain
+pub fn driven_torsion(
+    mol: &Molecule,
+    dihedral: [usize; 4],
+) -> Option<[usize; 4]> {
+    let maps = mol.atom_maps();
+    let ret = dihedral.map(|i| maps.position(i + 1));
+    let ret = [ret[0]?, ret[1]?, ret[2]?, ret[3]?];
+    ret.windows(2)
+        .all(|w| mol.neighbors(w[0]).any(|n| n == w[1]))
+        .then_some(ret)
+}
+
+/// the SMIRKS of [torsion_key] with the atoms of `torsion` mapped in order
+fn environment_smirks(mol: &Molecule, torsion: [usize; 4]) -> String {
+    let mut atoms = torsion.to_vec();
+    for &t in &torsion {
+        for n in mol.neighbors(t) {
+            if !atoms.contains(&n) && mol.atoms[n].atomic_number != 1 {
+                atoms.push(n);
+            }
+        }
+    }
+    let index: BTreeMap<usize, usize> =
+        atoms.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+    let frag_atoms = atoms
+        .iter()
+        .map(|&a| MolAtom {
+            isotope: None,
+            n_hydrogens: 0,
+            chirality: Chiral::None,
+            mol_index: None,
+            ..mol.atoms[a].clone()
+        })
+        .collect();
+    let bonds = mol
+        .bonds
+        .iter()
+        .filter_map(|b| {
+            Some(MolBond {
+                atom1: *index.get(&b.atom1)?,
+                atom2: *index.get(&b.atom2)?,
+                bond_type: b.bond_type,
+                direction: None,
+            })
+        })
+        .collect();
+    let frag = Molecule::new(frag_atoms, bonds);
+    // the torsion atoms come first, in order, and the context after them
+    let colors: Vec<usize> = (0..atoms.len()).map(|i| i.min(4)).collect();
+    let order = canonical_order_colored(&frag, &colors);
+    write_canonical(&frag, &order, |i| {
+        let a = &mol.atoms[atoms[i]];
+        let mut ret = format!("[#{}", a.atomic_number);
+        if a.aromatic {
+            ret.push('a');
+        }
+        if i < 4 {
+            let h = a.n_hydrogens
+                + mol
+                    .neighbors(atoms[i])
+                    .filter(|&n| mol.atoms[n].atomic_number == 1)
+                    .count();
+            let x = mol.total_degree(atoms[i]);
+            ret.push_str(&format!("X{x}H{h}"));
+        }
+        ret.push_str(&format!("{:+}", a.charge));
+        if i < 4 {
+            ret.push_str(&format!(":{}", i + 1));
+        }
+        ret.push(']');
+        ret
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::molecule::mol;
+
+    use super::*;
+
+    #[test]
+    fn classes() {
+        // ethylcyclobutane, with the ring atoms at positions 2 through 5
+        let m = mol("[#6H3]-[#6H2]-[#6H]1-[#6H2]-[#6H2]-[#6H2]-1");
+        use BondClass::*;
+        assert_eq!(bond_classes(&m), [Chain, Chain, Ring, Ring, Ring, Ring]);
+
+        let got = classified_torsions(&m);
+        let class =
+            |t: [usize; 4]| got.iter().find(|(u, _)| *u == t).map(|(_, c)| *c);
+        assert_eq!(class([0, 1, 2, 3]), Some(TorsionClass::RingAdjacent));
+        assert_eq!(class([1, 2, 3, 4]), Some(TorsionClass::InRing));
+        assert!(got.iter().all(|(_, c)| *c != TorsionClass::Acyclic));
+
+        let butane = mol("[#6H3]-[#6H2]-[#6H2]-[#6H3]");
+        assert_eq!(
+            classified_torsions(&butane),
+            [([0, 1, 2, 3], TorsionClass::Acyclic)]
+        );
+        // no torsions around a three-membered ring
+        assert!(torsions(&mol("[#6H2]1-[#6H2]-[#6H2]-1"))
+            .iter()
+            .all(|t| t[0] != t[3]));
+    }
+
+    #[test]
+    fn rotatable() {
+        let butane = mol("[#6H3]-[#6H2]-[#6H2]-[#6H3]");
+        assert_eq!(rotatable_bonds(&butane), [1]);
+        // the same with explicit hydrogens, where only the C-C bond in the
+        // middle can rotate
+        let explicit = mol(
+            "[#6](-[#1])(-[#1])(-[#1])-[#6](-[#1])(-[#1])-[#6](-[#1])(-[#1])\
+             -[#6](-[#1])(-[#1])-[#1]",
+        );
+        let got = rotatable_bonds(&explicit);
+        assert_eq!(got.len(), 1);
+        let b = &explicit.bonds[got[0]];
+        assert_eq!((b.atom1, b.atom2), (4, 7));
+    }
+
+    #[test]
+    fn symmetry() {
+        // the nine H-C-C-H torsions of ethane are all equivalent
+        let ethane = mol("[#1]-[#6](-[#1])(-[#1])-[#6](-[#1])(-[#1])-[#1]");
+        let got = symmetry_classes(&ethane);
+        assert_eq!(got.len(), 9);
+        assert!(got.iter().all(|&(_, c)| c == 0));
+
+        // with implicit hydrogens, propanol has a single heavy-atom torsion
+        let m = mol("[#6H3]-[#6H2]-[#6H2]-[#8H]");
+        assert_eq!(symmetry_classes(&m), [([0, 1, 2, 3], 0)]);
+    }
+
+    #[test]
+    fn keys() {
+        let propanol = mol("[#6H3]-[#6H2]-[#6H2]-[#8H]");
+        let key = torsion_key(&propanol, [0, 1, 2, 3]);
+        assert_eq!(key, "[#6X4H3+0:1]-[#6X4H2+0:2]-[#6X4H2+0:3]-[#8X2H1+0:4]");
+        assert_eq!(torsion_key(&propanol, [3, 2, 1, 0]), key);
+        let explicit = mol(
+            "[#6](-[#1])(-[#1])(-[#1])-[#6](-[#1])(-[#1])-[#6](-[#1])(-[#1])\
+             -[#8]-[#1]",
+        );
+        assert_eq!(torsion_key(&explicit, [0, 4, 7, 10]), key);
+
+        // the same central torsion of hexane and heptane, with one carbon of
+        // context on each side
+        let hexane = mol("[#6H3]-[#6H2]-[#6H2]-[#6H2]-[#6H2]-[#6H3]");
+        let heptane = mol("[#6H3]-[#6H2]-[#6H2]-[#6H2]-[#6H2]-[#6H2]-[#6H3]");
+        let key = torsion_key(&hexane, [1, 2, 3, 4]);
+        assert_eq!(torsion_key(&heptane, [4, 3, 2, 1]), key);
+        assert_eq!(
+            key,
+            "[#6X4H2+0:1](-[#6X4H2+0:2]-[#6X4H2+0:3]-[#6X4H2+0:4]-[#6+0])\
+             -[#6+0]"
+        );
+        assert_ne!(torsion_key(&hexane, [0, 1, 2, 3]), key);
+
+        // a QCArchive dihedral in map order
+        let mapped = mol("[#6H3:3]-[#6H2:1]-[#6H2:2]-[#8H:4]");
+        let got = driven_torsion(&mapped, [2, 0, 1, 3]);
+        assert_eq!(got, Some([0, 1, 2, 3]));
+        assert_eq!(
+            torsion_key(&mapped, got.unwrap()),
+            torsion_key(&propanol, [0, 1, 2, 3])
+        );
+        assert_eq!(driven_torsion(&mapped, [2, 0, 3, 1]), None);
+        assert_eq!(driven_torsion(&mapped, [2, 0, 1, 4]), None);
+    }
+}
@@ -0,0 +1,389 @@
+//! Assigning rotatable bonds to the classes of a torsion library, in the style
+//! of the TorsionLib of Schärfe et al.
+//!
+//! A library is a tree of SMARTS patterns read from TorsionLib XML, where
+//! `hierarchyClass`, `hierarchySubClass`, and `torsionRule` elements become
+//! nodes and everything else only contributes its children. Each pattern maps
+//! its central bond with atom maps 2 and 3. A bond is assigned by walking down
+//! the tree, at each level taking the first node whose pattern matches across
+//! the bond, so more specific rules should come before more general ones, as
+//! in the original library
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs::read_to_string,
+    path::Path,
+};
+
+use crate::{
+    error::ChomperError,
+    matcher::{find_matches, MatchOptions},
+    molecule::Molecule,
+    query::{Query, Target},
+    smarts::{InputKind, Smarts},
+    torsion::rotatable_bonds,
+};
+
+/// One class or rule in a [TorsionLibrary]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryNode {
+    /// the `name` attribute, or the SMARTS for rules without one
+    pub name: String,
+    pub smarts: Option<String>,
+    pattern: Option<Smarts>,
+    /// the positions in `pattern` of the atoms mapped 2 and 3
+    central: (usize, usize),
+    /// the position of this node in a preorder walk of the library
+    id: usize,
+    pub children: Vec<LibraryNode>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TorsionLibrary {
+    pub roots: Vec<LibraryNode>,
+}
+
+impl TorsionLibrary {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChomperError> {
+        Self::from_xml(&read_to_string(path)?)
+    }
+
+    /// parse a library from TorsionLib XML. only the element structure and
+    /// the `name` and `smarts` attributes are used
+    pub fn from_xml(s: &str) -> Result<Self, ChomperError> {
+        // the root collects the top-level nodes
+        let mut stack: Vec<(String, Option<LibraryNode>, Vec<LibraryNode>)> =
+            vec![(String::new(), None, Vec::new())];
+        for tag in tags(s)? {
+            match tag {
+                Tag::Open {
+                    name,
+                    attrs,
+                    self_closing,
+                } => {
+                    let node = if NODE_TAGS.contains(&name.as_str()) {
+                        Some(node(&attrs)?)
+                    } else {
+                        None
+                    };
+                    stack.push((name, node, Vec::new()));
+                    if self_closing {
+                        close(&mut stack);
+                    }
+                }
+                Tag::Close(name) => {
+                    if stack.len() < 2 || stack.last().unwrap().0 != name {
+                        return Err(malformed(format!("unexpected </{name}>")));
+                    }
+                    close(&mut stack);
+                }
+            }
+        }
+        if stack.len() != 1 {
+            let open = &stack.last().unwrap().0;
+            return Err(malformed(format!("unclosed <{open}>")));
+        }
+        let mut roots = stack.pop().unwrap().2;
+        number(&mut roots, &mut 0);
+        Ok(Self { roots })
+    }
+
+    /// assign each rotatable bond of `mol`, as given by [rotatable_bonds], to
+    /// a class, named by the path of node names from the root joined by `/`.
+    /// bonds matched by no top-level node are assigned `None`
+    pub fn assign(&self, mol: &Molecule) -> Vec<(usize, Option<String>)> {
+        let target = Target::new(mol);
+        let options = MatchOptions {
+            unique: false,
+            ..Default::default()
+        };
+        // node ID -> the central bonds matched by its pattern, computed
+        // lazily since most of the tree is never visited
+        let mut cache: HashMap<usize, HashSet<(usize, usize)>> = HashMap::new();
+        let mut hits = |node: &LibraryNode, bond: (usize, usize)| {
+            let Some(pattern) = &node.pattern else {
+                return true;
+            };
+            cache
+                .entry(node.id)
+                .or_insert_with(|| {
+                    let (a, b) = node.central;
+                    find_matches(&Query::from(pattern), &target, &options)
+                        .matches
+                        .iter()
+                        .map(|m| (m[a].min(m[b]), m[a].max(m[b])))
+                        .collect()
+                })
+                .contains(&bond)
+        };
+        rotatable_bonds(mol)
+            .into_iter()
+            .map(|b| {
+                let bond = &mol.bonds[b];
+                let bond =
+                    (bond.atom1.min(bond.atom2), bond.atom1.max(bond.atom2));
+                let mut path = Vec::new();
+                let mut level = &self.roots;
+                while let Some(node) = level.iter().find(|n| hits(n, bond)) {
+                    path.push(node.name.as_str());
+                    level = &node.children;
+                }
+                (b, (!path.is_empty()).then(|| path.join("/")))
+            })
+            .collect()
+    }
+
+    /// count how many rotatable bonds across `mols` are assigned to each class
+    pub fn class_counts(&self, mols: &[Molecule]) -> ClassCounts {
+        let mut ret = ClassCounts::default();
+        for mol in mols {
+            for (_, class) in self.assign(mol) {
+                match class {
+                    Some(c) => *ret.counts.entry(c).or_default() += 1,
+                    None => ret.unassigned += 1,
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// The number of rotatable bonds assigned to each class by
+/// [TorsionLibrary::class_counts]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClassCounts {
+    pub counts: BTreeMap<String, usize>,
+    pub unassigned: usize,
+}
+
+/// Display one `count class` line per class, from most to least common, and
+/// then the number of unassigned bonds
+impl Display for ClassCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (class, n) in counts {
+            writeln!(f, "{n:>8} {class}")?;
+        }
+        writeln!(f, "{:>8} (unassigned)", self.unassigned)
+    }
+}
+
+const NODE_TAGS: [&str; 3] =
+    ["hierarchyClass", "hierarchySubClass", "torsionRule"];
+
+/// pop the innermost element off `stack`, attaching it to its parent if it
+/// is a node, or passing its children up if it is not
+fn close(stack: &mut Vec<(String, Option<LibraryNode>, Vec<LibraryNode>)>) {
+    let (_, node, children) = stack.pop().unwrap();
+    let parent = &mut stack.last_mut().unwrap().2;
+    match node {
+        Some(mut node) => {
+            node.children = children;
+            parent.push(node);
+        }
+        None => parent.extend(children),
+    }
+}
+
+/// assign preorder IDs to `nodes` and their descendants, starting from `next`
+fn number(nodes: &mut [LibraryNode], next: &mut usize) {
+    for node in nodes {
+        node.id = *next;
+        *next += 1;
+        number(&mut node.children, next);
+    }
+}
+
+fn node(attrs: &[(String, String)]) -> Result<LibraryNode, ChomperError> {
+    let attr =
+        |k: &str| attrs.iter().find(|(a, _)| a == k).map(|(_, v)| v.clone());
+    let smarts = attr("smarts");
+    let name = attr("name")
+        .or_else(|| smarts.clone())
+        .ok_or_else(|| malformed("library node without a name or smarts"))?;
+    let (pattern, central) = match &smarts {
+        Some(s) => {
+            let invalid = |error| ChomperError::Pattern {
+                name: name.clone(),
+                smarts: s.clone(),
+                error: Box::new(error),
+            };
+            let pattern = Smarts::try_parse_as(s.clone(), InputKind::Smarts)
+                .map_err(invalid)?;
+            let maps = pattern.atom_maps();
+            let (Some(a), Some(b)) = (maps.position(2), maps.position(3))
+            else {
+                return Err(invalid(malformed("needs atoms mapped 2 and 3")));
+            };
+            (Some(pattern), (a, b))
+        }
+        None => (None, (0, 0)),
+    };
+    Ok(LibraryNode {
+        name,
+        smarts,
+        pattern,
+        central,
+        id: 0,
+        children: Vec::new(),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Tag {
+    Open {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    Close(String),
+}
+
+/// split `s` into its element tags, skipping text, comments, and processing
+/// instructions. this is just enough XML for TorsionLib files
+fn tags(s: &str) -> Result<Vec<Tag>, ChomperError> {
+    let mut ret = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(r) = rest.strip_prefix("<!--") {
+            let end = r
+                .find("-->")
+                .ok_or_else(|| malformed("unterminated comment"))?;
+            rest = &r[end + 3..];
+            continue;
+        }
+        // find the end of the tag, skipping any `>` inside quoted values
+        let mut quote = None;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| match quote {
+                Some(q) => {
+                    if c == q {
+                        quote = None;
+                    }
+                    false
+                }
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c == '>',
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(|| malformed("unterminated tag"))?;
+        let body = &rest[1..end];
+        rest = &rest[end + 1..];
+        if body.starts_with('?') || body.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = body.strip_prefix('/') {
+            ret.push(Tag::Close(name.trim().to_owned()));
+            continue;
+        }
+        let (body, self_closing) = match body.strip_suffix('/') {
+            Some(b) => (b, true),
+            None => (body, false),
+        };
+        let body = body.trim();
+        let (name, mut attrs_str) =
+            body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        let mut attrs = Vec::new();
+        loop {
+            attrs_str = attrs_str.trim_start();
+            if attrs_str.is_empty() {
+                break;
+            }
+            let (key, r) = attrs_str.split_once('=').ok_or_else(|| {
+                malformed(format!("malformed attribute in <{name}>"))
+            })?;
+            let r = r.trim_start();
+            let q = r
+                .chars()
+                .next()
+                .filter(|&c| c == '"' || c == '\'')
+                .ok_or_else(|| {
+                    malformed(format!("unquoted attribute in <{name}>"))
+                })?;
+            let close = r[1..].find(q).ok_or_else(|| {
+                malformed(format!("unterminated attribute in <{name}>"))
+            })?;
+            attrs.push((key.trim().to_owned(), unescape(&r[1..close + 1])));
+            attrs_str = &r[close + 2..];
+        }
+        ret.push(Tag::Open {
+            name: name.to_owned(),
+            attrs,
+            self_closing,
+        });
+    }
+    Ok(ret)
+}
+
+/// a [ChomperError::Format] describing input that isn't a valid library
+fn malformed(message: impl Into<String>) -> ChomperError {
+    ChomperError::Format(message.into())
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::molecule::mol;
+
+    #[test]
+    fn assign() {
+        let lib = TorsionLibrary::load("testfiles/torsionlib.xml").unwrap();
+        assert_eq!(lib.roots.len(), 2);
+        assert_eq!(lib.roots[0].children.len(), 2);
+
+        // in propyl methyl ether, the C-C bond next to the oxygen falls under
+        // the generic C-C rule and the C-O bond under the ether rule. the
+        // bonds to the methyl groups aren't rotatable
+        let ether = mol("[#6H3]-[#6H2]-[#6H2]-[#8]-[#6H3]");
+        let got = lib.assign(&ether);
+        assert_eq!(
+            got,
+            [
+                (1, Some("CC/[#6:1]-[#6:2]-[#6:3]-[#8:4]".to_owned())),
+                (2, Some("CO/ether/[#6:1]-[#6:2]-[#8:3]-[#6H3:4]".to_owned())),
+            ]
+        );
+
+        // no class for C-N bonds
+        let amine = mol("[#6H3]-[#6H2]-[#7H]-[#6H3]");
+        assert_eq!(lib.assign(&amine), [(1, None)]);
+
+        let counts = lib.class_counts(&[ether, amine]);
+        assert_eq!(counts.unassigned, 1);
+        assert_eq!(counts.counts["CO/ether/[#6:1]-[#6:2]-[#8:3]-[#6H3:4]"], 1);
+        assert_eq!(counts.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(TorsionLibrary::from_xml("<library><torsionRule").is_err());
+        assert!(TorsionLibrary::from_xml("<a></b>").is_err());
+        assert!(TorsionLibrary::from_xml(
+            r#"<torsionRule smarts="[#6:1]-[#6:4]"/>"#
+        )
+        .is_err());
+        let got = TorsionLibrary::from_xml(
+            r#"<torsionRule smarts="[#6:2]?[#6:3]"/>"#,
+        );
+        assert!(got.unwrap_err().to_string().starts_with("invalid pattern"));
+        let got = TorsionLibrary::from_xml(
+            r#"<?xml version="1.0"?><!-- <x> --><library/>"#,
+        );
+        assert_eq!(got.unwrap(), TorsionLibrary::default());
+    }
+}
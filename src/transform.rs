@@ -0,0 +1,309 @@
+//! Applying reaction SMIRKS to molecules
+
+use std::collections::BTreeMap;
+
+use crate::{
+    error::ChomperError,
+    matcher::{CompiledQuery, MatchOptions},
+    molecule::Molecule,
+    query::{Query, Target},
+    smarts::{map_table, Atom, Bond, InputKind, Smarts},
+};
+
+/// A reaction SMIRKS of the form `reactants>>products`.
+///
+/// Atoms are paired across the two sides by their atom maps, so every atom on
+/// both sides must carry one. A mapped reactant atom with no counterpart in
+/// the products is deleted, and a product atom whose map does not appear in the
+/// reactants is created. For paired atoms, any field that differs between the
+/// reactant and product atoms is copied from the product onto the matched
+/// atom, and bonds between paired atoms are added, removed, or changed to
+/// agree with the product side. Both sides are parsed as [InputKind::Smarts], so
+/// an H count omitted on both sides is left alone, while one omitted only on
+/// a created atom is taken to be zero
+pub struct Transform {
+    reactant: Smarts,
+    product: Smarts,
+    query: CompiledQuery,
+}
+
+impl Transform {
+    /// parse `smirks`, failing if it has no `>>`, if either side is
+    /// malformed, or if any atom is unmapped
+    pub fn new(smirks: &str) -> Result<Self, ChomperError> {
+        let Some((reactant, product)) = smirks.split_once(">>") else {
+            return Err(ChomperError::Format(format!(
+                "missing `>>` in SMIRKS {smirks}"
+            )));
+        };
+        let reactant =
+            Smarts::try_parse_as(reactant.to_owned(), InputKind::Smarts)?;
+        let product =
+            Smarts::try_parse_as(product.to_owned(), InputKind::Smarts)?;
+        for atom in reactant.atoms.iter().chain(&product.atoms) {
+            if atom.mol_index.is_none() {
+                return Err(ChomperError::Format(format!(
+                    "unmapped atom in SMIRKS {smirks}"
+                )));
+            }
+        }
+        let query = CompiledQuery::new(
+            &Query::from(&reactant),
+            MatchOptions {
+                unique: true,
+                ..Default::default()
+            },
+        );
+        Ok(Self {
+            reactant,
+            product,
+            query,
+        })
+    }
+
+    /// rewrite the atom maps on both sides of `self` to be contiguous from 1,
+    /// keeping the pairing between them, and return the table from old maps
+    /// to new maps
+    pub fn compress_maps(&mut self) -> BTreeMap<usize, usize> {
+        let mut maps = self.reactant.maps();
+        maps.extend(self.product.maps());
+        maps.sort();
+        maps.dedup();
+        let table = map_table(maps, 1);
+        self.reactant.remap(&table);
+        self.product.remap(&table);
+        table
+    }
+
+    /// apply `self` to the first match of the reactant side in `mol`, if any.
+    /// `mol` must be a concrete molecule, as accepted by [Molecule::try_from];
+    /// anything else has no matches
+    pub fn apply(&self, mol: &Smarts) -> Option<Smarts> {
+        self.matches(mol).first().map(|m| self.edit(mol, m))
+    }
+
+    /// apply `self` separately to each unique match of the reactant side in
+    /// `mol`, returning one product per match
+    pub fn apply_all(&self, mol: &Smarts) -> Vec<Smarts> {
+        self.matches(mol)
+            .iter()
+            .map(|m| self.edit(mol, m))
+            .collect()
+    }
+
+    /// the unique matches of the reactant side in `mol`
+    fn matches(&self, mol: &Smarts) -> Vec<Vec<usize>> {
+        let Ok(mol) = Molecule::try_from(mol) else {
+            return Vec::new();
+        };
+        self.query.find_matches(&Target::new(&mol)).matches
+    }
+
+    /// edit a copy of `mol` according to the match `m` of the reactant side
+    fn edit(&self, mol: &Smarts, m: &[usize]) -> Smarts {
+        let mut out = mol.clone();
+        // product position -> position in `out`
+        let mut pt: Vec<Option<usize>> = vec![None; self.product.atoms.len()];
+        // reactant position -> product position
+        let mut rp: Vec<Option<usize>> = Vec::new();
+        let mut delete = vec![false; out.atoms.len()];
+        let product_maps = self.product.atom_maps();
+        for (ra, &t) in self.reactant.atoms.iter().zip(m) {
+            // every atom is mapped, as checked in Transform::new
+            let p = ra.mol_index.and_then(|map| product_maps.position(map));
+            rp.push(p);
+            match p {
+                Some(p) => {
+                    pt[p] = Some(t);
+                    update_atom(&mut out.atoms[t], ra, &self.product.atoms[p]);
+                }
+                None => delete[t] = true,
+            }
+        }
+
+        let mapped = out.atoms.iter().any(|a| a.mol_index.is_some());
+        let mut next = out.atoms.iter().filter_map(|a| a.mol_index).max();
+        for (p, pa) in self.product.atoms.iter().enumerate() {
+            if pt[p].is_none() {
+                pt[p] = Some(out.atoms.len());
+                let mol_index = if mapped {
+                    next = Some(next.unwrap_or(0) + 1);
+                    next
+                } else {
+                    None
+                };
+                out.atoms.push(Atom {
+                    n_hydrogens: pa.n_hydrogens.or(Some(0)),
+                    explicit_charge: true,
+                    mol_index,
+                    ..pa.clone()
+                });
+            }
+        }
+
+        let find = |bonds: &[Bond], a: usize, b: usize| {
+            bonds.iter().position(|bond| {
+                (bond.atom1, bond.atom2) == (a, b)
+                    || (bond.atom1, bond.atom2) == (b, a)
+            })
+        };
+        for rb in &self.reactant.bonds {
+            let (Some(p1), Some(p2)) = (rp[rb.atom1], rp[rb.atom2]) else {
+                // one end is being deleted, which takes care of the bond
+                continue;
+            };
+            let Some(i) = find(&out.bonds, m[rb.atom1], m[rb.atom2]) else {
+                continue;
+            };
+            match find(&self.product.bonds, p1, p2) {
+                Some(j) => {
+                    let pb = &self.product.bonds[j];
+                    if pb.order != rb.order {
+                        out.bonds[i].order = pb.order.clone();
+                    }
+                }
+                None => out.retain_bonds(|k, _| k != i),
+            }
+        }
+        for pb in &self.product.bonds {
+            let (a, b) = (pt[pb.atom1].unwrap(), pt[pb.atom2].unwrap());
+            if find(&out.bonds, a, b).is_none() {
+                out.bonds
+                    .push(Bond::new(a.min(b), a.max(b), pb.order.clone()));
+            }
+        }
+
+        // remove the deleted atoms and shift the bond indices to match
+        delete.resize(out.atoms.len(), false);
+        let mut new = Vec::with_capacity(out.atoms.len());
+        let mut n = 0;
+        for &d in &delete {
+            new.push(n);
+            if !d {
+                n += 1;
+            }
+        }
+        let mut i = 0;
+        out.atoms.retain(|_| {
+            i += 1;
+            !delete[i - 1]
+        });
+        out.retain_bonds(|_, b| !delete[b.atom1] && !delete[b.atom2]);
+        for bond in &mut out.bonds {
+            bond.atom1 = new[bond.atom1];
+            bond.atom2 = new[bond.atom2];
+        }
+        out
+    }
+}
+
+/// copy the fields that change between `reactant` and `product` onto `atom`
+fn update_atom(atom: &mut Atom, reactant: &Atom, product: &Atom) {
+    if reactant.atomic_number != product.atomic_number {
+        atom.atomic_number = product.atomic_number;
+    }
+    if reactant.n_hydrogens != product.n_hydrogens {
+        atom.n_hydrogens = product.n_hydrogens;
+    }
+    if reactant.charge != product.charge {
+        atom.charge = product.charge;
+    }
+    if reactant.chirality != product.chirality {
+        atom.chirality = product.chirality.clone();
+    }
+    if reactant.aromatic != product.aromatic {
+        atom.aromatic = product.aromatic;
+    }
+    if reactant.aliphatic != product.aliphatic {
+        atom.aliphatic = product.aliphatic;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::smarts::{BondOrder, Chiral};
+
+    use super::*;
+
+    fn parse(s: &str) -> Smarts {
+        Smarts::parse(s.to_owned())
+    }
+
+    #[test]
+    fn protonate() {
+        let t = Transform::new("[#7H2:1]>>[#7H3+:1]").unwrap();
+        let got = t.apply(&parse("[#6H3:1]-[#7H2:2]")).unwrap();
+        assert_eq!(got.atoms[1], Atom::new(7, 3, 1, Chiral::None, 2));
+        assert_eq!(got.bonds, [Bond::new(0, 1, BondOrder::Single)]);
+    }
+
+    #[test]
+    fn bonds() {
+        let t = Transform::new("[#6:1]=[#8:2]>>[#6:1]-[#8:2]").unwrap();
+        let got = t.apply(&parse("[#6H3:1]-[#6H:2]=[#8:3]")).unwrap();
+        assert_eq!(
+            got.bonds,
+            [
+                Bond::new(0, 1, BondOrder::Single),
+                Bond::new(1, 2, BondOrder::Single),
+            ]
+        );
+        assert!(t.apply(&parse("[#6H4:1]")).is_none());
+    }
+
+    #[test]
+    fn add_and_delete() {
+        let t = Transform::new("[#6:1]-[#8H:2]>>[#6:1]").unwrap();
+        let got = t.apply(&parse("[#6H3:1]-[#6H2:2]-[#8H:3]")).unwrap();
+        assert_eq!(got.atoms.len(), 2);
+        assert_eq!(got.bonds, [Bond::new(0, 1, BondOrder::Single)]);
+
+        let t = Transform::new("[#6H3:1]>>[#6H2:1]-[#17:2]").unwrap();
+        let got = t.apply_all(&parse("[#6H3:1]-[#6H3:2]"));
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].atoms[2], Atom::new(17, 0, 0, Chiral::None, 3));
+        assert_eq!(got[0].atoms[0].n_hydrogens, Some(2));
+        assert_eq!(got[0].bonds[1], Bond::new(0, 2, BondOrder::Single));
+        assert_eq!(got[1].bonds[1], Bond::new(1, 2, BondOrder::Single));
+    }
+
+    #[test]
+    fn compress_maps() {
+        let mut t =
+            Transform::new("[#6:4]-[#8H:9]>>[#6:4]-[#8:9]-[#6H3:20]").unwrap();
+        let table = t.compress_maps();
+        assert_eq!(table, BTreeMap::from([(4, 1), (9, 2), (20, 3)]));
+        assert_eq!(t.reactant.maps(), [1, 2]);
+        assert_eq!(t.product.maps(), [1, 2, 3]);
+        let got = t.apply(&parse("[#6H3]-[#8H]")).unwrap();
+        assert_eq!(got.atoms.len(), 3);
+    }
+
+    #[test]
+    fn unmapped_target() {
+        let t = Transform::new("[#8H:1]>>[#8:1]-[#6H3:2]").unwrap();
+        let got = t.apply(&parse("[#6H3]-[#8H]")).unwrap();
+        assert_eq!(got.atoms[2], Atom::new(6, 3, 0, Chiral::None, None));
+        assert_eq!(got.bonds[1], Bond::new(1, 2, BondOrder::Single));
+
+        // deleting an atom from the middle shifts the later bonds down
+        let t = Transform::new("[#7:1]-[#6:2]>>[#7:1]").unwrap();
+        let got = t.apply(&parse("[#8H]-[#6H2]-[#7H2]")).unwrap();
+        assert_eq!(got.atoms.len(), 2);
+        assert!(got.bonds.is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(
+            Transform::new("[#6:1]"),
+            Err(ChomperError::Format(_))
+        ));
+        assert!(matches!(
+            Transform::new("[#6:1]>>[#6:1]-[#8]"),
+            Err(ChomperError::Format(_))
+        ));
+        assert!(Transform::new("[#6:1]?>>[#6:1]").is_err());
+        assert!(Transform::new("[#6:1]>>[#6:1]1").is_err());
+    }
+}
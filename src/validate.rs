@@ -0,0 +1,240 @@
+//! Checking generated SMARTS against rdkit's reading of them.
+//!
+//! A pattern generated from a molecule should at least match that molecule,
+//! and at the atoms it was generated from. If rdkit can't parse the pattern,
+//! or parses it but finds no match at those atoms of its source, the pattern
+//! means something different to rdkit than it does to chomper, which is worth knowing before a set of SMIRKS ships in a force
+//! field
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::{generate::Generated, rdkit, Provenance};
+
+/// One pattern to check and the molecule it was generated from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    /// a label for the pattern in the report, like its parameter ID
+    pub label: String,
+    pub smarts: String,
+    /// the SMILES of the source molecule
+    pub source: String,
+    /// the atoms of `source` in rdkit's order that the mapped atoms of the
+    /// pattern were generated from, in map order
+    pub atoms: Vec<usize>,
+    pub provenance: Option<Provenance>,
+}
+
+impl Case {
+    /// one case for each cluster member that contributed to `generated`,
+    /// labeled `label`. `smiles` holds the SMILES of each molecule passed to
+    /// [crate::generate::generate], in the same order, with the atoms of each
+    /// in the order of the corresponding molecule
+    pub fn from_generated(
+        label: &str,
+        generated: &Generated,
+        smiles: &[String],
+    ) -> Vec<Case> {
+        // every member contributes exactly one element to each atom
+        let mut seen = BTreeSet::new();
+        let mut ret = Vec::new();
+        let contributors = generated
+            .atoms
+            .first()
+            .into_iter()
+            .flat_map(|a| &a.elements)
+            .flat_map(|d| &d.contributors);
+        for c in contributors {
+            if seen.insert(c.member) {
+                let atoms = generated
+                    .atoms
+                    .iter()
+                    .filter_map(|a| {
+                        a.elements
+                            .iter()
+                            .flat_map(|d| &d.contributors)
+                            .find(|d| d.member == c.member)
+                            .map(|d| d.atom)
+                    })
+                    .collect();
+                ret.push(Case {
+                    label: label.to_owned(),
+                    smarts: generated.smirks.clone(),
+                    source: smiles[c.molecule].clone(),
+                    atoms,
+                    provenance: c.provenance.clone(),
+                });
+            }
+        }
+        ret
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// rdkit could not read the pattern or its source
+    Rdkit(String),
+    /// rdkit read the pattern but found no match in its source
+    NoMatch,
+    /// rdkit found the pattern in its source, but never at the atoms it was
+    /// generated from
+    WrongAtoms,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Rdkit(msg) => write!(f, "{msg}"),
+            ValidationError::NoMatch => {
+                write!(f, "pattern does not match its source molecule")
+            }
+            ValidationError::WrongAtoms => write!(
+                f,
+                "pattern does not match the atoms it was generated from"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The outcome of [validate]: the number of cases checked and each failing
+/// case with the reason it failed, in input order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub n_checked: usize,
+    pub failures: Vec<(Case, ValidationError)>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Display the number of failures followed by one block per failing case
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} of {} patterns failed",
+            self.failures.len(),
+            self.n_checked
+        )?;
+        for (case, err) in &self.failures {
+            write!(f, "{}: {}", case.label, case.smarts)?;
+            if let Some(p) = &case.provenance {
+                write!(f, " ({})", p.dataset_key)?;
+            }
+            writeln!(f)?;
+            writeln!(f, "    {}: {err}", case.source)?;
+        }
+        Ok(())
+    }
+}
+
+/// check that rdkit can read each pattern in `cases` and finds it at the
+/// case's atoms of its source molecule
+pub fn validate(cases: impl IntoIterator<Item = Case>) -> ValidationReport {
+    validate_with(cases, |pattern, smiles| {
+        rdkit::substructure_matches(pattern, smiles).map_err(|e| e.to_string())
+    })
+}
+
+/// [validate] with `matches` in place of rdkit
+fn validate_with(
+    cases: impl IntoIterator<Item = Case>,
+    matches: impl Fn(&str, &str) -> Result<Vec<Vec<usize>>, String>,
+) -> ValidationReport {
+    let mut ret = ValidationReport::default();
+    for case in cases {
+        ret.n_checked += 1;
+        let err = match matches(&case.smarts, &case.source) {
+            Ok(m) if m.contains(&case.atoms) => continue,
+            Ok(m) if m.is_empty() => ValidationError::NoMatch,
+            Ok(_) => ValidationError::WrongAtoms,
+            Err(e) => ValidationError::Rdkit(e),
+        };
+        ret.failures.push((case, err));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        generate::{generate, Member},
+        molecule::Molecule,
+        smarts::Smarts,
+    };
+
+    use super::*;
+
+    #[test]
+    fn report() {
+        let smiles: Vec<String> = ["[CH3][CH2][OH]", "[CH3][NH][CH3]"]
+            .map(String::from)
+            .into();
+        let mols: Vec<_> = ["[#6H3]-[#6H2]-[#8H]", "[#6H3]-[#7H]-[#6H3]"]
+            .map(|s| Molecule::try_from(&Smarts::parse(s.to_owned())).unwrap())
+            .into();
+        let cluster = [
+            Member {
+                molecule: 0,
+                atoms: vec![1, 2],
+            },
+            Member {
+                molecule: 1,
+                atoms: vec![0, 1],
+            },
+        ];
+        let generated = generate(&mols, &cluster).unwrap();
+        let cases = Case::from_generated("b1", &generated, &smiles);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[1].source, smiles[1]);
+        assert_eq!(
+            (&cases[0].atoms, &cases[1].atoms),
+            (&vec![1, 2], &vec![0, 1])
+        );
+
+        // pretend that rdkit only finds the pattern in ethanol
+        let got = validate_with(cases.clone(), |_, source| {
+            if source.contains('N') {
+                Ok(vec![])
+            } else {
+                Ok(vec![vec![1, 2]])
+            }
+        });
+        assert_eq!(got.n_checked, 2);
+        assert_eq!(got.failures.len(), 1);
+        assert_eq!(got.failures[0].1, ValidationError::NoMatch);
+        assert_eq!(
+            got.to_string(),
+            format!(
+                "1 of 2 patterns failed\nb1: {}\n    [CH3][NH][CH3]: pattern \
+                 does not match its source molecule\n",
+                generated.smirks
+            )
+        );
+
+        let got = validate_with(
+            vec![Case {
+                label: "bad".to_owned(),
+                smarts: "[".to_owned(),
+                source: "C".to_owned(),
+                atoms: vec![0],
+                provenance: None,
+            }],
+            |s, _| Err(format!("rdkit could not read SMARTS {s}")),
+        );
+        assert!(!got.passed());
+        assert_eq!(
+            got.failures[0].1.to_string(),
+            "rdkit could not read SMARTS ["
+        );
+
+        // a match elsewhere in the molecule doesn't count
+        let got = validate_with(cases, |_, _| Ok(vec![vec![2, 1]]));
+        assert_eq!(got.failures.len(), 2);
+        assert_eq!(got.failures[0].1, ValidationError::WrongAtoms);
+    }
+}
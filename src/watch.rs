@@ -0,0 +1,241 @@
+//! Polling a directory for new dataset files and processing each one as it
+//! arrives
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{metadata, read_dir, write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    catalog::PatternCatalog,
+    error::ChomperError,
+    matcher::MatchOptions,
+    molecule::Molecule,
+    report::{coverage_table, Report},
+    schema::Document,
+    Dataset, DatasetFormat,
+};
+
+/// suffixes appended to a dataset's file stem for each of the outputs. files
+/// containing these are never treated as inputs
+const JSON_SUFFIX: &str = ".chomper.json";
+const HTML_SUFFIX: &str = ".report.html";
+
+/// The pipeline run on each new file. Every dataset is parsed and written out
+/// as a [Document]. If `catalog` is provided, its patterns are also matched
+/// against the molecules, with the counts added to the document and an HTML
+/// coverage report written next to it
+pub struct WatchConfig {
+    pub catalog: Option<PatternCatalog>,
+    pub options: MatchOptions,
+    /// how long [Watcher::run] sleeps between polls
+    pub interval: Duration,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            catalog: None,
+            options: MatchOptions::default(),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The result of processing a single input file: either the paths of the
+/// outputs written, or the reason it failed
+pub type Outcome = Result<Vec<PathBuf>, ChomperError>;
+
+/// Watches a directory for new dataset files in any of the formats
+/// [DatasetFormat::detect] recognizes, compressed or not. A file is only
+/// processed once its size is unchanged between two polls, so that files
+/// still being copied in are not read partway through. Files whose outputs already exist
+/// are skipped, which means restarting a watcher does not redo earlier work
+pub struct Watcher {
+    dir: PathBuf,
+    config: WatchConfig,
+    /// files that have already been processed, successfully or not
+    seen: HashSet<PathBuf>,
+    /// files waiting for their size to settle, with the size at the last poll
+    pending: HashMap<PathBuf, u64>,
+}
+
+impl Watcher {
+    pub fn new(dir: impl Into<PathBuf>, config: WatchConfig) -> Self {
+        Self {
+            dir: dir.into(),
+            config,
+            seen: HashSet::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// check the directory once, processing any files that are ready and
+    /// returning the outcome for each of them. only failing to list the
+    /// directory is an error here; a file that can't be read, even to check
+    /// its size, gets an error [Outcome] instead
+    pub fn poll(&mut self) -> Result<Vec<(PathBuf, Outcome)>, ChomperError> {
+        let mut paths = read_dir(&self.dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        let mut ret = Vec::new();
+        for path in paths {
+            if self.seen.contains(&path) || !is_input(&path) {
+                continue;
+            }
+            if outputs(&path).0.exists() {
+                self.seen.insert(path);
+                continue;
+            }
+            let size = match metadata(&path) {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    self.pending.remove(&path);
+                    self.seen.insert(path.clone());
+                    ret.push((path, Err(e.into())));
+                    continue;
+                }
+            };
+            if self.pending.insert(path.clone(), size) != Some(size) {
+                continue;
+            }
+            self.pending.remove(&path);
+            let outcome = self.process(&path);
+            self.seen.insert(path.clone());
+            ret.push((path, outcome));
+        }
+        Ok(ret)
+    }
+
+    /// poll forever, calling `f` with each outcome and sleeping for the
+    /// configured interval between polls
+    pub fn run(
+        &mut self,
+        mut f: impl FnMut(&Path, &Outcome),
+    ) -> Result<(), ChomperError> {
+        loop {
+            for (path, outcome) in self.poll()? {
+                f(&path, &outcome);
+            }
+            std::thread::sleep(self.config.interval);
+        }
+    }
+
+    fn process(&self, path: &Path) -> Outcome {
+        let mols = Dataset::load(path)?.parse()?;
+        let (json, html) = outputs(path);
+        let mut doc = Document::new().with_molecules(&mols);
+        let mut written = Vec::new();
+        if let Some(catalog) = &self.config.catalog {
+            let mols = mols
+                .iter()
+                .map(Molecule::try_from)
+                .collect::<Result<Vec<_>, _>>()?;
+            let matrix = catalog.match_all(&mols, &self.config.options);
+            doc = doc.with_match_counts(&matrix);
+            let mut report =
+                Report::new(format!("Coverage of {}", path.display()));
+            report.push(coverage_table(catalog, &matrix, true));
+            write(&html, report.to_html())?;
+            written.push(html);
+        }
+        write(&json, doc.to_json())?;
+        written.insert(0, json);
+        Ok(written)
+    }
+}
+
+fn is_input(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    DatasetFormat::detect(path).is_some() && !name.ends_with(JSON_SUFFIX)
+}
+
+/// the JSON and HTML output paths for the input `path`
+fn outputs(path: &Path) -> (PathBuf, PathBuf) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    (
+        path.with_file_name(format!("{stem}{JSON_SUFFIX}")),
+        path.with_file_name(format!("{stem}{HTML_SUFFIX}")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+
+    use super::*;
+
+    #[test]
+    fn poll() {
+        let dir = std::env::temp_dir().join("chomper-watch-test");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        write(dir.join("empty.json"), r#"{"entries": {}}"#).unwrap();
+        write(dir.join("bad.json"), "not json").unwrap();
+        write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let mut w = Watcher::new(&dir, WatchConfig::default());
+        // the first poll only records the sizes
+        assert!(w.poll().unwrap().is_empty());
+        let got = w.poll().unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].0, dir.join("bad.json"));
+        assert!(got[0].1.is_err());
+        assert_eq!(got[1].0, dir.join("empty.json"));
+        let out = got[1].1.as_ref().unwrap();
+        assert_eq!(out, &[dir.join("empty.chomper.json")]);
+        let doc = Document::from_json(&read_to_string(&out[0]).unwrap());
+        assert!(doc.unwrap().molecules.is_empty());
+
+        // nothing new, including the output that was just written
+        assert!(w.poll().unwrap().is_empty());
+        assert!(w.poll().unwrap().is_empty());
+
+        // a fresh watcher skips files that were already processed
+        let mut w = Watcher::new(&dir, WatchConfig::default());
+        w.poll().unwrap();
+        let got = w.poll().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, dir.join("bad.json"));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inputs() {
+        assert!(is_input(Path::new("a.json")));
+        assert!(is_input(Path::new("a.sdf")));
+        assert!(is_input(Path::new("a.smi.gz")));
+        assert!(is_input(Path::new("a.msgpack.zst")));
+        assert!(is_input(Path::new("a.parquet")));
+        assert!(is_input(Path::new("a.csv")));
+        assert!(!is_input(Path::new("a.chomper.json")));
+        assert!(!is_input(Path::new("a.report.html")));
+        assert!(!is_input(Path::new("notes.txt")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable() {
+        let dir = std::env::temp_dir().join("chomper-watch-unreadable");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        std::os::unix::fs::symlink(dir.join("gone"), dir.join("dangling.json"))
+            .unwrap();
+        write(dir.join("empty.json"), r#"{"entries": {}}"#).unwrap();
+
+        // the dangling link fails on its own without stopping the poll
+        let mut w = Watcher::new(&dir, WatchConfig::default());
+        let got = w.poll().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, dir.join("dangling.json"));
+        assert!(matches!(got[0].1, Err(ChomperError::Io(_))));
+        let got = w.poll().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, dir.join("empty.json"));
+        assert!(got[0].1.is_ok());
+        remove_dir_all(&dir).unwrap();
+    }
+}